@@ -0,0 +1,39 @@
+//! The async functions only rely on `std::task::Waker`, so they work with any executor.
+//! This example coordinates the computation of a value among several async-std tasks.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use quick_cache::sync::Cache;
+
+#[async_std::main]
+async fn main() {
+    let cache: Cache<u64, String> = Cache::new(100);
+    let computations = Arc::new(AtomicUsize::new(0));
+    let tasks = (0..8)
+        .map(|_| {
+            let cache = cache.clone();
+            let computations = computations.clone();
+            async_std::task::spawn(async move {
+                cache
+                    .get_or_insert_async(&1, async {
+                        computations.fetch_add(1, Ordering::Relaxed);
+                        async_std::task::sleep(Duration::from_millis(10)).await;
+                        Ok::<_, ()>("one".to_string())
+                    })
+                    .await
+            })
+        })
+        .collect::<Vec<_>>();
+    for task in tasks {
+        assert_eq!(task.await.as_deref(), Ok("one"));
+    }
+    // only one of the tasks computed the value, the others waited for it
+    assert_eq!(computations.load(Ordering::Relaxed), 1);
+    println!("{:?}", cache.get(&1));
+}
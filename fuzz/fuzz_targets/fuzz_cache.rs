@@ -1,14 +1,13 @@
 #![no_main]
 use libfuzzer_sys::fuzz_target;
 use quick_cache::{sync::KQCache, DefaultHashBuilder, OptionsBuilder, Weighter};
-use std::num::NonZeroU32;
 
 #[derive(Clone)]
 struct MyWeighter;
 
 impl Weighter<u16, u16, ()> for MyWeighter {
-    fn weight(&self, key: &u16, _qey: &u16, _val: &()) -> NonZeroU32 {
-        (*key as u32).max(1).try_into().unwrap()
+    fn weight(&self, key: &u16, _qey: &u16, _val: &()) -> u32 {
+        *key as u32
     }
 }
 
@@ -21,20 +20,39 @@ fuzz_target!(|ops: Vec<u16>| {
     let hot_allocation = ops[3] as f64 / (u16::MAX as f64);
     let ghost_allocation = ops[4] as f64 / (u16::MAX as f64);
     let shards = (ops[5] as usize) % 10;
-    let options = OptionsBuilder::new()
+    let doorkeeper = ops[5] > u16::MAX / 2;
+    let frequency_sketch = ops[4] % 2 == 1;
+    let adaptive = !frequency_sketch && ops[3] % 2 == 1;
+    let shard_hash_seeds = ops[2] % 2 == 1;
+    let key_affinity = ops[0] % 2 == 1;
+    let mut options = OptionsBuilder::new();
+    options
         .estimated_items_capacity(estimated_items_capacity)
         .weight_capacity(weight_capacity)
         .hot_allocation(hot_allocation)
         .ghost_allocation(ghost_allocation)
         .shards(shards)
-        .build()
-        .unwrap();
-    let cache = KQCache::with_options(options, MyWeighter, DefaultHashBuilder::default());
+        .doorkeeper(doorkeeper)
+        .frequency_sketch(frequency_sketch)
+        .shard_hash_seeds(shard_hash_seeds)
+        .key_affinity(key_affinity);
+    if adaptive {
+        options.adaptive_hot_allocation(hot_allocation / 2.0, hot_allocation.max(0.5));
+    }
+    if ops[1] % 2 == 1 {
+        options.max_items(ops[1] as usize / 2 + 1);
+    }
+    let options = options.build().unwrap();
+    let mut cache = KQCache::with_options(options, MyWeighter, DefaultHashBuilder::default());
     for op in &ops {
         if cache.get(op, op).is_none() {
             cache.insert(*op, *op, ());
             cache.validate();
         }
+        if op % 4096 == 0 {
+            cache.rehash_with(DefaultHashBuilder::default());
+            cache.validate();
+        }
     }
     cache.validate();
 });
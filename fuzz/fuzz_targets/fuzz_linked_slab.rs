@@ -10,6 +10,17 @@ fuzz_target!(|ops: Vec<Result<u16, u16>>| {
     let mut slab = LinkedSlab::<u16>::with_capacity(0);
     let mut models = (0..3).map(|_| BTreeMap::new()).collect::<Vec<_>>();
     for op in ops {
+        if op == Ok(u16::MAX) {
+            // relocate entries and check that all lists remain intact
+            slab.compact(|&i, old, new| {
+                let token = models
+                    .iter_mut()
+                    .find_map(|m| m.get_mut(&i).filter(|t| **t == old));
+                *token.unwrap() = new;
+                true
+            });
+            continue;
+        }
         for model in &mut models {
             match op {
                 Ok(i) => {
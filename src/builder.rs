@@ -0,0 +1,149 @@
+use std::{
+    hash::{BuildHasher, Hash},
+    marker::PhantomData,
+};
+
+use crate::{
+    options::{Error, OptionsBuilder},
+    sync::Cache,
+    DefaultHashBuilder, UnitWeighter, Weighter,
+};
+
+/// Builder for a [sync::Cache](crate::sync::Cache), see `Cache::builder`.
+///
+/// Configures the [OptionsBuilder] settings along with the weighter and the hash builder,
+/// which default to [UnitWeighter] and [DefaultHashBuilder].
+/// Options not exposed here can be set with [CacheBuilder::options].
+///
+/// # Example
+///
+/// ```rust
+/// use quick_cache::{sync::Cache, Weighter};
+///
+/// #[derive(Clone)]
+/// struct StringWeighter;
+///
+/// impl Weighter<u64, (), String> for StringWeighter {
+///     fn weight(&self, _key: &u64, _qey: &(), val: &String) -> u32 {
+///         val.len().clamp(1, u32::MAX as usize) as u32
+///     }
+/// }
+///
+/// let cache = Cache::builder()
+///     .estimated_items_capacity(10000)
+///     .weight_capacity(1_000_000)
+///     .options(|o| {
+///         o.doorkeeper(true);
+///     })
+///     .weighter(StringWeighter)
+///     .build()
+///     .unwrap();
+/// cache.insert(1, "1".to_string());
+/// ```
+pub struct CacheBuilder<Key, Val, We = UnitWeighter, B = DefaultHashBuilder> {
+    options: OptionsBuilder,
+    weighter: We,
+    hash_builder: B,
+    _marker: PhantomData<fn() -> (Key, Val)>,
+}
+
+impl<Key, Val> CacheBuilder<Key, Val> {
+    /// Creates a builder with the default options, weighter and hash builder.
+    pub fn new() -> Self {
+        Self {
+            options: OptionsBuilder::new(),
+            weighter: UnitWeighter,
+            hash_builder: DefaultHashBuilder::default(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<Key, Val> Default for CacheBuilder<Key, Val> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Key, Val, We, B> CacheBuilder<Key, Val, We, B> {
+    /// Sets the number of internal shards, see [OptionsBuilder::shards].
+    pub fn shards(mut self, shards: usize) -> Self {
+        self.options.shards(shards);
+        self
+    }
+
+    /// Sets the estimated number of items the cache is expected to hold,
+    /// see [OptionsBuilder::estimated_items_capacity].
+    pub fn estimated_items_capacity(mut self, estimated_items_capacity: usize) -> Self {
+        self.options
+            .estimated_items_capacity(estimated_items_capacity);
+        self
+    }
+
+    /// Sets the max weight that the cache can hold, see [OptionsBuilder::weight_capacity].
+    pub fn weight_capacity(mut self, weight_capacity: u64) -> Self {
+        self.options.weight_capacity(weight_capacity);
+        self
+    }
+
+    /// Sets the fraction of the cache space reserved for "hot" items,
+    /// see [OptionsBuilder::hot_allocation].
+    pub fn hot_allocation(mut self, hot_allocation: f64) -> Self {
+        self.options.hot_allocation(hot_allocation);
+        self
+    }
+
+    /// Sets the number of ghost keys tracked by the cache, see [OptionsBuilder::ghost_capacity].
+    pub fn ghost_capacity(mut self, ghost_capacity: usize) -> Self {
+        self.options.ghost_capacity(ghost_capacity);
+        self
+    }
+
+    /// Configures the remaining options, e.g. the doorkeeper.
+    pub fn options(mut self, f: impl FnOnce(&mut OptionsBuilder)) -> Self {
+        f(&mut self.options);
+        self
+    }
+
+    /// Sets the weighter of the cache items.
+    pub fn weighter<We2>(self, weighter: We2) -> CacheBuilder<Key, Val, We2, B> {
+        CacheBuilder {
+            options: self.options,
+            weighter,
+            hash_builder: self.hash_builder,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Sets the hash builder of the cache keys.
+    pub fn hash_builder<B2>(self, hash_builder: B2) -> CacheBuilder<Key, Val, We, B2> {
+        CacheBuilder {
+            options: self.options,
+            weighter: self.weighter,
+            hash_builder,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<Key: Eq + Hash, Val: Clone, We: Weighter<Key, (), Val> + Clone, B: BuildHasher + Clone>
+    CacheBuilder<Key, Val, We, B>
+{
+    /// Builds the cache, failing if the options are invalid, e.g. the capacities aren't set.
+    pub fn build(self) -> Result<Cache<Key, Val, We, B>, Error> {
+        let options = self.options.build()?;
+        Ok(Cache::with_options(
+            options,
+            self.weighter,
+            self.hash_builder,
+        ))
+    }
+}
+
+impl<Key, Val, We, B> std::fmt::Debug for CacheBuilder<Key, Val, We, B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CacheBuilder")
+            .field("options", &self.options)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,152 @@
+//! Coalescing of repeated insertions of the same key.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::time::Duration;
+//! use quick_cache::{coalesce::Coalescer, sync::Cache};
+//!
+//! let cache = Cache::<&str, u64>::new(100);
+//! let counters = Coalescer::new(&cache, Duration::from_millis(100), |total: &mut u64, n| *total += n);
+//! for _ in 0..1000 {
+//!     counters.insert("requests", 1);
+//! }
+//! counters.flush();
+//! assert_eq!(cache.get("requests"), Some(1000));
+//! ```
+
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, Hash},
+    mem,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{sync::Cache, DefaultHashBuilder, UnitWeighter, Weighter};
+
+/// Buffers insertions into a [Cache], merging the values inserted for the same key
+/// with a user closure, and writes them to the cache at most once per window.
+///
+/// This reduces the write lock churn when the same keys are inserted many times in quick
+/// succession. The merged value is inserted as is, so it replaces
+/// the value previously in the cache.
+///
+/// Buffered values aren't visible through the cache until they're flushed, which happens on
+/// the first insertion after the window elapsed, on [Coalescer::flush] or when dropped.
+pub struct Coalescer<
+    'a,
+    Key: Eq + Hash,
+    Val: Clone,
+    F: Fn(&mut Val, Val),
+    We: Weighter<Key, (), Val> + Clone = UnitWeighter,
+    B: BuildHasher + Clone = DefaultHashBuilder,
+> {
+    cache: &'a Cache<Key, Val, We, B>,
+    merge: F,
+    window: Duration,
+    pending: Mutex<Pending<Key, Val>>,
+}
+
+struct Pending<Key, Val> {
+    values: HashMap<Key, Val>,
+    since: Instant,
+}
+
+impl<
+        'a,
+        Key: Eq + Hash,
+        Val: Clone,
+        F: Fn(&mut Val, Val),
+        We: Weighter<Key, (), Val> + Clone,
+        B: BuildHasher + Clone,
+    > Coalescer<'a, Key, Val, F, We, B>
+{
+    /// Creates a coalescer writing to `cache` every `window`,
+    /// `merge` being called to merge a value into the one buffered for the same key.
+    pub fn new(cache: &'a Cache<Key, Val, We, B>, window: Duration, merge: F) -> Self {
+        Self {
+            cache,
+            merge,
+            window,
+            pending: Mutex::new(Pending {
+                values: HashMap::new(),
+                since: Instant::now(),
+            }),
+        }
+    }
+
+    /// Buffers an item with key `key`, merging it with the one already buffered, if any.
+    pub fn insert(&self, key: Key, value: Val) {
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(buffered) = pending.values.get_mut(&key) {
+            (self.merge)(buffered, value);
+        } else {
+            pending.values.insert(key, value);
+        }
+        if pending.since.elapsed() >= self.window {
+            let values = Self::take(&mut pending);
+            drop(pending);
+            self.write(values);
+        }
+    }
+
+    /// Writes the buffered items to the cache.
+    pub fn flush(&self) {
+        let values = Self::take(&mut self.pending.lock().unwrap_or_else(|e| e.into_inner()));
+        self.write(values);
+    }
+
+    /// Number of buffered items.
+    pub fn pending_len(&self) -> usize {
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .values
+            .len()
+    }
+
+    fn take(pending: &mut Pending<Key, Val>) -> HashMap<Key, Val> {
+        pending.since = Instant::now();
+        mem::take(&mut pending.values)
+    }
+
+    fn write(&self, values: HashMap<Key, Val>) {
+        for (key, value) in values {
+            self.cache.insert(key, value);
+        }
+    }
+}
+
+impl<
+        'a,
+        Key: Eq + Hash,
+        Val: Clone,
+        F: Fn(&mut Val, Val),
+        We: Weighter<Key, (), Val> + Clone,
+        B: BuildHasher + Clone,
+    > Drop for Coalescer<'a, Key, Val, F, We, B>
+{
+    fn drop(&mut self) {
+        let pending = self.pending.get_mut().unwrap_or_else(|e| e.into_inner());
+        for (key, value) in pending.values.drain() {
+            self.cache.insert(key, value);
+        }
+    }
+}
+
+impl<
+        'a,
+        Key: Eq + Hash,
+        Val: Clone,
+        F: Fn(&mut Val, Val),
+        We: Weighter<Key, (), Val> + Clone,
+        B: BuildHasher + Clone,
+    > std::fmt::Debug for Coalescer<'a, Key, Val, F, We, B>
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Coalescer")
+            .field("window", &self.window)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,171 @@
+//! Transparent compression of byte values with [lz4_flex](https://crates.io/crates/lz4_flex).
+//!
+//! The `compression` feature requires Rust 1.81 or newer, as does `lz4_flex` 0.11.
+//!
+//! # Example
+//!
+//! ```rust
+//! use quick_cache::compress::CompressedCache;
+//!
+//! let cache = CompressedCache::new(100, 1_000_000, 64);
+//! let json = br#"{"name": "alice", "tags": ["a", "a", "a", "a", "a", "a", "a", "a", "a", "a"]}"#.repeat(10);
+//! cache.insert("alice", &json);
+//! assert_eq!(cache.get("alice").as_deref(), Some(&json[..]));
+//! assert!(cache.weight() < json.len() as u64);
+//! ```
+
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+    sync::Arc,
+};
+
+use crate::{sync::Cache, DefaultHashBuilder, Options, Stats, Weighter};
+
+/// A stored value, compressed or not.
+#[derive(Clone)]
+struct Blob {
+    bytes: Arc<[u8]>,
+    compressed: bool,
+}
+
+/// Charges the stored size of the values, i.e. their compressed size if compressed.
+#[derive(Clone)]
+struct BlobWeighter;
+
+impl<Key> Weighter<Key, (), Blob> for BlobWeighter {
+    #[inline]
+    fn weight(&self, _key: &Key, _qey: &(), val: &Blob) -> u32 {
+        val.bytes.len().clamp(1, u32::MAX as usize) as u32
+    }
+}
+
+/// A concurrent cache of byte values, where the values larger than a
+/// threshold are compressed on insertion and decompressed on retrieval.
+///
+/// The weight of an entry is the stored size of its value, so the weight capacity is the
+/// (approximate) amount of memory used by the values. Values that don't shrink when compressed
+/// are stored as is.
+///
+/// Contrary to the other caches, retrievals return an owned copy of the value as they
+/// may have to decompress it.
+pub struct CompressedCache<Key, B = DefaultHashBuilder> {
+    cache: Cache<Key, Blob, BlobWeighter, B>,
+    threshold: usize,
+}
+
+impl<Key: Eq + Hash> CompressedCache<Key> {
+    /// Creates a new cache that can hold up to `weight_capacity` bytes of stored values,
+    /// compressing the values larger than `threshold` bytes.
+    /// `estimated_items_capacity` is the estimated number of items the cache is expected to hold.
+    pub fn new(estimated_items_capacity: usize, weight_capacity: u64, threshold: usize) -> Self {
+        Self {
+            cache: Cache::with_weighter(estimated_items_capacity, weight_capacity, BlobWeighter),
+            threshold,
+        }
+    }
+}
+
+impl<Key: Eq + Hash, B: BuildHasher + Clone> CompressedCache<Key, B> {
+    /// Constructs a cache based on [OptionsBuilder](crate::OptionsBuilder), compressing the values
+    /// larger than `threshold` bytes. The weight capacity is expressed in bytes of stored values.
+    pub fn with_options(options: Options, hash_builder: B, threshold: usize) -> Self {
+        Self {
+            cache: Cache::with_options(options, BlobWeighter, hash_builder),
+            threshold,
+        }
+    }
+
+    /// Returns whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    /// Returns the number of cached items
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Returns the total stored size of cached values
+    pub fn weight(&self) -> u64 {
+        self.cache.weight()
+    }
+
+    /// Returns the maximum stored size of cached values
+    pub fn capacity(&self) -> u64 {
+        self.cache.capacity()
+    }
+
+    /// Returns a snapshot of the cache counters
+    pub fn stats(&self) -> Stats {
+        self.cache.stats()
+    }
+
+    /// Fetches an item from the cache, decompressing it if needed.
+    pub fn get<Q>(&self, key: &Q) -> Option<Vec<u8>>
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.cache.get(key).map(Self::unpack)
+    }
+
+    /// Peeks an item from the cache, decompressing it if needed.
+    /// Contrary to gets, peeks don't alter the key "hotness".
+    pub fn peek<Q>(&self, key: &Q) -> Option<Vec<u8>>
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.cache.peek(key).map(Self::unpack)
+    }
+
+    /// Remove an item from the cache whose key is `key`.
+    /// Returns whether an entry was removed.
+    pub fn remove<Q>(&self, key: &Q) -> bool
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.cache.remove(key)
+    }
+
+    /// Inserts an item in the cache with key `key`,
+    /// compressing it if larger than the threshold.
+    pub fn insert(&self, key: Key, value: &[u8]) {
+        self.cache.insert(key, self.pack(value));
+    }
+
+    fn pack(&self, value: &[u8]) -> Blob {
+        if value.len() > self.threshold {
+            let compressed = lz4_flex::compress_prepend_size(value);
+            if compressed.len() < value.len() {
+                return Blob {
+                    bytes: compressed.into(),
+                    compressed: true,
+                };
+            }
+        }
+        Blob {
+            bytes: value.into(),
+            compressed: false,
+        }
+    }
+
+    fn unpack(blob: Blob) -> Vec<u8> {
+        if blob.compressed {
+            lz4_flex::decompress_size_prepended(&blob.bytes)
+                .expect("values are compressed by the cache")
+        } else {
+            blob.bytes.to_vec()
+        }
+    }
+}
+
+impl<Key, B> std::fmt::Debug for CompressedCache<Key, B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CompressedCache")
+            .field("threshold", &self.threshold)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,61 @@
+/// Bloom filter used to only admit keys seen at least twice within a window (aka. doorkeeper).
+///
+/// The filter is sized at ~8 bits per item, with 3 probes derived from the key hash, and it's
+/// cleared after recording as many new keys as the shard items capacity, so old sightings age out.
+#[derive(Clone)]
+pub struct Doorkeeper {
+    bits: Vec<u64>,
+    /// Number of bits - 1, the number of bits is always a power of two.
+    mask: u64,
+    additions: usize,
+    reset_at: usize,
+}
+
+const BITS_PER_ITEM: usize = 8;
+const PROBES: u64 = 3;
+
+impl Doorkeeper {
+    pub fn new(items_capacity: usize) -> Self {
+        // A shard can't hold more than u32::MAX items
+        let items_capacity = items_capacity.clamp(1, u32::MAX as usize);
+        let num_bits = (items_capacity * BITS_PER_ITEM).next_power_of_two().max(64);
+        Self {
+            bits: vec![0; num_bits / 64],
+            mask: num_bits as u64 - 1,
+            additions: 0,
+            reset_at: items_capacity,
+        }
+    }
+
+    /// Bytes allocated by the filter.
+    pub fn allocated_bytes(&self) -> usize {
+        self.bits.capacity() * std::mem::size_of::<u64>()
+    }
+
+    /// Records a sighting of the key with the given hash.
+    /// Returns whether the key was (probably) seen before.
+    pub fn insert(&mut self, hash: u64) -> bool {
+        // Double hashing using both halves of the hash, the second half is made odd
+        // so probes don't collapse into the same bit.
+        let (h1, h2) = (hash, (hash >> 32) | 1);
+        let mut seen = true;
+        for i in 0..PROBES {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) & self.mask;
+            let (word, mask) = ((bit / 64) as usize, 1u64 << (bit % 64));
+            seen &= self.bits[word] & mask != 0;
+            self.bits[word] |= mask;
+        }
+        if !seen {
+            self.additions += 1;
+            if self.additions >= self.reset_at {
+                self.clear();
+            }
+        }
+        seen
+    }
+
+    pub fn clear(&mut self) {
+        self.bits.fill(0);
+        self.additions = 0;
+    }
+}
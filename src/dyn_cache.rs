@@ -0,0 +1,152 @@
+use std::{
+    convert::Infallible,
+    hash::{BuildHasher, Hash},
+};
+
+use crate::{sync::Cache, NoopCache, Stats, Weighter};
+
+/// Object safe interface of the thread safe caches with fixed key and value types,
+/// so they can be used as `Arc<dyn DynCache<Key, Val>>` and swapped at runtime.
+///
+/// # Example
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use quick_cache::{sync::Cache, DynCache, NoopCache};
+///
+/// fn make_cache(enabled: bool) -> Arc<dyn DynCache<u64, String>> {
+///     if enabled {
+///         Arc::new(Cache::new(100))
+///     } else {
+///         Arc::new(NoopCache::new())
+///     }
+/// }
+///
+/// let cache = make_cache(true);
+/// cache.insert(1, "one".to_string());
+/// assert_eq!(cache.get(&1).as_deref(), Some("one"));
+/// let cache = make_cache(false);
+/// cache.insert(1, "one".to_string());
+/// assert_eq!(cache.get(&1), None);
+/// ```
+pub trait DynCache<Key, Val>: Send + Sync {
+    /// Returns the number of cached items
+    fn len(&self) -> usize;
+
+    /// Returns whether the cache is empty
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the total weight of cached items
+    fn weight(&self) -> u64;
+
+    /// Returns the maximum weight of cached items
+    fn capacity(&self) -> u64;
+
+    /// Returns a snapshot of the cache counters
+    fn stats(&self) -> Stats;
+
+    /// Fetches an item from the cache.
+    fn get(&self, key: &Key) -> Option<Val>;
+
+    /// Peeks an item from the cache. Contrary to gets, peeks don't alter the key "hotness".
+    fn peek(&self, key: &Key) -> Option<Val>;
+
+    /// Remove an item from the cache whose key is `key`.
+    /// Returns whether an entry was removed.
+    fn remove(&self, key: &Key) -> bool;
+
+    /// Inserts an item in the cache with key `key`.
+    fn insert(&self, key: Key, value: Val);
+
+    /// Gets or inserts an item in the cache with key `key`, computing it with `with` if absent.
+    fn get_or_insert_with(&self, key: &Key, with: &mut dyn FnMut() -> Val) -> Val;
+}
+
+impl<Key, Val, We, B> DynCache<Key, Val> for Cache<Key, Val, We, B>
+where
+    Key: Eq + Hash + Clone + Send + Sync,
+    Val: Clone + Send + Sync,
+    We: Weighter<Key, (), Val> + Clone + Send + Sync,
+    B: BuildHasher + Clone + Send + Sync,
+{
+    fn len(&self) -> usize {
+        Cache::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        Cache::is_empty(self)
+    }
+
+    fn weight(&self) -> u64 {
+        Cache::weight(self)
+    }
+
+    fn capacity(&self) -> u64 {
+        Cache::capacity(self)
+    }
+
+    fn stats(&self) -> Stats {
+        Cache::stats(self)
+    }
+
+    fn get(&self, key: &Key) -> Option<Val> {
+        Cache::get(self, key)
+    }
+
+    fn peek(&self, key: &Key) -> Option<Val> {
+        Cache::peek(self, key)
+    }
+
+    fn remove(&self, key: &Key) -> bool {
+        Cache::remove(self, key)
+    }
+
+    fn insert(&self, key: Key, value: Val) {
+        Cache::insert(self, key, value)
+    }
+
+    fn get_or_insert_with(&self, key: &Key, with: &mut dyn FnMut() -> Val) -> Val {
+        match Cache::get_or_insert_with(self, key, || Ok::<_, Infallible>(with())) {
+            Ok(value) => value,
+            Err(never) => match never {},
+        }
+    }
+}
+
+impl<Key, Val> DynCache<Key, Val> for NoopCache<Key, Val> {
+    fn len(&self) -> usize {
+        0
+    }
+
+    fn weight(&self) -> u64 {
+        0
+    }
+
+    fn capacity(&self) -> u64 {
+        0
+    }
+
+    fn stats(&self) -> Stats {
+        Stats::default()
+    }
+
+    fn get(&self, _key: &Key) -> Option<Val> {
+        None
+    }
+
+    fn peek(&self, _key: &Key) -> Option<Val> {
+        None
+    }
+
+    fn remove(&self, _key: &Key) -> bool {
+        false
+    }
+
+    fn insert(&self, _key: Key, _value: Val) {}
+
+    fn get_or_insert_with(&self, _key: &Key, with: &mut dyn FnMut() -> Val) -> Val {
+        with()
+    }
+}
@@ -0,0 +1,179 @@
+//! C API over a thread safe cache of byte strings.
+//!
+//! The functions use C compatible signatures, so a header can be generated with
+//! [cbindgen](https://crates.io/crates/cbindgen). To link them into a C program, build a `staticlib`
+//! or `cdylib` crate depending on `quick_cache` with the `ffi` feature enabled.
+//!
+//! Keys and values are copied into the cache. The weight of an entry is its key length plus its
+//! value length (at least 1). Lookups copy the value into a caller provided buffer.
+//!
+//! ```c
+//! QcCache *cache = qc_cache_new(1000, 1 << 20);
+//! qc_cache_insert(cache, (const uint8_t *)"key", 3, (const uint8_t *)"value", 5);
+//! uint8_t buf[64];
+//! size_t len;
+//! if (qc_cache_get(cache, (const uint8_t *)"key", 3, buf, sizeof(buf), &len)) {
+//!     // buf holds the first min(len, sizeof(buf)) bytes of the value
+//! }
+//! qc_cache_free(cache);
+//! ```
+
+use std::{slice, sync::Arc};
+
+use crate::{sync::Cache, DefaultHashBuilder, Weighter};
+
+#[derive(Debug, Clone)]
+struct BytesWeighter;
+
+impl Weighter<Box<[u8]>, (), Arc<[u8]>> for BytesWeighter {
+    #[inline]
+    fn weight(&self, key: &Box<[u8]>, _qey: &(), val: &Arc<[u8]>) -> u32 {
+        key.len()
+            .saturating_add(val.len())
+            .clamp(1, u32::MAX as usize) as u32
+    }
+}
+
+/// Opaque handle to a cache, created with [qc_cache_new] and released with [qc_cache_free].
+#[derive(Debug)]
+pub struct QcCache(Cache<Box<[u8]>, Arc<[u8]>, BytesWeighter, DefaultHashBuilder>);
+
+/// Cache statistics, see [crate::Stats].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QcStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub len: u64,
+    pub weight: u64,
+}
+
+/// # Safety
+/// `ptr` must be valid for reads of `len` bytes, or `len` must be 0.
+unsafe fn bytes<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(ptr, len)
+    }
+}
+
+/// Creates a cache holding up to `weight_capacity` bytes, `estimated_items_capacity` being the
+/// expected number of entries. The cache must be released with [qc_cache_free].
+#[no_mangle]
+pub extern "C" fn qc_cache_new(
+    estimated_items_capacity: usize,
+    weight_capacity: u64,
+) -> *mut QcCache {
+    let cache = Cache::with(
+        estimated_items_capacity,
+        weight_capacity,
+        BytesWeighter,
+        DefaultHashBuilder::default(),
+    );
+    Box::into_raw(Box::new(QcCache(cache)))
+}
+
+/// Releases a cache created with [qc_cache_new]. Does nothing if `cache` is null.
+///
+/// # Safety
+/// `cache` must be null or a pointer returned by [qc_cache_new] that wasn't released yet,
+/// and it must not be used concurrently or afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn qc_cache_free(cache: *mut QcCache) {
+    if !cache.is_null() {
+        drop(Box::from_raw(cache));
+    }
+}
+
+/// Inserts a copy of `value` with a copy of `key` as its key.
+///
+/// # Safety
+/// `cache` must be a live pointer returned by [qc_cache_new]. `key` and `value` must be valid
+/// for reads of `key_len` and `value_len` bytes respectively (they may be null if their length is 0).
+#[no_mangle]
+pub unsafe extern "C" fn qc_cache_insert(
+    cache: *const QcCache,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+) {
+    let cache = &*cache;
+    cache
+        .0
+        .insert(bytes(key, key_len).into(), bytes(value, value_len).into());
+}
+
+/// Looks up `key`, returning whether it was found.
+/// If so, the value length is written to `value_len` and its first `min(value_len, out_capacity)`
+/// bytes are copied to `out`. Callers can retry with a larger buffer if it was too small.
+///
+/// # Safety
+/// `cache` must be a live pointer returned by [qc_cache_new]. `key` must be valid for reads of
+/// `key_len` bytes, `out` must be valid for writes of `out_capacity` bytes (they may be null if
+/// their length is 0) and `value_len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn qc_cache_get(
+    cache: *const QcCache,
+    key: *const u8,
+    key_len: usize,
+    out: *mut u8,
+    out_capacity: usize,
+    value_len: *mut usize,
+) -> bool {
+    let cache = &*cache;
+    // the value is copied outside of the shard lock
+    match cache.0.get(bytes(key, key_len)) {
+        Some(value) => {
+            let copied = value.len().min(out_capacity);
+            if copied != 0 {
+                slice::from_raw_parts_mut(out, copied).copy_from_slice(&value[..copied]);
+            }
+            *value_len = value.len();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Removes `key`, returning whether an entry was removed.
+///
+/// # Safety
+/// `cache` must be a live pointer returned by [qc_cache_new] and `key` must be valid for reads
+/// of `key_len` bytes (it may be null if `key_len` is 0).
+#[no_mangle]
+pub unsafe extern "C" fn qc_cache_remove(
+    cache: *const QcCache,
+    key: *const u8,
+    key_len: usize,
+) -> bool {
+    let cache = &*cache;
+    cache.0.remove(bytes(key, key_len))
+}
+
+/// Returns the number of cached entries.
+///
+/// # Safety
+/// `cache` must be a live pointer returned by [qc_cache_new].
+#[no_mangle]
+pub unsafe extern "C" fn qc_cache_len(cache: *const QcCache) -> usize {
+    (*cache).0.len()
+}
+
+/// Writes the cache statistics to `stats`.
+///
+/// # Safety
+/// `cache` must be a live pointer returned by [qc_cache_new] and `stats` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn qc_cache_stats(cache: *const QcCache, stats: *mut QcStats) {
+    let s = (*cache).0.stats();
+    *stats = QcStats {
+        hits: s.hits,
+        misses: s.misses,
+        evictions: s.evictions,
+        len: s.len as u64,
+        weight: s.weight,
+    };
+}
@@ -0,0 +1,185 @@
+use std::collections::VecDeque;
+
+use hashbrown::raw::RawTable;
+
+use crate::sketch::FrequencySketch;
+
+/// Bounded FIFO set of hash fingerprints, used to remember keys recently evicted during their
+/// test period (aka. ghost or non-resident keys).
+///
+/// Only a 32 bit fingerprint of the key hash is kept, in both a ring buffer (eviction order) and
+/// a table (lookups). Each ghost costs roughly 9 bytes, instead of a slab entry plus a map slot.
+/// A fingerprint collision makes a non-resident key look like a ghost, which only affects
+/// whether it's admitted as hot or cold.
+#[derive(Clone)]
+pub struct Ghosts {
+    /// Fingerprints in the order they were added.
+    /// Fingerprints removed by `remove` are left behind in the ring until they're popped.
+    ring: VecDeque<u32>,
+    /// Fingerprints currently tracked, possibly with duplicates.
+    set: RawTable<u32>,
+    capacity: usize,
+}
+
+#[inline]
+fn fingerprint(hash: u64) -> u32 {
+    // hashbrown relies on the lowest and highest bits of the hash,
+    // picking the middle bits avoids some correlation with the map placement.
+    (hash >> 16) as u32
+}
+
+#[inline]
+fn fingerprint_hash(fingerprint: u32) -> u64 {
+    // Spread the fingerprint bits over the 64 bits hash expected by hashbrown
+    (fingerprint as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+}
+
+impl Ghosts {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            ring: VecDeque::new(),
+            set: RawTable::new(),
+            capacity,
+        }
+    }
+
+    /// Number of tracked ghosts.
+    pub fn len(&self) -> usize {
+        self.set.len()
+    }
+
+    #[cfg(fuzzing)]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Bytes allocated by the ring and the table.
+    pub fn allocated_bytes(&self) -> usize {
+        self.ring.capacity() * std::mem::size_of::<u32>() + self.set.allocation_info().1.size()
+    }
+
+    /// Tracks the key with the given hash, forgetting the oldest ghost if at capacity.
+    /// Returns whether a ghost expired, that is, it was forgotten without ever being hit.
+    pub fn insert(&mut self, hash: u64) -> bool {
+        if self.capacity == 0 {
+            return false;
+        }
+        let expired = self.ring.len() >= self.capacity && self.pop();
+        let fingerprint = fingerprint(hash);
+        self.ring.push_back(fingerprint);
+        self.set
+            .insert(fingerprint_hash(fingerprint), fingerprint, |&f| {
+                fingerprint_hash(f)
+            });
+        expired
+    }
+
+    /// Forgets the ghost with the given hash, returning whether it was tracked.
+    pub fn remove(&mut self, hash: u64) -> bool {
+        let fingerprint = fingerprint(hash);
+        self.set
+            .erase_entry(fingerprint_hash(fingerprint), |&f| f == fingerprint)
+    }
+
+    /// Forgets the oldest ghost, returning whether it was still tracked.
+    fn pop(&mut self) -> bool {
+        let Some(fingerprint) = self.ring.pop_front() else {
+            return false;
+        };
+        self.set
+            .erase_entry(fingerprint_hash(fingerprint), |&f| f == fingerprint)
+    }
+
+    /// Changes the max number of ghosts, forgetting the oldest ones if above it.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.ring.len() > capacity {
+            self.pop();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.ring.clear();
+        self.set.clear();
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.ring.shrink_to_fit();
+        self.set.shrink_to(0, |&f| fingerprint_hash(f));
+    }
+}
+
+/// Record of the keys recently evicted during their test period, see [Ghosts] and [FrequencySketch].
+#[derive(Clone)]
+pub enum History {
+    Ghosts(Ghosts),
+    Sketch(FrequencySketch),
+}
+
+impl History {
+    /// Number of tracked ghosts, always 0 for sketches as they don't track individual keys.
+    pub fn len(&self) -> usize {
+        match self {
+            History::Ghosts(ghosts) => ghosts.len(),
+            History::Sketch(_) => 0,
+        }
+    }
+
+    #[cfg(fuzzing)]
+    pub fn capacity(&self) -> usize {
+        match self {
+            History::Ghosts(ghosts) => ghosts.capacity(),
+            History::Sketch(_) => 0,
+        }
+    }
+
+    pub fn allocated_bytes(&self) -> usize {
+        match self {
+            History::Ghosts(ghosts) => ghosts.allocated_bytes(),
+            History::Sketch(sketch) => sketch.allocated_bytes(),
+        }
+    }
+
+    /// Records the eviction of the key with the given hash.
+    /// Returns whether an older ghost expired, which is never the case for sketches.
+    pub fn insert(&mut self, hash: u64) -> bool {
+        match self {
+            History::Ghosts(ghosts) => ghosts.insert(hash),
+            History::Sketch(sketch) => {
+                sketch.increment(hash);
+                false
+            }
+        }
+    }
+
+    /// Returns whether the key with the given hash was recently evicted, forgetting it if possible.
+    /// Sketches can't forget keys, their counters only fade with aging.
+    pub fn remove(&mut self, hash: u64) -> bool {
+        match self {
+            History::Ghosts(ghosts) => ghosts.remove(hash),
+            History::Sketch(sketch) => sketch.estimate(hash) != 0,
+        }
+    }
+
+    /// Changes the number of keys tracked, see [Ghosts::set_capacity] and [FrequencySketch::resize].
+    pub fn set_capacity(&mut self, capacity: usize) {
+        match self {
+            History::Ghosts(ghosts) => ghosts.set_capacity(capacity),
+            History::Sketch(sketch) => sketch.resize(capacity),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        match self {
+            History::Ghosts(ghosts) => ghosts.clear(),
+            History::Sketch(sketch) => sketch.clear(),
+        }
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        match self {
+            History::Ghosts(ghosts) => ghosts.shrink_to_fit(),
+            History::Sketch(_) => (),
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::vec;
+
+use crate::shard::Resident;
+
+/// An owning iterator over the items of a [sync::Cache](crate::sync::Cache)
+/// or an [unsync::Cache](crate::unsync::Cache), see their `into_iter`
+/// and `drain`.
+pub struct IntoIter<Key, Val>(KQIntoIter<Key, (), Val>);
+
+impl<Key, Val> IntoIter<Key, Val> {
+    pub(crate) fn new(inner: KQIntoIter<Key, (), Val>) -> Self {
+        Self(inner)
+    }
+}
+
+impl<Key, Val> Iterator for IntoIter<Key, Val> {
+    type Item = (Key, Val);
+
+    #[inline]
+    fn next(&mut self) -> Option<(Key, Val)> {
+        self.0.next().map(|(key, _, val)| (key, val))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<Key, Val> ExactSizeIterator for IntoIter<Key, Val> {}
+
+impl<Key, Val> std::fmt::Debug for IntoIter<Key, Val> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IntoIter")
+            .field("remaining", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// An owning iterator over the items of a [sync::KQCache](crate::sync::KQCache)
+/// or an [unsync::KQCache](crate::unsync::KQCache), see their `into_iter`
+/// and `drain`.
+pub struct KQIntoIter<Key, Qey, Val>(vec::IntoIter<Resident<Key, Qey, Val>>);
+
+impl<Key, Qey, Val> KQIntoIter<Key, Qey, Val> {
+    pub(crate) fn new(residents: Vec<Resident<Key, Qey, Val>>) -> Self {
+        Self(residents.into_iter())
+    }
+}
+
+impl<Key, Qey, Val> Iterator for KQIntoIter<Key, Qey, Val> {
+    type Item = (Key, Qey, Val);
+
+    #[inline]
+    fn next(&mut self) -> Option<(Key, Qey, Val)> {
+        self.0.next().map(Resident::into_parts)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<Key, Qey, Val> ExactSizeIterator for KQIntoIter<Key, Qey, Val> {}
+
+impl<Key, Qey, Val> std::fmt::Debug for KQIntoIter<Key, Qey, Val> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KQIntoIter")
+            .field("remaining", &self.len())
+            .finish_non_exhaustive()
+    }
+}
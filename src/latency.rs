@@ -0,0 +1,156 @@
+use std::{
+    sync::atomic::{self, AtomicU64},
+    time::Duration,
+};
+
+/// Number of buckets, the last one covers latencies of 2^31ns (~2s) and above.
+const BUCKETS: usize = 32;
+
+/// Coarse latency histogram with power of two buckets, in nanoseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Histogram {
+    buckets: [u64; BUCKETS],
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: [0; BUCKETS],
+        }
+    }
+}
+
+impl Histogram {
+    /// Number of recorded operations.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Iterator over the non-empty buckets, as their exclusive upper bound and count.
+    /// The last bucket has no upper bound, `Duration::MAX` is returned instead.
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count != 0)
+            .map(|(i, &count)| (Self::upper_bound(i), count))
+    }
+
+    /// Upper bound of the bucket containing the `quantile` (within `[0..=1.0]`) of the recorded latencies.
+    /// Returns `None` if nothing was recorded.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((count as f64 * quantile.clamp(0.0, 1.0)).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, &bucket_count) in self.buckets.iter().enumerate() {
+            seen += bucket_count;
+            if seen >= rank {
+                return Some(Self::upper_bound(i));
+            }
+        }
+        unreachable!()
+    }
+
+    fn upper_bound(bucket: usize) -> Duration {
+        if bucket == BUCKETS - 1 {
+            Duration::MAX
+        } else {
+            Duration::from_nanos(2 << bucket)
+        }
+    }
+
+    pub(crate) fn merge(mut self, other: Self) -> Self {
+        for (a, b) in self.buckets.iter_mut().zip(other.buckets) {
+            *a += b;
+        }
+        self
+    }
+}
+
+/// Latency histograms of the cache operations. Only the time spent inside the cache internal
+/// structures is measured, that is, waiting for locks or computing values isn't included.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyHistograms {
+    /// Lookups, including the ones from `get_or_insert` and `get_value_or_guard` functions.
+    pub get: Histogram,
+    /// Insertions, including the time spent evicting other entries.
+    pub insert: Histogram,
+}
+
+impl LatencyHistograms {
+    pub(crate) fn merge(self, other: Self) -> Self {
+        Self {
+            get: self.get.merge(other.get),
+            insert: self.insert.merge(other.insert),
+        }
+    }
+}
+
+/// Concurrent version of [Histogram].
+#[derive(Debug)]
+pub struct AtomicHistogram {
+    buckets: [AtomicU64; BUCKETS],
+}
+
+impl Clone for AtomicHistogram {
+    fn clone(&self) -> Self {
+        Self {
+            buckets: std::array::from_fn(|i| {
+                AtomicU64::new(self.buckets[i].load(atomic::Ordering::Relaxed))
+            }),
+        }
+    }
+}
+
+impl Default for AtomicHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+}
+
+impl AtomicHistogram {
+    #[inline]
+    pub fn record(&self, latency: Duration) {
+        let nanos = latency.as_nanos().min(u64::MAX as u128) as u64;
+        let bucket = (u64::BITS - nanos.leading_zeros()).saturating_sub(1) as usize;
+        self.buckets[bucket.min(BUCKETS - 1)].fetch_add(1, atomic::Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> Histogram {
+        Histogram {
+            buckets: std::array::from_fn(|i| self.buckets[i].load(atomic::Ordering::Relaxed)),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for bucket in &mut self.buckets {
+            *bucket.get_mut() = 0;
+        }
+    }
+}
+
+/// Per shard latency histograms.
+#[derive(Debug, Clone, Default)]
+pub struct ShardLatency {
+    pub get: AtomicHistogram,
+    pub insert: AtomicHistogram,
+}
+
+impl ShardLatency {
+    pub fn snapshot(&self) -> LatencyHistograms {
+        LatencyHistograms {
+            get: self.get.snapshot(),
+            insert: self.insert.snapshot(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.get.reset();
+        self.insert.reset();
+    }
+}
@@ -23,12 +23,27 @@
 //! # User defined weight
 //!
 //! By implementing the [Weighter] trait the user can define different weights for each cache entry.
+//! Entries may weigh `0`: they don't count against the weight capacity, but they do count as items
+//! and are evicted like any other entry.
 //!
 //! # Atomic operations
 //!
 //! By using the `get_or_insert` or `get_value_or_guard` family of functions (both sync and async variants
 //! are available, they can be mix and matched) the user can coordinate the insertion of entries, so only
 //! one value is "computed" and inserted after a cache miss.
+//! The async variants only rely on the standard `Waker` mechanism, so they work with any async runtime
+//! (e.g. tokio, async-std or smol), see the `async_std` example.
+//!
+//! # Namespaces
+//!
+//! A [NamespacedCache] partitions its capacity into namespaces with their own quota and stats,
+//! so inserting in one namespace never evicts entries of the others. Similarly, a [TenantCache] creates
+//! a cache per tenant on demand, which can be evicted along with all its entries at once.
+//!
+//! # Write coalescing
+//!
+//! When the same keys are inserted many times in quick succession, a [coalesce::Coalescer] can merge
+//! the values with a user closure and write them to a sync cache at most once per time window.
 //!
 //! # Hasher
 //!
@@ -36,36 +51,142 @@
 //! a crate feature with the same name. If the `ahash` feature is disabled the crate defaults to the std lib
 //! implementation instead (currently Siphash13). Note that a custom hasher can also be provided if desirable.
 //!
+//! Alternatively, disabling the default features and enabling one of the `foldhash`
+//! ([foldhash](https://crates.io/crates/foldhash)), `xxhash` ([twox-hash](https://crates.io/crates/twox-hash))
+//! or `fxhash` ([fxhash](https://crates.io/crates/fxhash)) features selects another default hasher.
+//! If several are enabled they take precedence in that order, after `ahash`.
+//! Note that `fxhash` isn't randomly seeded, so it shouldn't be used with untrusted keys.
+//!
+//! # Disabling caching
+//!
+//! A cache with a weight capacity of `0` stores nothing, every entry being heavier than the capacity.
+//! Lookups miss and insertions are dropped, while the `get_or_insert` and `get_value_or_guard` families
+//! of functions still coordinate concurrent computations of the same key. This allows disabling caching
+//! through configuration while keeping the same cache type. Alternatively, [NoopCache] offers the same
+//! basic interface without any hashing or locking.
+//!
+//! # Testing
+//!
+//! With the `mock` feature enabled, the `mock` module provides a [DynCache] implementation that records
+//! the operations performed on it and can be scripted to hit or miss, for testing code using a cache.
+//!
+//! # C API
+//!
+//! With the `ffi` feature enabled, the `ffi` module exposes a cache of byte strings through C compatible
+//! functions. This is the only part of the crate using unsafe code.
+//!
+//! # Limits
+//!
+//! Entries are addressed internally with 32 bit indices in order to keep the per-entry overhead low.
+//! As a consequence each shard can hold at most `u32::MAX - 1` entries, counting both resident entries
+//! and pending placeholders. Exceeding it results in a panic.
+//!
+//! # Simulation
+//!
+//! With the `simulate` feature enabled, the `simulate` module can replay access
+//! traces through a given cache configuration, reporting hit rates and evictions.
+//! Traces of real workloads can be captured with the `trace` feature, see the `trace` module.
+//!
+//! # Latency histograms
+//!
+//! With the `latency` feature enabled, caches record coarse histograms of the get and insert latencies
+//! (including the time spent evicting entries), available through the `latency_histograms` functions.
+//!
+//! # OpenTelemetry
+//!
+//! With the `opentelemetry` feature enabled, the `otel` module can export the cache statistics as
+//! OpenTelemetry instruments. Note that this feature requires a newer Rust version than the rest of the crate.
+//!
+//! # Compression
+//!
+//! With the `compression` feature enabled, the `compress` module provides a cache of byte values that
+//! transparently compresses the larger values with lz4,
+//! charging their compressed size against the weight capacity.
+//! Note that this feature requires Rust 1.81 or newer, as does `lz4_flex` 0.11.
+//!
 //! # Synchronization primitives
 //!
 //! By default the crate uses [parking_lot](https://crates.io/crates/parking_lot), which is enabled (by default) via
 //! a crate feature with the same name. If the `parking_lot` feature is disabled the crate defaults to the std lib
 //! implementation instead.
 
-use std::num::NonZeroU32;
-
+mod builder;
+mod cache_padded;
+pub mod coalesce;
+#[cfg(feature = "compression")]
+pub mod compress;
+mod doorkeeper;
+mod dyn_cache;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod ghost;
+mod iter;
+#[cfg(feature = "latency")]
+mod latency;
 #[cfg(not(fuzzing))]
 mod linked_slab;
 #[cfg(fuzzing)]
 pub mod linked_slab;
+#[cfg(feature = "mock")]
+pub mod mock;
+mod namespaced;
+mod noop;
 #[cfg(not(fuzzing))]
 mod options;
 #[cfg(fuzzing)]
 pub mod options;
+#[cfg(feature = "opentelemetry")]
+pub mod otel;
 mod placeholder;
+mod prehashed;
 mod rw_lock;
+mod set;
 mod shard;
+mod shard_lock;
+#[cfg(feature = "simulate")]
+pub mod simulate;
+mod sketch;
+mod stats;
 /// Concurrent cache variants that can be used from multiple threads.
 pub mod sync;
+mod tenant;
+mod timestamps;
+#[cfg(feature = "trace")]
+pub mod trace;
 /// Non-concurrent cache variants.
 pub mod unsync;
 
+pub use builder::CacheBuilder;
+pub use dyn_cache::DynCache;
+pub use iter::{IntoIter, KQIntoIter};
+#[cfg(feature = "latency")]
+pub use latency::{Histogram, LatencyHistograms};
+pub use namespaced::NamespacedCache;
+pub use noop::NoopCache;
 pub use options::{Options, OptionsBuilder};
 pub use placeholder::{GuardResult, PlaceholderGuard};
+pub use prehashed::{PreHashed, PreHashedBuildHasher, PreHashedHasher};
+pub use set::CacheSet;
+pub use stats::{HandleStats, MemoryUsage, Rejections, Stats};
+pub use tenant::TenantCache;
 
 #[cfg(feature = "ahash")]
 pub type DefaultHashBuilder = ahash::RandomState;
-#[cfg(not(feature = "ahash"))]
+#[cfg(all(not(feature = "ahash"), feature = "foldhash"))]
+pub type DefaultHashBuilder = foldhash::fast::RandomState;
+#[cfg(all(not(any(feature = "ahash", feature = "foldhash")), feature = "xxhash"))]
+pub type DefaultHashBuilder = twox_hash::RandomXxHashBuilder64;
+#[cfg(all(
+    not(any(feature = "ahash", feature = "foldhash", feature = "xxhash")),
+    feature = "fxhash"
+))]
+pub type DefaultHashBuilder = fxhash::FxBuildHasher;
+#[cfg(not(any(
+    feature = "ahash",
+    feature = "foldhash",
+    feature = "xxhash",
+    feature = "fxhash"
+)))]
 pub type DefaultHashBuilder = std::collections::hash_map::RandomState;
 
 /// Defines the weight of a cache entry.
@@ -74,14 +195,13 @@ pub type DefaultHashBuilder = std::collections::hash_map::RandomState;
 ///
 /// ```
 /// use quick_cache::{sync::Cache, Weighter};
-/// use std::num::NonZeroU32;
 ///
 /// #[derive(Clone)]
 /// struct StringWeighter;
 ///
 /// impl Weighter<u64, (), String> for StringWeighter {
-///     fn weight(&self, _key: &u64, _qey: &(), val: &String) -> NonZeroU32 {
-///         NonZeroU32::new(val.len().clamp(1, u32::MAX as usize) as u32).unwrap()
+///     fn weight(&self, _key: &u64, _qey: &(), val: &String) -> u32 {
+///         val.len().clamp(1, u32::MAX as usize) as u32
 ///     }
 /// }
 ///
@@ -90,12 +210,100 @@ pub type DefaultHashBuilder = std::collections::hash_map::RandomState;
 /// ```
 pub trait Weighter<Key, Qey, Val> {
     /// Returns the weight of the cache item.
-    /// Note that this it's undefined behavior for a cache item to change its weight.
+    /// The weight is computed when the item is inserted. If it changes afterwards (e.g. the weighter
+    /// is reconfigured or the value is mutated), the cache keeps accounting the original weight
+    /// until the `reweigh_all` functions are called.
+    ///
+    /// Items of weight `0` don't count against the weight capacity, so they are never evicted to
+    /// make room for heavier items. They still count as items though: unless `max_items` is set,
+    /// a cache holds at most as many items as its weight capacity, so zero weight entries are
+    /// evicted in clock order like the others once that count is reached.
     ///
     /// For performance reasons this function should be trivially cheap as
     /// it's called during the cache eviction routine.
     /// If weight is expensive to calculate, consider caching it alongside the value.
-    fn weight(&self, key: &Key, qey: &Qey, val: &Val) -> NonZeroU32;
+    fn weight(&self, key: &Key, qey: &Qey, val: &Val) -> u32;
+}
+
+/// Error returned by the `try_reserve` functions when the requested capacity
+/// overflows or the allocator reports a failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TryReserveError(());
+
+impl std::fmt::Display for TryReserveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("memory allocation failed")
+    }
+}
+
+impl std::error::Error for TryReserveError {}
+
+impl From<std::collections::TryReserveError> for TryReserveError {
+    fn from(_: std::collections::TryReserveError) -> Self {
+        Self(())
+    }
+}
+
+impl From<hashbrown::TryReserveError> for TryReserveError {
+    fn from(_: hashbrown::TryReserveError) -> Self {
+        Self(())
+    }
+}
+
+/// Error returned by the `insert_checked` functions when an item can't be cached,
+/// giving the value back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejected<Val> {
+    /// The item weights more than the
+    /// [max_item_weight](OptionsBuilder::max_item_weight) or the capacity of its shard.
+    TooHeavy(Val),
+    /// The cache is frozen, see the `set_frozen` functions.
+    Frozen(Val),
+}
+
+impl<Val> Rejected<Val> {
+    /// Returns the value that couldn't be cached.
+    pub fn into_value(self) -> Val {
+        match self {
+            Rejected::TooHeavy(value) | Rejected::Frozen(value) => value,
+        }
+    }
+}
+
+impl<Val> std::fmt::Display for Rejected<Val> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejected::TooHeavy(_) => f.write_str("the item is too heavy to be cached"),
+            Rejected::Frozen(_) => f.write_str("the cache is frozen"),
+        }
+    }
+}
+
+impl<Val: std::fmt::Debug> std::error::Error for Rejected<Val> {}
+
+/// Error returned by the `remove_checked` functions when nothing can be removed
+/// because the cache is frozen, see the `set_frozen` functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frozen;
+
+impl std::fmt::Display for Frozen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the cache is frozen")
+    }
+}
+
+impl std::error::Error for Frozen {}
+
+/// What to do with an item after running the closure of the `compute` functions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Compute<Val> {
+    /// Keeps the item as modified in place by the closure, recomputing its weight.
+    /// Nothing is inserted if the item isn't cached.
+    Keep,
+    /// Inserts the value, replacing the cached one if any.
+    Insert(Val),
+    /// Removes the item, if cached.
+    Remove,
 }
 
 /// Each cache entry weights exactly `1` unit of weight.
@@ -104,20 +312,32 @@ pub struct UnitWeighter;
 
 impl<Key, Qey, Val> Weighter<Key, Qey, Val> for UnitWeighter {
     #[inline]
-    fn weight(&self, _key: &Key, _qey: &Qey, _val: &Val) -> NonZeroU32 {
-        NonZeroU32::new(1).unwrap()
+    fn weight(&self, _key: &Key, _qey: &Qey, _val: &Val) -> u32 {
+        1
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::{
-        sync::{atomic::AtomicUsize, Arc},
+        sync::{
+            atomic::{self, AtomicU32, AtomicUsize},
+            Arc,
+        },
         time::Duration,
     };
 
     use super::*;
 
+    #[derive(Clone)]
+    struct StringWeighter;
+
+    impl Weighter<u64, (), String> for StringWeighter {
+        fn weight(&self, _key: &u64, _qey: &(), val: &String) -> u32 {
+            val.len() as u32
+        }
+    }
+
     #[test]
     fn test_new() {
         sync::KQCache::<u64, u64, u64>::new(0);
@@ -134,15 +354,6 @@ mod tests {
 
     #[test]
     fn test_custom_cost() {
-        #[derive(Clone)]
-        struct StringWeighter;
-
-        impl Weighter<u64, (), String> for StringWeighter {
-            fn weight(&self, _key: &u64, _qey: &(), val: &String) -> NonZeroU32 {
-                NonZeroU32::new(val.len().clamp(1, u32::MAX as usize) as u32).unwrap()
-            }
-        }
-
         let cache = sync::Cache::with_weighter(100, 100_000, StringWeighter);
         cache.insert(1, "1".to_string());
         cache.insert(54, "54".to_string());
@@ -158,6 +369,2587 @@ mod tests {
         assert_eq!(cache.get("square", &2022).unwrap(), "blue");
     }
 
+    #[test]
+    fn test_shrink_to_fit() {
+        let mut cache = unsync::Cache::new(1000);
+        for i in 0..2000 {
+            cache.insert(i, i);
+        }
+        let resident = (0..2000)
+            .filter(|i| cache.peek(i).is_some())
+            .collect::<Vec<_>>();
+        for i in resident.iter().filter(|&i| i % 10 != 0) {
+            cache.remove(i);
+        }
+        cache.shrink_to_fit();
+        for i in &resident {
+            assert_eq!(cache.peek(i).is_some(), i % 10 == 0);
+        }
+        for i in 2000..3000 {
+            cache.insert(i, i);
+            assert_eq!(cache.get(&i), Some(&i));
+        }
+
+        let cache = sync::Cache::new(1000);
+        for i in 0..1000 {
+            cache.insert(i, i);
+        }
+        let before = cache.memory_usage().total_bytes();
+        cache.retain(|&k, _| k % 10 == 0);
+        cache.shrink_to_fit();
+        assert!(cache.memory_usage().total_bytes() < before);
+        assert!(cache.len() > 50);
+        for i in 0..1000 {
+            assert!(cache.peek(&i).is_none() || i % 10 == 0);
+        }
+    }
+
+    #[test]
+    fn test_sync_shrink_to_fit() {
+        let cache = sync::Cache::with_options(
+            OptionsBuilder::new()
+                .estimated_items_capacity(1000)
+                .weight_capacity(1000)
+                .shards(4)
+                .build()
+                .unwrap(),
+            UnitWeighter,
+            DefaultHashBuilder::default(),
+        );
+        for i in 0..1000 {
+            cache.insert(i, i);
+        }
+        let GuardResult::Guard(guard) = cache.get_value_or_guard(&5000, None) else {
+            panic!()
+        };
+        let versions = (0..1000)
+            .filter(|i| i % 10 == 0)
+            .filter_map(|i| Some((i, cache.peek_with_version(&i)?.1)))
+            .collect::<Vec<_>>();
+        cache.retain(|&k, _| k % 10 == 0);
+        let (len, weight) = (cache.len(), cache.weight());
+        let before = cache.memory_usage().total_bytes();
+        cache.shrink_to_fit();
+        assert!(cache.memory_usage().total_bytes() < before);
+        assert_eq!((cache.len(), cache.weight()), (len, weight));
+        // the entries and their versions survive the compaction, as does the pending placeholder
+        for (i, version) in versions {
+            assert_eq!(cache.peek_with_version(&i), Some((i, version)));
+        }
+        guard.insert(5000);
+        assert_eq!(cache.get(&5000), Some(5000));
+        for i in 1000..3000 {
+            cache.insert(i, i);
+            assert_eq!(cache.peek(&i), Some(i));
+        }
+        assert!(cache.len() <= 1000);
+    }
+
+    #[test]
+    fn test_compact_skips_vetoed() {
+        let mut slab = linked_slab::LinkedSlab::with_capacity(0);
+        let head = slab.insert(0, None);
+        let tokens = (1..10)
+            .map(|i| slab.insert(i, Some(head)))
+            .collect::<Vec<_>>();
+        for &token in &tokens[..4] {
+            slab.remove(token);
+        }
+        // the vetoed entry at the tail stays, the ones before it still fill the holes
+        let mut moved = Vec::new();
+        slab.compact(|&i, _, new| {
+            if i == 9 {
+                return false;
+            }
+            moved.push((i, new.get()));
+            true
+        });
+        assert_eq!(moved, [(8, 2), (7, 3), (6, 4), (5, 5)]);
+        assert_eq!(slab.num_slots(), 10);
+        let order = std::iter::successors(Some(head), |&t| slab.get(t).map(|(_, next)| next))
+            .take(7)
+            .map(|t| *slab.get(t).unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(order, [0, 5, 6, 7, 8, 9, 0]);
+        slab.remove(tokens[8]);
+        slab.compact(|_, _, _| true);
+        assert_eq!(slab.num_slots(), 5);
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        let cache = sync::Cache::<u64, u64>::new(1000);
+        assert_eq!(cache.memory_usage().total_bytes(), 0);
+        assert_eq!(cache.memory_usage().overhead_per_entry(), 0.0);
+        for i in 0..2000 {
+            cache.insert(i, i);
+        }
+        let usage = cache.memory_usage();
+        assert_eq!(usage.len, cache.len());
+        assert_eq!(usage.weight, cache.weight());
+        assert!(usage.non_resident > 0);
+        assert_eq!(usage.data_bytes, usage.len * 16);
+        assert!(usage.map_bytes > 0);
+        assert!(usage.slab_bytes > usage.data_bytes);
+        assert!(usage.overhead_per_entry() > 0.0);
+    }
+
+    #[test]
+    fn test_doorkeeper() {
+        let mut cache = unsync::Cache::with_options(
+            OptionsBuilder::new()
+                .estimated_items_capacity(100)
+                .weight_capacity(100)
+                .doorkeeper(true)
+                .build()
+                .unwrap(),
+            UnitWeighter,
+            DefaultHashBuilder::default(),
+        );
+        cache.insert(1, 1);
+        assert_eq!(cache.get(&1), None);
+        cache.insert(1, 1);
+        assert_eq!(cache.get(&1), Some(&1));
+        // a one off scan is mostly rejected, only bloom filter false positives get through
+        for i in 1000..1050 {
+            cache.insert(i, i);
+        }
+        assert!(cache.len() < 10);
+
+        let cache = sync::Cache::with_options(
+            OptionsBuilder::new()
+                .estimated_items_capacity(100)
+                .weight_capacity(100)
+                .doorkeeper(true)
+                .build()
+                .unwrap(),
+            UnitWeighter,
+            DefaultHashBuilder::default(),
+        );
+        assert_eq!(cache.get_or_insert_with(&1, || Ok::<_, ()>(1)), Ok(1));
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get_or_insert_with(&1, || Ok::<_, ()>(1)), Ok(1));
+        assert_eq!(cache.get(&1), Some(1));
+    }
+
+    #[test]
+    fn test_frequency_sketch() {
+        let mut cache = unsync::Cache::with_options(
+            OptionsBuilder::new()
+                .estimated_items_capacity(100)
+                .weight_capacity(100)
+                .frequency_sketch(true)
+                .build()
+                .unwrap(),
+            UnitWeighter,
+            DefaultHashBuilder::default(),
+        );
+        for i in 0..1000 {
+            cache.insert(i, i);
+            assert_eq!(cache.get(&i), Some(&i));
+        }
+        assert_eq!(cache.len(), 100);
+        let usage = cache.memory_usage();
+        assert_eq!(usage.non_resident, 0);
+        assert!(usage.ghost_bytes > 0);
+    }
+
+    #[test]
+    fn test_adaptive_hot_allocation() {
+        assert!(OptionsBuilder::new()
+            .estimated_items_capacity(100)
+            .weight_capacity(100)
+            .frequency_sketch(true)
+            .adaptive_hot_allocation(0.5, 0.99)
+            .build()
+            .is_err());
+        let mut cache = unsync::Cache::with_options(
+            OptionsBuilder::new()
+                .estimated_items_capacity(100)
+                .weight_capacity(100)
+                .adaptive_hot_allocation(0.1, 0.99)
+                .build()
+                .unwrap(),
+            UnitWeighter,
+            DefaultHashBuilder::default(),
+        );
+        // a loop slightly larger than the cache, which is only served well by a large cold section
+        for _ in 0..10 {
+            for i in 0..120 {
+                if cache.get(&i).is_none() {
+                    cache.insert(i, i);
+                }
+            }
+        }
+        assert_eq!(cache.len(), 100);
+        assert!(cache.hits() > 0);
+    }
+
+    #[test]
+    fn test_ghost_capacity() {
+        for ghost_capacity in [0, 10, 1000] {
+            let mut cache = unsync::Cache::with_options(
+                OptionsBuilder::new()
+                    .estimated_items_capacity(100)
+                    .weight_capacity(100)
+                    .ghost_capacity(ghost_capacity)
+                    .build()
+                    .unwrap(),
+                UnitWeighter,
+                DefaultHashBuilder::default(),
+            );
+            for i in 0..2000 {
+                cache.insert(i, i);
+            }
+            assert_eq!(
+                cache.memory_usage().non_resident,
+                ghost_capacity.min(2000 - 100)
+            );
+        }
+    }
+
+    #[test]
+    fn test_shard_hash_seeds() {
+        let cache = sync::Cache::with_options(
+            OptionsBuilder::new()
+                .estimated_items_capacity(1000)
+                .weight_capacity(1000)
+                .shards(4)
+                .shard_hash_seeds(true)
+                .build()
+                .unwrap(),
+            UnitWeighter,
+            DefaultHashBuilder::default(),
+        );
+        for i in 0..500 {
+            cache.insert(i, i);
+        }
+        assert_eq!(cache.len(), 500);
+        for i in 0..500 {
+            assert_eq!(cache.get(&i), Some(i));
+        }
+        assert_eq!(cache.get_or_insert_with(&1000, || Ok::<_, ()>(1)), Ok(1));
+        assert_eq!(cache.get(&1000), Some(1));
+        assert!(cache.remove(&1000));
+        assert_eq!(cache.get(&1000), None);
+    }
+
+    #[test]
+    fn test_rehash_with() {
+        let mut cache = unsync::Cache::new(100);
+        for i in 0..100 {
+            cache.insert(i, i);
+        }
+        for i in 0..50 {
+            cache.get(&i);
+        }
+        cache.rehash_with(DefaultHashBuilder::default());
+        assert_eq!(cache.len(), 100);
+        for i in 0..100 {
+            assert_eq!(cache.peek(&i), Some(&i));
+        }
+        // the hot entries are still the ones accessed before the rehash
+        for i in 100..200 {
+            cache.insert(i, i);
+        }
+        assert!((0..50).all(|i| cache.peek(&i).is_some()));
+
+        let mut cache = sync::Cache::with_options(
+            OptionsBuilder::new()
+                .estimated_items_capacity(1000)
+                .weight_capacity(1000)
+                .shards(4)
+                .shard_hash_seeds(true)
+                .build()
+                .unwrap(),
+            UnitWeighter,
+            DefaultHashBuilder::default(),
+        );
+        for i in 0..500 {
+            cache.insert(i, i);
+        }
+        let handle = cache.clone();
+        assert!(cache.rehash_with(DefaultHashBuilder::default()).is_err());
+        assert_eq!(handle.len(), 500);
+        drop(handle);
+        assert!(cache.rehash_with(DefaultHashBuilder::default()).is_ok());
+        assert_eq!(cache.len(), 500);
+        for i in 0..500 {
+            assert_eq!(cache.get(&i), Some(i));
+        }
+    }
+
+    #[test]
+    fn test_zero_capacity() {
+        let cache = sync::Cache::new(0);
+        cache.insert(1, 1);
+        assert_eq!(cache.get(&1), None);
+        assert!(cache.is_empty());
+        assert_eq!(cache.get_or_insert_with(&1, || Ok::<_, ()>(1)), Ok(1));
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.len(), 0);
+
+        let mut cache = unsync::Cache::new(0);
+        cache.insert(1, 1);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.len(), 0);
+
+        let cache = NoopCache::<u64, u64>::new();
+        cache.insert(1, 1);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get_or_insert_with(&1, || Ok::<_, ()>(1)), Ok(1));
+        assert!(!cache.remove(&1));
+
+        // copyable regardless of the key and value types
+        struct NotClone;
+        let cache = NoopCache::<NotClone, Vec<u8>>::default();
+        let copy = cache;
+        assert_eq!(format!("{:?}", (cache, copy)), "(NoopCache, NoopCache)");
+    }
+
+    #[cfg(feature = "ffi")]
+    #[test]
+    fn test_ffi() {
+        use ffi::*;
+        unsafe {
+            let cache = qc_cache_new(100, 1000);
+            qc_cache_insert(cache, b"key".as_ptr(), 3, b"value".as_ptr(), 5);
+            let mut buf = [0u8; 3];
+            let mut len = 0;
+            assert!(!qc_cache_get(
+                cache,
+                b"nope".as_ptr(),
+                4,
+                buf.as_mut_ptr(),
+                3,
+                &mut len
+            ));
+            assert!(qc_cache_get(
+                cache,
+                b"key".as_ptr(),
+                3,
+                buf.as_mut_ptr(),
+                3,
+                &mut len
+            ));
+            assert_eq!((&buf, len), (b"val", 5));
+            assert_eq!(qc_cache_len(cache), 1);
+            let mut stats = QcStats::default();
+            qc_cache_stats(cache, &mut stats);
+            assert_eq!((stats.hits, stats.misses, stats.weight), (1, 1, 8));
+            assert!(qc_cache_remove(cache, b"key".as_ptr(), 3));
+            assert_eq!(qc_cache_len(cache), 0);
+            qc_cache_free(cache);
+        }
+    }
+
+    #[test]
+    fn test_iter_mut() {
+        let mut cache = unsync::Cache::new(100);
+        for i in 0..10 {
+            cache.insert(i, i);
+        }
+        for (key, value) in cache.iter_mut() {
+            *value += key;
+        }
+        let mut entries = cache
+            .iter_mut()
+            .map(|(&k, &mut v)| (k, v))
+            .collect::<Vec<_>>();
+        entries.sort_unstable();
+        assert_eq!(entries, (0..10).map(|i| (i, i * 2)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_cursor() {
+        use unsync::Section;
+        let mut cache = unsync::Cache::new(100);
+        for i in 0..10 {
+            cache.insert(i, i);
+        }
+        let mut cursor = cache.cursor(Section::Hot);
+        let mut visited = 0;
+        while let Some((&key, _, _)) = cursor.current() {
+            visited += 1;
+            if key % 2 == 1 {
+                assert_eq!(cursor.remove_current(), Some((key, (), key)));
+            } else {
+                cursor.demote_current();
+            }
+        }
+        assert_eq!(visited, 10);
+        assert_eq!(cache.len(), 5);
+
+        let mut cursor = cache.cursor(Section::Cold);
+        let mut keys = Vec::new();
+        while let Some((&key, _, _)) = cursor.current() {
+            keys.push(key);
+            cursor.move_next();
+        }
+        keys.sort_unstable();
+        assert_eq!(keys, [0, 2, 4, 6, 8]);
+        assert!(cache.cursor(Section::Hot).current().is_none());
+    }
+
+    #[test]
+    fn test_compare_exchange() {
+        let cache = sync::Cache::new(100);
+        assert_eq!(cache.compare_exchange(1, 0, 1), Err(None));
+        cache.insert(1, 1);
+        let (value, version) = cache.get_with_version(&1).unwrap();
+        assert_eq!(value, 1);
+        assert_eq!(cache.peek_with_version(&1), Some((1, version)));
+        let new_version = cache.compare_exchange(1, version, 2).unwrap();
+        assert_ne!(new_version, version);
+        assert_eq!(
+            cache.compare_exchange(1, version, 3),
+            Err(Some(new_version))
+        );
+        assert_eq!(cache.get_with_version(&1), Some((2, new_version)));
+        cache.insert(1, 2);
+        assert_ne!(cache.peek_with_version(&1).unwrap().1, new_version);
+
+        let mut cache = unsync::Cache::new(100);
+        cache.insert(1, 1);
+        let (_, version) = cache.peek_with_version(&1).unwrap();
+        *cache.get_mut(&1).unwrap() += 1;
+        assert!(cache.compare_exchange(1, version, 3).is_err());
+        let (_, version) = cache.get_with_version(&1).unwrap();
+        assert!(cache.compare_exchange(1, version, 3).is_ok());
+        assert_eq!(cache.get(&1), Some(&3));
+
+        #[derive(Clone)]
+        struct ValueWeighter;
+
+        impl Weighter<u64, (), u64> for ValueWeighter {
+            fn weight(&self, _key: &u64, _qey: &(), val: &u64) -> u32 {
+                *val as u32
+            }
+        }
+
+        // a value too heavy to be cached removes the item, which isn't a success
+        let cache = sync::Cache::with_weighter(10, 10, ValueWeighter);
+        cache.insert(1, 1);
+        let (_, version) = cache.peek_with_version(&1).unwrap();
+        assert_eq!(cache.compare_exchange(1, version, 11), Err(None));
+        assert_eq!(cache.peek(&1), None);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_coalescer() {
+        use std::time::Duration;
+        let cache = sync::Cache::new(100);
+        let coalescer = coalesce::Coalescer::new(&cache, Duration::from_secs(3600), |a, b| *a += b);
+        for i in 0..100 {
+            coalescer.insert(i % 10, 1);
+        }
+        assert_eq!(coalescer.pending_len(), 10);
+        assert!(cache.is_empty());
+        coalescer.insert(0, 1);
+        drop(coalescer);
+        assert_eq!(cache.get(&0), Some(11));
+        assert_eq!(cache.get(&9), Some(10));
+
+        let coalescer = coalesce::Coalescer::new(&cache, Duration::ZERO, |a, b| *a += b);
+        coalescer.insert(0, 1);
+        assert_eq!(coalescer.pending_len(), 0);
+        assert_eq!(cache.get(&0), Some(1));
+    }
+
+    #[test]
+    fn test_max_items() {
+        let options = OptionsBuilder::new()
+            .estimated_items_capacity(100)
+            .weight_capacity(10_000)
+            .max_items(50)
+            .build()
+            .unwrap();
+        let mut cache = unsync::Cache::with_options(
+            options.clone(),
+            UnitWeighter,
+            DefaultHashBuilder::default(),
+        );
+        for i in 0..100 {
+            cache.insert(i, i);
+        }
+        assert_eq!(cache.len(), 50);
+        assert_eq!(cache.evictions(), 50);
+
+        let cache = sync::Cache::with_options(options, UnitWeighter, DefaultHashBuilder::default());
+        for i in 0..1000 {
+            cache.insert(i, i);
+        }
+        assert!(cache.len() <= 64);
+        assert!(OptionsBuilder::new()
+            .estimated_items_capacity(100)
+            .weight_capacity(100)
+            .max_items(0)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_namespaced() {
+        let cache = NamespacedCache::new([("a", 10), ("b", 100)]);
+        for i in 0..10 {
+            cache.insert("a", i, i);
+        }
+        for i in 0..1000 {
+            cache.insert("b", i, i);
+        }
+        cache.insert("c", 0, 0);
+        for i in 0..10 {
+            assert_eq!(cache.get("a", &i), Some(i));
+        }
+        assert_eq!(cache.get("c", &0), None);
+        let stats = cache.stats("a").unwrap();
+        assert_eq!((stats.hits, stats.evictions, stats.len), (10, 0, 10));
+        assert!(cache.stats("b").unwrap().evictions >= 900);
+        assert!(cache.stats("c").is_none());
+        assert_eq!(cache.capacity(), 110);
+        assert!(cache.remove("a", &0));
+        assert_eq!(cache.total_stats().len, cache.len());
+    }
+
+    #[test]
+    fn test_tenant() {
+        let cache = TenantCache::new(10);
+        cache.add_tenant(
+            "big".to_owned(),
+            OptionsBuilder::new()
+                .shards(1)
+                .estimated_items_capacity(100)
+                .weight_capacity(100)
+                .build()
+                .unwrap(),
+        );
+        for i in 0..100 {
+            cache.insert("big", i, i);
+            cache.insert("small", i, i);
+        }
+        assert_eq!(cache.num_tenants(), 2);
+        assert_eq!(cache.tenant("big").unwrap().len(), 100);
+        assert!(cache.tenant("small").unwrap().len() <= 10);
+        assert_eq!(cache.get("big", &0), Some(0));
+        assert_eq!(cache.get("other", &0), None);
+        let stats = cache.stats("big").unwrap();
+        assert_eq!((stats.hits, stats.misses), (1, 0));
+        let big = cache.tenant("big").unwrap();
+        assert!(cache.evict_tenant("big"));
+        assert!(!cache.evict_tenant("big"));
+        // the entries are gone even though a handle outlived the tenant
+        assert!(big.is_empty());
+        assert_eq!(cache.get("big", &0), None);
+        assert_eq!(cache.len(), cache.tenant("small").unwrap().len());
+    }
+
+    #[test]
+    fn test_key_affinity() {
+        let cache = sync::KQCache::with_options(
+            OptionsBuilder::new()
+                .shards(16)
+                .estimated_items_capacity(10_000)
+                .weight_capacity(10_000)
+                .key_affinity(true)
+                .build()
+                .unwrap(),
+            UnitWeighter,
+            DefaultHashBuilder::default(),
+        );
+        for qey in 0..100 {
+            cache.insert(1, qey, qey);
+        }
+        for qey in 0..100 {
+            assert_eq!(cache.get(&1, &qey), Some(qey));
+        }
+        let shard_stats = cache.shard_stats();
+        assert!(shard_stats.len() > 1);
+        assert_eq!(
+            shard_stats
+                .iter()
+                .filter(|s| s.len != 0)
+                .collect::<Vec<_>>(),
+            [&Stats {
+                hits: 100,
+                misses: 0,
+                evictions: 0,
+                rejections: Default::default(),
+                len: 100,
+                weight: 100,
+            }]
+        );
+        assert!(cache.remove(&1, &0));
+    }
+
+    #[test]
+    fn test_get_or_insert_with_many() {
+        let cache = sync::Cache::new(100);
+        cache.insert(2, 20);
+        let values = cache.get_or_insert_with_many(&[1, 2, 3], |missing| {
+            assert_eq!(missing, [1, 3]);
+            Ok::<_, ()>(missing.iter().map(|k| k * 10).collect())
+        });
+        assert_eq!(values, Ok(vec![10, 20, 30]));
+        assert_eq!(cache.get(&3), Some(30));
+        let values =
+            cache.get_or_insert_with_many(&[3, 1], |_| -> Result<_, ()> { unreachable!() });
+        assert_eq!(values, Ok(vec![30, 10]));
+        assert_eq!(
+            cache.get_or_insert_with_many(&[4, 5], |_| Err("failed")),
+            Err("failed")
+        );
+        assert_eq!(cache.get_or_insert_with(&4, || Ok::<_, ()>(40)), Ok(40));
+    }
+
+    #[test]
+    fn test_buffered_stats() {
+        let cache = sync::Cache::with_options(
+            OptionsBuilder::new()
+                .shards(1)
+                .estimated_items_capacity(100)
+                .weight_capacity(100)
+                .buffered_stats(true)
+                .build()
+                .unwrap(),
+            UnitWeighter,
+            DefaultHashBuilder::default(),
+        );
+        cache.insert(1, 1);
+        for _ in 0..1000 {
+            assert_eq!(cache.get(&1), Some(1));
+        }
+        let stats = cache.stats();
+        assert!((768..=1000).contains(&stats.hits), "{stats:?}");
+        assert_eq!(stats.misses, 0);
+        // the buffer of a thread is flushed when it exits, joining the thread explicitly
+        // as the end of the scope may be signaled before its thread locals are destroyed
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..10 {
+                    cache.get(&i);
+                }
+            })
+            .join()
+            .unwrap();
+        });
+        let stats = cache.stats();
+        assert!((769..=1001).contains(&stats.hits), "{stats:?}");
+        assert_eq!(stats.misses, 9);
+
+        // a thread buffers the lookups of all the shards of a cache together
+        let options = OptionsBuilder::new()
+            .shards(64)
+            .estimated_items_capacity(10_000)
+            .weight_capacity(10_000)
+            .buffered_stats(true)
+            .build()
+            .unwrap();
+        let cache =
+            sync::Cache::with_options(options.clone(), UnitWeighter, DefaultHashBuilder::default());
+        assert_eq!(cache.shard_count(), 64);
+        for i in 0..1000 {
+            cache.insert(i, i);
+        }
+        for i in 0..1000 {
+            assert_eq!(cache.get(&i), Some(i));
+        }
+        let stats = cache.stats();
+        assert!((745..=1000).contains(&stats.hits), "{stats:?}");
+        assert!(cache.shard_stats().iter().all(|s| s.hits == 0));
+
+        // once a thread buffers the lookups of 32 caches, including the 2 above,
+        // the lookups of the other caches are counted right away
+        let caches = (0..40)
+            .map(|_| {
+                unsync::Cache::<u64, u64>::with_options(
+                    options.clone(),
+                    UnitWeighter,
+                    Default::default(),
+                )
+            })
+            .collect::<Vec<_>>();
+        for cache in &caches {
+            cache.get(&0);
+        }
+        assert_eq!(caches.iter().filter(|c| c.misses() == 1).count(), 10);
+        // until some of the caches are dropped
+        drop(caches);
+        let cache =
+            unsync::Cache::<u64, u64>::with_options(options, UnitWeighter, Default::default());
+        cache.get(&0);
+        assert_eq!(cache.misses(), 0);
+    }
+
+    #[test]
+    fn test_relieve_pressure() {
+        let cache = sync::Cache::new(1000);
+        for i in 0..1000 {
+            cache.insert(i, i);
+        }
+        let before = cache.stats();
+        let evicted = cache.relieve_pressure(0.25);
+        assert_eq!(cache.len(), before.len - evicted);
+        assert!(cache.weight() <= cache.capacity() / 4);
+        assert_eq!(cache.stats().delta(&before).evictions, evicted as u64);
+        assert_eq!(cache.capacity(), 1000);
+        assert_eq!(cache.relieve_pressure(0.25), 0);
+        cache.relieve_pressure(-1.0);
+        assert!(cache.is_empty());
+
+        let mut cache = unsync::Cache::new(100);
+        for i in 0..100 {
+            cache.insert(i, i);
+        }
+        assert_eq!(cache.relieve_pressure(0.5), 50);
+        assert_eq!(cache.len(), 50);
+    }
+
+    #[test]
+    fn test_zero_weight() {
+        let mut cache = unsync::Cache::with_weighter(10, 10, StringWeighter);
+        for i in 0..5 {
+            cache.insert(i, "ab".to_string());
+        }
+        for i in 5..10 {
+            cache.insert(i, String::new());
+        }
+        assert_eq!(cache.len(), 10);
+        assert_eq!(cache.weight(), 10);
+        // zero weight entries count as items
+        for i in 10..15 {
+            cache.insert(i, String::new());
+        }
+        assert_eq!(cache.len(), 10);
+        assert!(cache.weight() <= 10);
+        assert_eq!(cache.evictions(), 5);
+        cache.insert(5, "abcd".to_string());
+        assert!(cache.weight() <= 10);
+        for i in 0..15 {
+            cache.remove(&i);
+        }
+        assert_eq!((cache.len(), cache.weight()), (0, 0));
+
+        // a cache of capacity 0 still stores nothing
+        let mut cache = unsync::Cache::with_weighter(10, 0, StringWeighter);
+        cache.insert(1, String::new());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_prune_idle() {
+        let cache = sync::Cache::with_options(
+            OptionsBuilder::new()
+                .estimated_items_capacity(100)
+                .weight_capacity(100)
+                .idle_timeout(Duration::from_millis(500))
+                .build()
+                .unwrap(),
+            UnitWeighter,
+            DefaultHashBuilder::default(),
+        );
+        for i in 0..10 {
+            cache.insert(i, i);
+        }
+        assert_eq!(cache.prune_idle(), 0);
+        std::thread::sleep(Duration::from_millis(300));
+        for i in 0..5 {
+            cache.get(&i);
+        }
+        std::thread::sleep(Duration::from_millis(300));
+        // only the entries accessed recently are kept
+        assert_eq!(cache.prune_idle(), 5);
+        assert_eq!(cache.len(), 5);
+        assert_eq!(cache.stats().evictions, 5);
+        for i in 0..5 {
+            assert_eq!(cache.peek(&i), Some(i));
+        }
+
+        // access times follow the entries moved by shrink_to_fit
+        let mut cache = unsync::Cache::with_options(
+            OptionsBuilder::new()
+                .estimated_items_capacity(100)
+                .weight_capacity(100)
+                .idle_timeout(Duration::from_millis(500))
+                .build()
+                .unwrap(),
+            UnitWeighter,
+            DefaultHashBuilder::default(),
+        );
+        for i in 0..20 {
+            cache.insert(i, i);
+        }
+        std::thread::sleep(Duration::from_millis(300));
+        for i in 0..10 {
+            cache.remove(&i);
+        }
+        for i in 15..20 {
+            cache.get(&i);
+        }
+        cache.shrink_to_fit();
+        std::thread::sleep(Duration::from_millis(300));
+        assert_eq!(cache.prune_idle(), 5);
+        for i in 15..20 {
+            assert_eq!(cache.peek(&i), Some(&i));
+        }
+
+        let mut cache = unsync::Cache::new(10);
+        cache.insert(1, 1);
+        assert_eq!(cache.prune_idle(), 0);
+        assert!(OptionsBuilder::new()
+            .estimated_items_capacity(1)
+            .weight_capacity(1)
+            .idle_timeout(Duration::ZERO)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_decay_referenced() {
+        let mut cache = unsync::Cache::with_options(
+            OptionsBuilder::new()
+                .estimated_items_capacity(10)
+                .weight_capacity(10)
+                .hot_allocation(0.5)
+                .build()
+                .unwrap(),
+            UnitWeighter,
+            DefaultHashBuilder::default(),
+        );
+        for i in 0..10 {
+            cache.insert(i, i);
+            cache.get(&i);
+        }
+        assert_eq!(cache.decay_referenced(0.5), 5);
+        assert_eq!(cache.decay_referenced(0.5), 5);
+        assert_eq!(cache.decay_referenced(1.0), 0);
+        // without their referenced bits, the old entries are evicted first
+        for i in 10..20 {
+            cache.insert(i, i);
+            cache.get(&i);
+        }
+        assert!((0..10).all(|i| cache.peek(&i).is_none()));
+
+        let cache = sync::Cache::new(100);
+        for i in 0..100 {
+            cache.insert(i, i);
+        }
+        for i in 0..100 {
+            cache.get(&i);
+        }
+        assert_eq!(cache.decay_referenced(1.0), cache.len());
+        assert_eq!(cache.decay_referenced(1.0), 0);
+    }
+
+    #[test]
+    fn test_set_enabled() {
+        let cache = sync::Cache::new(100);
+        cache.insert(1, 1);
+        cache.insert(2, 2);
+        cache.set_enabled(false);
+        assert!(!cache.is_enabled());
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.peek(&1), None);
+        cache.insert(3, 3);
+        // a newer value invalidates the preserved one
+        cache.insert(2, 20);
+        assert_eq!(cache.get_or_insert_with(&4, || Ok::<_, ()>(4)), Ok(4));
+        assert_eq!(cache.len(), 1);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (0, 1));
+        cache.set_enabled(true);
+        assert_eq!(cache.get(&1), Some(1));
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&3), None);
+        assert_eq!(cache.get(&4), None);
+
+        let mut cache = unsync::Cache::new(100);
+        cache.insert(1, 1);
+        cache.set_enabled(false);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get_mut(&1), None);
+        cache.insert(2, 2);
+        cache.set_enabled(true);
+        assert_eq!(cache.get(&1), Some(&1));
+        assert_eq!(cache.get(&2), None);
+    }
+
+    #[test]
+    fn test_min_residency() {
+        let options = OptionsBuilder::new()
+            .estimated_items_capacity(10)
+            .weight_capacity(10)
+            .min_residency(Duration::from_secs(60))
+            .build()
+            .unwrap();
+        let mut cache =
+            unsync::Cache::with_options(options, UnitWeighter, DefaultHashBuilder::default());
+        for i in 0..10 {
+            cache.insert(i, i);
+        }
+        // a bulk insertion can't churn out the recent entries
+        for i in 10..100 {
+            cache.insert(i, i);
+        }
+        assert_eq!(cache.len(), 10);
+        assert!((0..10).all(|i| cache.peek(&i) == Some(&i)));
+        assert_eq!(cache.stats().evictions, 0);
+        // replacing a value is always possible
+        cache.insert(0, 100);
+        assert_eq!(cache.peek(&0), Some(&100));
+
+        let mut cache = unsync::Cache::new(10);
+        for i in 0..100 {
+            cache.insert(i, i);
+        }
+        assert!((0..10).any(|i| cache.peek(&i).is_none()));
+        assert!(OptionsBuilder::new()
+            .estimated_items_capacity(1)
+            .weight_capacity(1)
+            .min_residency(Duration::ZERO)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_min_residency_no_partial_eviction() {
+        let options = OptionsBuilder::new()
+            .estimated_items_capacity(10)
+            .weight_capacity(10)
+            .min_residency(Duration::from_millis(200))
+            .build()
+            .unwrap();
+        let mut cache =
+            unsync::Cache::with_options(options, StringWeighter, DefaultHashBuilder::default());
+        cache.insert(1, "a".repeat(3));
+        std::thread::sleep(Duration::from_millis(300));
+        cache.insert(2, "b".repeat(7));
+        // only the first entry is past its minimum residency, which isn't enough room
+        cache.insert(3, "c".repeat(6));
+        assert_eq!(cache.peek(&3), None);
+        assert_eq!(cache.peek(&1).map(|v| v.len()), Some(3));
+        assert_eq!(cache.weight(), 10);
+        assert_eq!(cache.stats().rejections.min_residency, 1);
+        // but it's enough for a lighter entry
+        cache.insert(4, "d".repeat(3));
+        assert_eq!(cache.peek(&1), None);
+        assert_eq!(cache.peek(&2).map(|v| v.len()), Some(7));
+        assert_eq!(cache.peek(&4).map(|v| v.len()), Some(3));
+        assert_eq!(cache.stats().evictions, 1);
+    }
+
+    #[test]
+    fn test_min_residency_bounded_work() {
+        use shard::{RESIDENCY_SCAN_LIMIT, RESIDENCY_VISITS};
+
+        let options = OptionsBuilder::new()
+            .estimated_items_capacity(20_000)
+            .weight_capacity(20_000)
+            .min_residency(Duration::from_secs(3600))
+            .build()
+            .unwrap();
+        let mut cache =
+            unsync::Cache::with_options(options, UnitWeighter, DefaultHashBuilder::default());
+        for i in 0..20_000 {
+            cache.insert(i, i);
+        }
+        // the whole cache is recent, so a bulk load of new keys is rejected
+        // without walking the cache for every key
+        RESIDENCY_VISITS.with(|visits| visits.set(0));
+        for i in 20_000..22_000 {
+            cache.insert(i, i);
+        }
+        let visits = RESIDENCY_VISITS.with(|visits| visits.get());
+        assert!(visits <= 2_000 * RESIDENCY_SCAN_LIMIT, "{visits}");
+        assert_eq!(cache.stats().rejections.min_residency, 2_000);
+        assert_eq!(cache.stats().evictions, 0);
+        assert_eq!(cache.len(), 20_000);
+    }
+
+    #[test]
+    fn test_max_item_weight() {
+        let options = OptionsBuilder::new()
+            .estimated_items_capacity(10)
+            .weight_capacity(100)
+            .max_item_weight(10)
+            .build()
+            .unwrap();
+        let mut cache = unsync::Cache::with_options(
+            options.clone(),
+            StringWeighter,
+            DefaultHashBuilder::default(),
+        );
+        assert_eq!(cache.insert_checked(1, "a".repeat(10)), Ok(()));
+        assert_eq!(
+            cache.insert_checked(2, "b".repeat(11)),
+            Err(Rejected::TooHeavy("b".repeat(11)))
+        );
+        // a too heavy replacement leaves the old value in place
+        cache.insert(1, "c".repeat(11));
+        assert_eq!(cache.get(&1).map(String::len), Some(10));
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.stats().rejections.max_item_weight, 2);
+
+        let cache =
+            sync::Cache::with_options(options, StringWeighter, DefaultHashBuilder::default());
+        let rejected = cache.insert_checked(1, "a".repeat(11)).unwrap_err();
+        assert_eq!(rejected.into_value(), "a".repeat(11));
+        // the capacity of the cache bounds the weight too
+        let mut cache = unsync::Cache::with_weighter(10, 10, StringWeighter);
+        assert!(cache.insert_checked(1, "a".repeat(11)).is_err());
+        assert_eq!(cache.stats().rejections.too_heavy, 1);
+        cache.reset_stats();
+        assert_eq!(cache.stats().rejections.total(), 0);
+    }
+
+    #[test]
+    fn test_shard_for() {
+        let cache = sync::Cache::with_options(
+            OptionsBuilder::new()
+                .estimated_items_capacity(1000)
+                .weight_capacity(1000)
+                .shards(8)
+                .build()
+                .unwrap(),
+            UnitWeighter,
+            DefaultHashBuilder::default(),
+        );
+        assert_eq!(cache.shard_count(), 8);
+        let mut per_shard = vec![0; cache.shard_count()];
+        for i in 0..100 {
+            cache.insert(i, i);
+            per_shard[cache.shard_for(&i)] += 1;
+        }
+        let shard_lens = cache
+            .shard_stats()
+            .iter()
+            .map(|s| s.len)
+            .collect::<Vec<_>>();
+        assert_eq!(shard_lens, per_shard);
+    }
+
+    #[test]
+    fn test_pop_n() {
+        let mut cache = unsync::Cache::new(10);
+        for i in 0..10 {
+            cache.insert(i, i);
+        }
+        let popped = cache.pop_n(3);
+        assert_eq!(popped.len(), 3);
+        assert_eq!(cache.len(), 7);
+        assert!(popped
+            .iter()
+            .all(|(k, v)| k == v && cache.peek(k).is_none()));
+        assert_eq!(cache.pop_weight(2).len(), 2);
+        let (k, v) = cache.pop().unwrap();
+        assert!(k == v && cache.peek(&k).is_none());
+        assert_eq!(cache.pop_n(100).len(), 4);
+        assert!(cache.is_empty());
+        assert_eq!(cache.pop(), None);
+
+        let cache = sync::Cache::new(1000);
+        for i in 0..500 {
+            cache.insert(i, i);
+        }
+        let len = cache.len();
+        let evictions = cache.stats().evictions;
+        assert_eq!(cache.pop_n(100).len(), 100);
+        assert_eq!(cache.len(), len - 100);
+        assert_eq!(cache.stats().evictions, evictions + 100);
+        assert_eq!(cache.pop_weight(100).len(), 100);
+        let (k, _) = cache.pop().unwrap();
+        assert_eq!(cache.peek(&k), None);
+        assert_eq!(cache.pop_weight(u64::MAX).len(), len - 201);
+        assert_eq!(cache.len(), 0);
+        assert_eq!(cache.pop(), None);
+
+        // zero weight items are popped as well, until every shard is empty
+        let mut cache = unsync::Cache::with_weighter(10, 10, StringWeighter);
+        cache.insert(1, String::new());
+        cache.insert(2, String::new());
+        assert_eq!(cache.pop_n(3).len(), 2);
+        assert!(cache.is_empty());
+        let cache = sync::Cache::with_options(
+            OptionsBuilder::new()
+                .estimated_items_capacity(10)
+                .weight_capacity(10)
+                .shards(4)
+                .build()
+                .unwrap(),
+            StringWeighter,
+            DefaultHashBuilder::default(),
+        );
+        for i in 0..5 {
+            cache.insert(i, String::new());
+        }
+        let len = cache.len();
+        assert!(len > 0);
+        assert_eq!(cache.pop_n(100).len(), len);
+        assert!(cache.is_empty());
+        assert!(cache.pop_n(1).is_empty());
+    }
+
+    #[test]
+    fn test_pop_follows_policy() {
+        let build = |churn: u64| {
+            let mut cache = unsync::Cache::new(10);
+            for i in 0..10 {
+                cache.insert(i, i);
+            }
+            for _ in 0..3 {
+                for i in 0..3 {
+                    cache.get(&i);
+                }
+            }
+            for i in 100..100 + churn {
+                cache.insert(i, i);
+            }
+            cache
+        };
+        for churn in 0..10 {
+            // the popped item is the one an insertion would have evicted
+            let mut cache = build(churn);
+            let mut inserted = build(churn);
+            inserted.insert(1000, 1000);
+            let (popped, _) = cache.pop().unwrap();
+            assert_eq!(inserted.peek(&popped), None);
+            assert!(cache.iter().all(|(k, _)| inserted.peek(k).is_some()));
+        }
+        // the frequently accessed items are evicted last
+        let mut cache = build(0);
+        for _ in 0..7 {
+            cache.pop().unwrap();
+        }
+        let mut rest = cache.iter().map(|(&k, _)| k).collect::<Vec<_>>();
+        rest.sort_unstable();
+        assert_eq!(rest, vec![0, 1, 2]);
+        assert_eq!(cache.stats().evictions, 7);
+    }
+
+    #[test]
+    fn test_pop_zero_weight() {
+        let mut cache = unsync::Cache::with_weighter(10, 10, StringWeighter);
+        cache.insert(1, String::new());
+        cache.insert(2, String::new());
+        assert!(cache.pop().is_some());
+        assert!(cache.pop().is_some());
+        assert_eq!(cache.pop(), None);
+        assert!(cache.is_empty());
+
+        let cache = sync::Cache::with_weighter(10, 10, StringWeighter);
+        cache.insert(1, String::new());
+        cache.insert(2, String::new());
+        assert!(cache.pop().is_some());
+        assert!(cache.pop().is_some());
+        assert_eq!(cache.pop(), None);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_reweigh_all() {
+        let mut cache = unsync::Cache::with_weighter(10, 100, StringWeighter);
+        for i in 0..10 {
+            cache.insert(i, "a".repeat(5));
+        }
+        assert_eq!(cache.weight(), 50);
+        cache.get_mut(&1).unwrap().push_str(&"b".repeat(45));
+        // the weight drifted unnoticed until the sweep
+        assert_eq!(cache.weight(), 50);
+        assert_eq!(cache.reweigh_all(), 1);
+        assert_eq!(cache.weight(), 95);
+        assert_eq!(cache.reweigh_all(), 0);
+        cache.get_mut(&2).unwrap().push_str(&"c".repeat(45));
+        assert_eq!(cache.reweigh_all(), 1);
+        assert!(cache.weight() <= 100);
+        assert!(cache.len() < 10);
+
+        #[derive(Clone)]
+        struct ScaledWeighter(Arc<AtomicU32>);
+
+        impl Weighter<u64, (), u64> for ScaledWeighter {
+            fn weight(&self, _key: &u64, _qey: &(), _val: &u64) -> u32 {
+                self.0.load(atomic::Ordering::Relaxed)
+            }
+        }
+
+        let scale = Arc::new(AtomicU32::new(1));
+        let cache = sync::Cache::with_weighter(1000, 1000, ScaledWeighter(scale.clone()));
+        for i in 0..400 {
+            cache.insert(i, i);
+        }
+        let len = cache.len();
+        assert_eq!(cache.weight(), len as u64);
+        scale.store(2, atomic::Ordering::Relaxed);
+        assert_eq!(cache.reweigh_all(), len);
+        assert_eq!(cache.weight(), 2 * cache.len() as u64);
+        assert!(cache.weight() <= cache.capacity());
+    }
+
+    #[test]
+    fn test_frozen() {
+        let cache = sync::Cache::new(100);
+        cache.insert(1, 1);
+        cache.insert(2, 2);
+        cache.set_frozen(true);
+        assert!(cache.is_frozen());
+        assert_eq!(cache.get(&1), Some(1));
+        assert_eq!(cache.peek(&2), Some(2));
+        cache.insert(3, 3);
+        assert_eq!(cache.insert_checked(1, 10), Err(Rejected::Frozen(10)));
+        assert!(!cache.remove(&2));
+        assert_eq!(cache.remove_checked(&2), Err(Frozen));
+        let (_, version) = cache.peek_with_version(&1).unwrap();
+        assert_eq!(cache.compare_exchange(1, version, 10), Err(Some(version)));
+        assert_eq!(cache.get_or_insert_with(&4, || Ok::<_, ()>(4)), Ok(4));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&1), Some(1));
+        assert_eq!(cache.get(&3), None);
+        assert_eq!(cache.get(&4), None);
+        cache.set_frozen(false);
+        cache.insert(3, 3);
+        assert!(cache.remove(&2));
+        assert_eq!(cache.get(&3), Some(3));
+
+        let mut cache = unsync::Cache::new(100);
+        cache.insert(1, 1);
+        cache.set_frozen(true);
+        assert_eq!(cache.get(&1), Some(&1));
+        assert_eq!(cache.get_mut(&1), None);
+        assert!(!cache.remove(&1));
+        assert_eq!(cache.remove_checked(&1), Err(Frozen));
+        assert_eq!(cache.remove_checked(&2), Err(Frozen));
+        cache.set_frozen(false);
+        assert_eq!(cache.get_mut(&1), Some(&mut 1));
+        assert_eq!(cache.remove_checked(&2), Ok(false));
+        assert_eq!(cache.remove_checked(&1), Ok(true));
+    }
+
+    #[test]
+    fn test_frozen_stable() {
+        let cache = sync::Cache::new(100);
+        for i in 0..10 {
+            cache.insert(i, i);
+        }
+        let other = sync::Cache::new(100);
+        other.insert(100, 100);
+        cache.set_frozen(true);
+        cache.retain(|_, v| v % 2 == 0);
+        assert_eq!(cache.extract_if(|_, _| true).count(), 0);
+        let options = OptionsBuilder::new()
+            .estimated_items_capacity(100)
+            .weight_capacity(100)
+            .build()
+            .unwrap();
+        assert!(cache.split_off(options, |_, _| true).is_empty());
+        cache.merge_from(&other);
+        assert_eq!(cache.len(), 10);
+        assert_eq!(other.len(), 1);
+        assert_eq!(cache.peek(&100), None);
+        cache.set_frozen(false);
+        cache.merge_from(&other);
+        assert_eq!(cache.len(), 11);
+        assert!(other.is_empty());
+        // whole cache functions still apply
+        cache.set_frozen(true);
+        cache.clear();
+        assert!(cache.is_empty());
+
+        let mut cache = unsync::Cache::new(100);
+        for i in 0..10 {
+            cache.insert(i, i);
+        }
+        cache.set_frozen(true);
+        cache.retain(|_, v| v % 2 == 0);
+        assert_eq!(cache.extract_if(|_, _| true).count(), 0);
+        assert_eq!(cache.len(), 10);
+    }
+
+    #[test]
+    fn test_rejections() {
+        let options = OptionsBuilder::new()
+            .estimated_items_capacity(100)
+            .weight_capacity(10)
+            .doorkeeper(true)
+            .min_residency(Duration::from_secs(60))
+            .build()
+            .unwrap();
+        // a deterministic hasher, so the outcome doesn't depend on doorkeeper false positives
+        let hash_builder =
+            std::hash::BuildHasherDefault::<std::collections::hash_map::DefaultHasher>::default();
+        let mut cache = unsync::Cache::with_options(options, UnitWeighter, hash_builder);
+        for i in 0..10 {
+            // the doorkeeper rejects the first insertion of each key
+            cache.insert(i, i);
+            cache.insert(i, i);
+        }
+        cache.insert(10, 10);
+        cache.insert(10, 10);
+        cache.set_frozen(true);
+        cache.insert(0, 0);
+        let rejections = cache.stats().rejections;
+        assert_eq!(
+            rejections,
+            Rejections {
+                admission: 11,
+                min_residency: 1,
+                frozen: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(rejections.total(), 13);
+    }
+
+    #[test]
+    fn test_pre_hashed() {
+        use std::hash::{BuildHasher, Hash, Hasher};
+
+        let mut hasher = PreHashedBuildHasher.build_hasher();
+        PreHashed::new(42, "a").hash(&mut hasher);
+        assert_eq!(hasher.finish(), 42);
+
+        let cache = sync::Cache::with_options(
+            OptionsBuilder::new()
+                .estimated_items_capacity(100)
+                .weight_capacity(100)
+                .shard_hash_seeds(true)
+                .build()
+                .unwrap(),
+            UnitWeighter,
+            PreHashedBuildHasher,
+        );
+        for i in 0..50u64 {
+            cache.insert(PreHashed::new(i.wrapping_mul(0x9e37_79b9_7f4a_7c15), i), i);
+        }
+        // colliding hashes are told apart by the keys
+        cache.insert(PreHashed::new(0, 100), 100);
+        assert_eq!(cache.get(&PreHashed::new(0, 0)), Some(0));
+        assert_eq!(cache.get(&PreHashed::new(0, 100)), Some(100));
+        assert_eq!(cache.get(&PreHashed::new(0, 1)), None);
+        let hits = (0..50u64)
+            .filter(|&i| {
+                cache
+                    .peek(&PreHashed::new(i.wrapping_mul(0x9e37_79b9_7f4a_7c15), i))
+                    .is_some()
+            })
+            .count();
+        assert_eq!(hits, cache.len() - 1);
+    }
+
+    #[test]
+    fn test_cache_set() {
+        let set = CacheSet::new(100);
+        let inserted = AtomicUsize::new(0);
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for i in 0..50 {
+                        if set.insert(i) {
+                            inserted.fetch_add(1, atomic::Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+        // each key is only reported as new once
+        assert_eq!(inserted.load(atomic::Ordering::Relaxed), 50);
+        assert_eq!(set.len(), 50);
+        assert!(set.contains(&1));
+        assert!(set.peek(&1));
+        assert!(set.remove(&1));
+        assert!(!set.contains(&1));
+        for i in 100..1000 {
+            set.insert(i);
+        }
+        assert!(set.len() <= 100);
+    }
+
+    #[test]
+    fn test_merge_split() {
+        let options = || {
+            OptionsBuilder::new()
+                .estimated_items_capacity(1000)
+                .weight_capacity(1000)
+                .build()
+                .unwrap()
+        };
+        let cache = sync::Cache::<u64, u64>::new(1000);
+        let other = sync::Cache::<u64, u64>::new(1000);
+        for i in 0..100 {
+            cache.insert(i, i);
+            other.insert(i + 50, i + 1000);
+        }
+        let GuardResult::Guard(guard) = other.get_value_or_guard(&2000, None) else {
+            panic!()
+        };
+        cache.merge_from(&other);
+        assert!(other.is_empty());
+        assert_eq!(cache.len(), 150);
+        assert_eq!(cache.get(&10), Some(10));
+        assert_eq!(cache.get(&60), Some(1010));
+        // the pending placeholder stays in other
+        guard.insert(2000);
+        assert_eq!(other.get(&2000), Some(2000));
+        assert_eq!(cache.get(&2000), None);
+        other.clear();
+        let split = cache.split_off(options(), |k, _| k % 2 == 0);
+        assert_eq!(split.len(), 75);
+        assert_eq!(cache.len(), 75);
+        assert_eq!(split.get(&60), Some(1010));
+        assert_eq!(cache.get(&60), None);
+        assert_eq!(cache.get(&61), Some(1011));
+
+        let mut cache = unsync::Cache::<u64, u64>::new(100);
+        let mut other = unsync::Cache::<u64, u64>::new(1000);
+        for i in 0..500 {
+            other.insert(i, i);
+        }
+        cache.merge_from(&mut other);
+        assert!(other.is_empty());
+        assert!(cache.len() <= 100);
+        let len = cache.len();
+        let split = cache.split_off(options(), |_, _| true);
+        assert_eq!(split.len(), len);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_flags() {
+        const DIRTY: u16 = 1;
+        const PROVISIONAL: u16 = 2;
+        let cache = sync::Cache::<u64, u64>::new(100);
+        cache.insert_with_flags(1, 10, DIRTY);
+        cache.insert(2, 20);
+        assert_eq!(cache.get_with_flags(&1), Some((10, DIRTY)));
+        assert_eq!(cache.peek_with_flags(&2), Some((20, 0)));
+        let (_, version) = cache.peek_with_version(&1).unwrap();
+        assert_eq!(cache.set_flags(&1, DIRTY | PROVISIONAL), Some(DIRTY));
+        assert_eq!(cache.set_flags(&3, DIRTY), None);
+        // flags aren't part of the value version
+        assert!(cache.compare_exchange(1, version, 11).is_ok());
+        assert_eq!(cache.peek_with_flags(&1), Some((11, DIRTY | PROVISIONAL)));
+        cache.insert(1, 12);
+        assert_eq!(cache.peek_with_flags(&1), Some((12, 0)));
+
+        let mut cache = unsync::Cache::<u64, u64>::new(100);
+        cache.insert_with_flags(1, 10, PROVISIONAL);
+        assert_eq!(cache.set_flags(&1, 0), Some(PROVISIONAL));
+        assert_eq!(cache.get_with_flags(&1), Some((&10, 0)));
+    }
+
+    #[test]
+    fn test_entry() {
+        use unsync::Entry;
+
+        let mut cache = unsync::Cache::with_weighter(100, 100, StringWeighter);
+        match cache.entry(1) {
+            Entry::Vacant(entry) => entry.insert("a".to_string()).unwrap(),
+            Entry::Occupied(_) => panic!(),
+        }
+        assert_eq!(cache.weight(), 1);
+        let Entry::Occupied(mut entry) = cache.entry(1) else {
+            panic!()
+        };
+        entry.get_mut().unwrap().push('b');
+        assert_eq!(entry.get(), "ab");
+        assert_eq!(entry.insert("abcd".to_string()), Ok("ab".to_string()));
+        // the replaced value is accounted for
+        assert_eq!(cache.weight(), 4);
+        let Entry::Occupied(entry) = cache.entry(1) else {
+            panic!()
+        };
+        assert!(matches!(
+            entry.insert("x".repeat(101)),
+            Err(Rejected::TooHeavy(_))
+        ));
+        assert_eq!(cache.peek(&1).unwrap(), "abcd");
+        cache.set_frozen(true);
+        let Entry::Occupied(mut entry) = cache.entry(1) else {
+            panic!()
+        };
+        assert_eq!(entry.get_mut(), Err(Frozen));
+        assert_eq!(entry.remove(), Err(Frozen));
+        assert_eq!(cache.peek(&1).unwrap(), "abcd");
+        cache.set_frozen(false);
+        let Entry::Occupied(entry) = cache.entry(1) else {
+            panic!()
+        };
+        assert_eq!(entry.remove(), Ok("abcd".to_string()));
+        assert!(cache.is_empty());
+        assert_eq!(cache.weight(), 0);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (4, 1));
+    }
+
+    #[test]
+    fn test_contains_key() {
+        let cache = sync::Cache::<u64, u64>::new(100);
+        cache.insert(1, 1);
+        assert!(cache.contains_key(&1));
+        assert!(!cache.contains_key(&2));
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (0, 0));
+        cache.set_enabled(false);
+        assert!(!cache.contains_key(&1));
+
+        let mut cache = unsync::Cache::<u64, u64>::new(100);
+        cache.insert(1, 1);
+        assert!(cache.contains_key(&1));
+        assert!(!cache.contains_key(&2));
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (0, 0));
+    }
+
+    #[test]
+    fn test_clear() {
+        let cache = sync::Cache::<u64, u64>::new(100);
+        for i in 0..100 {
+            cache.insert(i, i);
+        }
+        let GuardResult::Guard(guard) = cache.get_value_or_guard(&1000, None) else {
+            panic!()
+        };
+        cache.clear();
+        assert!(cache.is_empty());
+        assert_eq!(cache.weight(), 0);
+        assert_eq!(cache.get(&1), None);
+        // the pending placeholder survives the clear
+        guard.insert(1000);
+        assert_eq!(cache.get(&1000), Some(1000));
+
+        let mut cache = unsync::Cache::<u64, u64>::new(100);
+        for i in 0..100 {
+            cache.insert(i, i);
+        }
+        cache.clear();
+        assert!(cache.is_empty());
+        assert_eq!(cache.weight(), 0);
+        cache.insert(1, 1);
+        assert_eq!(cache.get(&1), Some(&1));
+    }
+
+    #[test]
+    fn test_retain() {
+        let cache = sync::KQCache::<u64, u64, u64>::new(1000);
+        for client in 0..4 {
+            for i in 0..100 {
+                cache.insert(client, i, i);
+            }
+        }
+        cache.retain(|&client, _, _| client != 2);
+        assert_eq!(cache.len(), 300);
+        assert_eq!(cache.get(&2, &1), None);
+        assert_eq!(cache.get(&3, &1), Some(1));
+
+        let mut cache = unsync::Cache::<u64, u64>::new(1000);
+        for i in 0..100 {
+            cache.insert(i, i);
+        }
+        cache.retain(|_, &v| v % 2 == 0);
+        assert_eq!(cache.len(), 50);
+        assert_eq!(cache.weight(), 50);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some(&2));
+        // the cache keeps working normally
+        for i in 100..2000 {
+            cache.insert(i, i);
+        }
+        assert!(cache.len() <= 1000);
+    }
+
+    #[test]
+    fn test_iter() {
+        let mut cache = unsync::Cache::<u64, u64>::new(100);
+        for i in 0..50 {
+            cache.insert(i, i * 10);
+        }
+        let mut items = cache.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>();
+        items.sort_unstable();
+        assert_eq!(items, (0..50).map(|i| (i, i * 10)).collect::<Vec<_>>());
+        // the iteration doesn't count as accesses
+        assert_eq!(cache.stats().hits, 0);
+
+        let cache = sync::Cache::<u64, u64>::new(2000);
+        for i in 0..1000 {
+            cache.insert(i, i * 10);
+        }
+        let mut items = cache.iter().collect::<Vec<_>>();
+        items.sort_unstable();
+        assert_eq!(items, (0..1000).map(|i| (i, i * 10)).collect::<Vec<_>>());
+        assert_eq!(cache.stats().hits, 0);
+        let mut keys = cache.keys().collect::<Vec<_>>();
+        keys.sort_unstable();
+        assert_eq!(keys, (0..1000).collect::<Vec<_>>());
+        assert_eq!(cache.values().sum::<u64>(), (0..1000).sum::<u64>() * 10);
+        cache.set_enabled(false);
+        assert_eq!(cache.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_iter_concurrent() {
+        let cache = sync::Cache::with_options(
+            OptionsBuilder::new()
+                .estimated_items_capacity(10_000)
+                .weight_capacity(10_000)
+                .shards(4)
+                .build()
+                .unwrap(),
+            UnitWeighter,
+            DefaultHashBuilder::default(),
+        );
+        for i in 0..1000u64 {
+            cache.insert(i, i);
+        }
+        std::thread::scope(|s| {
+            // writers only touch keys the iteration isn't asserting about
+            s.spawn(|| {
+                for i in 1000..5000u64 {
+                    cache.insert(i, i);
+                    cache.remove(&(i - 500));
+                }
+            });
+            let items = cache.iter().collect::<std::collections::HashMap<_, _>>();
+            assert!(items.iter().all(|(k, v)| k == v));
+            assert!((0..500).all(|i| items.get(&i) == Some(&i)));
+        });
+        // the iteration doesn't alter the entries, nor counts as accesses
+        assert_eq!(cache.stats().hits, 0);
+        assert!((0..500).all(|i| cache.peek(&i) == Some(i)));
+    }
+
+    #[test]
+    fn test_keys_values() {
+        static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Debug, PartialEq)]
+        struct Counted(u64);
+
+        impl Clone for Counted {
+            fn clone(&self) -> Self {
+                CLONES.fetch_add(1, atomic::Ordering::Relaxed);
+                Counted(self.0)
+            }
+        }
+
+        let cache = sync::KQCache::<u64, u64, Counted>::new(1000);
+        for i in 0..100 {
+            cache.insert(i, i + 1, Counted(i * 10));
+        }
+        let mut keys = cache.keys().collect::<Vec<_>>();
+        keys.sort_unstable();
+        assert_eq!(keys, (0..100).map(|i| (i, i + 1)).collect::<Vec<_>>());
+        // listing the keys doesn't clone the values
+        assert_eq!(CLONES.load(atomic::Ordering::Relaxed), 0);
+        let mut values = cache.values().map(|v| v.0).collect::<Vec<_>>();
+        values.sort_unstable();
+        assert_eq!(values, (0..100).map(|i| i * 10).collect::<Vec<_>>());
+        assert_eq!(CLONES.load(atomic::Ordering::Relaxed), 100);
+        assert_eq!(cache.stats().hits, 0);
+        cache.remove(&0, &1);
+        assert_eq!(cache.keys().count(), 99);
+        assert_eq!(cache.values().count(), 99);
+    }
+
+    #[test]
+    fn test_into_iter() {
+        let cache = sync::Cache::<u64, String>::new(2000);
+        for i in 0..1000 {
+            cache.insert(i, i.to_string());
+        }
+        let mut items = cache.into_iter().collect::<Vec<_>>();
+        items.sort_unstable();
+        assert_eq!(
+            items,
+            (0..1000).map(|i| (i, i.to_string())).collect::<Vec<_>>()
+        );
+
+        let mut cache = unsync::KQCache::<u64, u64, String>::new(100);
+        for i in 0..50 {
+            cache.insert(i, i + 1, i.to_string());
+        }
+        let iter = cache.into_iter();
+        assert_eq!(iter.len(), 50);
+        for (key, qey, val) in iter {
+            assert_eq!(qey, key + 1);
+            assert_eq!(val, key.to_string());
+        }
+    }
+
+    #[test]
+    fn test_drain() {
+        let cache = sync::Cache::<u64, u64>::new(2000);
+        for i in 0..1000 {
+            cache.insert(i, i);
+        }
+        let mut drained = cache.drain().collect::<Vec<_>>();
+        drained.sort_unstable();
+        assert_eq!(drained, (0..1000).map(|i| (i, i)).collect::<Vec<_>>());
+        assert_eq!(cache.len(), 0);
+        assert_eq!(cache.weight(), 0);
+
+        let mut cache = unsync::Cache::<u64, u64>::new(100);
+        for i in 0..50 {
+            cache.insert(i, i);
+        }
+        assert_eq!(cache.drain().len(), 50);
+        assert!(cache.is_empty());
+        cache.insert(1, 1);
+        assert_eq!(cache.get(&1), Some(&1));
+    }
+
+    #[test]
+    fn test_replace() {
+        let cache = sync::Cache::with_weighter(100, 100, StringWeighter);
+        assert_eq!(cache.replace(1, "a".to_string()), Ok(None));
+        assert_eq!(
+            cache.replace(1, "abc".to_string()),
+            Ok(Some("a".to_string()))
+        );
+        assert_eq!(cache.weight(), 3);
+        assert_eq!(cache.get(&1).as_deref(), Some("abc"));
+        // a value too heavy to be cached removes the stale one
+        assert_eq!(
+            cache.replace(1, "x".repeat(101)),
+            Ok(Some("abc".to_string()))
+        );
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.weight(), 0);
+
+        let mut cache = unsync::Cache::<u64, u64>::new(100);
+        assert_eq!(cache.replace(1, 1), Ok(None));
+        assert_eq!(cache.replace(1, 2), Ok(Some(1)));
+        assert_eq!(cache.get(&1), Some(&2));
+        assert_eq!(cache.len(), 1);
+        cache.set_frozen(true);
+        assert_eq!(cache.replace(1, 3), Err(Rejected::Frozen(3)));
+        assert_eq!(cache.replace(2, 3), Err(Rejected::Frozen(3)));
+        assert_eq!(cache.get(&1), Some(&2));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_insert_if_absent() {
+        let cache = sync::Cache::<u64, u64>::new(100);
+        assert_eq!(cache.insert_if_absent(1, 1), Ok(None));
+        assert_eq!(cache.insert_if_absent(1, 2), Ok(Some(1)));
+        assert_eq!(cache.get(&1), Some(1));
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (2, 1));
+        cache.set_frozen(true);
+        assert_eq!(cache.insert_if_absent(1, 3), Ok(Some(1)));
+        assert_eq!(cache.insert_if_absent(2, 3), Err(Rejected::Frozen(3)));
+        assert_eq!(cache.get(&2), None);
+
+        let mut cache = unsync::Cache::<u64, u64>::new(100);
+        assert_eq!(cache.insert_if_absent(1, 1), Ok(None));
+        assert_eq!(cache.insert_if_absent(1, 2), Ok(Some(&1)));
+        assert_eq!(cache.get(&1), Some(&1));
+        cache.set_frozen(true);
+        assert_eq!(cache.insert_if_absent(2, 3), Err(Rejected::Frozen(3)));
+    }
+
+    #[test]
+    fn test_get_mut_guard() {
+        let cache = sync::Cache::with_options(
+            OptionsBuilder::new()
+                .shards(1)
+                .estimated_items_capacity(10)
+                .weight_capacity(10)
+                .build()
+                .unwrap(),
+            StringWeighter,
+            DefaultHashBuilder::default(),
+        );
+        cache.insert(1, "a".to_string());
+        cache.insert(2, "b".to_string());
+        assert!(cache.get_mut(&3).is_none());
+        {
+            let mut value = cache.get_mut(&1).unwrap();
+            assert_eq!(*value.key(), 1);
+            value.push_str("bcd");
+        }
+        assert_eq!(cache.get(&1).unwrap(), "abcd");
+        assert_eq!(cache.weight(), 5);
+        cache.get_mut(&1).unwrap().push_str("efghij");
+        assert!(cache.weight() <= 10);
+        assert_eq!(cache.len(), 1);
+
+        cache.set_frozen(true);
+        assert!(cache.get_mut(&1).is_none() && cache.get_mut(&2).is_none());
+    }
+
+    #[test]
+    fn test_peek_mut() {
+        let cache = sync::Cache::<u64, u64>::new(100);
+        cache.insert(1, 1);
+        assert!(cache.peek_mut(&2).is_none());
+        *cache.peek_mut(&1).unwrap() += 1;
+        assert_eq!(cache.peek(&1), Some(2));
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (0, 0));
+
+        let mut cache = unsync::Cache::<u64, u64>::new(100);
+        cache.insert(1, 1);
+        *cache.peek_mut(&1).unwrap() += 1;
+        assert_eq!(cache.peek(&1), Some(&2));
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (0, 0));
+    }
+
+    #[test]
+    fn test_get_many() {
+        let cache = sync::Cache::<u64, u64>::new(1000);
+        for i in 0..100 {
+            cache.insert(i, i * 10);
+        }
+        let keys: Vec<u64> = (90..110).rev().collect();
+        let values = cache.get_many(&keys);
+        let expected: Vec<_> = keys.iter().map(|&k| (k < 100).then_some(k * 10)).collect();
+        assert_eq!(values, expected);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (10, 10));
+        assert!(cache.get_many(&[] as &[u64]).is_empty());
+    }
+
+    #[test]
+    fn test_insert_many() {
+        let cache = sync::Cache::<u64, u64>::new(1000);
+        cache.insert(1, 0);
+        let mut displaced = cache.insert_many((0..100).map(|i| (i, i)).chain([(2, 20)]));
+        displaced.sort_unstable();
+        assert_eq!(displaced, vec![(1, 0), (2, 2)]);
+        assert_eq!(cache.len(), 100);
+        assert_eq!(cache.get(&1), Some(1));
+        assert_eq!(cache.get(&2), Some(20));
+
+        let cache = sync::Cache::<u64, u64>::new(10);
+        let displaced = cache.insert_many((0..100).map(|i| (i, i)));
+        assert_eq!(displaced.len() + cache.len(), 100);
+    }
+
+    #[test]
+    fn test_insert_many_displaces_several() {
+        let cache = sync::Cache::with_options(
+            OptionsBuilder::new()
+                .shards(1)
+                .estimated_items_capacity(10)
+                .weight_capacity(10)
+                .build()
+                .unwrap(),
+            StringWeighter,
+            DefaultHashBuilder::default(),
+        );
+        for i in 0..5 {
+            cache.insert(i, "ab".to_string());
+        }
+        assert_eq!(cache.weight(), 10);
+        // a single heavy insert evicts several entries
+        let displaced = cache.insert_many([(5, "abcdefgh".to_string())]);
+        assert_eq!(displaced.len(), 4);
+        assert!(displaced.iter().all(|(k, v)| *k < 5 && v == "ab"));
+        assert_eq!(cache.weight(), 10);
+
+        // a replacement followed by an eviction
+        let (kept, _) = (0..5).find_map(|k| Some((k, cache.peek(&k)?))).unwrap();
+        let mut displaced = cache.insert_many([(5, "abcdefghij".to_string())]);
+        displaced.sort_unstable();
+        assert_eq!(
+            displaced,
+            vec![(kept, "ab".to_string()), (5, "abcdefgh".to_string())]
+        );
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.weight(), 10);
+    }
+
+    #[test]
+    fn test_remove_many() {
+        let cache = sync::Cache::<u64, u64>::new(1000);
+        for i in 0..100 {
+            cache.insert(i, i);
+        }
+        let keys: Vec<u64> = (50..150).collect();
+        assert_eq!(cache.remove_many(&keys), 50);
+        assert_eq!(cache.len(), 50);
+        assert_eq!(cache.remove_many(&keys), 0);
+        assert_eq!(cache.get(&49), Some(49));
+    }
+
+    #[test]
+    fn test_is_empty() {
+        let cache = sync::Cache::<u64, u64>::with_options(
+            OptionsBuilder::new()
+                .shards(4)
+                .estimated_items_capacity(100)
+                .weight_capacity(100)
+                .build()
+                .unwrap(),
+            UnitWeighter,
+            DefaultHashBuilder::default(),
+        );
+        assert!(cache.is_empty());
+        cache.insert(1, 1);
+        assert!(!cache.is_empty());
+        assert_eq!(cache.len(), 1);
+        cache.remove(&1);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_builder() {
+        let cache: sync::Cache<u64, u64> = sync::Cache::builder()
+            .shards(2)
+            .estimated_items_capacity(100)
+            .weight_capacity(100)
+            .hot_allocation(0.9)
+            .ghost_capacity(10)
+            .hash_builder(DefaultHashBuilder::default())
+            .build()
+            .unwrap();
+        assert_eq!(cache.shard_count(), 2);
+        assert_eq!(cache.capacity(), 100);
+        cache.insert(1, 1);
+        assert_eq!(cache.get(&1), Some(1));
+
+        assert!(sync::Cache::<u64, u64>::builder()
+            .estimated_items_capacity(100)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_debug_default() {
+        #[derive(Debug, Default)]
+        struct Caches {
+            sync: sync::Cache<u64, u64>,
+            unsync: unsync::Cache<u64, u64>,
+        }
+
+        let mut caches = Caches::default();
+        assert_eq!(caches.sync.capacity(), 1024);
+        assert_eq!(caches.unsync.capacity(), 1024);
+        caches.sync.insert(1, 1);
+        caches.sync.get(&1);
+        caches.unsync.insert(1, 1);
+        caches.unsync.get(&2);
+        assert_eq!(
+            format!("{:?}", caches.sync),
+            "Cache { capacity: 1024, len: 1, weight: 1, hits: 1, misses: 0, .. }"
+        );
+        assert_eq!(
+            format!("{:?}", caches.unsync),
+            "Cache { capacity: 1024, len: 1, weight: 1, hits: 0, misses: 1, .. }"
+        );
+    }
+
+    #[test]
+    fn test_clone() {
+        let cache = sync::Cache::<u64, u64>::new(100);
+        let handle = cache.clone();
+        handle.insert(1, 1);
+        assert_eq!(cache.get(&1), Some(1));
+        assert_eq!(handle.stats().hits, 1);
+        let items: Vec<_> = handle.into_iter().collect();
+        assert_eq!(items, vec![(1, 1)]);
+        // the other handle keeps the items
+        assert_eq!(cache.get(&1), Some(1));
+        let items: Vec<_> = cache.into_iter().collect();
+        assert_eq!(items, vec![(1, 1)]);
+
+        let mut cache = unsync::Cache::<u64, u64>::new(100);
+        cache.insert(1, 1);
+        let mut copy = cache.clone();
+        copy.insert(2, 2);
+        cache.remove(&1);
+        assert_eq!(copy.get(&1), Some(&1));
+        assert_eq!((cache.len(), copy.len()), (0, 2));
+    }
+
+    #[test]
+    fn test_estimated_len() {
+        let cache = sync::Cache::<u64, u64>::new(100);
+        assert_eq!((cache.estimated_len(), cache.estimated_weight()), (0, 0));
+        for i in 0..10 {
+            cache.insert(i, i);
+        }
+        assert_eq!(cache.estimated_len(), 10);
+        assert_eq!(cache.estimated_weight(), 10);
+        cache.remove(&0);
+        cache.get_value_or_guard(&100, None);
+        assert_eq!(cache.estimated_len(), cache.len());
+        cache.clear();
+        assert_eq!((cache.estimated_len(), cache.estimated_weight()), (0, 0));
+    }
+
+    #[test]
+    fn test_set_capacity() {
+        let cache = sync::Cache::<u64, u64>::new(1000);
+        for i in 0..1000 {
+            cache.insert(i, i);
+        }
+        assert_eq!(cache.set_capacity(2000), 0);
+        assert!(cache.capacity() >= 2000);
+        for i in 1000..2000 {
+            cache.insert(i, i);
+        }
+        assert!(cache.len() > 1000);
+        let len = cache.len();
+        let evicted = cache.set_capacity(500);
+        assert_eq!(cache.len(), len - evicted);
+        assert!((500..600).contains(&cache.capacity()));
+        assert!(cache.weight() <= cache.capacity());
+        for i in 2000..3000 {
+            cache.insert(i, i);
+        }
+        assert!(cache.weight() <= cache.capacity());
+
+        let mut cache = unsync::Cache::<u64, u64>::new(100);
+        for i in 0..100 {
+            cache.insert(i, i);
+        }
+        assert_eq!(cache.set_capacity(10), 90);
+        assert_eq!((cache.len(), cache.capacity()), (10, 10));
+        cache.set_capacity(0);
+        assert!(cache.is_empty());
+        cache.insert(1, 1);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_compute() {
+        let cache = sync::Cache::with_options(
+            OptionsBuilder::new()
+                .shards(1)
+                .estimated_items_capacity(10)
+                .weight_capacity(10)
+                .build()
+                .unwrap(),
+            StringWeighter,
+            DefaultHashBuilder::default(),
+        );
+        assert_eq!(cache.compute(1, |_| Compute::Keep), None);
+        assert!(cache.is_empty());
+        assert_eq!(cache.compute(1, |_| Compute::Insert("a".to_string())), None);
+        cache.insert(2, "b".to_string());
+        assert_eq!(
+            cache.compute(1, |v| {
+                v.unwrap().push_str("bcd");
+                Compute::Keep
+            }),
+            None
+        );
+        assert_eq!(cache.get(&1).unwrap(), "abcd");
+        assert_eq!(cache.weight(), 5);
+        // growing past the capacity evicts
+        cache.compute(1, |v| {
+            v.unwrap().push_str("efghij");
+            Compute::Keep
+        });
+        assert!(cache.weight() <= 10);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(
+            cache.compute(1, |_| Compute::Insert("z".to_string())),
+            Some("abcdefghij".to_string())
+        );
+        assert_eq!(cache.weight(), 1);
+        assert_eq!(cache.compute(1, |_| Compute::Remove), Some("z".to_string()));
+        assert!(cache.is_empty());
+        cache.insert(1, "a".to_string());
+        cache.set_frozen(true);
+        assert_eq!(cache.compute(1, |_| unreachable!()), None);
+
+        let mut cache = unsync::Cache::new(100);
+        for _ in 0..3 {
+            cache.compute(1, |v| match v {
+                Some(v) => {
+                    *v += 1;
+                    Compute::Keep
+                }
+                None => Compute::Insert(1),
+            });
+        }
+        assert_eq!(cache.get(&1), Some(&3));
+    }
+
+    #[test]
+    fn test_random_entry() {
+        let cache = sync::Cache::<u64, u64>::new(1000);
+        assert_eq!(cache.random_entry(), None);
+        for i in 0..1000 {
+            cache.insert(i, i * 10);
+        }
+        let mut sampled = std::collections::HashSet::new();
+        for _ in 0..1000 {
+            let (key, val) = cache.random_entry().unwrap();
+            assert_eq!(val, key * 10);
+            sampled.insert(key);
+        }
+        // 1000 samples of 1000 items hit about 63% of them
+        assert!(sampled.len() > 400);
+        // sparse after removals
+        cache.retain(|&k, _| k == 7);
+        assert_eq!(cache.random_entry(), Some((7, 70)));
+
+        let mut cache = unsync::Cache::new(100);
+        assert_eq!(cache.random_entry(), None);
+        cache.insert(1, 2);
+        assert_eq!(cache.random_entry(), Some((&1, &2)));
+
+        // entries past long runs of vacant slots aren't favored
+        let mut cache = unsync::Cache::new(1000);
+        for i in 0..1000 {
+            cache.insert(i, i);
+        }
+        cache.retain(|&k, _| k == 0 || k == 999);
+        let mut first = 0;
+        for _ in 0..200 {
+            if cache.random_entry().unwrap().0 == &0 {
+                first += 1;
+            }
+        }
+        assert!((50..=150).contains(&first));
+    }
+
+    #[test]
+    fn test_extract_if() {
+        let cache = sync::Cache::<u64, u64>::new(10_000);
+        for i in 0..1000 {
+            cache.insert(i, i);
+        }
+        let mut extracted = cache.extract_if(|&k, _| k % 3 == 0);
+        let first = extracted.next().unwrap();
+        assert_eq!(first.0 % 3, 0);
+        assert_eq!(cache.len(), 999);
+        // the shards aren't locked in between
+        cache.insert(5000, 5000);
+        let mut extracted = std::iter::once(first).chain(extracted).collect::<Vec<_>>();
+        extracted.sort_unstable();
+        assert_eq!(
+            extracted,
+            (0..1000).step_by(3).map(|i| (i, i)).collect::<Vec<_>>()
+        );
+        assert_eq!(cache.len(), 1000 - extracted.len() + 1);
+        assert!(cache.iter().all(|(k, _)| k % 3 != 0 || k == 5000));
+
+        let mut cache = unsync::Cache::<u64, u64>::new(10_000);
+        for i in 0..1000 {
+            cache.insert(i, i);
+        }
+        assert_eq!(cache.extract_if(|&k, _| k < 600).take(10).count(), 10);
+        assert_eq!(cache.len(), 990);
+        assert_eq!(cache.extract_if(|&k, _| k < 600).count(), 590);
+        assert_eq!(cache.len(), 400);
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let cache = sync::KQCache::<u64, u64, u64>::with_options(
+            OptionsBuilder::new()
+                .estimated_items_capacity(100)
+                .weight_capacity(100)
+                .shards(1)
+                .build()
+                .unwrap(),
+            UnitWeighter,
+            DefaultHashBuilder::default(),
+        );
+        let timeout = Duration::from_millis(10);
+        assert_eq!(cache.insert_timeout(1, 1, 1, timeout), Ok(()));
+        assert_eq!(cache.get_timeout(&1, &1, timeout), Ok(Some(1)));
+        assert_eq!(cache.get_timeout(&1, &2, timeout), Ok(None));
+
+        // another thread holds the shard write lock until told to release it
+        let (locked_tx, locked_rx) = std::sync::mpsc::channel();
+        let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+        std::thread::scope(|s| {
+            let cache = &cache;
+            s.spawn(move || {
+                let guard = cache.peek_mut(&1, &1).unwrap();
+                locked_tx.send(()).unwrap();
+                release_rx.recv().unwrap();
+                drop(guard);
+            });
+            locked_rx.recv().unwrap();
+            assert_eq!(cache.get_timeout(&1, &1, timeout), Err(sync::WouldBlock));
+            assert_eq!(
+                cache.insert_timeout(2, 2, 2, timeout),
+                Err(sync::WouldBlock)
+            );
+            release_tx.send(()).unwrap();
+            // a long enough timeout waits for the lock to be released
+            let timeout = Duration::from_secs(60);
+            assert_eq!(cache.insert_timeout(2, 2, 2, timeout), Ok(()));
+            assert_eq!(cache.get_timeout(&2, &2, timeout), Ok(Some(2)));
+        });
+        assert_eq!(cache.len(), 2);
+
+        let cache = sync::Cache::<u64, u64>::new(100);
+        assert_eq!(cache.insert_timeout(1, 1, timeout), Ok(()));
+        assert_eq!(cache.get_timeout(&1, timeout), Ok(Some(1)));
+        let guard = cache.get_mut(&1).unwrap();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                assert_eq!(cache.get_timeout(&1, timeout), Err(sync::WouldBlock));
+                assert_eq!(cache.insert_timeout(1, 2, timeout), Err(sync::WouldBlock));
+            });
+        });
+        drop(guard);
+        assert_eq!(cache.get(&1), Some(1));
+    }
+
+    #[test]
+    fn test_try_reserve() {
+        let mut cache = unsync::Cache::<u64, u64>::new(100);
+        assert_eq!(cache.try_reserve(100), Ok(()));
+        assert!(cache.try_reserve(usize::MAX).is_err());
+        cache.insert(1, 1);
+        assert_eq!(cache.get(&1), Some(&1));
+
+        let cache = sync::Cache::<u64, u64>::new(100);
+        assert_eq!(cache.try_reserve(100), Ok(()));
+        assert!(cache.try_reserve(usize::MAX).is_err());
+        // shared handles can pre-size the cache too
+        let before = cache.memory_usage();
+        cache.clone().reserve(1000);
+        let after = cache.memory_usage();
+        assert!(after.map_bytes > before.map_bytes);
+        assert!(after.slab_bytes > before.slab_bytes);
+    }
+
+    #[test]
+    fn test_evictions() {
+        let mut cache = unsync::Cache::new(100);
+        for i in 0..100 {
+            cache.insert(i, i);
+        }
+        assert_eq!(cache.evictions(), 0);
+        for i in 100..150 {
+            cache.insert(i, i);
+        }
+        assert_eq!(cache.evictions(), 50);
+        let cache = sync::Cache::new(100);
+        for i in 0..1000 {
+            cache.insert(i, i);
+        }
+        assert_eq!(cache.evictions(), 1000 - cache.len() as u64);
+    }
+
+    #[test]
+    fn test_stats() {
+        let cache = sync::Cache::new(100);
+        for i in 0..150 {
+            cache.insert(i, i);
+        }
+        let earlier = cache.stats();
+        assert_eq!(earlier.len, cache.len());
+        assert_eq!(earlier.evictions, 50);
+        let resident = (0..10).filter(|i| cache.peek(i).is_some()).count() as u64;
+        for i in 0..10 {
+            cache.get(&i);
+        }
+        cache.get(&1000);
+        let delta = cache.stats().delta(&earlier);
+        assert_eq!(delta.hits, resident);
+        assert_eq!(delta.misses, 11 - resident);
+        assert_eq!(delta.evictions, 0);
+        assert_eq!(delta.len, cache.len());
+        cache.reset_stats();
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (0, 0, 0));
+        assert_eq!(stats.len, cache.len());
+    }
+
+    #[test]
+    fn test_handle_stats() {
+        let cache = sync::Cache::<u64, u64>::new(100);
+        let a = cache.with_handle_stats();
+        let b = a.clone();
+        let c = cache.with_handle_stats();
+        cache.insert(1, 1);
+        cache.get(&1);
+        assert_eq!(a.get(&1), Some(1));
+        assert_eq!(b.get_many([&1, &2, &3]), [Some(1), None, None]);
+        assert_eq!(a.peek(&2), None);
+        // clones of a handle share its counters
+        assert_eq!(a.handle_stats(), Some(HandleStats { hits: 2, misses: 2 }));
+        assert_eq!(b.handle_stats(), a.handle_stats());
+        assert_eq!(cache.handle_stats(), None);
+        // while the cache counts the lookups of all the handles
+        assert_eq!((cache.hits(), cache.misses()), (3, 2));
+
+        assert_eq!(c.get_or_insert_with(&2, || Ok::<_, ()>(2)), Ok(2));
+        assert_eq!(c.get_or_insert_with(&2, || Ok::<_, ()>(3)), Ok(2));
+        let GuardResult::Guard(guard) = c.get_value_or_guard(&3, None) else {
+            panic!()
+        };
+        guard.insert(3);
+        assert!(matches!(
+            c.get_value_or_guard(&3, None),
+            GuardResult::Value(3)
+        ));
+        let values = c.get_or_insert_with_many(&[1, 2, 4, 5], |missing| {
+            Ok::<_, ()>(missing.iter().map(|k| k * 10).collect())
+        });
+        assert_eq!(values, Ok(vec![1, 2, 40, 50]));
+        assert_eq!(c.get_mut(&6).map(|_| ()), None);
+        let stats = c.handle_stats().unwrap();
+        assert_eq!(stats, HandleStats { hits: 4, misses: 5 });
+        assert_eq!(stats.hit_rate(), 4.0 / 9.0);
+        assert_eq!(a.handle_stats(), Some(HandleStats { hits: 2, misses: 2 }));
+    }
+
+    #[test]
+    fn test_reset_stats() {
+        let cache = sync::Cache::with_options(
+            OptionsBuilder::new()
+                .estimated_items_capacity(100)
+                .weight_capacity(100)
+                .shards(4)
+                .build()
+                .unwrap(),
+            UnitWeighter,
+            DefaultHashBuilder::default(),
+        );
+        for i in 0..200 {
+            cache.insert(i, i);
+            cache.get(&i);
+            cache.get(&(i + 1000));
+        }
+        let stats = cache.stats();
+        assert!(stats.hits > 0 && stats.misses > 0 && stats.evictions > 0);
+        let len = cache.len();
+        cache.reset_stats();
+        // every shard is reset, but the contents are kept
+        assert!(cache
+            .shard_stats()
+            .iter()
+            .all(|s| (s.hits, s.misses, s.evictions) == (0, 0, 0)));
+        assert_eq!(cache.len(), len);
+        assert_eq!(cache.stats().len, len);
+        let resident = (0..200).find(|i| cache.peek(i).is_some()).unwrap();
+        // and counting resumes from there
+        cache.get(&resident);
+        cache.get(&1000);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (1, 1, 0));
+
+        let mut cache = unsync::Cache::new(10);
+        for i in 0..20 {
+            cache.insert(i, i);
+            cache.get(&i);
+        }
+        cache.reset_stats();
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (0, 0, 0));
+        assert_eq!(stats.len, 10);
+    }
+
+    #[cfg(feature = "latency")]
+    #[test]
+    fn test_latency_histograms() {
+        let cache = sync::Cache::new(100);
+        for i in 0..150 {
+            cache.insert(i, i);
+            cache.get(&i);
+        }
+        let histograms = cache.latency_histograms();
+        assert_eq!(histograms.get.count(), 150);
+        assert_eq!(histograms.insert.count(), 150);
+        let p50 = histograms.insert.quantile(0.5).unwrap();
+        assert!(p50 <= histograms.insert.quantile(1.0).unwrap());
+        assert_eq!(histograms.get.buckets().map(|(_, c)| c).sum::<u64>(), 150);
+        cache.reset_stats();
+        assert_eq!(cache.latency_histograms().get.quantile(0.5), None);
+    }
+
+    #[cfg(feature = "trace")]
+    #[test]
+    fn test_trace() {
+        use std::sync::Mutex;
+        use trace::{read_trace, TraceOp, TraceRecorder};
+
+        #[derive(Clone, Default)]
+        struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+        impl std::io::Write for SharedBuffer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buffer = SharedBuffer::default();
+        let mut builder = OptionsBuilder::new();
+        builder.estimated_items_capacity(100).weight_capacity(100);
+        let options = builder.build().unwrap();
+        let traced = builder
+            .trace_recorder(TraceRecorder::new(buffer.clone()))
+            .build()
+            .unwrap();
+        let mut cache =
+            unsync::Cache::with_options(traced, UnitWeighter, DefaultHashBuilder::default());
+        // a read-through workload skewed towards the low keys
+        let mut inserts = 0;
+        for i in 0..5000u64 {
+            let key = i % (1 + i % 300);
+            if cache.get(&key).is_none() {
+                cache.insert(key, key);
+                inserts += 1;
+            }
+        }
+        cache.remove(&0);
+
+        let records = read_trace(&buffer.0.lock().unwrap()[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let count = |op| records.iter().filter(|r| r.op == op).count() as u64;
+        let stats = cache.stats();
+        assert_eq!(count(TraceOp::Hit), stats.hits);
+        assert_eq!(count(TraceOp::Miss), stats.misses);
+        assert_eq!(count(TraceOp::Insert), inserts);
+        assert_eq!(count(TraceOp::Remove), 1);
+        // the weight is known for the resident entries only
+        assert!(records
+            .iter()
+            .all(|r| r.weight == u32::from(matches!(r.op, TraceOp::Hit | TraceOp::Insert))));
+        // every miss is followed by the insertion of the same key
+        for pair in records.windows(2) {
+            if pair[0].op == TraceOp::Miss {
+                assert_eq!((pair[1].op, pair[1].hash), (TraceOp::Insert, pair[0].hash));
+            }
+        }
+
+        // replaying the trace offline reproduces the live cache behavior
+        #[cfg(feature = "simulate")]
+        {
+            let report = simulate::simulate(&options, records.iter().filter_map(|r| r.access()));
+            assert_eq!(report.accesses, stats.hits + stats.misses);
+            assert_eq!(report.hits, stats.hits);
+            assert_eq!(report.evictions, stats.evictions);
+        }
+    }
+
+    #[cfg(feature = "opentelemetry")]
+    #[test]
+    fn test_otel_metrics() {
+        use opentelemetry::{
+            metrics::{
+                AsyncInstrument, AsyncInstrumentBuilder, Callback, InstrumentProvider, Meter,
+                ObservableCounter, ObservableGauge,
+            },
+            KeyValue,
+        };
+        use std::sync::Mutex;
+
+        /// Keeps the callbacks of the instruments, by instrument name.
+        #[derive(Default)]
+        struct TestProvider(Mutex<Vec<(String, Callback<u64>)>>);
+
+        impl InstrumentProvider for TestProvider {
+            fn u64_observable_counter(
+                &self,
+                builder: AsyncInstrumentBuilder<'_, ObservableCounter<u64>, u64>,
+            ) -> ObservableCounter<u64> {
+                let mut callbacks = self.0.lock().unwrap();
+                for callback in builder.callbacks {
+                    callbacks.push((builder.name.to_string(), callback));
+                }
+                ObservableCounter::new()
+            }
+
+            fn u64_observable_gauge(
+                &self,
+                builder: AsyncInstrumentBuilder<'_, ObservableGauge<u64>, u64>,
+            ) -> ObservableGauge<u64> {
+                let mut callbacks = self.0.lock().unwrap();
+                for callback in builder.callbacks {
+                    callbacks.push((builder.name.to_string(), callback));
+                }
+                ObservableGauge::new()
+            }
+        }
+
+        /// Keeps the observed values and their attributes.
+        #[derive(Default)]
+        struct TestObserver(Mutex<Vec<(u64, Vec<KeyValue>)>>);
+
+        impl AsyncInstrument<u64> for TestObserver {
+            fn observe(&self, measurement: u64, attributes: &[KeyValue]) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push((measurement, attributes.to_vec()));
+            }
+        }
+
+        let provider = Arc::new(TestProvider::default());
+        let meter = Meter::new(provider.clone());
+        let cache = Arc::new(sync::Cache::<u64, u64>::new(100));
+        let weak = Arc::downgrade(&cache);
+        let _metrics =
+            otel::register_metrics(&meter, &[KeyValue::new("cache", "users")], move || {
+                Some(weak.upgrade()?.stats())
+            });
+        for i in 0..150 {
+            cache.insert(i, i);
+        }
+        for i in 100..120 {
+            cache.get(&i);
+        }
+        cache.get(&1000);
+        cache.set_frozen(true);
+        cache.insert(1000, 1000);
+
+        let stats = cache.stats();
+        let callbacks = provider.0.lock().unwrap();
+        let value = |name: &str| {
+            let observer = TestObserver::default();
+            for (_, callback) in callbacks.iter().filter(|(n, _)| n == name) {
+                callback(&observer);
+            }
+            let observed = observer.0.into_inner().unwrap();
+            assert_eq!(observed.len(), 1, "{name}");
+            let (value, attributes) = &observed[0];
+            assert_eq!(attributes, &[KeyValue::new("cache", "users")]);
+            *value
+        };
+        assert_eq!(value("cache.hits"), stats.hits);
+        assert_eq!(value("cache.misses"), stats.misses);
+        assert_eq!(value("cache.evictions"), stats.evictions);
+        assert_eq!(value("cache.rejections"), 1);
+        assert_eq!(value("cache.entries"), cache.len() as u64);
+        assert_eq!(value("cache.weight"), cache.weight());
+        assert_eq!(callbacks.len(), 6);
+        // the callback doesn't keep the cache alive
+        assert_eq!(Arc::strong_count(&cache), 1);
+        drop(cache);
+        let observer = TestObserver::default();
+        for (_, callback) in callbacks.iter() {
+            callback(&observer);
+        }
+        assert!(observer.0.lock().unwrap().is_empty());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compression() {
+        let cache = compress::CompressedCache::new(10, 10_000, 16);
+        let blob = b"abcdefgh".repeat(100);
+        cache.insert(1, &blob);
+        cache.insert(2, b"small");
+        assert_eq!(cache.get(&1), Some(blob.clone()));
+        assert_eq!(cache.peek(&2).as_deref(), Some(&b"small"[..]));
+        assert!(cache.weight() < blob.len() as u64);
+        // incompressible values are stored as is
+        let random: Vec<u8> = (0..100u32)
+            .map(|i| i.wrapping_mul(2654435761) as u8)
+            .collect();
+        cache.insert(3, &random);
+        assert_eq!(cache.get(&3), Some(random));
+        assert!(cache.remove(&1));
+        assert_eq!(cache.len(), 2);
+    }
+
     #[test]
     fn test_borrow_keys() {
         let cache = sync::KQCache::<Vec<u8>, Vec<u8>, u64>::new(0);
@@ -1,6 +1,11 @@
+use std::collections::TryReserveError;
+
+/// Index of an entry in the slab, offset by one so `Option<Token>` has the same size as `Token`.
+/// Tokens are 32 bits wide even on 64 bit targets to keep the per-entry overhead low,
+/// which limits a slab to `u32::MAX - 1` entries.
 pub type Token = std::num::NonZeroU32;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Entry<T> {
     item: Option<T>,
     /// The next item in the list (possibly itself).
@@ -12,7 +17,7 @@ struct Entry<T> {
 }
 
 /// A slab like structure that also maintains circular lists with its items.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LinkedSlab<T> {
     entries: Vec<Entry<T>>,
     next_free: Token,
@@ -31,11 +36,21 @@ impl<T> LinkedSlab<T> {
         self.entries.iter().filter(|e| e.item.is_some()).count()
     }
 
-    #[cfg(fuzzing)]
+    /// Iterates over the items in slot order.
     pub fn iter_entries(&self) -> impl Iterator<Item = &T> + '_ {
         self.entries.iter().filter_map(|e| e.item.as_ref())
     }
 
+    /// Number of slots, occupied or free.
+    pub fn num_slots(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Iterates over the items in slot order.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> + '_ {
+        self.entries.iter_mut().filter_map(|e| e.item.as_mut())
+    }
+
     /// Inserts a new entry in the list, link it before `head`.
     /// If `head` is not set the item will belong to a list only containing itself.
     ///
@@ -150,8 +165,94 @@ impl<T> LinkedSlab<T> {
         Some((old_item, next))
     }
 
+    /// Reserves space for `additional` more entries than the slab currently holds,
+    /// including vacant ones.
+    pub fn reserve(&mut self, additional: usize) {
+        self.entries.reserve(additional)
+    }
+
+    /// Tries to reserve space for `additional` more entries than the slab currently holds,
+    /// including vacant ones.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        self.entries.try_reserve(additional)
+    }
+
+    /// Bytes allocated by the slab, including vacant entries.
+    pub fn allocated_bytes(&self) -> usize {
+        self.entries.capacity() * std::mem::size_of::<Entry<T>>()
+    }
+
     /// The Token that will be returned by the next call to `insert()`
     pub fn next_free(&self) -> Token {
         self.next_free
     }
+
+    /// Relocates entries from the end of the slab into the vacant slots closer to its start,
+    /// then truncates the trailing vacant slots and releases the excess memory.
+    ///
+    /// `relocate` is called with the entry, its current token and its new token before each move,
+    /// so any external reference to the entry can be updated. If it returns `false` the entry is
+    /// left in place and compaction continues with the entries before it.
+    pub fn compact(&mut self, mut relocate: impl FnMut(&T, Token, Token) -> bool) {
+        let vacant = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(i, e)| e.item.is_none().then_some(i))
+            .collect::<Vec<_>>();
+        let mut vacant_iter = vacant.into_iter().peekable();
+        for i in (0..self.entries.len()).rev() {
+            let Some(item) = &self.entries[i].item else {
+                continue;
+            };
+            match vacant_iter.peek() {
+                Some(&hole) if hole < i => {
+                    let old = Token::new(i as u32 + 1).unwrap();
+                    let new = Token::new(hole as u32 + 1).unwrap();
+                    if relocate(item, old, new) {
+                        self.relocate(old, new);
+                        vacant_iter.next();
+                    }
+                }
+                _ => break,
+            }
+        }
+        while matches!(self.entries.last(), Some(e) if e.item.is_none()) {
+            self.entries.pop();
+        }
+
+        // Rebuild the free list with the remaining holes, lowest tokens first.
+        self.next_free = Token::new(self.entries.len() as u32 + 1).unwrap();
+        for i in (0..self.entries.len()).rev() {
+            if self.entries[i].item.is_none() {
+                self.entries[i].next = self.next_free;
+                self.next_free = Token::new(i as u32 + 1).unwrap();
+            }
+        }
+        self.entries.shrink_to_fit();
+    }
+
+    /// Moves the entry at `old` into the vacant slot `new`, keeping it in the same position of its list.
+    ///
+    /// # Panics
+    /// Panics on out of bounds access.
+    /// Panics (in debug mode) if `old` is absent or `new` is present.
+    fn relocate(&mut self, old: Token, new: Token) {
+        let entry = &mut self.entries[(old.get() - 1) as usize];
+        let item = entry.item.take();
+        debug_assert!(item.is_some());
+        let (prev, next) = if entry.next == old {
+            // single item list, it links to itself
+            (new, new)
+        } else {
+            (entry.prev, entry.next)
+        };
+        let target = &mut self.entries[(new.get() - 1) as usize];
+        debug_assert!(target.item.is_none());
+        (target.item, target.prev, target.next) = (item, prev, next);
+        if prev != new {
+            self.entries[(prev.get() - 1) as usize].next = new;
+            self.entries[(next.get() - 1) as usize].prev = new;
+        }
+    }
 }
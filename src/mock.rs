@@ -0,0 +1,183 @@
+//! Test support cache that records the operations performed on it, with scriptable lookups.
+//!
+//! # Example
+//!
+//! ```rust
+//! use quick_cache::{mock::{MockCache, Op}, DynCache};
+//!
+//! fn lookup(cache: &dyn DynCache<u64, String>, key: u64) -> String {
+//!     cache.get_or_insert_with(&key, &mut || key.to_string())
+//! }
+//!
+//! let cache = MockCache::new();
+//! // the next lookup hits, whatever is stored in the cache
+//! cache.push_response(Some("cached".to_string()));
+//! assert_eq!(lookup(&cache, 1), "cached");
+//! assert_eq!(lookup(&cache, 1), "1");
+//! assert_eq!(lookup(&cache, 1), "1");
+//! assert_eq!(cache.operations(), [Op::GetOrInsert(1), Op::GetOrInsert(1), Op::GetOrInsert(1)]);
+//! assert_eq!(cache.stats().misses, 1);
+//! ```
+
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::{DynCache, Stats};
+
+/// An operation performed on a [MockCache].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op<Key, Val> {
+    Get(Key),
+    Peek(Key),
+    Insert(Key, Val),
+    Remove(Key),
+    GetOrInsert(Key),
+}
+
+#[derive(Debug)]
+struct State<Key, Val> {
+    operations: Vec<Op<Key, Val>>,
+    responses: VecDeque<Option<Val>>,
+    entries: HashMap<Key, Val>,
+    hits: u64,
+    misses: u64,
+}
+
+/// A cache backed by an unbounded map that records all the operations performed on it.
+///
+/// Lookups (`get`, `peek` and `get_or_insert_with`) are answered by the responses queued with
+/// [MockCache::push_response], in order, falling back to the map once the queue is empty.
+/// Each entry has a weight of 1.
+#[derive(Debug)]
+pub struct MockCache<Key, Val> {
+    state: Mutex<State<Key, Val>>,
+}
+
+impl<Key, Val> Default for MockCache<Key, Val> {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(State {
+                operations: Vec::new(),
+                responses: VecDeque::new(),
+                entries: HashMap::new(),
+                hits: 0,
+                misses: 0,
+            }),
+        }
+    }
+}
+
+impl<Key: Eq + Hash + Clone, Val: Clone> MockCache<Key, Val> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues the response of a future lookup, `None` being a miss.
+    pub fn push_response(&self, response: Option<Val>) {
+        self.state().responses.push_back(response);
+    }
+
+    /// Returns the operations performed so far, in order.
+    pub fn operations(&self) -> Vec<Op<Key, Val>> {
+        self.state().operations.clone()
+    }
+
+    /// Forgets the operations performed so far.
+    pub fn clear_operations(&self) {
+        self.state().operations.clear();
+    }
+
+    fn state(&self) -> MutexGuard<'_, State<Key, Val>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<Key: Eq + Hash + Clone, Val: Clone> State<Key, Val> {
+    fn lookup(&mut self, key: &Key) -> Option<Val> {
+        let result = match self.responses.pop_front() {
+            Some(response) => response,
+            None => self.entries.get(key).cloned(),
+        };
+        if result.is_some() {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        result
+    }
+}
+
+impl<Key, Val> DynCache<Key, Val> for MockCache<Key, Val>
+where
+    Key: Eq + Hash + Clone + Send,
+    Val: Clone + Send,
+{
+    fn len(&self) -> usize {
+        self.state().entries.len()
+    }
+
+    fn weight(&self) -> u64 {
+        self.len() as u64
+    }
+
+    fn capacity(&self) -> u64 {
+        u64::MAX
+    }
+
+    fn stats(&self) -> Stats {
+        let state = self.state();
+        Stats {
+            hits: state.hits,
+            misses: state.misses,
+            evictions: 0,
+            rejections: Default::default(),
+            len: state.entries.len(),
+            weight: state.entries.len() as u64,
+        }
+    }
+
+    fn get(&self, key: &Key) -> Option<Val> {
+        let mut state = self.state();
+        state.operations.push(Op::Get(key.clone()));
+        state.lookup(key)
+    }
+
+    fn peek(&self, key: &Key) -> Option<Val> {
+        let mut state = self.state();
+        state.operations.push(Op::Peek(key.clone()));
+        match state.responses.pop_front() {
+            Some(response) => response,
+            None => state.entries.get(key).cloned(),
+        }
+    }
+
+    fn remove(&self, key: &Key) -> bool {
+        let mut state = self.state();
+        state.operations.push(Op::Remove(key.clone()));
+        state.entries.remove(key).is_some()
+    }
+
+    fn insert(&self, key: Key, value: Val) {
+        let mut state = self.state();
+        state
+            .operations
+            .push(Op::Insert(key.clone(), value.clone()));
+        state.entries.insert(key, value);
+    }
+
+    fn get_or_insert_with(&self, key: &Key, with: &mut dyn FnMut() -> Val) -> Val {
+        let mut state = self.state();
+        state.operations.push(Op::GetOrInsert(key.clone()));
+        if let Some(value) = state.lookup(key) {
+            return value;
+        }
+        // like the real caches, the value isn't computed while holding the lock
+        drop(state);
+        let value = with();
+        self.state().entries.insert(key.clone(), value.clone());
+        value
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    hash::{BuildHasher, Hash},
+};
+
+use crate::{
+    sync::Cache, DefaultHashBuilder, Options, OptionsBuilder, Stats, UnitWeighter, Weighter,
+};
+
+/// A concurrent cache whose capacity is partitioned into namespaces, each one with its own quota.
+///
+/// Every namespace is an independent [Cache], so inserting in a namespace only ever evicts entries
+/// of that namespace, and one noisy class of data can't evict everything else.
+/// Namespaces are fixed on construction, items of other namespaces aren't cached.
+///
+/// # Example
+///
+/// ```rust
+/// use quick_cache::NamespacedCache;
+///
+/// let cache = NamespacedCache::new([("users", 1000), ("sessions", 100)]);
+/// cache.insert("users", 1, "alice");
+/// for i in 0..1000 {
+///     cache.insert("sessions", i, "session");
+/// }
+/// assert_eq!(cache.get("users", &1), Some("alice"));
+/// assert!(cache.stats("sessions").unwrap().evictions > 0);
+/// ```
+pub struct NamespacedCache<Ns, Key, Val, We = UnitWeighter, B = DefaultHashBuilder> {
+    namespaces: HashMap<Ns, Cache<Key, Val, We, B>>,
+}
+
+impl<Ns: Eq + Hash, Key: Eq + Hash, Val: Clone> NamespacedCache<Ns, Key, Val> {
+    /// Creates a cache with the given namespaces, each one holding up to its number of items.
+    pub fn new(quotas: impl IntoIterator<Item = (Ns, usize)>) -> Self {
+        Self::with_options(
+            quotas.into_iter().map(|(ns, items_capacity)| {
+                let options = OptionsBuilder::new()
+                    .estimated_items_capacity(items_capacity)
+                    .weight_capacity(items_capacity as u64)
+                    .build()
+                    .unwrap();
+                (ns, options)
+            }),
+            UnitWeighter,
+            DefaultHashBuilder::default(),
+        )
+    }
+}
+
+impl<
+        Ns: Eq + Hash,
+        Key: Eq + Hash,
+        Val: Clone,
+        We: Weighter<Key, (), Val> + Clone,
+        B: BuildHasher + Clone,
+    > NamespacedCache<Ns, Key, Val, We, B>
+{
+    /// Creates a cache with the given namespaces, each one configured with its own [Options].
+    pub fn with_options(
+        namespaces: impl IntoIterator<Item = (Ns, Options)>,
+        weighter: We,
+        hash_builder: B,
+    ) -> Self {
+        Self {
+            namespaces: namespaces
+                .into_iter()
+                .map(|(ns, options)| {
+                    let cache =
+                        Cache::with_options(options, weighter.clone(), hash_builder.clone());
+                    (ns, cache)
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the cache of namespace `ns`, giving access to its whole interface.
+    pub fn namespace<N>(&self, ns: &N) -> Option<&Cache<Key, Val, We, B>>
+    where
+        Ns: Borrow<N>,
+        N: Eq + Hash + ?Sized,
+    {
+        self.namespaces.get(ns)
+    }
+
+    /// Iterates over the namespaces and their caches, in an arbitrary order.
+    pub fn namespaces(&self) -> impl Iterator<Item = (&Ns, &Cache<Key, Val, We, B>)> + '_ {
+        self.namespaces.iter()
+    }
+
+    /// Fetches an item of namespace `ns` from the cache.
+    pub fn get<N, Q>(&self, ns: &N, key: &Q) -> Option<Val>
+    where
+        Ns: Borrow<N>,
+        N: Eq + Hash + ?Sized,
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.namespace(ns)?.get(key)
+    }
+
+    /// Peeks an item of namespace `ns` from the cache. Contrary to gets, peeks don't alter the key "hotness".
+    pub fn peek<N, Q>(&self, ns: &N, key: &Q) -> Option<Val>
+    where
+        Ns: Borrow<N>,
+        N: Eq + Hash + ?Sized,
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.namespace(ns)?.peek(key)
+    }
+
+    /// Inserts an item in namespace `ns` of the cache, only evicting entries of the same namespace.
+    /// Nothing is inserted if the namespace doesn't exist.
+    pub fn insert<N>(&self, ns: &N, key: Key, value: Val)
+    where
+        Ns: Borrow<N>,
+        N: Eq + Hash + ?Sized,
+    {
+        if let Some(cache) = self.namespace(ns) {
+            cache.insert(key, value);
+        }
+    }
+
+    /// Remove an item of namespace `ns` from the cache.
+    /// Returns whether an entry was removed.
+    pub fn remove<N, Q>(&self, ns: &N, key: &Q) -> bool
+    where
+        Ns: Borrow<N>,
+        N: Eq + Hash + ?Sized,
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.namespace(ns).map_or(false, |cache| cache.remove(key))
+    }
+
+    /// Returns a snapshot of the counters of namespace `ns`.
+    pub fn stats<N>(&self, ns: &N) -> Option<Stats>
+    where
+        Ns: Borrow<N>,
+        N: Eq + Hash + ?Sized,
+    {
+        self.namespace(ns).map(Cache::stats)
+    }
+
+    /// Returns a snapshot of the counters of all the namespaces combined.
+    pub fn total_stats(&self) -> Stats {
+        self.namespaces
+            .values()
+            .map(Cache::stats)
+            .fold(Stats::default(), Stats::merge)
+    }
+
+    /// Returns the number of cached items, in all the namespaces
+    pub fn len(&self) -> usize {
+        self.namespaces.values().map(Cache::len).sum()
+    }
+
+    /// Returns whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.namespaces.values().all(Cache::is_empty)
+    }
+
+    /// Returns the maximum weight of cached items, that is the sum of the namespace quotas
+    pub fn capacity(&self) -> u64 {
+        self.namespaces.values().map(Cache::capacity).sum()
+    }
+}
+
+impl<Ns, Key, Val, We, B> std::fmt::Debug for NamespacedCache<Ns, Key, Val, We, B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NamespacedCache").finish_non_exhaustive()
+    }
+}
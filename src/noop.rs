@@ -0,0 +1,110 @@
+use std::{borrow::Borrow, future::Future, hash::Hash, marker::PhantomData};
+
+use crate::Stats;
+
+/// A cache that stores nothing, with the same basic interface as [crate::sync::Cache].
+///
+/// Every lookup misses and insertions are dropped right away, without any hashing or locking.
+/// Note that a regular cache with a weight capacity of 0 behaves similarly while keeping the same type,
+/// and its `get_or_insert` family of functions still coordinates concurrent computations of a key.
+pub struct NoopCache<Key, Val>(PhantomData<fn(Key) -> Val>);
+
+// Implemented by hand as derives would require `Key` and `Val` to implement these traits.
+impl<Key, Val> Clone for NoopCache<Key, Val> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<Key, Val> Copy for NoopCache<Key, Val> {}
+
+impl<Key, Val> Default for NoopCache<Key, Val> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Key, Val> std::fmt::Debug for NoopCache<Key, Val> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NoopCache").finish()
+    }
+}
+
+impl<Key, Val> NoopCache<Key, Val> {
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+
+    /// Always returns `true`
+    pub fn is_empty(&self) -> bool {
+        true
+    }
+
+    /// Always returns `0`
+    pub fn len(&self) -> usize {
+        0
+    }
+
+    /// Always returns `0`
+    pub fn weight(&self) -> u64 {
+        0
+    }
+
+    /// Always returns `0`
+    pub fn capacity(&self) -> u64 {
+        0
+    }
+
+    /// Always returns empty stats, as lookups aren't counted
+    pub fn stats(&self) -> Stats {
+        Stats::default()
+    }
+
+    /// Always returns `None`
+    pub fn get<Q>(&self, _key: &Q) -> Option<Val>
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        None
+    }
+
+    /// Always returns `None`
+    pub fn peek<Q>(&self, _key: &Q) -> Option<Val>
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        None
+    }
+
+    /// Always returns `false`
+    pub fn remove<Q>(&self, _key: &Q) -> bool
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        false
+    }
+
+    /// Drops the item
+    pub fn insert(&self, _key: Key, _value: Val) {}
+
+    /// Always computes the value with `with`
+    pub fn get_or_insert_with<E>(
+        &self,
+        _key: &Key,
+        with: impl FnOnce() -> Result<Val, E>,
+    ) -> Result<Val, E> {
+        with()
+    }
+
+    /// Always computes the value with `with`
+    pub async fn get_or_insert_async<E>(
+        &self,
+        _key: &Key,
+        with: impl Future<Output = Result<Val, E>>,
+    ) -> Result<Val, E> {
+        with.await
+    }
+}
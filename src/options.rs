@@ -1,14 +1,31 @@
+use std::time::Duration;
+
 pub const DEFAULT_HOT_ALLOCATION: f64 = 0.99;
 pub const DEFAULT_GHOST_ALLOCATION: f64 = 0.5;
+/// Items capacity of the unit weighted caches created with `Default`.
+pub const DEFAULT_ITEMS_CAPACITY: usize = 1024;
 
 /// Cache options. Built with [OptionsBuilder].
 #[derive(Debug, Clone)]
 pub struct Options {
     pub(crate) shards: usize,
     pub(crate) hot_allocation: f64,
+    pub(crate) adaptive_hot_allocation: Option<(f64, f64)>,
     pub(crate) ghost_allocation: f64,
+    pub(crate) ghost_capacity: Option<usize>,
+    pub(crate) doorkeeper: bool,
+    pub(crate) frequency_sketch: bool,
+    pub(crate) shard_hash_seeds: bool,
+    pub(crate) key_affinity: bool,
+    pub(crate) buffered_stats: bool,
     pub(crate) estimated_items_capacity: usize,
     pub(crate) weight_capacity: u64,
+    pub(crate) max_items: Option<usize>,
+    pub(crate) max_item_weight: Option<u64>,
+    pub(crate) idle_timeout: Option<Duration>,
+    pub(crate) min_residency: Option<Duration>,
+    #[cfg(feature = "trace")]
+    pub(crate) trace_recorder: Option<std::sync::Arc<crate::trace::TraceRecorder>>,
 }
 
 /// Builder for [Options].
@@ -32,9 +49,43 @@ pub struct Options {
 pub struct OptionsBuilder {
     shards: Option<usize>,
     hot_allocation: Option<f64>,
+    adaptive_hot_allocation: Option<(f64, f64)>,
     ghost_allocation: Option<f64>,
+    ghost_capacity: Option<usize>,
+    doorkeeper: Option<bool>,
+    frequency_sketch: Option<bool>,
+    shard_hash_seeds: Option<bool>,
+    key_affinity: Option<bool>,
+    buffered_stats: Option<bool>,
     estimated_items_capacity: Option<usize>,
     weight_capacity: Option<u64>,
+    max_items: Option<usize>,
+    max_item_weight: Option<u64>,
+    idle_timeout: Option<Duration>,
+    min_residency: Option<Duration>,
+    #[cfg(feature = "trace")]
+    trace_recorder: Option<std::sync::Arc<crate::trace::TraceRecorder>>,
+}
+
+impl Options {
+    /// Number of ghost keys tracked by each of the `num_shards` shards,
+    /// each one holding up to `shard_items_capacity` items.
+    pub(crate) fn shard_ghost_capacity(
+        &self,
+        shard_items_capacity: usize,
+        num_shards: usize,
+    ) -> usize {
+        match self.ghost_capacity {
+            Some(ghost_capacity) => ghost_capacity.saturating_add(num_shards - 1) / num_shards,
+            None => (shard_items_capacity as f64 * self.ghost_allocation) as usize,
+        }
+    }
+
+    /// Max number of items held by each of the `num_shards` shards, if limited.
+    pub(crate) fn shard_max_items(&self, num_shards: usize) -> Option<usize> {
+        self.max_items
+            .map(|max_items| max_items.saturating_add(num_shards - 1) / num_shards)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -78,6 +129,62 @@ impl OptionsBuilder {
         self
     }
 
+    /// The max number of items that the cache can hold, enforced along with `weight_capacity`:
+    /// entries are evicted as soon as either limit is reached. This bounds the per-entry overhead
+    /// even if the weight capacity isn't exhausted, e.g. when many items are lighter than expected.
+    /// As with the weight capacity, the limit is split evenly among the shards of sync caches,
+    /// so a shard may evict entries before the whole cache reaches the limit.
+    ///
+    /// Defaults to: the weight capacity, which only limits caches holding entries of weight 0.
+    pub fn max_items(&mut self, max_items: usize) -> &mut Self {
+        self.max_items = Some(max_items);
+        self
+    }
+
+    /// The max weight of a single item. Insertions of heavier items are rejected: the plain
+    /// `insert` functions drop them, while the `insert_checked` functions return
+    /// [Rejected::TooHeavy](crate::Rejected::TooHeavy) with the value. Either way the rejections are
+    /// counted in [Stats::rejections](crate::Stats::rejections).
+    ///
+    /// Items heavier than the capacity of a shard are always rejected.
+    ///
+    /// Defaults to: no limit other than the shard capacity.
+    pub fn max_item_weight(&mut self, max_item_weight: u64) -> &mut Self {
+        self.max_item_weight = Some(max_item_weight);
+        self
+    }
+
+    /// Entries that weren't accessed (with `get` or an insertion) for `idle_timeout` are evicted
+    /// by the `prune_idle` functions, even if the cache is under capacity. This reclaims memory
+    /// during quiet periods instead of holding stale entries forever. The cache has no background
+    /// threads, so `prune_idle` should be called periodically, e.g. from a timer.
+    ///
+    /// Note that enabling this reads the clock on every hit and takes 8 bytes per entry to
+    /// track the access times.
+    ///
+    /// Defaults to: disabled.
+    pub fn idle_timeout(&mut self, idle_timeout: Duration) -> &mut Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    /// Entries inserted less than `min_residency` ago aren't evicted to make room for new keys,
+    /// protecting freshly loaded values from being churned out by
+    /// unrelated bulk insertions. A new key is rejected instead if the next entries in eviction
+    /// order are that recent. Only a few dozen entries are considered, so insertions stay cheap
+    /// while most of the cache is recent. Replacing the value of a cached key may still evict
+    /// recent entries.
+    ///
+    /// Note that enabling this reads the clock on every insertion and takes 8 bytes per entry to
+    /// track the insertion times, and that explicit evictions (e.g. `relieve_pressure` or
+    /// `prune_idle`) don't honor it.
+    ///
+    /// Defaults to: disabled.
+    pub fn min_residency(&mut self, min_residency: Duration) -> &mut Self {
+        self.min_residency = Some(min_residency);
+        self
+    }
+
     /// What percentage `[0..=1.0]` of the cache space to reserve for "hot" items.
     /// If your workload exhibit heavy bias towards recency instead of frequency try
     /// lowering this setting. In practice the useful ranges are between 50% to 99%
@@ -93,6 +200,24 @@ impl OptionsBuilder {
         self
     }
 
+    /// Lets the cache adjust the hot allocation by itself within `[min..=max]`, starting from
+    /// `hot_allocation`. Every ghost key hit shrinks the hot section (and grows the cold one),
+    /// since a larger cold section would have kept that key, while every ghost key that expires
+    /// without being hit grows the hot section. The cache thus tunes itself between recency and
+    /// frequency biased workloads.
+    ///
+    /// Note that this relies on ghost keys and can't be combined with `frequency_sketch`.
+    ///
+    /// Defaults to: disabled.
+    pub fn adaptive_hot_allocation(&mut self, min: f64, max: f64) -> &mut Self {
+        assert!(
+            min.clamp(0.0, 1.0) == min && max.clamp(0.0, 1.0) == max && min <= max,
+            "adaptive_hot_allocation must satisfy 0 <= min <= max <= 1"
+        );
+        self.adaptive_hot_allocation = Some((min, max));
+        self
+    }
+
     /// The cache optimistically tracks recently seen keys that are not resident
     /// in the cache. These keys are called ghost keys. If a ghost key is seen
     /// again items it will be admitted as "hot".
@@ -109,6 +234,94 @@ impl OptionsBuilder {
         self
     }
 
+    /// Replaces the ghost keys with a count-min sketch of the recently evicted keys.
+    /// Keys with a non-zero estimate are admitted as "hot", like ghost keys, and the
+    /// sketch counters are periodically halved so old evictions are forgotten.
+    ///
+    /// The sketch takes a fixed ~4 bytes per ghost key allocated with `ghost_allocation`,
+    /// no matter how many distinct keys go through the cache, at the cost of occasional
+    /// false positives.
+    ///
+    /// Defaults to: `false`.
+    pub fn frequency_sketch(&mut self, enabled: bool) -> &mut Self {
+        self.frequency_sketch = Some(enabled);
+        self
+    }
+
+    /// Enables a doorkeeper, a small bloom filter placed in front of the cache that rejects
+    /// the first insertion of a key. Only keys inserted again before the filter is periodically
+    /// reset are admitted (ghost keys are admitted right away), so one-hit wonders in scan heavy
+    /// workloads never take space in the cache.
+    ///
+    /// The filter takes ~1 byte per item of `estimated_items_capacity`.
+    ///
+    /// Defaults to: `false`.
+    pub fn doorkeeper(&mut self, enabled: bool) -> &mut Self {
+        self.doorkeeper = Some(enabled);
+        self
+    }
+
+    /// Hashes the keys of each shard with an independent random seed, in addition to the hash
+    /// used to pick the shard. A set of keys crafted to collide (hash flooding) can then only
+    /// degrade a single shard, at the cost of hashing each key twice.
+    /// Only the sync caches are affected, as the unsync ones have a single shard.
+    ///
+    /// Defaults to: `false`.
+    pub fn shard_hash_seeds(&mut self, enabled: bool) -> &mut Self {
+        self.shard_hash_seeds = Some(enabled);
+        self
+    }
+
+    /// Routes the entries of two keys caches to shards by their key alone, ignoring the qey,
+    /// so all the entries sharing a key (e.g. a tenant id) are co-located in the same shard.
+    /// Operations on related entries then touch few shards and can be monitored with `shard_stats`,
+    /// at the cost of hashing each key twice and of unbalanced shards if a few keys dominate.
+    /// Single key caches are unaffected, as their qey is always `()`.
+    ///
+    /// Defaults to: `false`.
+    pub fn key_affinity(&mut self, enabled: bool) -> &mut Self {
+        self.key_affinity = Some(enabled);
+        self
+    }
+
+    /// Counts the hits and misses of lookups in thread local buffers, flushed to the cache counters
+    /// every 256 lookups of a thread on a cache and when the thread exits. This removes the atomic
+    /// increment of a shared counter from every lookup, which is contended when many threads read
+    /// the same shards, at the cost of approximate and delayed stats: up to 255 lookups per thread
+    /// may be missing from the hits and misses counters at any given time. The buffered lookups
+    /// are counted for the whole cache only, so they're missing from the shard stats, and the
+    /// lookups buffered before a `reset_stats` are flushed after it, so the stats aren't exact
+    /// after a reset.
+    ///
+    /// Defaults to: `false`.
+    pub fn buffered_stats(&mut self, enabled: bool) -> &mut Self {
+        self.buffered_stats = Some(enabled);
+        self
+    }
+
+    /// Sets the number of ghost keys tracked by the cache, overriding `ghost_allocation`.
+    ///
+    /// Entries are inserted in the cold section, where they're under test: if they're accessed
+    /// again they get promoted to the hot section, otherwise their key becomes a ghost once they're
+    /// evicted. How long a key remains a ghost is what effectively bounds the test period of the
+    /// entries, as a ghost hit readmits the key as hot.
+    /// A smaller ghost capacity increases scan resistance, as only keys re-accessed shortly after
+    /// eviction are deemed hot, while a larger one favors workloads with large reuse distances.
+    /// The pace at which hot entries are demoted to the cold section is controlled by `hot_allocation`.
+    ///
+    /// Defaults to: `ghost_allocation * estimated_items_capacity`.
+    pub fn ghost_capacity(&mut self, ghost_capacity: usize) -> &mut Self {
+        self.ghost_capacity = Some(ghost_capacity);
+        self
+    }
+
+    /// Records the cache operations with `recorder`, see the [trace](crate::trace) module.
+    #[cfg(feature = "trace")]
+    pub fn trace_recorder(&mut self, recorder: crate::trace::TraceRecorder) -> &mut Self {
+        self.trace_recorder = Some(std::sync::Arc::new(recorder));
+        self
+    }
+
     /// Builds an `Option` struct which can be used in `Cache::with_options` and `KQCache::with_options` constructors.
     pub fn build(&self) -> Result<Options, Error> {
         let shards = self
@@ -116,18 +329,46 @@ impl OptionsBuilder {
             .unwrap_or_else(|| std::thread::available_parallelism().map_or(4, |n| n.get() * 4));
         let hot_allocation = self.hot_allocation.unwrap_or(DEFAULT_HOT_ALLOCATION);
         let ghost_allocation = self.ghost_allocation.unwrap_or(DEFAULT_GHOST_ALLOCATION);
+        let frequency_sketch = self.frequency_sketch.unwrap_or_default();
+        if frequency_sketch && self.adaptive_hot_allocation.is_some() {
+            return Err(Error(
+                "adaptive_hot_allocation can't be combined with frequency_sketch",
+            ));
+        }
         let weight_capacity = self
             .weight_capacity
             .ok_or(Error("weight_capacity is not set"))?;
         let estimated_items_capacity = self
             .estimated_items_capacity
             .ok_or(Error("estimated_items_capacity is not set"))?;
+        if self.max_items == Some(0) {
+            return Err(Error("max_items must be greater than 0"));
+        }
+        if self.idle_timeout == Some(Duration::ZERO) {
+            return Err(Error("idle_timeout must be greater than 0"));
+        }
+        if self.min_residency == Some(Duration::ZERO) {
+            return Err(Error("min_residency must be greater than 0"));
+        }
         Ok(Options {
             shards,
             hot_allocation,
+            adaptive_hot_allocation: self.adaptive_hot_allocation,
             ghost_allocation,
+            ghost_capacity: self.ghost_capacity,
+            doorkeeper: self.doorkeeper.unwrap_or_default(),
+            frequency_sketch,
+            shard_hash_seeds: self.shard_hash_seeds.unwrap_or_default(),
+            key_affinity: self.key_affinity.unwrap_or_default(),
+            buffered_stats: self.buffered_stats.unwrap_or_default(),
             estimated_items_capacity,
             weight_capacity,
+            max_items: self.max_items,
+            max_item_weight: self.max_item_weight,
+            idle_timeout: self.idle_timeout,
+            min_residency: self.min_residency,
+            #[cfg(feature = "trace")]
+            trace_recorder: self.trace_recorder.clone(),
         })
     }
 }
@@ -0,0 +1,112 @@
+//! Export of the cache metrics as [OpenTelemetry](https://crates.io/crates/opentelemetry) instruments.
+//!
+//! The `opentelemetry` feature requires Rust 1.75 or newer, as does `opentelemetry` 0.31,
+//! while the rest of the crate keeps supporting the older compilers of its `rust-version`.
+//!
+//! # Example
+//!
+//! The callback only holds a weak reference to the cache, so reporting stops once the cache
+//! is dropped. Capturing a clone of the cache handle instead would keep the cache alive
+//! for as long as the meter provider keeps the callback.
+//!
+//! ```rust
+//! use std::sync::Arc;
+//!
+//! use opentelemetry::KeyValue;
+//! use quick_cache::{otel::register_metrics, sync::Cache};
+//!
+//! let cache = Arc::new(Cache::<u64, u64>::new(100));
+//! let meter = opentelemetry::global::meter("my_app");
+//! let weak = Arc::downgrade(&cache);
+//! let _metrics = register_metrics(
+//!     &meter,
+//!     &[KeyValue::new("cache", "users")],
+//!     move || Some(weak.upgrade()?.stats()),
+//! );
+//! ```
+
+use std::sync::Arc;
+
+use opentelemetry::{
+    metrics::{AsyncInstrument, Meter, ObservableCounter, ObservableGauge},
+    KeyValue,
+};
+
+use crate::Stats;
+
+/// Instruments registered by [register_metrics].
+///
+/// Depending on the meter provider, dropping the instruments may not unregister their callbacks.
+pub struct CacheMetrics {
+    pub hits: ObservableCounter<u64>,
+    pub misses: ObservableCounter<u64>,
+    pub evictions: ObservableCounter<u64>,
+    pub rejections: ObservableCounter<u64>,
+    pub entries: ObservableGauge<u64>,
+    pub weight: ObservableGauge<u64>,
+}
+
+impl std::fmt::Debug for CacheMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CacheMetrics").finish_non_exhaustive()
+    }
+}
+
+/// Registers observable instruments in `meter` reporting the cache [Stats], returned by `stats`,
+/// with the given `attributes` (e.g. a cache name).
+///
+/// The instruments are: `cache.hits`, `cache.misses`, `cache.evictions` and `cache.rejections` counters,
+/// and `cache.entries` and `cache.weight` gauges.
+/// `stats` should return `None` once the cache is gone, in which case nothing is reported.
+///
+/// Note that the counters are expected to be monotonic, which isn't the case if
+/// `reset_stats` is called on the cache.
+pub fn register_metrics(
+    meter: &Meter,
+    attributes: &[KeyValue],
+    stats: impl Fn() -> Option<Stats> + Send + Sync + 'static,
+) -> CacheMetrics {
+    let stats = Arc::new(stats);
+    let attributes: Arc<[KeyValue]> = attributes.into();
+    let callback = |metric: fn(&Stats) -> u64| {
+        let stats = stats.clone();
+        let attributes = attributes.clone();
+        move |observer: &dyn AsyncInstrument<u64>| {
+            if let Some(stats) = stats() {
+                observer.observe(metric(&stats), &attributes);
+            }
+        }
+    };
+    CacheMetrics {
+        hits: meter
+            .u64_observable_counter("cache.hits")
+            .with_description("Number of lookups that found the key")
+            .with_callback(callback(|s| s.hits))
+            .build(),
+        misses: meter
+            .u64_observable_counter("cache.misses")
+            .with_description("Number of lookups that didn't find the key")
+            .with_callback(callback(|s| s.misses))
+            .build(),
+        evictions: meter
+            .u64_observable_counter("cache.evictions")
+            .with_description("Number of entries evicted to make room for others")
+            .with_callback(callback(|s| s.evictions))
+            .build(),
+        rejections: meter
+            .u64_observable_counter("cache.rejections")
+            .with_description("Number of insertions rejected, for any reason")
+            .with_callback(callback(|s| s.rejections.total()))
+            .build(),
+        entries: meter
+            .u64_observable_gauge("cache.entries")
+            .with_description("Number of resident entries")
+            .with_callback(callback(|s| s.len as u64))
+            .build(),
+        weight: meter
+            .u64_observable_gauge("cache.weight")
+            .with_description("Total weight of the resident entries")
+            .with_callback(callback(|s| s.weight))
+            .build(),
+    }
+}
@@ -12,8 +12,9 @@ use std::{
 
 use crate::{
     linked_slab::Token,
-    rw_lock::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+    rw_lock::{RwLock, RwLockReadGuard},
     shard::KQCacheShard,
+    shard_lock::{ShardLock, ShardWriteGuard},
     Weighter,
 };
 
@@ -57,7 +58,7 @@ enum LoadingState<Val> {
 }
 
 pub struct PlaceholderGuard<'a, Key, Qey, Val, We, B> {
-    shard: &'a RwLock<KQCacheShard<Key, Qey, Val, We, B>>,
+    shard: &'a ShardLock<Key, Qey, Val, We, B>,
     shared: SharedPlaceholder<Val>,
     inserted: bool,
 }
@@ -112,7 +113,7 @@ pub enum GuardResult<'a, Key, Qey, Val, We, B> {
 
 impl<'a, Key, Qey, Val, We, B> PlaceholderGuard<'a, Key, Qey, Val, We, B> {
     pub fn start_loading(
-        shard: &'a RwLock<KQCacheShard<Key, Qey, Val, We, B>>,
+        shard: &'a ShardLock<Key, Qey, Val, We, B>,
         shared: SharedPlaceholder<Val>,
     ) -> Self {
         debug_assert!(matches!(
@@ -132,10 +133,10 @@ impl<'a, Key, Qey, Val, We, B> PlaceholderGuard<'a, Key, Qey, Val, We, B> {
         // We take shard lock here even if unused, as manipulating the waiters list
         // requires holding it to avoid races.
         _shard_lock: Result<
-            RwLockWriteGuard<'a, KQCacheShard<Key, Qey, Val, We, B>>,
+            ShardWriteGuard<'a, Key, Qey, Val, We, B>,
             RwLockReadGuard<'a, KQCacheShard<Key, Qey, Val, We, B>>,
         >,
-        shard: &'a RwLock<KQCacheShard<Key, Qey, Val, We, B>>,
+        shard: &'a ShardLock<Key, Qey, Val, We, B>,
         shared: &SharedPlaceholder<Val>,
         notified: bool,
         waiter_fn: impl FnOnce() -> Waiter,
@@ -177,7 +178,7 @@ impl<
     > PlaceholderGuard<'a, Key, Qey, Val, We, B>
 {
     pub fn join(
-        shard: &'a RwLock<KQCacheShard<Key, Qey, Val, We, B>>,
+        shard: &'a ShardLock<Key, Qey, Val, We, B>,
         hash: u64,
         key: Key,
         qey: Qey,
@@ -301,13 +302,13 @@ impl<'a, Key, Qey, Val, We, B> std::fmt::Debug for PlaceholderGuard<'a, Key, Qey
 /// Future that results in an Ok(Value) or Err(Guard)
 pub enum JoinFuture<'a, 'b, Key, Qey, Val, We, B> {
     Created {
-        shard: &'a RwLock<KQCacheShard<Key, Qey, Val, We, B>>,
+        shard: &'a ShardLock<Key, Qey, Val, We, B>,
         hash: u64,
         key: &'b Key,
         qey: &'b Qey,
     },
     Pending {
-        shard: &'a RwLock<KQCacheShard<Key, Qey, Val, We, B>>,
+        shard: &'a ShardLock<Key, Qey, Val, We, B>,
         shared: SharedPlaceholder<Val>,
         waiter: Option<SharedTaskWaiter>,
     },
@@ -316,7 +317,7 @@ pub enum JoinFuture<'a, 'b, Key, Qey, Val, We, B> {
 
 impl<'a, 'b, Key, Qey, Val, We, B> JoinFuture<'a, 'b, Key, Qey, Val, We, B> {
     pub fn new(
-        shard: &'a RwLock<KQCacheShard<Key, Qey, Val, We, B>>,
+        shard: &'a ShardLock<Key, Qey, Val, We, B>,
         hash: u64,
         key: &'b Key,
         qey: &'b Qey,
@@ -331,7 +332,14 @@ impl<'a, 'b, Key, Qey, Val, We, B> JoinFuture<'a, 'b, Key, Qey, Val, We, B> {
 
     #[cold]
     fn drop_pending_waiter(&mut self) {
-        let Self::Pending { shard, shared, waiter: Some(waiter) } = self else { unreachable!() };
+        let Self::Pending {
+            shard,
+            shared,
+            waiter: Some(waiter),
+        } = self
+        else {
+            unreachable!()
+        };
         let mut state = shared.state.write();
         let notified = waiter.read().notified; // Drop waiter guard to avoid a deadlock with start_loading
         if notified {
@@ -429,7 +437,14 @@ impl<
             JoinFuture::Done => panic!("Polled after ready"),
         };
 
-        let Self::Pending { shard, shared, waiter } = &mut *self else { unreachable!() };
+        let Self::Pending {
+            shard,
+            shared,
+            waiter,
+        } = &mut *self
+        else {
+            unreachable!()
+        };
         // If we reach here and waiter is some, it means we got a notification.
         match PlaceholderGuard::join_internal(shard_guard, shard, shared, waiter.is_some(), || {
             let task_waiter = waiter
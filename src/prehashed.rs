@@ -0,0 +1,112 @@
+use std::hash::{BuildHasher, Hash, Hasher};
+
+/// A key along with its precomputed hash.
+///
+/// Used with [PreHashedBuildHasher], the cache uses the precomputed hash as is instead of hashing
+/// the key again. Keys are still compared for equality, so hash collisions are handled as usual,
+/// but the hashes should be well distributed.
+///
+/// # Example
+///
+/// ```rust
+/// use quick_cache::{sync::Cache, PreHashed, PreHashedBuildHasher, OptionsBuilder, UnitWeighter};
+///
+/// let cache = Cache::with_options(
+///     OptionsBuilder::new()
+///         .estimated_items_capacity(100)
+///         .weight_capacity(100)
+///         .build()
+///         .unwrap(),
+///     UnitWeighter,
+///     PreHashedBuildHasher,
+/// );
+/// let digest = 0x9e37_79b9_7f4a_7c15;
+/// cache.insert(PreHashed::new(digest, "blob"), vec![1, 2, 3]);
+/// assert_eq!(cache.get(&PreHashed::new(digest, "blob")), Some(vec![1, 2, 3]));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct PreHashed<Key> {
+    hash: u64,
+    key: Key,
+}
+
+impl<Key> PreHashed<Key> {
+    /// Pairs `key` with its precomputed `hash`.
+    /// The same key must always be paired with the same hash.
+    pub fn new(hash: u64, key: Key) -> Self {
+        Self { hash, key }
+    }
+
+    /// Returns the precomputed hash.
+    pub fn hash_value(&self) -> u64 {
+        self.hash
+    }
+
+    /// Returns the key.
+    pub fn key(&self) -> &Key {
+        &self.key
+    }
+
+    /// Returns the key, dropping the hash.
+    pub fn into_key(self) -> Key {
+        self.key
+    }
+}
+
+impl<Key: PartialEq> PartialEq for PreHashed<Key> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash && self.key == other.key
+    }
+}
+
+impl<Key: Eq> Eq for PreHashed<Key> {}
+
+impl<Key> Hash for PreHashed<Key> {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash);
+    }
+}
+
+/// Hash builder passing the precomputed hash of [PreHashed] keys through.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PreHashedBuildHasher;
+
+impl BuildHasher for PreHashedBuildHasher {
+    type Hasher = PreHashedHasher;
+
+    #[inline]
+    fn build_hasher(&self) -> PreHashedHasher {
+        PreHashedHasher(0)
+    }
+}
+
+/// Hasher of [PreHashedBuildHasher].
+///
+/// A single `u64` is returned as is. Further writes, like the per shard seeds
+/// (see [OptionsBuilder::shard_hash_seeds](crate::OptionsBuilder::shard_hash_seeds)),
+/// are cheaply mixed in.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PreHashedHasher(u64);
+
+impl Hasher for PreHashedHasher {
+    #[inline]
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    #[inline]
+    fn write_u64(&mut self, n: u64) {
+        self.0 = self.0.rotate_left(26) ^ n;
+    }
+
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.write_u64(u64::from_le_bytes(word));
+        }
+    }
+}
@@ -1,4 +1,7 @@
-use std::ops::{Deref, DerefMut};
+use std::{
+    ops::{Deref, DerefMut},
+    time::Duration,
+};
 
 #[cfg(feature = "parking_lot")]
 type InnerRwLock<T> = parking_lot::RwLock<T>;
@@ -116,6 +119,104 @@ impl<T: ?Sized> RwLock<T> {
             self.0.write().unwrap()
         })
     }
+
+    /// Attempts to acquire this `RwLock` with shared read access.
+    ///
+    /// If the access could not be granted at this time, then `None` is returned.
+    /// Otherwise, an RAII guard is returned which will release the shared access
+    /// when it is dropped.
+    ///
+    /// This function does not block.
+    ///
+    /// # Panics
+    ///
+    /// This function might panic if the `RwLock` is poisoned.
+    #[inline]
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        #[cfg(feature = "parking_lot")]
+        let guard = self.0.try_read();
+        #[cfg(not(feature = "parking_lot"))]
+        let guard = match self.0.try_read() {
+            Ok(guard) => Some(guard),
+            Err(std::sync::TryLockError::WouldBlock) => None,
+            Err(e @ std::sync::TryLockError::Poisoned(_)) => panic!("{}", e),
+        };
+        guard.map(RwLockReadGuard)
+    }
+
+    /// Attempts to lock this `RwLock` with exclusive write access.
+    ///
+    /// If the lock could not be acquired at this time, then `None` is returned.
+    /// Otherwise, an RAII guard is returned which will release the lock when
+    /// it is dropped.
+    ///
+    /// This function does not block.
+    ///
+    /// # Panics
+    ///
+    /// This function might panic if the `RwLock` is poisoned.
+    #[inline]
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        #[cfg(feature = "parking_lot")]
+        let guard = self.0.try_write();
+        #[cfg(not(feature = "parking_lot"))]
+        let guard = match self.0.try_write() {
+            Ok(guard) => Some(guard),
+            Err(std::sync::TryLockError::WouldBlock) => None,
+            Err(e @ std::sync::TryLockError::Poisoned(_)) => panic!("{}", e),
+        };
+        guard.map(RwLockWriteGuard)
+    }
+
+    /// Attempts to acquire this `RwLock` with shared read access until a timeout is reached.
+    ///
+    /// If the access could not be granted before the timeout expires, then `None` is returned.
+    /// Without the `parking_lot` feature the lock is polled until the timeout expires.
+    ///
+    /// # Panics
+    ///
+    /// This function might panic if the `RwLock` is poisoned.
+    #[inline]
+    pub fn try_read_for(&self, timeout: Duration) -> Option<RwLockReadGuard<'_, T>> {
+        #[cfg(feature = "parking_lot")]
+        {
+            self.0.try_read_for(timeout).map(RwLockReadGuard)
+        }
+        #[cfg(not(feature = "parking_lot"))]
+        poll_until(timeout, || self.try_read())
+    }
+
+    /// Attempts to lock this `RwLock` with exclusive write access until a timeout is reached.
+    ///
+    /// If the lock could not be acquired before the timeout expires, then `None` is returned.
+    /// Without the `parking_lot` feature the lock is polled until the timeout expires.
+    ///
+    /// # Panics
+    ///
+    /// This function might panic if the `RwLock` is poisoned.
+    #[inline]
+    pub fn try_write_for(&self, timeout: Duration) -> Option<RwLockWriteGuard<'_, T>> {
+        #[cfg(feature = "parking_lot")]
+        {
+            self.0.try_write_for(timeout).map(RwLockWriteGuard)
+        }
+        #[cfg(not(feature = "parking_lot"))]
+        poll_until(timeout, || self.try_write())
+    }
+}
+
+#[cfg(not(feature = "parking_lot"))]
+fn poll_until<G>(timeout: Duration, mut try_lock: impl FnMut() -> Option<G>) -> Option<G> {
+    let start = std::time::Instant::now();
+    loop {
+        if let Some(guard) = try_lock() {
+            return Some(guard);
+        }
+        if start.elapsed() >= timeout {
+            return None;
+        }
+        std::thread::yield_now();
+    }
 }
 
 impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
@@ -0,0 +1,120 @@
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+};
+
+use crate::{sync::Cache, DefaultHashBuilder, GuardResult, Options, Stats, UnitWeighter, Weighter};
+
+/// A concurrent set of keys, with the same eviction policy as a [Cache].
+///
+/// Useful for membership and deduplication.
+/// No value is stored, so the entries are as small as the keys.
+///
+/// # Example
+///
+/// ```rust
+/// use quick_cache::CacheSet;
+///
+/// let seen = CacheSet::new(1000);
+/// assert!(seen.insert("message-1"));
+/// assert!(!seen.insert("message-1"));
+/// assert!(seen.contains("message-1"));
+/// assert!(seen.remove("message-1"));
+/// assert!(!seen.contains("message-1"));
+/// ```
+pub struct CacheSet<Key, We = UnitWeighter, B = DefaultHashBuilder>(Cache<Key, (), We, B>);
+
+impl<Key: Eq + Hash> CacheSet<Key> {
+    /// Creates a new set which holds up to `items_capacity` keys (approximately).
+    pub fn new(items_capacity: usize) -> Self {
+        Self(Cache::new(items_capacity))
+    }
+}
+
+impl<Key: Eq + Hash, We: Weighter<Key, (), ()> + Clone, B: BuildHasher + Clone>
+    CacheSet<Key, We, B>
+{
+    /// Constructs a set based on [OptionsBuilder](crate::OptionsBuilder).
+    /// The `weighter` is given `&()` as the value.
+    pub fn with_options(options: Options, weighter: We, hash_builder: B) -> Self {
+        Self(Cache::with_options(options, weighter, hash_builder))
+    }
+
+    /// Returns whether the set is empty
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the number of keys in the set
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns the total weight of the keys in the set
+    pub fn weight(&self) -> u64 {
+        self.0.weight()
+    }
+
+    /// Returns the maximum weight of the keys in the set
+    pub fn capacity(&self) -> u64 {
+        self.0.capacity()
+    }
+
+    /// Returns a snapshot of the set counters, where membership checks count as lookups.
+    pub fn stats(&self) -> Stats {
+        self.0.stats()
+    }
+
+    /// Returns whether `key` is in the set, counting as an access to the key.
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.0.get(key).is_some()
+    }
+
+    /// Returns whether `key` is in the set, without altering the key "hotness".
+    pub fn peek<Q>(&self, key: &Q) -> bool
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.0.contains_key(key)
+    }
+
+    /// Inserts `key` in the set, counting as an access to the key.
+    /// Returns whether the key wasn't already in the set, in which case it may still be rejected
+    /// (e.g. by the doorkeeper) or evicted right away.
+    ///
+    /// Concurrent insertions of the same key are coordinated, so only one of them returns `true`.
+    pub fn insert(&self, key: Key) -> bool
+    where
+        Key: Clone,
+    {
+        match self.0.get_value_or_guard(&key, None) {
+            GuardResult::Value(()) => false,
+            GuardResult::Guard(guard) => {
+                guard.insert(());
+                true
+            }
+            GuardResult::Timeout => unreachable!(),
+        }
+    }
+
+    /// Removes `key` from the set.
+    /// Returns whether the key was in the set.
+    pub fn remove<Q>(&self, key: &Q) -> bool
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.0.remove(key)
+    }
+}
+
+impl<Key, We, B> std::fmt::Debug for CacheSet<Key, We, B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CacheSet").finish_non_exhaustive()
+    }
+}
@@ -2,8 +2,9 @@ use std::{
     borrow::Borrow,
     hash::{BuildHasher, Hash, Hasher},
     mem,
+    ops::Range,
     sync::{
-        atomic::{self, AtomicBool, AtomicU64},
+        atomic::{self, AtomicBool, AtomicU16, AtomicU64},
         Arc,
     },
 };
@@ -11,11 +12,111 @@ use std::{
 use hashbrown::raw::RawTable;
 
 use crate::{
+    doorkeeper::Doorkeeper,
+    ghost::{Ghosts, History},
     linked_slab::{LinkedSlab, Token},
     placeholder::{new_shared_placeholder, SharedPlaceholder},
+    sketch::FrequencySketch,
+    stats::{record_buffered, BufferedCounters},
+    timestamps::Timestamps,
+    Compute, MemoryUsage, Options, Rejected, Rejections, Stats, TryReserveError,
 };
 
-/// Superset of Weighter (weights 1u32..=u32::MAX) that returns the same weight as u64.
+/// Hashes `key` and `qey` with `hash_builder`, preceded by `seed` if set.
+#[inline]
+pub fn hash_key<B, Q, W>(hash_builder: &B, seed: Option<u64>, key: &Q, qey: &W) -> u64
+where
+    B: BuildHasher,
+    Q: Hash + ?Sized,
+    W: Hash + ?Sized,
+{
+    let mut hasher = hash_builder.build_hasher();
+    if let Some(seed) = seed {
+        hasher.write_u64(seed);
+    }
+    key.hash(&mut hasher);
+    qey.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Max number of entries, in eviction order, considered to make room for a new key while
+/// sparing the entries within their minimum residency. This bounds the work done by insertions
+/// when most of the cache was inserted recently, e.g. during a bulk load.
+pub const RESIDENCY_SCAN_LIMIT: usize = 64;
+
+#[cfg(test)]
+thread_local! {
+    /// Number of entries visited by the current thread to spare the ones within their
+    /// minimum residency, to check that the work done is bounded.
+    pub static RESIDENCY_VISITS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// Counts an entry visited to spare the ones within their minimum residency, see [RESIDENCY_VISITS].
+#[inline]
+fn count_residency_visit() {
+    #[cfg(test)]
+    RESIDENCY_VISITS.with(|visits| visits.set(visits.get() + 1));
+}
+
+/// Returns a random number, e.g. for seeds or sampling.
+#[inline]
+pub fn random_u64() -> u64 {
+    // std RandomState is randomly keyed on creation, so hashing anything yields a random number
+    std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish()
+}
+
+/// Returns a uniformly distributed random number in `0..bound`, which must not be 0.
+pub fn random_below(bound: u64) -> u64 {
+    // Rejecting the numbers below 2^64 % bound leaves a multiple of bound values, avoiding
+    // the modulo bias
+    let threshold = bound.wrapping_neg() % bound;
+    loop {
+        let r = random_u64();
+        if r >= threshold {
+            return r % bound;
+        }
+    }
+}
+
+/// Records an operation in the shard trace recorder, if any.
+/// The weight expression is only evaluated for sampled keys.
+#[cfg(feature = "trace")]
+macro_rules! trace {
+    ($shard:expr, $op:ident, $hash:expr, $weight:expr) => {
+        if let Some(recorder) = &$shard.trace_recorder {
+            if recorder.is_sampled($hash) {
+                recorder.record(crate::trace::TraceOp::$op, $hash, $weight);
+            }
+        }
+    };
+}
+
+#[cfg(not(feature = "trace"))]
+macro_rules! trace {
+    ($shard:expr, $op:ident, $hash:expr, $weight:expr) => {};
+}
+
+/// Evaluates `$e`, recording its latency in the shard `$histogram` latency histogram.
+#[cfg(feature = "latency")]
+macro_rules! timed {
+    ($shard:ident.$histogram:ident, $e:expr) => {{
+        let start = std::time::Instant::now();
+        let result = $e;
+        $shard.latency.$histogram.record(start.elapsed());
+        result
+    }};
+}
+
+#[cfg(not(feature = "latency"))]
+macro_rules! timed {
+    ($shard:ident.$histogram:ident, $e:expr) => {
+        $e
+    };
+}
+
+/// Superset of Weighter (weights 0u32..=u32::MAX) that returns the same weight as u64.
 /// Since each shard can only hold up to u32::MAX - 1 items its internal weight cannot overflow.
 pub trait InternalWeighter<Key, Qey, Val> {
     fn weight(&self, key: &Key, qey: &Qey, val: &Val) -> u64;
@@ -27,7 +128,7 @@ where
 {
     #[inline]
     fn weight(&self, key: &Key, qey: &Qey, val: &Val) -> u64 {
-        crate::Weighter::weight(self, key, qey, val).get() as u64
+        crate::Weighter::weight(self, key, qey, val) as u64
     }
 }
 
@@ -45,6 +146,14 @@ pub struct Resident<Key, Qey, Val> {
     value: Val,
     state: ResidentState,
     referenced: AtomicBool,
+    /// Changes whenever the entry is modified, unique within the shard.
+    version: u64,
+    /// Hash of the key and qey, so evictions don't need to re-hash them.
+    hash: u64,
+    /// Weight accounted for the entry, the weighter is only re-run by `reweigh`.
+    weight: u32,
+    /// Flags set by the user, kept as is by the cache.
+    flags: AtomicU16,
 }
 
 #[derive(Debug)]
@@ -55,91 +164,368 @@ pub struct Placeholder<Key, Qey, Val> {
     shared: SharedPlaceholder<Val>,
 }
 
+impl<Key, Qey, Val> Resident<Key, Qey, Val> {
+    pub fn keys(&self) -> (&Key, &Qey) {
+        (&self.key, &self.qey)
+    }
+
+    pub fn into_parts(self) -> (Key, Qey, Val) {
+        (self.key, self.qey, self.value)
+    }
+}
+
+impl<Key: Clone, Qey: Clone, Val: Clone> Clone for Resident<Key, Qey, Val> {
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key.clone(),
+            qey: self.qey.clone(),
+            value: self.value.clone(),
+            state: self.state,
+            referenced: AtomicBool::new(self.referenced.load(atomic::Ordering::Relaxed)),
+            version: self.version,
+            hash: self.hash,
+            weight: self.weight,
+            flags: AtomicU16::new(self.flags.load(atomic::Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Clones share the loading state of the original placeholder.
+impl<Key: Clone, Qey: Clone, Val> Clone for Placeholder<Key, Qey, Val> {
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key.clone(),
+            qey: self.qey.clone(),
+            hot: self.hot,
+            shared: self.shared.clone(),
+        }
+    }
+}
+
 pub enum Entry<Key, Qey, Val> {
     Resident(Resident<Key, Qey, Val>),
     Placeholder(Placeholder<Key, Qey, Val>),
-    Ghost(u64),
 }
 
-impl<Key, Qey, Val> Entry<Key, Qey, Val> {
-    fn dbg(&self) -> &'static str {
+impl<Key: Clone, Qey: Clone, Val: Clone> Clone for Entry<Key, Qey, Val> {
+    fn clone(&self) -> Self {
         match self {
-            Entry::Resident(_) => "Resident",
-            Entry::Placeholder(_) => "Placeholder",
-            Entry::Ghost(_) => "Ghost",
+            Entry::Resident(resident) => Entry::Resident(resident.clone()),
+            Entry::Placeholder(placeholder) => Entry::Placeholder(placeholder.clone()),
+        }
+    }
+}
+
+/// Entries displaced by an operation, returned so they can be dropped outside the locks.
+/// The first two are stored inline, so an insertion replacing and/or evicting an entry
+/// doesn't allocate.
+pub struct Displaced<T> {
+    inline: [Option<T>; 2],
+    spilled: Vec<T>,
+}
+
+impl<T> Default for Displaced<T> {
+    fn default() -> Self {
+        Self {
+            inline: [None, None],
+            spilled: Vec::new(),
         }
     }
 }
 
+impl<T> Displaced<T> {
+    pub fn push(&mut self, item: T) {
+        match self.inline.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => *slot = Some(item),
+            None => self.spilled.push(item),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inline.iter().filter(|slot| slot.is_some()).count() + self.spilled.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inline[0].is_none()
+    }
+}
+
+impl<T> Extend<T> for Displaced<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for item in iter {
+            self.push(item);
+        }
+    }
+}
+
+impl<T> FromIterator<T> for Displaced<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut displaced = Self::default();
+        displaced.extend(iter);
+        displaced
+    }
+}
+
+impl<T> IntoIterator for Displaced<T> {
+    type Item = T;
+    type IntoIter = std::iter::Chain<
+        std::iter::Flatten<std::array::IntoIter<Option<T>, 2>>,
+        std::vec::IntoIter<T>,
+    >;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.inline.into_iter().flatten().chain(self.spilled)
+    }
+}
+
 /// A qey aware cache using a modified CLOCK-PRO eviction policy.
 /// The implementation allows some parallelism as gets don't require exclusive access.
 /// Any evicted items are returned so they can be dropped by the caller, outside the locks.
 pub struct KQCacheShard<Key, Qey, Val, We, B> {
     hash_builder: B,
+    /// Seed hashed before the keys, if the shard uses its own hash function.
+    hash_seed: Option<u64>,
     /// Map to an entry in the `entries` slab.
-    /// Note that the actual key/qey/value/hash are not stored in the map but in the slab.
-    map: RawTable<Token>,
+    /// The hash is stored alongside the token, so probes and resizes only access the slab
+    /// for matching hashes. Note that the actual key/qey/value are not stored in the map but in the slab.
+    map: RawTable<(u64, Token)>,
     /// Slab holding entries
     entries: LinkedSlab<Entry<Key, Qey, Val>>,
     /// Head of cold list, containing ColdInTest and ColdDemoted entries.
     cold_head: Option<Token>,
     /// Head of hot list, containing Hot entries.
     hot_head: Option<Token>,
-    /// Head of ghost list, containing non-resident/Hash entries.
-    ghost_head: Option<Token>,
+    /// Non-resident keys, recently evicted during their test period.
+    history: History,
+    /// Optional filter that rejects keys on their first insertion.
+    doorkeeper: Option<Doorkeeper>,
     weight_target_hot: u64,
+    /// Bounds of `weight_target_hot`, if it's adapted according to the ghost hits and expirations.
+    hot_target_bounds: Option<(u64, u64)>,
+    /// Hot allocation settings, kept to recompute the targets in `set_capacity`.
+    hot_allocation: f64,
+    adaptive_hot_allocation: Option<(f64, f64)>,
+    /// Ghost keys tracked per unit of weight capacity, see `set_capacity`.
+    ghosts_per_weight: f64,
     weight_capacity: u64,
+    /// Max number of resident entries, enforced along with `weight_capacity`.
+    max_items: usize,
+    /// Whether `max_items` follows `weight_capacity`, as it wasn't set explicitly.
+    max_items_from_weight: bool,
+    /// Max weight of a single entry, heavier entries are rejected.
+    max_item_weight: u64,
     weight_hot: u64,
     weight_cold: u64,
     num_hot: usize,
     num_cold: usize,
-    num_non_resident: usize,
-    capacity_non_resident: usize,
     hits: AtomicU64,
     misses: AtomicU64,
+    /// Counters of the lookups buffered by threads, if the stats are buffered.
+    /// They're shared by all the shards of a cache and aren't included in the shard counters.
+    buffered_counters: Option<Arc<BufferedCounters>>,
+    /// Number of resident entries evicted to make room for others.
+    evictions: u64,
+    /// Number of rejected insertions, by reason.
+    rejections: Rejections,
+    /// Version of the next entry modification.
+    next_version: u64,
+    /// Entries not accessed for this many milliseconds are removed by `prune_idle`, if set.
+    idle_timeout_ms: Option<u64>,
+    /// Entries inserted less than this many milliseconds ago aren't evicted to make room for
+    /// new keys, if set.
+    min_residency_ms: Option<u64>,
+    /// Access and insertion times of the entries, if one of the above is set.
+    timestamps: Timestamps,
+    /// Slot where the next `decay_referenced` pass starts.
+    decay_position: usize,
+    /// Whether lookups and insertions are served, see `set_enabled`.
+    enabled: bool,
+    /// Whether the entries are kept as they are, see `set_frozen`.
+    frozen: bool,
+    #[cfg(feature = "trace")]
+    trace_recorder: Option<Arc<crate::trace::TraceRecorder>>,
+    #[cfg(feature = "latency")]
+    latency: crate::latency::ShardLatency,
     weighter: We,
 }
 
+/// Deep copy of the shard, including its policy state and counters.
+/// Only meant for the unsync caches, which never hold placeholders when borrowed immutably.
+impl<Key: Clone, Qey: Clone, Val: Clone, We: Clone, B: Clone> Clone
+    for KQCacheShard<Key, Qey, Val, We, B>
+{
+    fn clone(&self) -> Self {
+        Self {
+            hash_builder: self.hash_builder.clone(),
+            hash_seed: self.hash_seed,
+            map: self.map.clone(),
+            entries: self.entries.clone(),
+            cold_head: self.cold_head,
+            hot_head: self.hot_head,
+            history: self.history.clone(),
+            doorkeeper: self.doorkeeper.clone(),
+            weight_target_hot: self.weight_target_hot,
+            hot_target_bounds: self.hot_target_bounds,
+            hot_allocation: self.hot_allocation,
+            adaptive_hot_allocation: self.adaptive_hot_allocation,
+            ghosts_per_weight: self.ghosts_per_weight,
+            weight_capacity: self.weight_capacity,
+            max_items: self.max_items,
+            max_items_from_weight: self.max_items_from_weight,
+            max_item_weight: self.max_item_weight,
+            weight_hot: self.weight_hot,
+            weight_cold: self.weight_cold,
+            num_hot: self.num_hot,
+            num_cold: self.num_cold,
+            hits: AtomicU64::new(self.hits.load(atomic::Ordering::Relaxed)),
+            misses: AtomicU64::new(self.misses.load(atomic::Ordering::Relaxed)),
+            buffered_counters: self
+                .buffered_counters
+                .as_deref()
+                .map(|counters| Arc::new(counters.clone())),
+            evictions: self.evictions,
+            rejections: self.rejections,
+            next_version: self.next_version,
+            idle_timeout_ms: self.idle_timeout_ms,
+            min_residency_ms: self.min_residency_ms,
+            timestamps: self.timestamps.clone(),
+            decay_position: self.decay_position,
+            enabled: self.enabled,
+            frozen: self.frozen,
+            #[cfg(feature = "trace")]
+            trace_recorder: self.trace_recorder.clone(),
+            #[cfg(feature = "latency")]
+            latency: self.latency.clone(),
+            weighter: self.weighter.clone(),
+        }
+    }
+}
+
 impl<Key, Qey, Val, We, B> KQCacheShard<Key, Qey, Val, We, B> {
+    pub fn len(&self) -> usize {
+        self.num_hot + self.num_cold
+    }
+
+    pub fn weight(&self) -> u64 {
+        self.weight_hot + self.weight_cold
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.weight_capacity
+    }
+
+    /// Hits counted by the shard itself, excluding the ones buffered by threads.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Misses counted by the shard itself, excluding the ones buffered by threads.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(atomic::Ordering::Relaxed)
+    }
+
+    pub fn buffered_counters(&self) -> Option<&BufferedCounters> {
+        self.buffered_counters.as_deref()
+    }
+
     pub fn remove_placeholder(&mut self, placeholder: &SharedPlaceholder<Val>) {
-        self.map.remove_entry(placeholder.hash, |&idx| {
+        let removed = self.map.erase_entry(placeholder.hash, |&(_, idx)| {
             if idx != placeholder.idx {
                 return false;
             }
             let (entry, _) = self.entries.get(idx).unwrap();
             matches!(entry, Entry::Placeholder(Placeholder { shared, .. }) if Arc::ptr_eq(shared, placeholder))
         });
+        if removed {
+            self.entries.remove(placeholder.idx);
+        }
     }
 }
 
 impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: InternalWeighter<Key, Qey, Val>, B: BuildHasher>
     KQCacheShard<Key, Qey, Val, We, B>
 {
+    /// Creates a shard with the policy settings from `options`, and capacities
+    /// `estimated_items_capacity`, `weight_capacity`, `max_items` and `capacity_non_resident` (ghost keys).
+    /// If `hash_seed` is set, it's hashed before the keys, see [hash_key].
+    /// Lookups are counted in `buffered_counters` if set, see [record_buffered].
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        hot_allocation: f64,
-        ghost_allocation: f64,
+        options: &Options,
         estimated_items_capacity: usize,
         weight_capacity: u64,
+        max_items: Option<usize>,
+        capacity_non_resident: usize,
         weighter: We,
         hash_builder: B,
+        hash_seed: Option<u64>,
+        buffered_counters: Option<Arc<BufferedCounters>>,
     ) -> Self {
-        let weight_target_hot = (weight_capacity as f64 * hot_allocation) as u64;
-        let capacity_non_resident = (estimated_items_capacity as f64 * ghost_allocation) as usize;
+        let hot_target_bounds = options.adaptive_hot_allocation.map(|(min, max)| {
+            (
+                (weight_capacity as f64 * min) as u64,
+                (weight_capacity as f64 * max) as u64,
+            )
+        });
+        let weight_target_hot = (weight_capacity as f64 * options.hot_allocation) as u64;
+        let weight_target_hot = hot_target_bounds.map_or(weight_target_hot, |(min, max)| {
+            weight_target_hot.clamp(min, max)
+        });
         Self {
             hash_builder,
+            hash_seed,
             map: RawTable::with_capacity(0),
             entries: LinkedSlab::with_capacity(0),
             weight_capacity,
+            // zero weight entries don't count against the weight capacity, so bound them by count
+            max_items: max_items
+                .unwrap_or_else(|| usize::try_from(weight_capacity).unwrap_or(usize::MAX)),
+            max_items_from_weight: max_items.is_none(),
+            max_item_weight: options.max_item_weight.unwrap_or(u64::MAX),
             hits: Default::default(),
             misses: Default::default(),
+            buffered_counters,
+            evictions: 0,
+            rejections: Default::default(),
+            next_version: 1,
+            idle_timeout_ms: options
+                .idle_timeout
+                .map(|timeout| timeout.as_millis().clamp(1, u64::MAX as u128) as u64),
+            min_residency_ms: options
+                .min_residency
+                .map(|min| min.as_millis().clamp(1, u64::MAX as u128) as u64),
+            timestamps: Timestamps::new(
+                options.idle_timeout.is_some(),
+                options.min_residency.is_some(),
+            ),
+            decay_position: 0,
+            enabled: true,
+            frozen: false,
+            #[cfg(feature = "trace")]
+            trace_recorder: options.trace_recorder.clone(),
+            #[cfg(feature = "latency")]
+            latency: Default::default(),
             cold_head: None,
             hot_head: None,
-            ghost_head: None,
-            capacity_non_resident,
+            history: if options.frequency_sketch {
+                History::Sketch(FrequencySketch::new(capacity_non_resident))
+            } else {
+                History::Ghosts(Ghosts::new(capacity_non_resident))
+            },
+            doorkeeper: options
+                .doorkeeper
+                .then(|| Doorkeeper::new(estimated_items_capacity)),
             weight_target_hot,
+            hot_target_bounds,
+            hot_allocation: options.hot_allocation,
+            adaptive_hot_allocation: options.adaptive_hot_allocation,
+            ghosts_per_weight: if weight_capacity == 0 {
+                0.0
+            } else {
+                capacity_non_resident as f64 / weight_capacity as f64
+            },
             num_hot: 0,
             num_cold: 0,
-            num_non_resident: 0,
             weight_hot: 0,
             weight_cold: 0,
             weighter,
@@ -150,10 +536,12 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: InternalWeighter<Key, Qey, Val>, B
     pub fn validate(&self) {
         let mut num_hot = 0;
         let mut num_cold = 0;
-        let mut num_non_resident = 0;
         let mut weight_hot = 0;
         let mut weight_cold = 0;
         for e in self.entries.iter_entries() {
+            if let Entry::Resident(r) = e {
+                assert_eq!(r.hash, self.hash(&r.key, &r.qey));
+            }
             match e {
                 Entry::Resident(r)
                     if matches!(
@@ -162,233 +550,1083 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: InternalWeighter<Key, Qey, Val>, B
                     ) =>
                 {
                     num_cold += 1;
-                    weight_cold += self.weighter.weight(&r.key, &r.qey, &r.value);
+                    weight_cold += r.weight as u64;
                 }
                 Entry::Resident(r) => {
                     num_hot += 1;
-                    weight_hot += self.weighter.weight(&r.key, &r.qey, &r.value);
-                }
-                Entry::Ghost(_) => {
-                    num_non_resident += 1;
+                    weight_hot += r.weight as u64;
                 }
                 Entry::Placeholder(_) => (),
             }
         }
         // eprintln!("-------------");
-        // dbg!(num_hot, num_cold, num_non_resident, weight_hot, weight_cold);
+        // dbg!(num_hot, num_cold, weight_hot, weight_cold);
         // dbg!(
         //     self.num_hot,
         //     self.num_cold,
-        //     self.num_non_resident,
+        //     self.history.len(),
         //     self.weight_hot,
         //     self.weight_cold,
         //     self.weight_target_hot,
-        //     self.capacity_non_resident
+        //     self.history.capacity()
         // );
         assert_eq!(num_hot, self.num_hot);
         assert_eq!(num_cold, self.num_cold);
-        assert_eq!(num_non_resident, self.num_non_resident);
         assert_eq!(weight_hot, self.weight_hot);
         assert_eq!(weight_cold, self.weight_cold);
+        assert!(num_hot + num_cold <= self.max_items);
         assert!(weight_hot <= self.weight_target_hot);
-        assert!(num_non_resident <= self.capacity_non_resident);
+        if let Some((min, max)) = self.hot_target_bounds {
+            assert!((min..=max).contains(&self.weight_target_hot));
+        }
+        assert!(self.history.len() <= self.history.capacity());
     }
 
-    /// Reserver additional space for `additional` entries.
+    /// Reserver additional space for `additional` entries in both the map and the slab.
     /// Note that this is counted in entries, and is not weighted.
     pub fn reserve(&mut self, additional: usize) {
-        // extra 50% for non-resident entries
-        let additional = additional.saturating_add(additional / 2);
-        self.map.reserve(additional, |&idx| {
-            let (entry, _) = self.entries.get(idx).unwrap();
-            match entry {
-                Entry::Resident(Resident { key, qey, .. })
-                | Entry::Placeholder(Placeholder { key, qey, .. }) => {
-                    Self::hash_static(&self.hash_builder, key, qey)
+        self.map.reserve(additional, |&(hash, _)| hash);
+        self.entries.reserve(additional);
+    }
+
+    /// Tries to reserve additional space for `additional` entries in both the map and the slab.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        self.map.try_reserve(additional, |&(hash, _)| hash)?;
+        self.entries.try_reserve(additional)?;
+        Ok(())
+    }
+
+    /// Compacts the entries slab, closing the gaps left by removed entries, and shrinks the map
+    /// to fit the remaining entries.
+    pub fn shrink_to_fit(&mut self) {
+        let Self {
+            map,
+            entries,
+            cold_head,
+            hot_head,
+            history,
+            timestamps,
+            ..
+        } = self;
+        entries.compact(|entry, old, new| {
+            // Placeholder guards reference their entry by token
+            let Entry::Resident(resident) = entry else {
+                return false;
+            };
+            let (_, idx) = map.get_mut(resident.hash, |&(_, idx)| idx == old).unwrap();
+            *idx = new;
+            for head in [&mut *cold_head, &mut *hot_head] {
+                if *head == Some(old) {
+                    *head = Some(new);
                 }
-                Entry::Ghost(non_resident_hash) => *non_resident_hash,
             }
-        })
+            timestamps.relocate(old, new);
+            true
+        });
+        timestamps.shrink_to(entries.num_slots());
+        map.shrink_to(0, |&(hash, _)| hash);
+        history.shrink_to_fit();
     }
 
-    pub fn weight(&self) -> u64 {
-        self.weight_hot + self.weight_cold
+    /// Clones all resident entries, keeping their state, in an arbitrary order.
+    pub fn clone_residents(&self) -> Vec<Resident<Key, Qey, Val>>
+    where
+        Key: Clone,
+        Qey: Clone,
+        Val: Clone,
+    {
+        self.entries
+            .iter_entries()
+            .filter_map(|entry| match entry {
+                Entry::Resident(resident) => Some(resident.clone()),
+                Entry::Placeholder(_) => None,
+            })
+            .collect()
     }
 
-    pub fn len(&self) -> usize {
-        self.num_hot + self.num_cold
+    /// Removes all resident entries in clock order, hot entries first, leaving the shard empty.
+    /// Ghost keys and the doorkeeper are reset as they only retain key hashes.
+    pub fn take_residents(&mut self) -> Vec<Resident<Key, Qey, Val>> {
+        let mut residents = Vec::with_capacity(self.len());
+        for head in [self.hot_head.take(), self.cold_head.take()] {
+            let mut next = head;
+            while let Some(idx) = next {
+                let (entry, following) = self.entries.remove(idx).unwrap();
+                if let Entry::Resident(resident) = entry {
+                    residents.push(resident);
+                }
+                next = following;
+            }
+        }
+        // Placeholders aren't part of any list, they could only be left behind by leaked guards
+        self.entries = LinkedSlab::with_capacity(0);
+        self.map = RawTable::new();
+        self.history.clear();
+        if let Some(doorkeeper) = &mut self.doorkeeper {
+            doorkeeper.clear();
+        }
+        self.num_hot = 0;
+        self.num_cold = 0;
+        self.weight_hot = 0;
+        self.weight_cold = 0;
+        residents
     }
 
-    pub fn capacity(&self) -> u64 {
-        self.weight_capacity
+    /// Removes all resident entries in clock order, hot entries first, along with the ghost keys
+    /// and the doorkeeper. Contrary to `take_residents`, pending placeholders are kept.
+    pub fn clear(&mut self) -> Vec<Resident<Key, Qey, Val>> {
+        let mut removed = Vec::with_capacity(self.len());
+        for hot in [true, false] {
+            let (mut next, _) = self.list(hot);
+            while let Some(idx) = next {
+                let (resident, following) = self.remove_at(idx);
+                removed.push(resident);
+                next = following;
+            }
+        }
+        self.history.clear();
+        if let Some(doorkeeper) = &mut self.doorkeeper {
+            doorkeeper.clear();
+        }
+        removed
     }
 
-    pub fn hits(&self) -> u64 {
-        self.hits.load(atomic::Ordering::Relaxed)
+    /// Replaces the shard hash function, see [hash_key]. The shard must be empty.
+    pub fn set_hasher(&mut self, hash_builder: B, hash_seed: Option<u64>) {
+        debug_assert!(self.map.is_empty());
+        self.hash_builder = hash_builder;
+        self.hash_seed = hash_seed;
     }
 
-    pub fn misses(&self) -> u64 {
-        self.misses.load(atomic::Ordering::Relaxed)
+    /// Inserts a resident taken with `take_residents` at the end of its list, keeping its state.
+    /// Its access and insertion times, which aren't part of the resident, are reset to now.
+    /// The shard capacity isn't enforced, see `evict_excess`.
+    pub fn restore_resident(&mut self, mut resident: Resident<Key, Qey, Val>) {
+        let hash = hash_key(
+            &self.hash_builder,
+            self.hash_seed,
+            &resident.key,
+            &resident.qey,
+        );
+        resident.hash = hash;
+        let weight = resident.weight as u64;
+        // the resident may come from another shard, keep its version unique in this one
+        self.next_version = self.next_version.max(resident.version + 1);
+        let list_head = if resident.state == ResidentState::Hot {
+            self.num_hot += 1;
+            self.weight_hot += weight;
+            &mut self.hot_head
+        } else {
+            self.num_cold += 1;
+            self.weight_cold += weight;
+            &mut self.cold_head
+        };
+        let idx = self.entries.insert(Entry::Resident(resident), *list_head);
+        if list_head.is_none() {
+            *list_head = Some(idx);
+        }
+        self.map_insert(hash, idx);
+        let now = self.timestamps.now();
+        self.timestamps.inserted(idx, now);
     }
 
-    #[inline]
-    fn hash_static<Q: ?Sized, W: ?Sized>(hasher: &B, key: &Q, qey: &W) -> u64
+    /// Demotes and evicts entries until the shard is within its targets.
+    /// Returns the evicted entries.
+    pub fn evict_excess(&mut self) -> Displaced<Resident<Key, Qey, Val>> {
+        while self.weight_hot > self.weight_target_hot {
+            self.advance_hot();
+        }
+        let mut evicted = Displaced::default();
+        while self.over_capacity() {
+            evicted.push(self.advance_cold());
+        }
+        evicted
+    }
+
+    /// Changes the weight capacity, recomputing the hot target, the max number of items (unless
+    /// set explicitly) and the history capacity in proportion. Hot entries above the new target
+    /// are demoted, but nothing is evicted, see `evict_excess` and `evict_down_to`.
+    pub fn set_capacity(&mut self, weight_capacity: u64) {
+        let hot_fraction = match self.adaptive_hot_allocation {
+            // keep the adapted fraction rather than starting over
+            Some(_) if self.weight_capacity != 0 => {
+                self.weight_target_hot as f64 / self.weight_capacity as f64
+            }
+            _ => self.hot_allocation,
+        };
+        self.hot_target_bounds = self.adaptive_hot_allocation.map(|(min, max)| {
+            (
+                (weight_capacity as f64 * min) as u64,
+                (weight_capacity as f64 * max) as u64,
+            )
+        });
+        let weight_target_hot = (weight_capacity as f64 * hot_fraction) as u64;
+        self.weight_target_hot = self
+            .hot_target_bounds
+            .map_or(weight_target_hot, |(min, max)| {
+                weight_target_hot.clamp(min, max)
+            });
+        self.weight_capacity = weight_capacity;
+        if self.max_items_from_weight {
+            self.max_items = usize::try_from(weight_capacity).unwrap_or(usize::MAX);
+        }
+        self.history
+            .set_capacity((weight_capacity as f64 * self.ghosts_per_weight) as usize);
+        while self.weight_hot > self.weight_target_hot {
+            self.advance_hot();
+        }
+    }
+
+    /// Evicts up to `max_evictions` entries, in eviction order, while the shard weight
+    /// is above `target_weight`. Returns the evicted entries.
+    pub fn evict_down_to(
+        &mut self,
+        target_weight: u64,
+        max_evictions: usize,
+    ) -> Vec<Resident<Key, Qey, Val>> {
+        let mut evicted = Vec::new();
+        while self.weight() > target_weight && self.len() != 0 && evicted.len() < max_evictions {
+            evicted.push(self.advance_cold());
+        }
+        evicted
+    }
+
+    /// Evicts up to `n` entries, in eviction order, regardless of their weight.
+    /// Returns the evicted entries.
+    pub fn evict_n(&mut self, n: usize) -> Vec<Resident<Key, Qey, Val>> {
+        let mut evicted = Vec::new();
+        while self.len() != 0 && evicted.len() < n {
+            evicted.push(self.advance_cold());
+        }
+        evicted
+    }
+
+    /// Rebuilds the shard with a new hash function, keeping the entries state.
+    /// Returns any evicted entries.
+    pub fn rehash(
+        &mut self,
+        hash_builder: B,
+        hash_seed: Option<u64>,
+    ) -> Displaced<Resident<Key, Qey, Val>> {
+        let residents = self.take_residents();
+        self.set_hasher(hash_builder, hash_seed);
+        for resident in residents {
+            self.restore_resident(resident);
+        }
+        self.evict_excess()
+    }
+
+    /// Removes the resident entries matching `predicate` in clock order, hot entries first.
+    pub fn take_matching<F>(&mut self, mut predicate: F) -> Vec<Resident<Key, Qey, Val>>
     where
-        Key: Borrow<Q>,
-        Q: Hash + Eq,
-        Qey: Borrow<W>,
-        W: Hash + Eq,
+        F: FnMut(&Key, &Qey, &Val) -> bool,
     {
-        let mut hasher = hasher.build_hasher();
-        key.hash(&mut hasher);
-        qey.hash(&mut hasher);
-        hasher.finish()
+        let mut taken = Vec::new();
+        if self.frozen {
+            return taken;
+        }
+        for hot in [true, false] {
+            let (mut next, len) = self.list(hot);
+            for _ in 0..len {
+                let idx = next.unwrap();
+                let (key, qey, val, following) = self.resident_at(idx);
+                if predicate(key, qey, val) {
+                    let (resident, following) = self.remove_at(idx);
+                    taken.push(resident);
+                    next = following;
+                } else {
+                    next = Some(following);
+                }
+            }
+        }
+        taken
+    }
+
+    /// Removes the first resident entry of the `slots` range matching `predicate`, in slot order,
+    /// advancing the start of the range past the inspected slots.
+    /// Other slots are unaffected, so the slab can be processed a range at a time.
+    pub fn take_first_matching_in<F>(
+        &mut self,
+        slots: &mut Range<usize>,
+        mut predicate: F,
+    ) -> Option<Resident<Key, Qey, Val>>
+    where
+        F: FnMut(&Key, &Qey, &Val) -> bool,
+    {
+        if self.frozen {
+            slots.start = slots.end;
+            return None;
+        }
+        let end = slots.end.min(self.entries.num_slots());
+        while slots.start < end {
+            let idx = Token::new(slots.start as u32 + 1).unwrap();
+            slots.start += 1;
+            if let Some((Entry::Resident(resident), _)) = self.entries.get(idx) {
+                if predicate(&resident.key, &resident.qey, &resident.value) {
+                    return Some(self.remove_at(idx).0);
+                }
+            }
+        }
+        None
+    }
+
+    /// Inserts a resident taken from another cache at the end of its list, keeping its state
+    /// but replacing any resident entry with the same keys, see `restore_resident`.
+    /// The weight is recomputed with this shard weighter, as the caches may weigh entries
+    /// differently. Returns the entry left out: the replaced one, or `resident` itself
+    /// if it's too heavy, a placeholder is pending for its keys or the shard is frozen.
+    pub fn merge_resident(
+        &mut self,
+        mut resident: Resident<Key, Qey, Val>,
+    ) -> Option<Resident<Key, Qey, Val>> {
+        if self.reject_frozen() {
+            return Some(resident);
+        }
+        let weight = self
+            .weighter
+            .weight(&resident.key, &resident.qey, &resident.value);
+        if self.reject_too_heavy(weight) {
+            return Some(resident);
+        }
+        let hash = self.hash(&resident.key, &resident.qey);
+        let replaced = match self.search(hash, &resident.key, &resident.qey) {
+            Some(idx) => match self.entries.get(idx) {
+                Some((Entry::Resident(_), _)) => Some(self.remove_at(idx).0),
+                _ => return Some(resident),
+            },
+            None => None,
+        };
+        resident.weight = weight as u32;
+        resident.version = self.new_version();
+        self.restore_resident(resident);
+        replaced
+    }
+
+    /// Number of entry slots, see `reweigh`.
+    pub fn num_slots(&self) -> usize {
+        self.entries.num_slots()
+    }
+
+    /// While disabled, lookups miss without being counted, insertions remove the previous value
+    /// of the key instead and placeholders are removed instead of being replaced.
+    /// Resident entries are otherwise kept, but `get_value_or_placeholder` still returns them,
+    /// see `remove_resident`.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// While frozen, insertions and merges are rejected, removals (including `take_matching` and
+    /// `take_first_matching_in`) and mutable lookups are ignored, and lookups don't alter the
+    /// entries hotness nor access time, so the entries stay as they are.
+    /// Explicit maintenance functions (e.g. `evict_down_to`, `prune_idle`, `clear` or
+    /// `take_residents`) still apply, as does the slot level access of the unsync iterators
+    /// and cursors (`iter_mut`, `value_mut_at` and `remove_at`).
+    pub fn set_frozen(&mut self, frozen: bool) {
+        self.frozen = frozen;
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// Whether an entry of `weight` can be added without exceeding the weight or item capacities.
+    #[inline]
+    fn has_room(&self, weight: u64) -> bool {
+        self.weight_hot + self.weight_cold + weight <= self.weight_capacity
+            && self.len() < self.max_items
+    }
+
+    /// Whether evicting the next entries past their minimum residency would make room for an entry
+    /// of `weight`, so insertions don't evict entries only to be rejected afterwards.
+    /// Only the next [RESIDENCY_SCAN_LIMIT] entries of the cold and then hot rings, the ones
+    /// the clock hands reach first, are considered.
+    fn can_make_room(&self, weight: u64) -> bool {
+        let Some(min_residency_ms) = self.min_residency_ms else {
+            return true;
+        };
+        let now = self.timestamps.now();
+        let mut excess_weight =
+            (self.weight_hot + self.weight_cold + weight).saturating_sub(self.weight_capacity);
+        let mut excess_items = (self.len() + 1).saturating_sub(self.max_items);
+        let mut budget = RESIDENCY_SCAN_LIMIT;
+        for (head, len) in [
+            (self.cold_head, self.num_cold),
+            (self.hot_head, self.num_hot),
+        ] {
+            let mut next = head;
+            for _ in 0..len.min(budget) {
+                count_residency_visit();
+                budget -= 1;
+                let idx = next.unwrap();
+                let (entry, after) = self.entries.get(idx).unwrap();
+                next = Some(after);
+                let Entry::Resident(resident) = entry else {
+                    unreachable!()
+                };
+                // referenced entries are passed over by the clock hands
+                if !resident.referenced.load(atomic::Ordering::Relaxed)
+                    && self.timestamps.resident_for(idx, now) >= min_residency_ms
+                {
+                    excess_weight = excess_weight.saturating_sub(resident.weight as u64);
+                    excess_items = excess_items.saturating_sub(1);
+                    if excess_weight == 0 && excess_items == 0 {
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    /// Whether an entry of `weight` can't be cached at all, counting the rejection if so.
+    #[inline]
+    fn reject_too_heavy(&mut self, weight: u64) -> bool {
+        if weight > self.weight_capacity {
+            self.rejections.too_heavy += 1;
+        } else if weight > self.max_item_weight {
+            self.rejections.max_item_weight += 1;
+        } else {
+            return false;
+        }
+        true
+    }
+
+    /// Whether insertions are rejected because the shard is frozen, counting the rejection if so.
+    #[inline]
+    fn reject_frozen(&mut self) -> bool {
+        self.rejections.frozen += self.frozen as u64;
+        self.frozen
+    }
+
+    #[inline]
+    fn over_capacity(&self) -> bool {
+        self.weight_hot + self.weight_cold > self.weight_capacity || self.len() > self.max_items
+    }
+
+    pub fn weighter(&self) -> &We {
+        &self.weighter
+    }
+
+    pub fn hash_builder(&self) -> &B {
+        &self.hash_builder
+    }
+
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            len: self.len(),
+            weight: self.weight(),
+            non_resident: self.history.len(),
+            map_bytes: self.map.allocation_info().1.size(),
+            slab_bytes: self.entries.allocated_bytes() + self.timestamps.allocated_bytes(),
+            ghost_bytes: self.history.allocated_bytes()
+                + self
+                    .doorkeeper
+                    .as_ref()
+                    .map_or(0, Doorkeeper::allocated_bytes),
+            data_bytes: self.len() * mem::size_of::<(Key, Qey, Val)>(),
+        }
+    }
+
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    pub fn rejections(&self) -> Rejections {
+        self.rejections
+    }
+
+    pub fn reset_stats(&mut self) {
+        *self.hits.get_mut() = 0;
+        *self.misses.get_mut() = 0;
+        self.evictions = 0;
+        self.rejections = Default::default();
+        #[cfg(feature = "latency")]
+        self.latency.reset();
+    }
+
+    #[cfg(feature = "latency")]
+    pub fn latency_histograms(&self) -> crate::LatencyHistograms {
+        self.latency.snapshot()
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            hits: self.hits(),
+            misses: self.misses(),
+            evictions: self.evictions,
+            rejections: self.rejections,
+            len: self.len(),
+            weight: self.weight(),
+        }
     }
 
     #[inline]
-    pub fn hash<Q: ?Sized, W: ?Sized>(&self, key: &Q, qey: &W) -> u64
+    pub fn hash<Q, W>(&self, key: &Q, qey: &W) -> u64
     where
         Key: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
         Qey: Borrow<W>,
-        W: Hash + Eq,
+        W: Hash + Eq + ?Sized,
     {
-        Self::hash_static(&self.hash_builder, key, qey)
+        hash_key(&self.hash_builder, self.hash_seed, key, qey)
     }
 
     #[inline]
-    fn search<Q: ?Sized, W: ?Sized>(&self, hash: u64, k: &Q, q: &W) -> Option<Token>
+    fn search<Q, W>(&self, hash: u64, k: &Q, q: &W) -> Option<Token>
     where
         Key: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
         Qey: Borrow<W>,
-        W: Hash + Eq,
+        W: Hash + Eq + ?Sized,
     {
-        // Safety for `RawTable::iter_hash` and `Bucket::as_ref`:
-        // * Their outputs do not outlive their HashBrown:
-        // The HashBrown instance is alive for the entirety of the function and
-        // the content references never leave the function.
-        // * HashBrown can't be mutated while being iterated with iter_hash:
-        // The HashBrown instance isn't mutated in this method.
-        unsafe {
-            let mut hash_match = None;
-            for bucket in self.map.iter_hash(hash) {
-                let idx = *bucket.as_ref();
+        self.map
+            .get(hash, |&(h, idx)| {
+                if h != hash {
+                    return false;
+                }
                 let (entry, _) = self.entries.get(idx).unwrap();
                 match entry {
                     Entry::Resident(Resident { key, qey, .. })
-                    | Entry::Placeholder(Placeholder { key, qey, .. })
-                        if key.borrow() == k && qey.borrow() == q =>
-                    {
-                        return Some(idx);
+                    | Entry::Placeholder(Placeholder { key, qey, .. }) => {
+                        key.borrow() == k && qey.borrow() == q
                     }
-                    Entry::Ghost(non_resident_hash) if *non_resident_hash == hash => {
-                        hash_match = Some(idx);
-                    }
-                    _ => (),
                 }
-            }
-            hash_match
-        }
+            })
+            .map(|&(_, idx)| idx)
     }
 
     #[inline]
-    fn search_resident<Q: ?Sized, W: ?Sized>(&self, hash: u64, k: &Q, q: &W) -> Option<Token>
+    fn search_resident<Q, W>(&self, hash: u64, k: &Q, q: &W) -> Option<Token>
     where
         Key: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
         Qey: Borrow<W>,
-        W: Hash + Eq,
+        W: Hash + Eq + ?Sized,
     {
         self.map
-            .get(hash, |&idx| {
+            .get(hash, |&(h, idx)| {
+                if h != hash {
+                    return false;
+                }
                 let (entry, _) = self.entries.get(idx).unwrap();
                 matches!(entry, Entry::Resident(Resident { key, qey, .. }) if key.borrow() == k && qey.borrow() == q)
             })
-            .copied()
+            .map(|&(_, idx)| idx)
+    }
+
+    pub fn get<Q, W>(&self, hash: u64, key: &Q, qey: &W) -> Option<&Val>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        timed!(self.get, self.get_untimed(hash, key, qey)).map(|r| &r.value)
+    }
+
+    /// Like `get`, but also returns the entry version.
+    pub fn get_with_version<Q, W>(&self, hash: u64, key: &Q, qey: &W) -> Option<(&Val, u64)>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        timed!(self.get, self.get_untimed(hash, key, qey)).map(|r| (&r.value, r.version))
+    }
+
+    /// Like `get`, but also returns the entry flags.
+    pub fn get_with_flags<Q, W>(&self, hash: u64, key: &Q, qey: &W) -> Option<(&Val, u16)>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        timed!(self.get, self.get_untimed(hash, key, qey))
+            .map(|r| (&r.value, r.flags.load(atomic::Ordering::Relaxed)))
     }
 
-    pub fn get<Q: ?Sized, W: ?Sized>(&self, hash: u64, key: &Q, qey: &W) -> Option<&Val>
+    #[inline]
+    fn get_untimed<Q, W>(&self, hash: u64, key: &Q, qey: &W) -> Option<&Resident<Key, Qey, Val>>
     where
         Key: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
         Qey: Borrow<W>,
-        W: Hash + Eq,
+        W: Hash + Eq + ?Sized,
     {
+        if !self.enabled {
+            return None;
+        }
         if let Some(idx) = self.search_resident(hash, key, qey) {
-            let Some((Entry::Resident(resident), _)) = self.entries.get(idx) else { unreachable!() };
-            resident.referenced.store(true, atomic::Ordering::Relaxed);
+            let Some((Entry::Resident(resident), _)) = self.entries.get(idx) else {
+                unreachable!()
+            };
+            if !self.frozen {
+                resident.referenced.store(true, atomic::Ordering::Relaxed);
+                self.timestamps.accessed(idx);
+            }
+            self.record_lookup(true);
+            trace!(self, Hit, hash, resident.weight as u64);
+            return Some(resident);
+        }
+        self.record_lookup(false);
+        trace!(self, Miss, hash, 0);
+        None
+    }
+
+    #[inline]
+    fn record_lookup(&self, hit: bool) {
+        if let Some(counters) = &self.buffered_counters {
+            record_buffered(counters, hit);
+        } else if hit {
             self.hits.fetch_add(1, atomic::Ordering::Relaxed);
-            return Some(&resident.value);
+        } else {
+            self.misses.fetch_add(1, atomic::Ordering::Relaxed);
         }
-        self.misses.fetch_add(1, atomic::Ordering::Relaxed);
+    }
+
+    pub fn get_mut<Q, W>(&mut self, hash: u64, key: &Q, qey: &W) -> Option<&mut Val>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        if !self.enabled || self.frozen {
+            return None;
+        }
+        #[cfg(feature = "latency")]
+        let start = std::time::Instant::now();
+        if let Some(idx) = self.search_resident(hash, key, qey) {
+            self.timestamps.accessed(idx);
+            let Some((Entry::Resident(resident), _)) = self.entries.get_mut(idx) else {
+                unreachable!()
+            };
+            *resident.referenced.get_mut() = true;
+            // the value may be modified through the returned reference
+            resident.version = self.next_version;
+            self.next_version += 1;
+            *self.hits.get_mut() += 1;
+            trace!(self, Hit, hash, resident.weight as u64);
+            #[cfg(feature = "latency")]
+            self.latency.get.record(start.elapsed());
+            return Some(&mut resident.value);
+        }
+        *self.misses.get_mut() += 1;
+        trace!(self, Miss, hash, 0);
+        #[cfg(feature = "latency")]
+        self.latency.get.record(start.elapsed());
         None
     }
 
-    pub fn get_mut<Q: ?Sized, W: ?Sized>(&mut self, hash: u64, key: &Q, qey: &W) -> Option<&mut Val>
-    where
-        Key: Borrow<Q>,
-        Q: Hash + Eq,
-        Qey: Borrow<W>,
-        W: Hash + Eq,
-    {
-        if let Some(idx) = self.search_resident(hash, key, qey) {
-            let Some((Entry::Resident(resident), _)) = self.entries.get_mut(idx) else { unreachable!() };
-            *resident.referenced.get_mut() = true;
-            *self.hits.get_mut() += 1;
-            return Some(&mut resident.value);
+    /// Finds the resident entry of `key` and `qey`, counting a hit or a miss.
+    /// A found entry is marked as accessed, like with `get_mut`.
+    pub fn lookup<Q, W>(&mut self, hash: u64, key: &Q, qey: &W) -> Option<Token>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        if !self.enabled {
+            return None;
+        }
+        let Some(idx) = self.search_resident(hash, key, qey) else {
+            *self.misses.get_mut() += 1;
+            trace!(self, Miss, hash, 0);
+            return None;
+        };
+        let frozen = self.frozen;
+        if !frozen {
+            self.timestamps.accessed(idx);
+        }
+        let Some((Entry::Resident(resident), _)) = self.entries.get_mut(idx) else {
+            unreachable!()
+        };
+        if !frozen {
+            *resident.referenced.get_mut() = true;
+        }
+        *self.hits.get_mut() += 1;
+        trace!(self, Hit, hash, resident.weight as u64);
+        Some(idx)
+    }
+
+    /// Finds the resident entry of `key` and `qey`, like `lookup` but without counting
+    /// a hit or a miss nor marking the entry as accessed.
+    pub fn find<Q, W>(&self, hash: u64, key: &Q, qey: &W) -> Option<Token>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        if !self.enabled {
+            return None;
+        }
+        self.search_resident(hash, key, qey)
+    }
+
+    /// Picks a resident entry uniformly at random, without marking it as accessed.
+    /// Slots are sampled until one holds a resident entry, and after a few misses (e.g. a sparse
+    /// slab after many removals) the entry of a random rank in slot order is picked instead.
+    pub fn random_resident(&self) -> Option<(&Key, &Qey, &Val)> {
+        const MAX_ATTEMPTS: usize = 16;
+        if !self.enabled || self.len() == 0 {
+            return None;
+        }
+        let num_slots = self.entries.num_slots();
+        let resident_at_slot = |slot: usize| match self.entries.get(Token::new(slot as u32 + 1)?) {
+            Some((Entry::Resident(resident), _)) => {
+                Some((&resident.key, &resident.qey, &resident.value))
+            }
+            _ => None,
+        };
+        (0..MAX_ATTEMPTS)
+            .find_map(|_| resident_at_slot(random_below(num_slots as u64) as usize))
+            .or_else(|| {
+                let nth = random_below(self.len() as u64) as usize;
+                (0..num_slots).filter_map(resident_at_slot).nth(nth)
+            })
+    }
+
+    pub fn peek<Q, W>(&self, hash: u64, key: &Q, qey: &W) -> Option<&Val>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        self.peek_with_version(hash, key, qey).map(|(v, _)| v)
+    }
+
+    /// Like `peek`, but also returns the entry version.
+    pub fn peek_with_version<Q, W>(&self, hash: u64, key: &Q, qey: &W) -> Option<(&Val, u64)>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        if !self.enabled {
+            return None;
+        }
+        let idx = self.search_resident(hash, key, qey)?;
+        let Some((Entry::Resident(resident), _)) = self.entries.get(idx) else {
+            unreachable!()
+        };
+        Some((&resident.value, resident.version))
+    }
+
+    /// Like `peek`, but also returns the entry flags.
+    pub fn peek_with_flags<Q, W>(&self, hash: u64, key: &Q, qey: &W) -> Option<(&Val, u16)>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        if !self.enabled {
+            return None;
+        }
+        let idx = self.search_resident(hash, key, qey)?;
+        let Some((Entry::Resident(resident), _)) = self.entries.get(idx) else {
+            unreachable!()
+        };
+        Some((
+            &resident.value,
+            resident.flags.load(atomic::Ordering::Relaxed),
+        ))
+    }
+
+    /// Replaces the flags of the resident entry, without altering its hotness.
+    /// Returns the previous flags, or `None` if the key isn't resident.
+    pub fn set_flags<Q, W>(&self, hash: u64, key: &Q, qey: &W, flags: u16) -> Option<u16>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        if !self.enabled {
+            return None;
+        }
+        let idx = self.search_resident(hash, key, qey)?;
+        let Some((Entry::Resident(resident), _)) = self.entries.get(idx) else {
+            unreachable!()
+        };
+        Some(resident.flags.swap(flags, atomic::Ordering::Relaxed))
+    }
+
+    pub fn peek_mut<Q, W>(&mut self, hash: u64, key: &Q, qey: &W) -> Option<&mut Val>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        if !self.enabled || self.frozen {
+            return None;
+        }
+        let idx = self.search_resident(hash, key, qey)?;
+        let Some((Entry::Resident(resident), _)) = self.entries.get_mut(idx) else {
+            unreachable!()
+        };
+        resident.version = self.next_version;
+        self.next_version += 1;
+        Some(&mut resident.value)
+    }
+
+    /// Replaces the value of the resident entry if its version is still `expected`.
+    /// Returns the new version and the replaced (or evicted) entries.
+    /// Otherwise returns the current version, or `None` if the key isn't resident.
+    ///
+    /// If the new value is too heavy to be cached the entry is removed instead,
+    /// and returned without a new version.
+    #[allow(clippy::type_complexity)]
+    pub fn compare_exchange(
+        &mut self,
+        hash: u64,
+        key: Key,
+        qey: Qey,
+        expected: u64,
+        value: Val,
+    ) -> Result<(Option<u64>, Displaced<Entry<Key, Qey, Val>>), Option<u64>> {
+        if !self.enabled {
+            self.remove_resident(hash, &key, &qey);
+            return Err(None);
+        }
+        let idx = self.search_resident(hash, &key, &qey).ok_or(None)?;
+        let Some((Entry::Resident(resident), _)) = self.entries.get(idx) else {
+            unreachable!()
+        };
+        if resident.version != expected || self.frozen {
+            return Err(Some(resident.version));
+        }
+        let flags = resident.flags.load(atomic::Ordering::Relaxed);
+        let weight = self.weighter.weight(&key, &qey, &value);
+        trace!(self, Insert, hash, weight);
+        if self.reject_too_heavy(weight) {
+            // the new value won't fit, remove the stale one instead of keeping it
+            return Ok((None, self.remove(hash, &key, &qey).into_iter().collect()));
+        }
+        let version = self.next_version;
+        let evicted = timed!(
+            self.insert,
+            self.insert_existing(idx, hash, key, qey, value, weight, flags)
+        );
+        Ok((Some(version), evicted))
+    }
+
+    /// Returns the head (clock hand) and length of the hot or cold list.
+    pub fn list(&self, hot: bool) -> (Option<Token>, usize) {
+        if hot {
+            (self.hot_head, self.num_hot)
+        } else {
+            (self.cold_head, self.num_cold)
+        }
+    }
+
+    /// Returns the resident at `idx` and the token of the next entry in its list.
+    pub fn resident_at(&self, idx: Token) -> (&Key, &Qey, &Val, Token) {
+        let Some((Entry::Resident(resident), next)) = self.entries.get(idx) else {
+            unreachable!()
+        };
+        (&resident.key, &resident.qey, &resident.value, next)
+    }
+
+    /// Returns the value of the resident at `idx` for modification, bumping its version.
+    pub fn value_mut_at(&mut self, idx: Token) -> &mut Val {
+        let Some((Entry::Resident(resident), _)) = self.entries.get_mut(idx) else {
+            unreachable!()
+        };
+        resident.version = self.next_version;
+        self.next_version += 1;
+        &mut resident.value
+    }
+
+    /// Replaces the value of the resident at `idx`, fixing the weight accounting and clearing
+    /// its flags. Returns the previous value and the entries evicted if the shard ended up over
+    /// capacity, which may include the replaced entry.
+    /// Gives `value` back if it's too heavy to be cached or the shard is frozen.
+    #[allow(clippy::type_complexity)]
+    pub fn replace_at(
+        &mut self,
+        idx: Token,
+        value: Val,
+    ) -> Result<(Val, Displaced<Resident<Key, Qey, Val>>), Rejected<Val>> {
+        if self.reject_frozen() {
+            return Err(Rejected::Frozen(value));
         }
-        *self.misses.get_mut() += 1;
-        None
+        let Some((Entry::Resident(resident), _)) = self.entries.get(idx) else {
+            unreachable!()
+        };
+        let weight = self.weighter.weight(&resident.key, &resident.qey, &value);
+        trace!(self, Insert, resident.hash, weight);
+        if self.reject_too_heavy(weight) {
+            return Err(Rejected::TooHeavy(value));
+        }
+        let version = self.new_version();
+        let now = self.timestamps.now();
+        self.timestamps.inserted(idx, now);
+        let Some((Entry::Resident(resident), _)) = self.entries.get_mut(idx) else {
+            unreachable!()
+        };
+        let old_weight = resident.weight as u64;
+        if resident.state == ResidentState::Hot {
+            self.weight_hot = self.weight_hot - old_weight + weight;
+        } else {
+            self.weight_cold = self.weight_cold - old_weight + weight;
+        }
+        resident.weight = weight as u32;
+        resident.version = version;
+        *resident.referenced.get_mut() = true; // re-insert counts as a hit
+        *resident.flags.get_mut() = 0;
+        let old_value = mem::replace(&mut resident.value, value);
+
+        // the replacement may have made the hot section/cache too big
+        while self.weight_hot > self.weight_target_hot {
+            self.advance_hot();
+        }
+        let mut evicted = Displaced::default();
+        while self.over_capacity() {
+            evicted.push(self.evict_for_replacement());
+        }
+        Ok((old_value, evicted))
     }
 
-    pub fn peek<Q: ?Sized, W: ?Sized>(&self, hash: u64, key: &Q, qey: &W) -> Option<&Val>
-    where
-        Key: Borrow<Q>,
-        Q: Hash + Eq,
-        Qey: Borrow<W>,
-        W: Hash + Eq,
-    {
-        let idx = self.search_resident(hash, key, qey)?;
-        let Some((Entry::Resident(resident), _)) = self.entries.get(idx) else { unreachable!() };
-        Some(&resident.value)
+    /// Removes the resident at `idx`.
+    /// Returns it and the token of the next entry in its list, if any.
+    pub fn remove_at(&mut self, idx: Token) -> (Resident<Key, Qey, Val>, Option<Token>) {
+        let (Entry::Resident(resident), next) = self.entries.remove(idx).unwrap() else {
+            unreachable!()
+        };
+        self.map_remove(resident.hash, idx);
+        let weight = resident.weight as u64;
+        let list_head = if resident.state == ResidentState::Hot {
+            self.num_hot -= 1;
+            self.weight_hot -= weight;
+            &mut self.hot_head
+        } else {
+            self.num_cold -= 1;
+            self.weight_cold -= weight;
+            &mut self.cold_head
+        };
+        if *list_head == Some(idx) {
+            *list_head = next;
+        }
+        (resident, next)
     }
 
-    pub fn peek_mut<Q: ?Sized, W: ?Sized>(
+    /// Removes the resident entry of `key` and `qey`, leaving placeholders in place.
+    pub fn remove_resident<Q, W>(
         &mut self,
         hash: u64,
         key: &Q,
         qey: &W,
-    ) -> Option<&mut Val>
+    ) -> Option<Resident<Key, Qey, Val>>
     where
         Key: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
         Qey: Borrow<W>,
-        W: Hash + Eq,
+        W: Hash + Eq + ?Sized,
     {
+        if self.frozen {
+            return None;
+        }
         let idx = self.search_resident(hash, key, qey)?;
-        let Some((Entry::Resident(resident), _)) = self.entries.get_mut(idx) else { unreachable!() };
-        Some(&mut resident.value)
+        Some(self.remove_at(idx).0)
     }
 
-    pub fn remove<Q: ?Sized, W: ?Sized>(
-        &mut self,
-        hash: u64,
-        key: &Q,
-        qey: &W,
-    ) -> Option<Entry<Key, Qey, Val>>
+    /// Demotes the hot resident at `idx` to the cold list, or clears the referenced bit of a cold
+    /// one, so it's evicted next time the clock hand reaches it.
+    /// Returns the token of the next entry in its former list, if any.
+    pub fn demote_at(&mut self, idx: Token) -> Option<Token> {
+        let (entry, next) = self.entries.get_mut(idx).unwrap();
+        let Entry::Resident(resident) = entry else {
+            unreachable!()
+        };
+        *resident.referenced.get_mut() = false;
+        let next = (next != idx).then_some(next);
+        if resident.state != ResidentState::Hot {
+            return next;
+        }
+        resident.state = ResidentState::ColdDemoted;
+        let weight = resident.weight as u64;
+        self.num_hot -= 1;
+        self.weight_hot -= weight;
+        self.num_cold += 1;
+        self.weight_cold += weight;
+        Self::relink(
+            &mut self.entries,
+            idx,
+            &mut self.hot_head,
+            &mut self.cold_head,
+        );
+        next
+    }
+
+    /// Iterates over the resident entries in an arbitrary order, without altering their hotness.
+    pub fn iter(&self) -> impl Iterator<Item = (&Key, &Qey, &Val)> + '_ {
+        self.entries.iter_entries().filter_map(|entry| match entry {
+            Entry::Resident(resident) => Some((&resident.key, &resident.qey, &resident.value)),
+            Entry::Placeholder(_) => None,
+        })
+    }
+
+    /// Iterates over the resident entries of the `slots` range, without altering their hotness.
+    pub fn iter_slots(&self, slots: Range<usize>) -> impl Iterator<Item = (&Key, &Qey, &Val)> + '_ {
+        (slots.start..slots.end.min(self.entries.num_slots())).filter_map(|slot| {
+            let idx = Token::new(slot as u32 + 1).unwrap();
+            match self.entries.get(idx) {
+                Some((Entry::Resident(resident), _)) => {
+                    Some((&resident.key, &resident.qey, &resident.value))
+                }
+                _ => None,
+            }
+        })
+    }
+
+    /// Iterates over the resident entries in an arbitrary order, without altering their hotness.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&Key, &Qey, &mut Val)> + '_ {
+        let next_version = &mut self.next_version;
+        self.entries
+            .iter_mut()
+            .filter_map(move |entry| match entry {
+                Entry::Resident(resident) => {
+                    resident.version = *next_version;
+                    *next_version += 1;
+                    Some((&resident.key, &resident.qey, &mut resident.value))
+                }
+                Entry::Placeholder(_) => None,
+            })
+    }
+
+    pub fn remove<Q, W>(&mut self, hash: u64, key: &Q, qey: &W) -> Option<Entry<Key, Qey, Val>>
     where
         Key: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
         Qey: Borrow<W>,
-        W: Hash + Eq,
+        W: Hash + Eq + ?Sized,
     {
-        let idx = self.search(hash, key, qey)?;
+        trace!(self, Remove, hash, 0);
+        if self.frozen {
+            return None;
+        }
+        let Some(idx) = self.search(hash, key, qey) else {
+            // Since this an user invoked remove we opt to remove even ghosts that could match it.
+            self.history.remove(hash);
+            return None;
+        };
         self.map_remove(hash, idx);
         let (entry, next) = self.entries.remove(idx).unwrap();
         let list_head = match &entry {
             Entry::Resident(r) => {
-                let weight = self.weighter.weight(&r.key, &r.qey, &r.value);
+                let weight = r.weight as u64;
                 if r.state == ResidentState::Hot {
                     self.num_hot -= 1;
                     self.weight_hot -= weight;
@@ -403,11 +1641,6 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: InternalWeighter<Key, Qey, Val>, B
                     &mut self.cold_head
                 }
             }
-            Entry::Ghost(_) => {
-                // Since this an user invoked remove we opt to remove even Ghost entries that could match it.
-                self.num_non_resident -= 1;
-                &mut self.ghost_head
-            }
             Entry::Placeholder(_) => {
                 // TODO: this is probably undesirable as it could leak to two placeholders for the same key.
                 return Some(entry);
@@ -423,6 +1656,21 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: InternalWeighter<Key, Qey, Val>, B
     /// Returns the evicted entry.
     /// Panics if the cache is empty.
     fn advance_cold(&mut self) -> Resident<Key, Qey, Val> {
+        self.advance_cold_with(false).unwrap()
+    }
+
+    /// Like `advance_cold`, but if `protect_recent` is set the entries inserted within
+    /// the minimum residency are skipped. Once all the cold entries, or [RESIDENCY_SCAN_LIMIT]
+    /// of them, were skipped, hot entries are demoted one by one to be considered as well.
+    /// Returns `None` if all the entries, or twice [RESIDENCY_SCAN_LIMIT] of them, were skipped.
+    fn advance_cold_with(&mut self, protect_recent: bool) -> Option<Resident<Key, Qey, Val>> {
+        let min_residency_ms = self.min_residency_ms.filter(|_| protect_recent);
+        let now = if min_residency_ms.is_some() {
+            self.timestamps.now()
+        } else {
+            0
+        };
+        let mut skipped = 0;
         loop {
             let idx = if let Some(idx) = self.cold_head {
                 idx
@@ -430,7 +1678,9 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: InternalWeighter<Key, Qey, Val>, B
                 self.advance_hot()
             };
             let (entry, next) = self.entries.get_mut(idx).unwrap();
-            let Entry::Resident(resident) = entry else { unreachable!() };
+            let Entry::Resident(resident) = entry else {
+                unreachable!()
+            };
             debug_assert!(matches!(
                 resident.state,
                 ResidentState::ColdDemoted | ResidentState::ColdInTest
@@ -441,9 +1691,7 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: InternalWeighter<Key, Qey, Val>, B
                     resident.state = ResidentState::Hot;
                     self.num_hot += 1;
                     self.num_cold -= 1;
-                    let weight =
-                        self.weighter
-                            .weight(&resident.key, &resident.qey, &resident.value);
+                    let weight = resident.weight as u64;
                     self.weight_hot += weight;
                     self.weight_cold -= weight;
                     Self::relink(
@@ -465,35 +1713,49 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: InternalWeighter<Key, Qey, Val>, B
                 }
                 continue;
             }
+            if let Some(min_residency_ms) = min_residency_ms {
+                if self.timestamps.resident_for(idx, now) < min_residency_ms {
+                    count_residency_visit();
+                    skipped += 1;
+                    self.cold_head = Some(next);
+                    if skipped >= self.num_cold.min(RESIDENCY_SCAN_LIMIT) {
+                        if self.num_hot == 0 || skipped >= 2 * RESIDENCY_SCAN_LIMIT {
+                            return None;
+                        }
+                        // the demoted entry is considered next
+                        self.cold_head = Some(self.advance_hot());
+                    }
+                    continue;
+                }
+            }
 
-            let weight = self
-                .weighter
-                .weight(&resident.key, &resident.qey, &resident.value);
-            let hash = Self::hash_static(&self.hash_builder, &resident.key, &resident.qey);
-            let Entry::Resident(resident) = mem::replace(entry, Entry::Ghost(hash)) else {
-                unreachable!()
-            };
+            let weight = resident.weight as u64;
+            let hash = resident.hash;
+            // Register a non-resident entry if ColdInTest
+            let ghost_expired =
+                resident.state == ResidentState::ColdInTest && self.history.insert(hash);
             self.num_cold -= 1;
             self.weight_cold -= weight;
-
-            // Register a non-resident entry if ColdInTest
-            if resident.state == ResidentState::ColdInTest {
-                self.num_non_resident += 1;
-                Self::relink(
-                    &mut self.entries,
-                    idx,
-                    &mut self.cold_head,
-                    &mut self.ghost_head,
-                );
-                if self.num_non_resident > self.capacity_non_resident {
-                    self.advance_ghost();
-                }
-            } else {
-                self.map_remove(hash, idx);
-                let (_, next) = self.entries.remove(idx).unwrap();
-                self.cold_head = next;
+            self.evictions += 1;
+            self.map_remove(hash, idx);
+            let (Entry::Resident(resident), next) = self.entries.remove(idx).unwrap() else {
+                unreachable!()
+            };
+            self.cold_head = next;
+            if ghost_expired {
+                // the test period was too long for that key, so there's room for more hot entries
+                self.adapt_hot_target(true);
             }
-            return resident;
+            return Some(resident);
+        }
+    }
+
+    /// Evicts to make room for a replaced value, sparing the entries within their minimum
+    /// residency if possible. The value is already in the cache so it can't be rejected.
+    fn evict_for_replacement(&mut self) -> Resident<Key, Qey, Val> {
+        match self.advance_cold_with(true) {
+            Some(resident) => resident,
+            None => self.advance_cold(),
         }
     }
 
@@ -506,7 +1768,9 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: InternalWeighter<Key, Qey, Val>, B
         loop {
             let idx = self.hot_head.unwrap();
             let (entry, next) = self.entries.get_mut(idx).unwrap();
-            let Entry::Resident(resident) = entry else { unreachable!() };
+            let Entry::Resident(resident) = entry else {
+                unreachable!()
+            };
             debug_assert_eq!(resident.state, ResidentState::Hot);
             if *resident.referenced.get_mut() {
                 *resident.referenced.get_mut() = false;
@@ -517,9 +1781,7 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: InternalWeighter<Key, Qey, Val>, B
             }
             self.num_hot -= 1;
             self.num_cold += 1;
-            let weight = self
-                .weighter
-                .weight(&resident.key, &resident.qey, &resident.value);
+            let weight = resident.weight as u64;
             self.weight_hot -= weight;
             self.weight_cold += weight;
             Self::relink(
@@ -532,33 +1794,108 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: InternalWeighter<Key, Qey, Val>, B
         }
     }
 
+    /// Clears the referenced bit of about `fraction` of the resident entries, continuing from where
+    /// the previous pass stopped. Entries not accessed since are then demoted or evicted when
+    /// the clock hand reaches them. Returns the number of cleared bits.
+    pub fn decay_referenced(&mut self, fraction: f64) -> usize {
+        let num_slots = self.entries.num_slots();
+        let to_visit = (num_slots as f64 * fraction.clamp(0.0, 1.0)).ceil() as usize;
+        let mut cleared = 0;
+        for _ in 0..to_visit {
+            if self.decay_position >= num_slots {
+                self.decay_position = 0;
+            }
+            let idx = Token::new(self.decay_position as u32 + 1).unwrap();
+            self.decay_position += 1;
+            if let Some((Entry::Resident(resident), _)) = self.entries.get_mut(idx) {
+                if mem::take(resident.referenced.get_mut()) {
+                    cleared += 1;
+                }
+            }
+        }
+        cleared
+    }
+
+    /// Re-runs the weighter over the resident entries of the `slots` range, fixing the accounting
+    /// of the ones whose weight changed. Returns the number of fixed entries.
+    /// The shard may be left over capacity, see `evict_excess`.
+    pub fn reweigh(&mut self, slots: Range<usize>) -> usize {
+        let mut fixed = 0;
+        for slot in slots.start..slots.end.min(self.entries.num_slots()) {
+            let idx = Token::new(slot as u32 + 1).unwrap();
+            let Some((Entry::Resident(resident), _)) = self.entries.get_mut(idx) else {
+                continue;
+            };
+            let weight = self
+                .weighter
+                .weight(&resident.key, &resident.qey, &resident.value);
+            let old_weight = resident.weight as u64;
+            if weight == old_weight {
+                continue;
+            }
+            resident.weight = weight as u32;
+            if resident.state == ResidentState::Hot {
+                self.weight_hot = self.weight_hot - old_weight + weight;
+            } else {
+                self.weight_cold = self.weight_cold - old_weight + weight;
+            }
+            fixed += 1;
+        }
+        fixed
+    }
+
+    /// Removes the entries that weren't accessed for the idle timeout, if set,
+    /// counting them as evictions. Returns the removed entries.
+    pub fn prune_idle(&mut self) -> Vec<Resident<Key, Qey, Val>> {
+        let Some(idle_timeout_ms) = self.idle_timeout_ms else {
+            return Vec::new();
+        };
+        let now = self.timestamps.now();
+        let mut idle = Vec::new();
+        for (head, len) in [
+            (self.hot_head, self.num_hot),
+            (self.cold_head, self.num_cold),
+        ] {
+            let mut next = head;
+            for _ in 0..len {
+                let idx = next.unwrap();
+                let (_, _, _, following) = self.resident_at(idx);
+                if self.timestamps.idle_for(idx, now) >= idle_timeout_ms {
+                    idle.push(idx);
+                }
+                next = Some(following);
+            }
+        }
+        self.evictions += idle.len() as u64;
+        idle.into_iter().map(|idx| self.remove_at(idx).0).collect()
+    }
+
     #[inline]
-    fn advance_ghost(&mut self) {
-        debug_assert_ne!(self.num_non_resident, 0);
-        let idx = self.ghost_head.unwrap();
-        let (entry, _) = self.entries.get_mut(idx).unwrap();
-        let Entry::Ghost(hash) = *entry else { unreachable!("{}", entry.dbg()) };
-        self.num_non_resident -= 1;
-        self.map_remove(hash, idx);
-        let (_, next) = self.entries.remove(idx).unwrap();
-        self.ghost_head = next;
+    fn new_version(&mut self) -> u64 {
+        let version = self.next_version;
+        self.next_version += 1;
+        version
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn insert_existing(
         &mut self,
         idx: Token,
+        hash: u64,
         key: Key,
         qey: Qey,
         value: Val,
         weight: u64,
-    ) -> Entry<Key, Qey, Val> {
+        flags: u16,
+    ) -> Displaced<Entry<Key, Qey, Val>> {
+        let version = self.new_version();
+        let now = self.timestamps.now();
+        self.timestamps.inserted(idx, now);
         let (entry, _) = self.entries.get_mut(idx).unwrap();
-        let mut evicted;
+        let replaced;
         match entry {
             Entry::Resident(resident) => {
-                let evicted_weight =
-                    self.weighter
-                        .weight(&resident.key, &resident.qey, &resident.value);
+                let evicted_weight = resident.weight as u64;
                 if resident.state == ResidentState::Hot {
                     self.weight_hot -= evicted_weight;
                     self.weight_hot += weight;
@@ -572,11 +1909,15 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: InternalWeighter<Key, Qey, Val>, B
                     value,
                     state: resident.state,
                     referenced: AtomicBool::new(true), // re-insert counts as a hit
+                    version,
+                    hash,
+                    weight: weight as u32,
+                    flags: AtomicU16::new(flags),
                 };
-                evicted = Entry::Resident(mem::replace(resident, new_resident));
+                replaced = Entry::Resident(mem::replace(resident, new_resident));
             }
-            Entry::Placeholder(..) | Entry::Ghost(..) => {
-                evicted = mem::replace(
+            Entry::Placeholder(..) => {
+                replaced = mem::replace(
                     entry,
                     Entry::Resident(Resident {
                         key,
@@ -584,18 +1925,18 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: InternalWeighter<Key, Qey, Val>, B
                         value,
                         state: ResidentState::Hot,
                         referenced: Default::default(),
+                        version,
+                        hash,
+                        weight: weight as u32,
+                        flags: AtomicU16::new(flags),
                     }),
                 );
                 self.num_hot += 1;
                 self.weight_hot += weight;
-                if matches!(evicted, Entry::Ghost(..)) {
-                    self.num_non_resident -= 1;
-                    Self::relink(
-                        &mut self.entries,
-                        idx,
-                        &mut self.ghost_head,
-                        &mut self.hot_head,
-                    );
+                // placeholders aren't part of any list
+                self.entries.link(idx, self.hot_head);
+                if self.hot_head.is_none() {
+                    self.hot_head = Some(idx);
                 }
             }
         }
@@ -604,12 +1945,44 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: InternalWeighter<Key, Qey, Val>, B
         while self.weight_hot > self.weight_target_hot {
             self.advance_hot();
         }
-        while self.weight_hot + self.weight_cold > self.weight_capacity {
-            evicted = Entry::Resident(self.advance_cold());
+        let mut evicted = Displaced::default();
+        evicted.push(replaced);
+        while self.over_capacity() {
+            evicted.push(Entry::Resident(self.evict_for_replacement()));
         }
         evicted
     }
 
+    /// Adapts the hot target, if enabled, by roughly the weight of an entry.
+    /// A ghost hit means the key would have been a hit with a larger cold section so the hot
+    /// target shrinks, while a ghost expiring unused grows it.
+    fn adapt_hot_target(&mut self, grow_hot: bool) {
+        let Some((min, max)) = self.hot_target_bounds else {
+            return;
+        };
+        let step = (self.weight() / self.len().max(1) as u64).max(1);
+        if grow_hot {
+            self.weight_target_hot = self.weight_target_hot.saturating_add(step).min(max);
+        } else {
+            self.weight_target_hot = self.weight_target_hot.saturating_sub(step).max(min);
+            while self.weight_hot > self.weight_target_hot {
+                self.advance_hot();
+            }
+        }
+    }
+
+    /// Records the insertion of a new key in the doorkeeper, if any.
+    /// Returns whether the key should be admitted, counting the rejection otherwise.
+    #[inline]
+    fn doorkeeper_admits(&mut self, hash: u64) -> bool {
+        let admits = self
+            .doorkeeper
+            .as_mut()
+            .map_or(true, |doorkeeper| doorkeeper.insert(hash));
+        self.rejections.admission += !admits as u64;
+        admits
+    }
+
     #[inline]
     fn relink<T>(
         ring: &mut LinkedSlab<T>,
@@ -629,21 +2002,12 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: InternalWeighter<Key, Qey, Val>, B
 
     #[inline]
     fn map_insert(&mut self, hash: u64, idx: Token) {
-        self.map.insert(hash, idx, |&i| {
-            let (entry, _) = self.entries.get(i).unwrap();
-            match entry {
-                Entry::Resident(Resident { key, qey, .. })
-                | Entry::Placeholder(Placeholder { key, qey, .. }) => {
-                    Self::hash_static(&self.hash_builder, key, qey)
-                }
-                Entry::Ghost(hash) => *hash,
-            }
-        });
+        self.map.insert(hash, (hash, idx), |&(h, _)| h);
     }
 
     #[inline]
     fn map_remove(&mut self, hash: u64, idx: Token) {
-        let removed = self.map.erase_entry(hash, |&i| i == idx);
+        let removed = self.map.erase_entry(hash, |&(_, i)| i == idx);
         debug_assert!(removed);
     }
 
@@ -652,8 +2016,20 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: InternalWeighter<Key, Qey, Val>, B
         placeholder: &SharedPlaceholder<Val>,
         referenced: bool,
         value: Val,
-    ) -> Result<Option<Entry<Key, Qey, Val>>, Val> {
-        let found = self.map.find(placeholder.hash, |&idx| {
+    ) -> Result<Displaced<Entry<Key, Qey, Val>>, Val> {
+        timed!(
+            self.insert,
+            self.replace_placeholder_untimed(placeholder, referenced, value)
+        )
+    }
+
+    fn replace_placeholder_untimed(
+        &mut self,
+        placeholder: &SharedPlaceholder<Val>,
+        referenced: bool,
+        value: Val,
+    ) -> Result<Displaced<Entry<Key, Qey, Val>>, Val> {
+        let found = self.map.find(placeholder.hash, |&(_, idx)| {
             if idx != placeholder.idx {
                 return false;
             }
@@ -663,16 +2039,33 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: InternalWeighter<Key, Qey, Val>, B
         if !found {
             return Err(value);
         }
-        let (entry, _) = self.entries.get_mut(placeholder.idx).unwrap();
-        let Entry::Placeholder(Placeholder { key, qey, hot: placeholder_hot, .. }) =
-            mem::replace(entry, Entry::Ghost(0)) else { unreachable!() };
-        let weight = self.weighter.weight(&key, &qey, &value);
-        if weight > self.weight_capacity {
-            // don't admit if it won't fit within the budget
-            return Ok(None);
+        let (entry, _) = self.entries.get(placeholder.idx).unwrap();
+        let Entry::Placeholder(Placeholder {
+            key,
+            qey,
+            hot: placeholder_hot,
+            ..
+        }) = entry
+        else {
+            unreachable!()
+        };
+        let weight = self.weighter.weight(key, qey, &value);
+        trace!(self, Insert, placeholder.hash, weight);
+        let placeholder_hot = *placeholder_hot;
+        if !self.enabled
+            || self.reject_frozen()
+            || self.reject_too_heavy(weight)
+            || self.max_items == 0
+            || (!placeholder_hot && !self.doorkeeper_admits(placeholder.hash))
+        {
+            // don't admit if it won't fit within the budget or it's the first sighting of the key
+            self.map_remove(placeholder.hash, placeholder.idx);
+            let (entry, _) = self.entries.remove(placeholder.idx).unwrap();
+            return Ok(Some(entry).into_iter().collect());
         }
-        let enter_hot =
-            placeholder_hot || self.weight_hot + self.weight_cold + weight <= self.weight_capacity;
+        let version = self.new_version();
+        let now = self.timestamps.now();
+        let enter_hot = placeholder_hot || self.has_room(weight);
         let (state, list_head) = if enter_hot {
             self.num_hot += 1;
             self.weight_hot += weight;
@@ -682,64 +2075,244 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: InternalWeighter<Key, Qey, Val>, B
             self.weight_cold += weight;
             (ResidentState::ColdInTest, &mut self.cold_head)
         };
-        *entry = Entry::Resident(Resident {
-            key,
-            qey,
-            value,
-            state,
-            referenced: referenced.into(),
-        });
-
-        self.entries.link(placeholder.idx, *list_head);
+        // The placeholder isn't linked to any list, so it can be removed and its slot
+        // immediately reused by the resident entry without disturbing the map.
+        let Some((Entry::Placeholder(Placeholder { key, qey, .. }), _)) =
+            self.entries.remove(placeholder.idx)
+        else {
+            unreachable!()
+        };
+        let idx = self.entries.insert(
+            Entry::Resident(Resident {
+                key,
+                qey,
+                value,
+                state,
+                referenced: referenced.into(),
+                version,
+                hash: placeholder.hash,
+                weight: weight as u32,
+                flags: Default::default(),
+            }),
+            *list_head,
+        );
+        debug_assert_eq!(idx, placeholder.idx);
         if list_head.is_none() {
-            *list_head = Some(placeholder.idx);
+            *list_head = Some(idx);
         }
+        self.timestamps.inserted(idx, now);
 
-        let mut evicted = None;
+        let mut evicted = Displaced::default();
         // the replacement may have made the hot section/cache too big
         while self.weight_hot > self.weight_target_hot {
             self.advance_hot();
         }
-        while self.weight_hot + self.weight_cold > self.weight_capacity {
-            evicted = Some(Entry::Resident(self.advance_cold()));
+        while self.over_capacity() {
+            evicted.push(Entry::Resident(self.evict_for_replacement()));
         }
 
         Ok(evicted)
     }
 
+    /// Inserts the value unless a resident entry of the keys exists, counting as a get of the keys.
+    /// Returns the value of the existing entry, if any, and the evicted entries,
+    /// or the value if it's rejected.
+    #[allow(clippy::type_complexity)]
+    pub fn insert_if_absent(
+        &mut self,
+        hash: u64,
+        key: Key,
+        qey: Qey,
+        value: Val,
+    ) -> Result<(Option<&Val>, Displaced<Entry<Key, Qey, Val>>), Rejected<Val>> {
+        if let Some(idx) = self.lookup(hash, &key, &qey) {
+            return Ok((Some(self.resident_at(idx).2), Displaced::default()));
+        }
+        let evicted = self.insert_checked(hash, key, qey, value, 0)?;
+        Ok((None, evicted))
+    }
+
+    /// Like `insert`, but also returns the previous value of the resident entry, if any.
+    /// If the new value is too heavy to be cached the previous entry is removed instead.
+    /// The evicted entries are returned alongside, so they can be dropped outside the locks.
+    /// The value is only given back if the shard is frozen.
+    #[allow(clippy::type_complexity)]
+    pub fn replace(
+        &mut self,
+        hash: u64,
+        key: Key,
+        qey: Qey,
+        value: Val,
+    ) -> Result<(Option<Val>, Displaced<Entry<Key, Qey, Val>>), Rejected<Val>> {
+        let idx = if self.enabled {
+            self.search_resident(hash, &key, &qey)
+        } else {
+            None
+        };
+        let Some(idx) = idx else {
+            return match self.insert_checked(hash, key, qey, value, 0) {
+                Ok(evicted) => Ok((None, evicted)),
+                Err(Rejected::TooHeavy(_)) => Ok((None, Displaced::default())),
+                Err(rejected) => Err(rejected),
+            };
+        };
+        match self.replace_at(idx, value) {
+            Ok((old_value, evicted)) => Ok((
+                Some(old_value),
+                evicted.into_iter().map(Entry::Resident).collect(),
+            )),
+            Err(Rejected::TooHeavy(_)) => {
+                // the new value won't fit, remove the stale one instead of keeping it
+                let (resident, _) = self.remove_at(idx);
+                Ok((Some(resident.into_parts().2), Displaced::default()))
+            }
+            Err(rejected) => Err(rejected),
+        }
+    }
+
+    /// Runs `f` with the value of the resident entry of `key` and `qey`, if any, and applies the
+    /// returned [Compute]. Kept values are reweighed, evicting entries if the shard ends up over
+    /// capacity, while inserted values follow `replace`. Counts as a get of the entry.
+    /// Nothing is done, and `f` isn't run, if the shard is frozen.
+    /// Returns the replaced or removed value, along with the evicted entries.
+    #[allow(clippy::type_complexity)]
+    pub fn compute<F>(
+        &mut self,
+        hash: u64,
+        key: Key,
+        qey: Qey,
+        f: F,
+    ) -> (Option<Val>, Displaced<Entry<Key, Qey, Val>>)
+    where
+        F: FnOnce(Option<&mut Val>) -> Compute<Val>,
+    {
+        if self.frozen {
+            return (None, Displaced::default());
+        }
+        let Some(idx) = self.lookup(hash, &key, &qey) else {
+            return match f(None) {
+                Compute::Insert(value) => (None, self.insert(hash, key, qey, value, 0)),
+                Compute::Keep | Compute::Remove => (None, Displaced::default()),
+            };
+        };
+        match f(Some(self.value_mut_at(idx))) {
+            Compute::Keep => {
+                let slot = idx.get() as usize - 1;
+                let evicted = if self.reweigh(slot..slot + 1) != 0 {
+                    self.evict_excess()
+                } else {
+                    Displaced::default()
+                };
+                (None, evicted.into_iter().map(Entry::Resident).collect())
+            }
+            Compute::Insert(value) => match self.replace_at(idx, value) {
+                Ok((old_value, evicted)) => (
+                    Some(old_value),
+                    evicted.into_iter().map(Entry::Resident).collect(),
+                ),
+                Err(_) => {
+                    // the new value won't fit, remove the stale one instead of keeping it
+                    let (resident, _) = self.remove_at(idx);
+                    (Some(resident.into_parts().2), Displaced::default())
+                }
+            },
+            Compute::Remove => {
+                let (resident, _) = self.remove_at(idx);
+                (Some(resident.into_parts().2), Displaced::default())
+            }
+        }
+    }
+
     pub fn insert(
         &mut self,
         hash: u64,
         key: Key,
         qey: Qey,
         value: Val,
-    ) -> Option<Entry<Key, Qey, Val>> {
+        flags: u16,
+    ) -> Displaced<Entry<Key, Qey, Val>> {
+        self.insert_checked(hash, key, qey, value, flags)
+            .unwrap_or_default()
+    }
+
+    /// Like `insert`, but gives the value back if it's too heavy to be cached.
+    pub fn insert_checked(
+        &mut self,
+        hash: u64,
+        key: Key,
+        qey: Qey,
+        value: Val,
+        flags: u16,
+    ) -> Result<Displaced<Entry<Key, Qey, Val>>, Rejected<Val>> {
+        timed!(
+            self.insert,
+            self.insert_untimed(hash, key, qey, value, flags)
+        )
+    }
+
+    fn insert_untimed(
+        &mut self,
+        hash: u64,
+        key: Key,
+        qey: Qey,
+        value: Val,
+        flags: u16,
+    ) -> Result<Displaced<Entry<Key, Qey, Val>>, Rejected<Val>> {
+        if self.reject_frozen() {
+            return Err(Rejected::Frozen(value));
+        }
+        if !self.enabled {
+            // the value may be newer than the cached one, which mustn't be served once re-enabled
+            return Ok(self
+                .remove_resident(hash, &key, &qey)
+                .map(Entry::Resident)
+                .into_iter()
+                .collect());
+        }
         let weight = self.weighter.weight(&key, &qey, &value);
-        if weight > self.weight_capacity {
+        trace!(self, Insert, hash, weight);
+        if self.reject_too_heavy(weight) {
+            return Err(Rejected::TooHeavy(value));
+        }
+        if self.max_items == 0 {
             // don't admit if it won't fit within the budget
-            return None;
+            return Ok(Displaced::default());
         }
 
         if let Some(idx) = self.search(hash, &key, &qey) {
-            return Some(self.insert_existing(idx, key, qey, value, weight));
+            return Ok(self.insert_existing(idx, hash, key, qey, value, weight, flags));
         }
 
-        let mut evicted;
-        let enter_hot = if self.weight_hot + self.weight_cold + weight > self.weight_capacity {
+        // a key evicted recently during its test period goes straight to the hot section
+        let ghost_hit = self.history.remove(hash);
+        if ghost_hit {
+            self.adapt_hot_target(false);
+        }
+        if !ghost_hit && !self.doorkeeper_admits(hash) {
+            return Ok(Displaced::default());
+        }
+        let mut evicted = Displaced::default();
+        let enter_hot = if !self.has_room(weight) {
+            if !self.can_make_room(weight) {
+                // too many entries are within their minimum residency, don't admit
+                self.rejections.min_residency += 1;
+                return Ok(evicted);
+            }
             // evict until we have enough space for this entry
             loop {
-                evicted = Some(Entry::Resident(self.advance_cold()));
-                if self.weight_hot + self.weight_cold + weight <= self.weight_capacity {
+                evicted.push(Entry::Resident(self.evict_for_replacement()));
+                if self.has_room(weight) {
                     break;
                 }
             }
-            false
+            ghost_hit
         } else {
             // cache is filling up
-            evicted = None;
-            self.weight_hot + weight <= self.weight_target_hot
+            ghost_hit || self.weight_hot + weight <= self.weight_target_hot
         };
 
+        let version = self.new_version();
+        let now = self.timestamps.now();
         let (state, list_head) = if enter_hot {
             self.num_hot += 1;
             self.weight_hot += weight;
@@ -756,15 +2329,24 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: InternalWeighter<Key, Qey, Val>, B
                 value,
                 state,
                 referenced: Default::default(),
+                version,
+                hash,
+                weight: weight as u32,
+                flags: AtomicU16::new(flags),
             }),
             *list_head,
         );
         if list_head.is_none() {
             *list_head = Some(idx);
         }
+        self.timestamps.inserted(idx, now);
         // insert the new key in the map
         self.map_insert(hash, idx);
-        evicted
+        // a ghost hit may have made the hot section too big
+        while self.weight_hot > self.weight_target_hot {
+            self.advance_hot();
+        }
+        Ok(evicted)
     }
 
     pub fn get_value_or_placeholder(
@@ -773,6 +2355,21 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: InternalWeighter<Key, Qey, Val>, B
         key: Key,
         qey: Qey,
     ) -> Result<Val, (SharedPlaceholder<Val>, bool)>
+    where
+        Val: Clone,
+    {
+        timed!(
+            self.get,
+            self.get_value_or_placeholder_untimed(hash, key, qey)
+        )
+    }
+
+    fn get_value_or_placeholder_untimed(
+        &mut self,
+        hash: u64,
+        key: Key,
+        qey: Qey,
+    ) -> Result<Val, (SharedPlaceholder<Val>, bool)>
     where
         Val: Clone,
     {
@@ -780,39 +2377,37 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: InternalWeighter<Key, Qey, Val>, B
             let (entry, _) = self.entries.get_mut(idx).unwrap();
             match entry {
                 Entry::Resident(resident) => {
-                    *resident.referenced.get_mut() = true;
+                    if !self.frozen {
+                        *resident.referenced.get_mut() = true;
+                        self.timestamps.accessed(idx);
+                    }
                     *self.hits.get_mut() += 1;
+                    trace!(self, Hit, hash, resident.weight as u64);
                     Ok(resident.value.clone())
                 }
                 Entry::Placeholder(p) => {
                     *self.hits.get_mut() += 1;
+                    // the value is still being computed, so its weight isn't known yet
+                    trace!(self, Hit, hash, 0);
                     Err((p.shared.clone(), false))
                 }
-                Entry::Ghost(..) => {
-                    *self.misses.get_mut() += 1;
-                    let shared = new_shared_placeholder(hash, idx);
-                    *entry = Entry::Placeholder(Placeholder {
-                        key,
-                        qey,
-                        hot: true,
-                        shared: shared.clone(),
-                    });
-                    let next_ghost = self.entries.unlink(idx);
-                    if self.ghost_head == Some(idx) {
-                        self.ghost_head = next_ghost;
-                    }
-                    Err((shared, true))
-                }
             }
         } else {
             *self.misses.get_mut() += 1;
+            trace!(self, Miss, hash, 0);
+            // a ghost hit makes the entry enter the hot section once the value is inserted,
+            // while frozen the value won't be inserted at all
+            let hot = !self.frozen && self.history.remove(hash);
+            if hot {
+                self.adapt_hot_target(false);
+            }
             let idx = self.entries.next_free();
             let shared = new_shared_placeholder(hash, idx);
             let idx_ = self.entries.insert(
                 Entry::Placeholder(Placeholder {
                     key,
                     qey,
-                    hot: false,
+                    hot,
                     shared: shared.clone(),
                 }),
                 None,
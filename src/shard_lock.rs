@@ -0,0 +1,116 @@
+use std::{
+    sync::atomic::{self, AtomicU64, AtomicUsize},
+    time::Duration,
+};
+
+use crate::{
+    rw_lock::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+    shard::KQCacheShard,
+};
+
+pub type ShardReadGuard<'a, Key, Qey, Val, We, B> =
+    RwLockReadGuard<'a, KQCacheShard<Key, Qey, Val, We, B>>;
+
+/// The lock of a shard, along with estimates of its size readable without locking.
+/// The estimates are refreshed whenever a write guard is released.
+pub struct ShardLock<Key, Qey, Val, We, B> {
+    lock: RwLock<KQCacheShard<Key, Qey, Val, We, B>>,
+    len: AtomicUsize,
+    weight: AtomicU64,
+}
+
+impl<Key, Qey, Val, We, B> ShardLock<Key, Qey, Val, We, B> {
+    pub fn new(shard: KQCacheShard<Key, Qey, Val, We, B>) -> Self {
+        Self {
+            lock: RwLock::new(shard),
+            len: AtomicUsize::new(0),
+            weight: AtomicU64::new(0),
+        }
+    }
+
+    #[inline]
+    pub fn read(&self) -> ShardReadGuard<'_, Key, Qey, Val, We, B> {
+        self.lock.read()
+    }
+
+    #[inline]
+    pub fn try_read(&self) -> Option<ShardReadGuard<'_, Key, Qey, Val, We, B>> {
+        self.lock.try_read()
+    }
+
+    #[inline]
+    pub fn try_read_for(
+        &self,
+        timeout: Duration,
+    ) -> Option<ShardReadGuard<'_, Key, Qey, Val, We, B>> {
+        self.lock.try_read_for(timeout)
+    }
+
+    #[inline]
+    pub fn write(&self) -> ShardWriteGuard<'_, Key, Qey, Val, We, B> {
+        ShardWriteGuard {
+            guard: self.lock.write(),
+            lock: self,
+        }
+    }
+
+    #[inline]
+    pub fn try_write(&self) -> Option<ShardWriteGuard<'_, Key, Qey, Val, We, B>> {
+        let guard = self.lock.try_write()?;
+        Some(ShardWriteGuard { guard, lock: self })
+    }
+
+    #[inline]
+    pub fn try_write_for(
+        &self,
+        timeout: Duration,
+    ) -> Option<ShardWriteGuard<'_, Key, Qey, Val, We, B>> {
+        let guard = self.lock.try_write_for(timeout)?;
+        Some(ShardWriteGuard { guard, lock: self })
+    }
+
+    #[inline]
+    pub fn estimated_len(&self) -> usize {
+        self.len.load(atomic::Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn estimated_weight(&self) -> u64 {
+        self.weight.load(atomic::Ordering::Relaxed)
+    }
+}
+
+/// Write guard of a [ShardLock], refreshing the size estimates of the shard when released.
+pub struct ShardWriteGuard<'a, Key, Qey, Val, We, B> {
+    guard: RwLockWriteGuard<'a, KQCacheShard<Key, Qey, Val, We, B>>,
+    lock: &'a ShardLock<Key, Qey, Val, We, B>,
+}
+
+impl<Key, Qey, Val, We, B> std::ops::Deref for ShardWriteGuard<'_, Key, Qey, Val, We, B> {
+    type Target = KQCacheShard<Key, Qey, Val, We, B>;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<Key, Qey, Val, We, B> std::ops::DerefMut for ShardWriteGuard<'_, Key, Qey, Val, We, B> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+impl<Key, Qey, Val, We, B> Drop for ShardWriteGuard<'_, Key, Qey, Val, We, B> {
+    #[inline]
+    fn drop(&mut self) {
+        // still under the lock, so the stores of consecutive writers can't be reordered
+        self.lock
+            .len
+            .store(self.guard.len(), atomic::Ordering::Relaxed);
+        self.lock
+            .weight
+            .store(self.guard.weight(), atomic::Ordering::Relaxed);
+    }
+}
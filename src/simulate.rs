@@ -0,0 +1,102 @@
+//! Offline replay of access traces through a cache configuration.
+//!
+//! # Example
+//!
+//! ```rust
+//! use quick_cache::{simulate::simulate, OptionsBuilder};
+//!
+//! // a zipf-like trace of keys and their weights
+//! let trace = (0..10_000u64).map(|i| (i % (1 + i % 100), 10));
+//! let report = simulate(
+//!     &OptionsBuilder::new()
+//!         .estimated_items_capacity(50)
+//!         .weight_capacity(500)
+//!         .build()
+//!         .unwrap(),
+//!     trace,
+//! );
+//! assert_eq!(report.accesses, 10_000);
+//! assert!(report.hit_rate() > 0.5);
+//! ```
+
+use std::hash::Hash;
+
+use crate::{unsync::Cache, DefaultHashBuilder, Options, Weighter};
+
+/// Weighs the simulated entries with the weight recorded in the trace, which is stored as the value.
+#[derive(Debug, Clone)]
+struct TraceWeighter;
+
+impl<Key> Weighter<Key, (), u32> for TraceWeighter {
+    #[inline]
+    fn weight(&self, _key: &Key, _qey: &(), val: &u32) -> u32 {
+        *val
+    }
+}
+
+/// Outcome of a simulation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Number of accesses in the trace.
+    pub accesses: u64,
+    /// Number of accesses that found the key in the cache.
+    pub hits: u64,
+    /// Total weight of the accessed entries.
+    pub weight_accessed: u64,
+    /// Total weight of the accessed entries that were found in the cache.
+    pub weight_hit: u64,
+    /// Number of entries evicted to make room for others.
+    pub evictions: u64,
+}
+
+impl Report {
+    /// Number of accesses that didn't find the key in the cache.
+    pub fn misses(&self) -> u64 {
+        self.accesses - self.hits
+    }
+
+    /// Ratio of accesses that found the key in the cache.
+    pub fn hit_rate(&self) -> f64 {
+        if self.accesses == 0 {
+            0.0
+        } else {
+            self.hits as f64 / self.accesses as f64
+        }
+    }
+
+    /// Ratio of the accessed weight (e.g. bytes) that was found in the cache.
+    pub fn byte_hit_rate(&self) -> f64 {
+        if self.weight_accessed == 0 {
+            0.0
+        } else {
+            self.weight_hit as f64 / self.weight_accessed as f64
+        }
+    }
+}
+
+/// Replays `trace`, a sequence of accessed keys and their weights, through a cache built with `options`.
+///
+/// Each access is a lookup followed by an insertion on a miss, like a read-through cache.
+pub fn simulate<Key: Eq + Hash>(
+    options: &Options,
+    trace: impl IntoIterator<Item = (Key, u32)>,
+) -> Report {
+    let mut cache = Cache::with_options(
+        options.clone(),
+        TraceWeighter,
+        DefaultHashBuilder::default(),
+    );
+    let mut report = Report::default();
+    for (key, weight) in trace {
+        report.accesses += 1;
+        report.weight_accessed += weight as u64;
+        if cache.get(&key).is_some() {
+            report.hits += 1;
+            report.weight_hit += weight as u64;
+        } else {
+            cache.insert(key, weight);
+        }
+    }
+    report.evictions = cache.evictions();
+    report
+}
@@ -0,0 +1,89 @@
+/// Count-min sketch of small saturating counters, with periodic aging.
+///
+/// Memory usage is fixed at construction, independently of the number of distinct keys recorded.
+/// After `10 * tracked items` increments all counters are halved so old history fades away.
+#[derive(Clone)]
+pub struct FrequencySketch {
+    counters: Vec<u8>,
+    /// Counters per row - 1, the number of counters per row is always a power of two.
+    mask: usize,
+    increments: usize,
+    reset_at: usize,
+}
+
+const ROWS: usize = 4;
+/// Odd multipliers used to derive a different index per row from the same hash.
+const SEEDS: [u64; ROWS] = [
+    0x9E37_79B9_7F4A_7C15,
+    0xC2B2_AE3D_27D4_EB4F,
+    0x1656_67B1_9E37_79F9,
+    0xD6E8_FEB8_6659_FD93,
+];
+
+impl FrequencySketch {
+    pub fn new(tracked_items: usize) -> Self {
+        // A shard can't hold more than u32::MAX items
+        let tracked_items = tracked_items.clamp(1, u32::MAX as usize);
+        let width = tracked_items.next_power_of_two().max(16);
+        Self {
+            counters: vec![0; width * ROWS],
+            mask: width - 1,
+            increments: 0,
+            reset_at: tracked_items.saturating_mul(10),
+        }
+    }
+
+    /// Resizes the sketch for `tracked_items`, the counters are kept if the width doesn't change.
+    pub fn resize(&mut self, tracked_items: usize) {
+        let resized = Self::new(tracked_items);
+        if resized.mask == self.mask {
+            self.reset_at = resized.reset_at;
+        } else {
+            *self = resized;
+        }
+    }
+
+    /// Bytes allocated by the sketch.
+    pub fn allocated_bytes(&self) -> usize {
+        self.counters.capacity()
+    }
+
+    #[inline]
+    fn index(&self, hash: u64, row: usize) -> usize {
+        let h = hash.wrapping_mul(SEEDS[row]);
+        row * (self.mask + 1) + ((h >> 32) as usize & self.mask)
+    }
+
+    /// Estimated number of times the key with the given hash was recorded.
+    pub fn estimate(&self, hash: u64) -> u8 {
+        (0..ROWS)
+            .map(|row| self.counters[self.index(hash, row)])
+            .min()
+            .unwrap()
+    }
+
+    /// Records an occurrence of the key with the given hash.
+    pub fn increment(&mut self, hash: u64) {
+        for row in 0..ROWS {
+            let idx = self.index(hash, row);
+            self.counters[idx] = self.counters[idx].saturating_add(1);
+        }
+        self.increments += 1;
+        if self.increments >= self.reset_at {
+            self.age();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.counters.fill(0);
+        self.increments = 0;
+    }
+
+    /// Halves all counters.
+    fn age(&mut self) {
+        for counter in &mut self.counters {
+            *counter /= 2;
+        }
+        self.increments /= 2;
+    }
+}
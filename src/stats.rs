@@ -0,0 +1,351 @@
+use std::{
+    cell::RefCell,
+    sync::{
+        atomic::{self, AtomicU64},
+        Arc,
+    },
+};
+
+/// Breakdown of the memory used by the internal structures of a cache.
+///
+/// Only the inline size (i.e. `size_of`) of keys and values is accounted for, memory owned by them
+/// (e.g. the heap buffer of a `String`) is not. The latter is usually what the [Weighter](crate::Weighter)
+/// measures, so comparing the weight with [MemoryUsage::overhead_bytes] shows how much memory the cache
+/// uses on top of the cached data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Number of resident entries.
+    pub len: usize,
+    /// Total weight of the resident entries.
+    pub weight: u64,
+    /// Number of non-resident (ghost) keys tracked by the eviction policy.
+    /// Always 0 when using a [frequency sketch](crate::OptionsBuilder::frequency_sketch).
+    pub non_resident: usize,
+    /// Bytes allocated by the hash tables.
+    pub map_bytes: usize,
+    /// Bytes allocated by the entry slabs, which hold the inline keys and values
+    /// alongside the list links and eviction policy metadata.
+    pub slab_bytes: usize,
+    /// Bytes of the slabs occupied by the inline keys and values of resident entries.
+    pub data_bytes: usize,
+    /// Bytes allocated to track non-resident keys, i.e. the ghosts (or frequency sketch)
+    /// and the doorkeeper filter.
+    pub ghost_bytes: usize,
+}
+
+impl MemoryUsage {
+    /// Total bytes allocated by the cache internal structures.
+    pub fn total_bytes(&self) -> usize {
+        self.map_bytes + self.slab_bytes + self.ghost_bytes
+    }
+
+    /// Bytes allocated by the cache that aren't holding resident keys and values,
+    /// that is: map slots, list links, eviction metadata, non-resident and vacant entries.
+    pub fn overhead_bytes(&self) -> usize {
+        self.total_bytes() - self.data_bytes
+    }
+
+    /// Average overhead, in bytes, per resident entry.
+    pub fn overhead_per_entry(&self) -> f64 {
+        if self.len == 0 {
+            0.0
+        } else {
+            self.overhead_bytes() as f64 / self.len as f64
+        }
+    }
+
+    pub(crate) fn merge(self, other: Self) -> Self {
+        Self {
+            len: self.len + other.len,
+            weight: self.weight + other.weight,
+            non_resident: self.non_resident + other.non_resident,
+            map_bytes: self.map_bytes + other.map_bytes,
+            slab_bytes: self.slab_bytes + other.slab_bytes,
+            data_bytes: self.data_bytes + other.data_bytes,
+            ghost_bytes: self.ghost_bytes + other.ghost_bytes,
+        }
+    }
+}
+
+/// Snapshot of the cache counters, see [Stats::delta] to compute per-interval values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of lookups that found the key.
+    pub hits: u64,
+    /// Number of lookups that didn't find the key.
+    pub misses: u64,
+    /// Number of entries evicted to make room for others.
+    pub evictions: u64,
+    /// Number of insertions rejected, by reason.
+    pub rejections: Rejections,
+    /// Number of resident entries.
+    pub len: usize,
+    /// Total weight of the resident entries.
+    pub weight: u64,
+}
+
+impl Stats {
+    /// Ratio of lookups that found the key.
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+
+    /// Returns the counters accumulated since the `earlier` snapshot,
+    /// alongside the current number of entries and weight.
+    ///
+    /// Counters are saturated at 0, in case they were reset in between snapshots.
+    pub fn delta(&self, earlier: &Stats) -> Stats {
+        Stats {
+            hits: self.hits.saturating_sub(earlier.hits),
+            misses: self.misses.saturating_sub(earlier.misses),
+            evictions: self.evictions.saturating_sub(earlier.evictions),
+            rejections: self.rejections.delta(&earlier.rejections),
+            len: self.len,
+            weight: self.weight,
+        }
+    }
+
+    /// Adds the lookups buffered by threads in `counters`, if any.
+    pub(crate) fn with_buffered(mut self, counters: Option<&BufferedCounters>) -> Self {
+        if let Some(counters) = counters {
+            self.hits += counters.hits();
+            self.misses += counters.misses();
+        }
+        self
+    }
+
+    pub(crate) fn merge(self, other: Self) -> Self {
+        Self {
+            hits: self.hits + other.hits,
+            misses: self.misses + other.misses,
+            evictions: self.evictions + other.evictions,
+            rejections: self.rejections.merge(other.rejections),
+            len: self.len + other.len,
+            weight: self.weight + other.weight,
+        }
+    }
+}
+
+/// Number of insertions rejected by a cache, by reason. Rejected items aren't cached.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rejections {
+    /// The item weighted more than the capacity of its shard.
+    pub too_heavy: u64,
+    /// The item weighted more than the [max_item_weight](crate::OptionsBuilder::max_item_weight).
+    pub max_item_weight: u64,
+    /// The key was seen for the first time by the [doorkeeper](crate::OptionsBuilder::doorkeeper).
+    pub admission: u64,
+    /// The cache was frozen.
+    pub frozen: u64,
+    /// All the entries that could be evicted to make room were inserted within the
+    /// [min_residency](crate::OptionsBuilder::min_residency).
+    pub min_residency: u64,
+}
+
+impl Rejections {
+    /// Number of rejected insertions, for any reason.
+    pub fn total(&self) -> u64 {
+        self.too_heavy + self.max_item_weight + self.admission + self.frozen + self.min_residency
+    }
+
+    fn delta(&self, earlier: &Rejections) -> Rejections {
+        Rejections {
+            too_heavy: self.too_heavy.saturating_sub(earlier.too_heavy),
+            max_item_weight: self.max_item_weight.saturating_sub(earlier.max_item_weight),
+            admission: self.admission.saturating_sub(earlier.admission),
+            frozen: self.frozen.saturating_sub(earlier.frozen),
+            min_residency: self.min_residency.saturating_sub(earlier.min_residency),
+        }
+    }
+
+    fn merge(self, other: Self) -> Self {
+        Self {
+            too_heavy: self.too_heavy + other.too_heavy,
+            max_item_weight: self.max_item_weight + other.max_item_weight,
+            admission: self.admission + other.admission,
+            frozen: self.frozen + other.frozen,
+            min_residency: self.min_residency + other.min_residency,
+        }
+    }
+}
+
+/// Snapshot of the lookups issued through a [Cache](crate::sync::Cache) handle,
+/// see [Cache::with_handle_stats](crate::sync::Cache::with_handle_stats).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HandleStats {
+    /// Number of lookups that found the key.
+    pub hits: u64,
+    /// Number of lookups that didn't find the key.
+    pub misses: u64,
+}
+
+impl HandleStats {
+    /// Ratio of lookups that found the key.
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+
+    /// Returns the counters accumulated since the `earlier` snapshot.
+    pub fn delta(&self, earlier: &HandleStats) -> HandleStats {
+        HandleStats {
+            hits: self.hits.saturating_sub(earlier.hits),
+            misses: self.misses.saturating_sub(earlier.misses),
+        }
+    }
+}
+
+/// Hit and miss counters shared by the clones of a [Cache](crate::sync::Cache) handle.
+#[derive(Debug, Default)]
+pub(crate) struct HandleCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl HandleCounters {
+    pub fn add(&self, hits: u64, misses: u64) {
+        if hits != 0 {
+            self.hits.fetch_add(hits, atomic::Ordering::Relaxed);
+        }
+        if misses != 0 {
+            self.misses.fetch_add(misses, atomic::Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> HandleStats {
+        HandleStats {
+            hits: self.hits.load(atomic::Ordering::Relaxed),
+            misses: self.misses.load(atomic::Ordering::Relaxed),
+        }
+    }
+}
+
+/// Number of lookups a thread buffers for some counters before flushing them.
+const FLUSH_THRESHOLD: u32 = 256;
+/// Max number of counters buffered by a thread, lookups for other counters aren't buffered.
+const MAX_BUFFERED: usize = 32;
+
+/// Hit and miss counters of a cache fed by thread local buffers, see [record_buffered].
+#[derive(Debug, Default)]
+pub struct BufferedCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Clone for BufferedCounters {
+    fn clone(&self) -> Self {
+        Self {
+            hits: AtomicU64::new(self.hits()),
+            misses: AtomicU64::new(self.misses()),
+        }
+    }
+}
+
+impl BufferedCounters {
+    pub fn hits(&self) -> u64 {
+        self.hits.load(atomic::Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Zeroes the counters. Counts still buffered by threads are flushed later on.
+    pub fn reset(&self) {
+        self.hits.store(0, atomic::Ordering::Relaxed);
+        self.misses.store(0, atomic::Ordering::Relaxed);
+    }
+
+    fn add(&self, hits: u64, misses: u64) {
+        if hits != 0 {
+            self.hits.fetch_add(hits, atomic::Ordering::Relaxed);
+        }
+        if misses != 0 {
+            self.misses.fetch_add(misses, atomic::Ordering::Relaxed);
+        }
+    }
+}
+
+struct PendingCounts {
+    counters: Arc<BufferedCounters>,
+    hits: u32,
+    misses: u32,
+}
+
+impl PendingCounts {
+    fn flush(&mut self) {
+        self.counters.add(self.hits as u64, self.misses as u64);
+        self.hits = 0;
+        self.misses = 0;
+    }
+}
+
+/// Pending counts of a thread, flushed when the thread exits.
+struct LocalBuffer(Vec<PendingCounts>);
+
+impl Drop for LocalBuffer {
+    fn drop(&mut self) {
+        for pending in &mut self.0 {
+            pending.flush();
+        }
+    }
+}
+
+thread_local! {
+    static LOCAL_BUFFER: RefCell<LocalBuffer> = const { RefCell::new(LocalBuffer(Vec::new())) };
+}
+
+/// Records a lookup in the current thread buffer for `counters`, which is flushed
+/// every [FLUSH_THRESHOLD] lookups, so `counters` are only eventually accurate.
+///
+/// A thread buffers the counters of up to [MAX_BUFFERED] caches, the lookups of any other cache
+/// are counted right away.
+pub(crate) fn record_buffered(counters: &Arc<BufferedCounters>, hit: bool) {
+    let buffered = LOCAL_BUFFER.try_with(|buffer| {
+        let buffer = &mut buffer.borrow_mut().0;
+        let idx = match buffer
+            .iter()
+            .position(|pending| Arc::ptr_eq(&pending.counters, counters))
+        {
+            Some(idx) => idx,
+            None => {
+                if buffer.len() == MAX_BUFFERED {
+                    // forget the counters of the caches that were dropped since
+                    buffer.retain(|pending| Arc::strong_count(&pending.counters) > 1);
+                    if buffer.len() == MAX_BUFFERED {
+                        return false;
+                    }
+                }
+                buffer.push(PendingCounts {
+                    counters: counters.clone(),
+                    hits: 0,
+                    misses: 0,
+                });
+                buffer.len() - 1
+            }
+        };
+        let pending = &mut buffer[idx];
+        if hit {
+            pending.hits += 1;
+        } else {
+            pending.misses += 1;
+        }
+        if pending.hits + pending.misses >= FLUSH_THRESHOLD {
+            pending.flush();
+        }
+        true
+    });
+    if buffered != Ok(true) {
+        // the buffer is full, or the thread is exiting and its buffer is gone
+        counters.add(hit as u64, !hit as u64);
+    }
+}
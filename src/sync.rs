@@ -1,17 +1,45 @@
+#[cfg(feature = "latency")]
+use crate::LatencyHistograms;
 use crate::{
-    options::{Options, OptionsBuilder},
+    cache_padded::CachePadded,
+    linked_slab::Token,
+    options::{Options, OptionsBuilder, DEFAULT_ITEMS_CAPACITY},
     placeholder::{GuardResult, JoinFuture, PlaceholderGuard},
-    rw_lock::RwLock,
-    shard::{Entry, KQCacheShard},
-    DefaultHashBuilder, UnitWeighter, Weighter,
+    shard::{hash_key, random_below, random_u64, Displaced, Entry, KQCacheShard, Resident},
+    shard_lock::{ShardLock, ShardWriteGuard},
+    stats::{BufferedCounters, HandleCounters},
+    CacheBuilder, Compute, DefaultHashBuilder, Frozen, HandleStats, IntoIter, KQIntoIter,
+    MemoryUsage, Rejected, Stats, TryReserveError, UnitWeighter, Weighter,
 };
 use std::{
     borrow::Borrow,
     future::Future,
-    hash::{BuildHasher, Hash, Hasher},
+    hash::{BuildHasher, Hash},
+    sync::{
+        atomic::{self, AtomicBool},
+        Arc, Mutex,
+    },
     time::Duration,
 };
 
+/// Error returned by the `try_` and `_timeout` functions when the shard lock couldn't be acquired
+/// without blocking or before the timeout expired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WouldBlock;
+
+impl std::fmt::Display for WouldBlock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the cache shard is locked")
+    }
+}
+
+impl std::error::Error for WouldBlock {}
+
+/// Random hash seeds, one per shard.
+fn random_seeds(num_shards: usize) -> Box<[u64]> {
+    (0..num_shards).map(|_| random_u64()).collect()
+}
+
 /// A concurrent two keys cache.
 ///
 /// # Key and Qey
@@ -30,7 +58,20 @@ use std::{
 pub struct KQCache<Key, Qey, Val, We = UnitWeighter, B = DefaultHashBuilder> {
     hash_builder: B,
     #[allow(clippy::type_complexity)]
-    shards: Box<[RwLock<KQCacheShard<Key, Qey, Val, We, B>>]>,
+    shards: Box<[CachePadded<ShardLock<Key, Qey, Val, We, B>>]>,
+    /// Per shard hash seeds, if the shards hash keys independently from the shard selection.
+    shard_hash_seeds: Option<Box<[u64]>>,
+    /// Whether the shard is picked from the hash of the key alone.
+    key_affinity: bool,
+    /// Whether the cache is enabled, checked before accessing the shards, see [KQCache::set_enabled].
+    enabled: AtomicBool,
+    /// Whether the cache is frozen, see [KQCache::set_frozen].
+    frozen: AtomicBool,
+    /// Serializes the toggles applied to all the shards, so they can't end up mixed.
+    toggles: Mutex<()>,
+    /// Counters of the lookups buffered by threads, shared by the shards, see
+    /// [OptionsBuilder::buffered_stats].
+    buffered_counters: Option<Arc<BufferedCounters>>,
     shards_mask: u64,
 }
 
@@ -48,6 +89,15 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val: Clone>
     }
 }
 
+/// Creates a cache which holds up to 1024 items (approximately), see [KQCache::new].
+impl<Key: Eq + Hash, Qey: Eq + Hash, Val: Clone> Default
+    for KQCache<Key, Qey, Val, UnitWeighter, DefaultHashBuilder>
+{
+    fn default() -> Self {
+        Self::new(DEFAULT_ITEMS_CAPACITY)
+    }
+}
+
 impl<Key: Eq + Hash, Qey: Eq + Hash, Val: Clone, We: Weighter<Key, Qey, Val> + Clone>
     KQCache<Key, Qey, Val, We, DefaultHashBuilder>
 {
@@ -124,20 +174,36 @@ impl<
             shard_items_cap = estimated_items_capacity.saturating_add(num_shards - 1) / num_shards;
             shard_weight_cap = weight_capacity.saturating_add(num_shards - 1) / num_shards;
         }
-        let shards = (0..num_shards)
-            .map(|_| {
-                RwLock::new(KQCacheShard::new(
-                    options.hot_allocation,
-                    options.ghost_allocation,
+        let shard_hash_seeds = options
+            .shard_hash_seeds
+            .then(|| random_seeds(num_shards as usize));
+        let buffered_counters = options
+            .buffered_stats
+            .then(|| Arc::new(BufferedCounters::default()));
+        let shards = (0..num_shards as usize)
+            .map(|i| {
+                // padded so that the locks and counters of adjacent shards don't false share
+                CachePadded::new(ShardLock::new(KQCacheShard::new(
+                    &options,
                     shard_items_cap as usize,
                     shard_weight_cap,
+                    options.shard_max_items(num_shards as usize),
+                    options.shard_ghost_capacity(shard_items_cap as usize, num_shards as usize),
                     weighter.clone(),
                     hash_builder.clone(),
-                ))
+                    shard_hash_seeds.as_ref().map(|seeds| seeds[i]),
+                    buffered_counters.clone(),
+                )))
             })
             .collect::<Vec<_>>();
         Self {
             shards: shards.into_boxed_slice(),
+            shard_hash_seeds,
+            key_affinity: options.key_affinity,
+            enabled: AtomicBool::new(true),
+            frozen: AtomicBool::new(false),
+            toggles: Mutex::new(()),
+            buffered_counters,
             hash_builder,
             shards_mask: num_shards - 1,
         }
@@ -150,12 +216,61 @@ impl<
         }
     }
 
-    /// Returns whether the cache is empty
+    /// Returns whether the cache is empty.
+    /// Only takes the shard read locks, one at a time, stopping at the first non-empty shard.
     pub fn is_empty(&self) -> bool {
-        self.shards.iter().any(|s| s.read().len() == 0)
+        self.shards.iter().all(|s| s.read().len() == 0)
+    }
+
+    /// Enables or disables the cache at runtime.
+    ///
+    /// While disabled, gets and peeks return `None` without locking (nor counting a miss) and
+    /// insertions are dropped. The cached entries are preserved, so they're served again once the
+    /// cache is re-enabled; use [KQCache::clear] to drop them as well.
+    /// To avoid serving stale values after re-enabling the cache, insertions of a key while
+    /// disabled still remove its cached value, as do the `get_or_insert` and `get_value_or_guard`
+    /// families of functions, which load the value without inserting it. Concurrent loads of a key
+    /// are still coordinated.
+    pub fn set_enabled(&self, enabled: bool) {
+        let _toggles = self.toggles.lock().unwrap_or_else(|e| e.into_inner());
+        for s in &*self.shards {
+            s.write().set_enabled(enabled);
+        }
+        self.enabled.store(enabled, atomic::Ordering::Relaxed);
+    }
+
+    /// Returns whether the cache is enabled, see [KQCache::set_enabled].
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Freezes or unfreezes the cache.
+    ///
+    /// While frozen, gets and peeks are still served but gets don't alter the entries hotness.
+    /// Insertions are dropped ([KQCache::insert_checked] returns [Rejected::Frozen]), removals
+    /// remove nothing, including [KQCache::retain], [KQCache::extract_if], [KQCache::merge_from] and
+    /// [KQCache::split_off], and [KQCache::compare_exchange] returns the current version. The
+    /// `get_or_insert` and `get_value_or_guard` families of functions return the loaded values
+    /// without inserting them. Whole cache and maintenance functions, like [KQCache::clear],
+    /// [KQCache::drain], [KQCache::relieve_pressure] or [KQCache::prune_idle], still remove entries.
+    ///
+    /// Shards are frozen one after the other, and concurrent calls are serialized so all the shards
+    /// end up in the state of the last call.
+    pub fn set_frozen(&self, frozen: bool) {
+        let _toggles = self.toggles.lock().unwrap_or_else(|e| e.into_inner());
+        self.frozen.store(frozen, atomic::Ordering::Relaxed);
+        for s in &*self.shards {
+            s.write().set_frozen(frozen);
+        }
+    }
+
+    /// Returns whether the cache is frozen, see [KQCache::set_frozen].
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.load(atomic::Ordering::Relaxed)
     }
 
-    /// Returns the number of cached items
+    /// Returns the number of cached items.
+    /// Only takes the shard read locks, one at a time, so it doesn't block concurrent gets.
     pub fn len(&self) -> usize {
         self.shards.iter().map(|s| s.read().len()).sum()
     }
@@ -165,49 +280,479 @@ impl<
         self.shards.iter().map(|s| s.read().weight()).sum()
     }
 
+    /// Returns an estimate of the number of cached items, without taking any lock.
+    /// Each shard publishes its number of items when it's modified, so concurrent operations
+    /// may not be accounted for yet.
+    pub fn estimated_len(&self) -> usize {
+        self.shards.iter().map(|s| s.estimated_len()).sum()
+    }
+
+    /// Returns an estimate of the total weight of cached items, without taking any lock,
+    /// see [KQCache::estimated_len].
+    pub fn estimated_weight(&self) -> u64 {
+        self.shards.iter().map(|s| s.estimated_weight()).sum()
+    }
+
     /// Returns the maximum weight of cached items
     pub fn capacity(&self) -> u64 {
         self.shards.iter().map(|s| s.read().capacity()).sum()
     }
 
+    /// Returns a breakdown of the memory used by the cache internal structures
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.shards
+            .iter()
+            .map(|s| s.read().memory_usage())
+            .fold(MemoryUsage::default(), MemoryUsage::merge)
+    }
+
     /// Returns the number of misses
     pub fn misses(&self) -> u64 {
-        self.shards.iter().map(|s| s.read().misses()).sum()
+        let buffered = self.buffered_counters.as_ref().map_or(0, |c| c.misses());
+        self.shards.iter().map(|s| s.read().misses()).sum::<u64>() + buffered
     }
 
     /// Returns the number of hits
     pub fn hits(&self) -> u64 {
-        self.shards.iter().map(|s| s.read().hits()).sum()
+        let buffered = self.buffered_counters.as_ref().map_or(0, |c| c.hits());
+        self.shards.iter().map(|s| s.read().hits()).sum::<u64>() + buffered
     }
 
-    #[allow(clippy::type_complexity)]
+    /// Returns the number of entries evicted to make room for others
+    pub fn evictions(&self) -> u64 {
+        self.shards.iter().map(|s| s.read().evictions()).sum()
+    }
+
+    /// Returns a snapshot of the cache counters, see [Stats::delta] to compute per-interval values.
+    pub fn stats(&self) -> Stats {
+        self.shards
+            .iter()
+            .map(|s| s.read().stats())
+            .fold(Stats::default(), Stats::merge)
+            .with_buffered(self.buffered_counters.as_deref())
+    }
+
+    /// Returns a snapshot of the counters of each shard.
+    ///
+    /// With [OptionsBuilder::buffered_stats], hits and misses are counted for the whole cache
+    /// and are missing from the shard counters.
+    pub fn shard_stats(&self) -> Vec<Stats> {
+        self.shards.iter().map(|s| s.read().stats()).collect()
+    }
+
+    /// Evicts entries, in eviction order, until the weight of the cache is at most `fraction`
+    /// of its capacity.
+    /// The capacity is unchanged, so the cache can grow back afterwards.
+    ///
+    /// Eviction is incremental: shards are processed one after the other, a batch of entries
+    /// at a time, and evicted entries are dropped outside of the shard locks.
+    /// `fraction` is clamped to `0.0..=1.0`. Returns the number of evicted entries.
+    pub fn relieve_pressure(&self, fraction: f64) -> usize {
+        const BATCH_SIZE: usize = 64;
+        let fraction = fraction.clamp(0.0, 1.0);
+        let mut num_evicted = 0;
+        for s in &*self.shards {
+            loop {
+                let mut shard = s.write();
+                let target_weight = (shard.capacity() as f64 * fraction) as u64;
+                let evicted = shard.evict_down_to(target_weight, BATCH_SIZE);
+                drop(shard);
+                num_evicted += evicted.len();
+                if evicted.len() < BATCH_SIZE {
+                    break;
+                }
+            }
+        }
+        num_evicted
+    }
+
+    /// Changes the maximum weight of the cache at runtime.
+    /// The hot and cold targets and the ghost capacity are recomputed in proportion.
+    ///
+    /// When shrinking, entries are evicted by the eviction policy until the cache fits.
+    /// Eviction is incremental: shards are processed one after the other, a batch of entries
+    /// at a time, and evicted entries are dropped outside of the shard locks.
+    /// Returns the number of evicted entries.
+    pub fn set_capacity(&self, weight_capacity: u64) -> usize {
+        const BATCH_SIZE: usize = 64;
+        let num_shards = self.shards.len() as u64;
+        let shard_weight_cap = weight_capacity.saturating_add(num_shards - 1) / num_shards;
+        let mut num_evicted = 0;
+        for s in &*self.shards {
+            s.write().set_capacity(shard_weight_cap);
+            loop {
+                let mut shard = s.write();
+                let mut evicted = shard.evict_down_to(shard_weight_cap, BATCH_SIZE);
+                let done = evicted.len() < BATCH_SIZE;
+                if done {
+                    // also enforces the max number of items
+                    evicted.extend(shard.evict_excess());
+                }
+                drop(shard);
+                num_evicted += evicted.len();
+                if done {
+                    break;
+                }
+            }
+        }
+        num_evicted
+    }
+
+    /// Runs the eviction policy once, returning the evicted item, if any.
+    /// The victim is chosen by the eviction policy of the heaviest shard.
+    pub fn pop(&self) -> Option<(Key, Qey, Val)> {
+        let s = self.shards.iter().max_by_key(|s| {
+            let shard = s.read();
+            (shard.weight(), shard.len())
+        })?;
+        // The guard is released before the entry is unpacked
+        let evicted = s.write().evict_n(1);
+        evicted.into_iter().next().map(Resident::into_parts)
+    }
+
+    /// Runs the eviction policy up to `n` times, returning the evicted items.
+    ///
+    /// The evictions are spread over the shards, which are processed one after the other.
+    /// The items of each shard are returned in eviction order.
+    pub fn pop_n(&self, n: usize) -> Vec<(Key, Qey, Val)> {
+        let mut popped = Vec::new();
+        while popped.len() < n {
+            let before = popped.len();
+            let per_shard =
+                (n - popped.len()).saturating_add(self.shards.len() - 1) / self.shards.len();
+            for s in &*self.shards {
+                let max_evictions = per_shard.min(n - popped.len());
+                // The guard is released before the entries are unpacked
+                let evicted = s.write().evict_n(max_evictions);
+                popped.extend(evicted.into_iter().map(Resident::into_parts));
+            }
+            if popped.len() == before {
+                break;
+            }
+        }
+        popped
+    }
+
+    /// Runs the eviction policy until at least `weight` is freed or the cache is empty,
+    /// returning the evicted items.
+    ///
+    /// The evictions are spread over the shards, which are processed one after the other.
+    /// The items of each shard are returned in eviction order.
+    pub fn pop_weight(&self, weight: u64) -> Vec<(Key, Qey, Val)> {
+        let mut popped = Vec::new();
+        let mut freed = 0;
+        while freed < weight {
+            let before = popped.len();
+            let per_shard = (weight - freed).saturating_add(self.shards.len() as u64 - 1)
+                / self.shards.len() as u64;
+            for s in &*self.shards {
+                let mut shard = s.write();
+                let shard_weight = shard.weight();
+                let target_weight = shard_weight.saturating_sub(per_shard.min(weight - freed));
+                let evicted = shard.evict_down_to(target_weight, usize::MAX);
+                freed += shard_weight - shard.weight();
+                drop(shard);
+                popped.extend(evicted.into_iter().map(Resident::into_parts));
+                if freed >= weight {
+                    break;
+                }
+            }
+            if popped.len() == before {
+                break;
+            }
+        }
+        popped
+    }
+
+    /// Re-runs the weighter over every cached item, fixing the accounted weight of the items whose
+    /// weight changed. Items are evicted if the cache ends up over capacity.
+    ///
+    /// The sweep is incremental: shards are processed one after the other, a batch of entries
+    /// at a time, and evicted entries are dropped outside of the shard locks.
+    /// Returns the number of items whose weight was fixed.
+    pub fn reweigh_all(&self) -> usize {
+        const BATCH_SIZE: usize = 256;
+        let mut fixed = 0;
+        for s in &*self.shards {
+            let mut start = 0;
+            loop {
+                let mut shard = s.write();
+                let end = start + BATCH_SIZE;
+                fixed += shard.reweigh(start..end);
+                let evicted = shard.evict_excess();
+                let done = end >= shard.num_slots();
+                drop(shard);
+                drop(evicted);
+                if done {
+                    break;
+                }
+                start = end;
+            }
+        }
+        fixed
+    }
+
+    /// Removes all the items.
+    /// Ghost keys and the doorkeeper are reset as well, while the stats are kept.
+    /// Shards are cleared one after the other and the removed items are dropped outside
+    /// of the shard locks. Pending placeholders aren't affected, so the values being computed
+    /// are still inserted.
+    pub fn clear(&self) {
+        for s in &*self.shards {
+            let removed = s.write().clear();
+            drop(removed);
+        }
+    }
+
+    /// Iterates over clones of the cached items in an arbitrary order.
+    /// Like peeks, the iteration doesn't alter the keys "hotness".
+    ///
+    /// The iteration is weakly consistent: a single shard is locked at a time, for a batch of
+    /// entries, so items inserted or removed concurrently may or may not be yielded,
+    /// and items moved during the iteration may even be yielded twice.
+    pub fn iter(&self) -> impl Iterator<Item = (Key, Qey, Val)> + '_
+    where
+        Key: Clone,
+        Qey: Clone,
+    {
+        self.iter_with(|key, qey, val| (key.clone(), qey.clone(), val.clone()))
+    }
+
+    /// Iterates over clones of the keys of the cached items in an arbitrary order,
+    /// without cloning the values. The iteration is weakly consistent, see [KQCache::iter].
+    pub fn keys(&self) -> impl Iterator<Item = (Key, Qey)> + '_
+    where
+        Key: Clone,
+        Qey: Clone,
+    {
+        self.iter_with(|key, qey, _| (key.clone(), qey.clone()))
+    }
+
+    /// Iterates over clones of the cached values in an arbitrary order.
+    /// The iteration is weakly consistent, see [KQCache::iter].
+    pub fn values(&self) -> impl Iterator<Item = Val> + '_ {
+        self.iter_with(|_, _, val| val.clone())
+    }
+
+    /// Weakly consistent iteration, see [KQCache::iter], yielding `f` of the cached items.
+    fn iter_with<'a, T: 'a, F>(&'a self, mut f: F) -> impl Iterator<Item = T> + 'a
+    where
+        F: FnMut(&Key, &Qey, &Val) -> T + 'a,
+    {
+        const BATCH_SIZE: usize = 256;
+        let mut shard_idx = 0;
+        let mut start = 0;
+        let mut batch = Vec::new().into_iter();
+        std::iter::from_fn(move || loop {
+            if let Some(item) = batch.next() {
+                return Some(item);
+            }
+            if !self.is_enabled() {
+                return None;
+            }
+            let shard = self.shards.get(shard_idx)?.read();
+            let end = start + BATCH_SIZE;
+            batch = shard
+                .iter_slots(start..end)
+                .map(|(key, qey, val)| f(key, qey, val))
+                .collect::<Vec<_>>()
+                .into_iter();
+            if end >= shard.num_slots() {
+                shard_idx += 1;
+                start = 0;
+            } else {
+                start = end;
+            }
+        })
+    }
+
+    /// Removes all the items, like [KQCache::clear], returning them in an arbitrary order.
+    /// Shards are drained one after the other, so items inserted concurrently may be left behind.
+    pub fn drain(&self) -> KQIntoIter<Key, Qey, Val> {
+        let mut residents = Vec::with_capacity(self.len());
+        for s in &*self.shards {
+            residents.append(&mut s.write().clear());
+        }
+        KQIntoIter::new(residents)
+    }
+
+    /// Removes the items for which `predicate` returns `false`,
+    /// without altering the "hotness" of the kept ones.
+    /// Shards are processed one after the other, `predicate` being called with the shard lock held,
+    /// and the removed items are dropped outside of the shard locks.
+    pub fn retain<F>(&self, mut predicate: F)
+    where
+        F: FnMut(&Key, &Qey, &Val) -> bool,
+    {
+        for s in &*self.shards {
+            let removed = s
+                .write()
+                .take_matching(|key, qey, val| !predicate(key, qey, val));
+            drop(removed);
+        }
+    }
+
+    /// Lazily removes the items for which `predicate` returns `true`, yielding them in an
+    /// arbitrary order.
+    ///
+    /// Contrary to [KQCache::retain], a shard lock is only held while looking for the next
+    /// matching item, up to a batch of slots at a time, and the iteration is weakly consistent,
+    /// see [KQCache::iter]. Items not reached before the iterator is dropped are kept.
+    pub fn extract_if<'a, F>(
+        &'a self,
+        mut predicate: F,
+    ) -> impl Iterator<Item = (Key, Qey, Val)> + 'a
+    where
+        F: FnMut(&Key, &Qey, &Val) -> bool + 'a,
+    {
+        const BATCH_SIZE: usize = 256;
+        let mut shard_idx = 0;
+        let mut start = 0;
+        std::iter::from_fn(move || loop {
+            let mut shard = self.shards.get(shard_idx)?.write();
+            let mut slots = start..start + BATCH_SIZE;
+            let taken = shard.take_first_matching_in(&mut slots, &mut predicate);
+            if slots.start >= shard.num_slots() {
+                shard_idx += 1;
+                start = 0;
+            } else {
+                start = slots.start;
+            }
+            drop(shard);
+            if let Some(resident) = taken {
+                return Some(resident.into_parts());
+            }
+        })
+    }
+
+    /// Evicts the entries that weren't accessed for the `idle_timeout` set in the [Options],
+    /// even if the cache is under capacity. Does nothing if `idle_timeout` isn't set.
+    /// Shards are pruned one after the other. Returns the number of evicted entries.
+    pub fn prune_idle(&self) -> usize {
+        let mut num_evicted = 0;
+        for s in &*self.shards {
+            let evicted = s.write().prune_idle();
+            num_evicted += evicted.len();
+        }
+        num_evicted
+    }
+
+    /// Ages the cache by clearing the "referenced" bit of about `fraction` of its entries, each pass
+    /// continuing where the previous one stopped. Entries not accessed since lose the protection of
+    /// past accesses, so they are demoted or evicted when the clock hand reaches them.
+    ///
+    /// In workloads with few insertions the clock hand rarely moves, so calling this periodically
+    /// prevents entries that were popular long ago from staying in the cache indefinitely.
+    /// Returns the number of cleared bits.
+    pub fn decay_referenced(&self, fraction: f64) -> usize {
+        self.shards
+            .iter()
+            .map(|s| s.write().decay_referenced(fraction))
+            .sum()
+    }
+
+    /// Resets the hits, misses, evictions and rejections counters.
+    /// Each shard is reset atomically, but the shards are reset one after the other.
+    ///
+    /// With [OptionsBuilder::buffered_stats], the lookups buffered by threads before the reset
+    /// are flushed after it, so the hits and misses aren't exact after a reset.
+    pub fn reset_stats(&self) {
+        for s in &*self.shards {
+            s.write().reset_stats();
+        }
+        if let Some(counters) = &self.buffered_counters {
+            counters.reset();
+        }
+    }
+
+    /// Returns the latency histograms of the cache operations.
+    #[cfg(feature = "latency")]
+    pub fn latency_histograms(&self) -> LatencyHistograms {
+        self.shards
+            .iter()
+            .map(|s| s.read().latency_histograms())
+            .fold(LatencyHistograms::default(), LatencyHistograms::merge)
+    }
+
+    /// Returns the number of shards of the cache.
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Returns the index, in `0..shard_count()`, of the shard holding the item with key `key`
+    /// and qey `qey`.
+    /// The mapping only depends on the hash builder and the number of shards.
+    pub fn shard_for<Q, W>(&self, key: &Q, qey: &W) -> usize
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        self.shard_index(key, qey).0
+    }
+
+    /// Returns the index of the shard of the key and qey, along with the hash it was picked from.
     #[inline]
-    fn shard_for<Q: ?Sized, W: ?Sized>(
-        &self,
-        key: &Q,
-        qey: &W,
-    ) -> Option<(&RwLock<KQCacheShard<Key, Qey, Val, We, B>>, u64)>
+    fn shard_index<Q, W>(&self, key: &Q, qey: &W) -> (usize, u64)
     where
         Key: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
         Qey: Borrow<W>,
-        W: Hash + Eq,
+        W: Hash + Eq + ?Sized,
     {
-        let mut hasher = self.hash_builder.build_hasher();
-        key.hash(&mut hasher);
-        qey.hash(&mut hasher);
-        let hash = hasher.finish();
+        let hash = if self.key_affinity {
+            hash_key(&self.hash_builder, None, key, &())
+        } else {
+            hash_key(&self.hash_builder, None, key, qey)
+        };
         // When choosing the shard, rotate the hash bits usize::BITS / 2 so that we
         // give preference to the bits in the middle of the hash.
         // Internally hashbrown uses the lower bits for start of probing + the 7 highest,
         // so by picking something else we improve the real entropy available to each hashbrown shard.
         let shard_idx = (hash.rotate_right(usize::BITS / 2) & self.shards_mask) as usize;
-        self.shards.get(shard_idx).map(|s| (s, hash))
+        (shard_idx, hash)
+    }
+
+    #[allow(clippy::type_complexity)]
+    #[inline]
+    fn shard_and_hash<Q, W>(
+        &self,
+        key: &Q,
+        qey: &W,
+    ) -> Option<(&ShardLock<Key, Qey, Val, We, B>, u64)>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        let (shard_idx, hash) = self.locate(key, qey);
+        self.shards.get(shard_idx).map(|s| (&**s, hash))
+    }
+
+    /// Returns the index of the shard of the key and qey, along with their hash within the shard.
+    #[inline]
+    fn locate<Q, W>(&self, key: &Q, qey: &W) -> (usize, u64)
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        let (shard_idx, hash) = self.shard_index(key, qey);
+        let hash = match &self.shard_hash_seeds {
+            Some(seeds) => hash_key(&self.hash_builder, Some(seeds[shard_idx]), key, qey),
+            None if self.key_affinity => hash_key(&self.hash_builder, None, key, qey),
+            None => hash,
+        };
+        (shard_idx, hash)
     }
 
     /// Reserver additional space for `additional` entries.
+    /// The space is split evenly between the shards.
     /// Note that this is counted in entries, and is not weighted.
-    pub fn reserve(&mut self, additional: usize) {
+    pub fn reserve(&self, additional: usize) {
         let additional_per_shard =
             additional.saturating_add(self.shards.len() - 1) / self.shards.len();
         for s in &*self.shards {
@@ -215,291 +760,1715 @@ impl<
         }
     }
 
+    /// Tries to reserve additional space for `additional` entries,
+    /// returning an error instead of aborting if the allocation fails.
+    /// Note that this is counted in entries, and is not weighted.
+    /// On error, some shards may have already reserved their share.
+    pub fn try_reserve(&self, additional: usize) -> Result<(), TryReserveError> {
+        let additional_per_shard =
+            additional.saturating_add(self.shards.len() - 1) / self.shards.len();
+        for s in &*self.shards {
+            s.write().try_reserve(additional_per_shard)?;
+        }
+        Ok(())
+    }
+
+    /// Shrinks the internal storage as much as possible, releasing the memory held
+    /// for entries that were evicted or removed.
+    /// Shards are compacted one after the other, each under its write lock.
+    pub fn shrink_to_fit(&self) {
+        for s in &*self.shards {
+            s.write().shrink_to_fit();
+        }
+    }
+
+    /// Rebuilds the cache internal tables with `hash_builder`.
+    /// The shard hash seeds, if enabled, are also regenerated.
+    /// Entries keep their hot/cold state, but ghost keys and the doorkeeper are reset,
+    /// as they only retain key hashes. Entries are redistributed among the shards, so some of them
+    /// may be evicted if a shard ends up over its capacity.
+    pub fn rehash_with(&mut self, hash_builder: B) {
+        let mut residents = Vec::with_capacity(self.len());
+        for s in &*self.shards {
+            residents.append(&mut s.write().take_residents());
+        }
+        self.hash_builder = hash_builder;
+        if let Some(seeds) = &mut self.shard_hash_seeds {
+            *seeds = random_seeds(seeds.len());
+        }
+        for (i, s) in self.shards.iter().enumerate() {
+            let seed = self.shard_hash_seeds.as_ref().map(|seeds| seeds[i]);
+            s.write().set_hasher(self.hash_builder.clone(), seed);
+        }
+        for resident in residents {
+            let (key, qey) = resident.keys();
+            let (shard, _) = self.shard_and_hash(key, qey).unwrap();
+            shard.write().restore_resident(resident);
+        }
+        for s in &*self.shards {
+            s.write().evict_excess();
+        }
+    }
+
+    /// Moves all the items of `other` into this cache, leaving `other` empty.
+    /// Items keep their hot/cold state and replace the items with the same keys in this cache.
+    /// Their weight is recomputed with the weighter of this cache, and items are evicted
+    /// if the cache ends up over capacity.
+    ///
+    /// Shards of `other` are moved one after the other, so concurrent readers may find
+    /// an item in neither cache while it's moved. Pending placeholders of `other` are kept,
+    /// so their guards still insert into `other`. Nothing is moved if either cache is frozen.
+    pub fn merge_from(&self, other: &Self) {
+        if self.is_frozen() {
+            return;
+        }
+        for s in &*other.shards {
+            let residents = s.write().take_matching(|_, _, _| true);
+            self.merge_residents(residents);
+        }
+    }
+
+    /// Moves the items matching `predicate` into a new cache built from `options`,
+    /// with clones of the weighter and hash builder of this cache.
+    /// Items keep their hot/cold state, and the new cache evicts them as usual if they don't fit.
+    ///
+    /// Shards are split one after the other and `predicate` is called with the shard lock held.
+    pub fn split_off<F>(&self, options: Options, mut predicate: F) -> Self
+    where
+        F: FnMut(&Key, &Qey, &Val) -> bool,
+    {
+        let weighter = self.shards[0].read().weighter().clone();
+        let split = Self::with_options(options, weighter, self.hash_builder.clone());
+        for s in &*self.shards {
+            let residents = s.write().take_matching(&mut predicate);
+            split.merge_residents(residents);
+        }
+        split
+    }
+
+    /// Merges residents taken from a cache into the matching shards, dropping the entries
+    /// left out outside of the shard locks.
+    fn merge_residents(&self, residents: Vec<Resident<Key, Qey, Val>>) {
+        let mut per_shard: Vec<Vec<_>> = self.shards.iter().map(|_| Vec::new()).collect();
+        for resident in residents {
+            let (key, qey) = resident.keys();
+            let (shard_idx, _) = self.shard_index(key, qey);
+            per_shard[shard_idx].push(resident);
+        }
+        for (s, residents) in self.shards.iter().zip(per_shard) {
+            if residents.is_empty() {
+                continue;
+            }
+            let mut shard = s.write();
+            let mut left_out = Vec::new();
+            for resident in residents {
+                left_out.extend(shard.merge_resident(resident));
+            }
+            left_out.extend(shard.evict_excess());
+            drop(shard);
+            drop(left_out);
+        }
+    }
+
     /// Fetches an item from the cache whose keys are `key` + `qey`.
-    pub fn get<Q: ?Sized, W: ?Sized>(&self, key: &Q, qey: &W) -> Option<Val>
+    pub fn get<Q, W>(&self, key: &Q, qey: &W) -> Option<Val>
     where
         Key: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
         Qey: Borrow<W>,
-        W: Hash + Eq,
+        W: Hash + Eq + ?Sized,
     {
-        let (shard, hash) = self.shard_for(key, qey)?;
+        if !self.is_enabled() {
+            return None;
+        }
+        let (shard, hash) = self.shard_and_hash(key, qey)?;
         shard.read().get(hash, key, qey).cloned()
     }
 
-    /// Peeks an item from the cache whose keys are `key` + `qey`.
-    /// Contrary to gets, peeks don't alter the key "hotness".
-    pub fn peek<Q: ?Sized, W: ?Sized>(&self, key: &Q, qey: &W) -> Option<Val>
+    /// Fetches many items from the cache at once, with keys and qeys `keys`.
+    /// Returns the values in the order of `keys`, `None` for the ones that aren't cached.
+    ///
+    /// The keys are grouped by shard so each shard lock is acquired only once.
+    pub fn get_many<'k, Q, W, I>(&self, keys: I) -> Vec<Option<Val>>
     where
+        I: IntoIterator<Item = (&'k Q, &'k W)>,
         Key: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized + 'k,
         Qey: Borrow<W>,
-        W: Hash + Eq,
+        W: Hash + Eq + ?Sized + 'k,
     {
-        let (shard, hash) = self.shard_for(key, qey)?;
-        shard.read().peek(hash, key, qey).cloned()
+        let mut located: Vec<_> = keys
+            .into_iter()
+            .enumerate()
+            .map(|(i, (key, qey))| {
+                let (shard_idx, hash) = self.locate(key, qey);
+                (shard_idx, hash, i, key, qey)
+            })
+            .collect();
+        let mut values = vec![None; located.len()];
+        if !self.is_enabled() {
+            return values;
+        }
+        located.sort_unstable_by_key(|l| l.0);
+        let mut located = located.into_iter().peekable();
+        while let Some(&(shard_idx, ..)) = located.peek() {
+            let shard = self.shards[shard_idx].read();
+            while let Some((_, hash, i, key, qey)) = located.next_if(|l| l.0 == shard_idx) {
+                values[i] = shard.get(hash, key, qey).cloned();
+            }
+        }
+        values
     }
 
-    /// Remove an item from the cache whose key is `key` and qey is `qey`.
-    /// Returns whether an entry was removed.
-    pub fn remove<Q: ?Sized, W: ?Sized>(&self, key: &Q, qey: &W) -> bool
+    /// Fetches an item from the cache whose keys are `key` + `qey`,
+    /// without waiting if the shard is locked by a writer.
+    pub fn try_get<Q, W>(&self, key: &Q, qey: &W) -> Result<Option<Val>, WouldBlock>
     where
         Key: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
         Qey: Borrow<W>,
-        W: Hash + Eq,
+        W: Hash + Eq + ?Sized,
     {
-        if let Some((shard, hash)) = self.shard_for(key, qey) {
-            // Any evictions will be dropped outside of the lock
-            let evicted = shard.write().remove(hash, key, qey);
-            matches!(evicted, Some(Entry::Resident(_)))
+        if !self.is_enabled() {
+            return Ok(None);
+        }
+        if let Some((shard, hash)) = self.shard_and_hash(key, qey) {
+            let shard = shard.try_read().ok_or(WouldBlock)?;
+            Ok(shard.get(hash, key, qey).cloned())
         } else {
-            false
+            Ok(None)
         }
     }
 
-    /// Inserts an item in the cache with key `key` and qey `qey`.
-    pub fn insert(&self, key: Key, qey: Qey, value: Val) {
-        if let Some((shard, hash)) = self.shard_for(&key, &qey) {
-            // Any evictions will be dropped outside of the lock
-            let _evicted = shard.write().insert(hash, key, qey, value);
+    /// Fetches an item from the cache whose keys are `key` + `qey`,
+    /// waiting at most `timeout` if the shard is locked by a writer.
+    pub fn get_timeout<Q, W>(
+        &self,
+        key: &Q,
+        qey: &W,
+        timeout: Duration,
+    ) -> Result<Option<Val>, WouldBlock>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        if !self.is_enabled() {
+            return Ok(None);
+        }
+        if let Some((shard, hash)) = self.shard_and_hash(key, qey) {
+            let shard = shard.try_read_for(timeout).ok_or(WouldBlock)?;
+            Ok(shard.get(hash, key, qey).cloned())
+        } else {
+            Ok(None)
         }
     }
 
-    /// Gets an item from the cache with key `key` and qey `qey`.
-    /// If the corresponding value isn't present in the cache, this functions returns a guard
-    /// that can be used to insert the value once it's computed.
-    /// While the returned guard is alive, other calls with the same key and qey using the
-    /// `get_value_guard` or `get_or_insert` family of functions will wait until the guard
-    /// is dropped or the value is inserted.
-    pub fn get_value_or_guard<'a>(
-        &'a self,
-        key: &Key,
-        qey: &Qey,
-        timeout: Option<Duration>,
-    ) -> GuardResult<'a, Key, Qey, Val, We, B>
+    /// Samples an item of the cache at random.
+    /// Like peeks, sampling doesn't alter the key "hotness".
+    ///
+    /// Shards are picked in proportion to their number of items, so all the items are about
+    /// equally likely. Only a single shard read lock is usually taken.
+    /// Returns `None` if the cache is empty or disabled.
+    pub fn random_entry(&self) -> Option<(Key, Qey, Val)>
     where
         Key: Clone,
         Qey: Clone,
     {
-        let (shard, hash) = self.shard_for(key, qey).unwrap();
-        if let Some(v) = shard.read().get(hash, key, qey) {
-            return GuardResult::Value(v.clone());
+        if !self.is_enabled() {
+            return None;
         }
-        PlaceholderGuard::join(shard, hash, key.clone(), qey.clone(), timeout)
+        let clone_parts =
+            |(key, qey, val): (&Key, &Qey, &Val)| (key.clone(), qey.clone(), val.clone());
+        let total: usize = self.shards.iter().map(|s| s.estimated_len()).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut nth = random_below(total as u64) as usize;
+        let picked = self.shards.iter().find(|s| {
+            let len = s.estimated_len();
+            if nth < len {
+                return true;
+            }
+            nth -= len;
+            false
+        });
+        if let Some(item) = picked.and_then(|s| s.read().random_resident().map(clone_parts)) {
+            return Some(item);
+        }
+        // the shards changed in between, settle for any item
+        self.shards
+            .iter()
+            .find_map(|s| s.read().random_resident().map(clone_parts))
     }
 
-    /// Gets or inserts an item in the cache with key `key` and qey `qey`.
-    pub fn get_or_insert_with<E>(
-        &self,
-        key: &Key,
-        qey: &Qey,
+    /// Peeks an item from the cache whose keys are `key` + `qey`.
+    /// Contrary to gets, peeks don't alter the key "hotness".
+    pub fn peek<Q, W>(&self, key: &Q, qey: &W) -> Option<Val>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        if !self.is_enabled() {
+            return None;
+        }
+        let (shard, hash) = self.shard_and_hash(key, qey)?;
+        shard.read().peek(hash, key, qey).cloned()
+    }
+
+    /// Returns whether an item whose keys are `key` + `qey` is cached, without cloning it.
+    /// Like peeks, this doesn't alter the key "hotness" nor count a hit or a miss.
+    pub fn contains_key<Q, W>(&self, key: &Q, qey: &W) -> bool
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        if !self.is_enabled() {
+            return false;
+        }
+        let Some((shard, hash)) = self.shard_and_hash(key, qey) else {
+            return false;
+        };
+        shard.read().peek(hash, key, qey).is_some()
+    }
+
+    /// Fetches an item from the cache whose keys are `key` + `qey` for modification.
+    ///
+    /// The returned guard holds the shard write lock, blocking all the other operations
+    /// on the shard until it's dropped. On drop the weight of the item is recomputed with the
+    /// [Weighter], evicting items if the cache ended up over capacity.
+    /// Mutable accesses through the guard change the item version.
+    ///
+    /// Returns `None` if the item isn't cached, or the cache is disabled or frozen.
+    pub fn get_mut<Q, W>(&self, key: &Q, qey: &W) -> Option<RefMut<'_, Key, Qey, Val, We, B>>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        if !self.is_enabled() {
+            return None;
+        }
+        let (shard, hash) = self.shard_and_hash(key, qey)?;
+        let mut shard = shard.write();
+        if shard.is_frozen() {
+            return None;
+        }
+        let idx = shard.lookup(hash, key, qey)?;
+        Some(RefMut {
+            shard: Some(shard),
+            idx,
+        })
+    }
+
+    /// Peeks an item from the cache whose keys are `key` + `qey` for modification.
+    /// Contrary to gets, peeks don't alter the key "hotness" nor count a hit or a miss.
+    ///
+    /// Like with [KQCache::get_mut], the returned guard holds the shard write lock
+    /// and recomputes the weight of the item when dropped.
+    ///
+    /// Returns `None` if the item isn't cached, or the cache is disabled or frozen.
+    pub fn peek_mut<Q, W>(&self, key: &Q, qey: &W) -> Option<RefMut<'_, Key, Qey, Val, We, B>>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        if !self.is_enabled() {
+            return None;
+        }
+        let (shard, hash) = self.shard_and_hash(key, qey)?;
+        let shard = shard.write();
+        if shard.is_frozen() {
+            return None;
+        }
+        let idx = shard.find(hash, key, qey)?;
+        Some(RefMut {
+            shard: Some(shard),
+            idx,
+        })
+    }
+
+    /// Fetches an item from the cache whose keys are `key` + `qey`, along with its version.
+    ///
+    /// The version changes every time the entry is modified, see [KQCache::compare_exchange].
+    pub fn get_with_version<Q, W>(&self, key: &Q, qey: &W) -> Option<(Val, u64)>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        if !self.is_enabled() {
+            return None;
+        }
+        let (shard, hash) = self.shard_and_hash(key, qey)?;
+        let shard = shard.read();
+        let (value, version) = shard.get_with_version(hash, key, qey)?;
+        Some((value.clone(), version))
+    }
+
+    /// Peeks an item from the cache whose keys are `key` + `qey`, along with its version.
+    /// Contrary to gets, peeks don't alter the key "hotness".
+    pub fn peek_with_version<Q, W>(&self, key: &Q, qey: &W) -> Option<(Val, u64)>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        if !self.is_enabled() {
+            return None;
+        }
+        let (shard, hash) = self.shard_and_hash(key, qey)?;
+        let shard = shard.read();
+        let (value, version) = shard.peek_with_version(hash, key, qey)?;
+        Some((value.clone(), version))
+    }
+
+    /// Fetches an item from the cache whose keys are `key` + `qey`, along with its flags.
+    ///
+    /// Flags are a few bits set by the user and kept as is by the cache, see [KQCache::insert_with_flags].
+    pub fn get_with_flags<Q, W>(&self, key: &Q, qey: &W) -> Option<(Val, u16)>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        if !self.is_enabled() {
+            return None;
+        }
+        let (shard, hash) = self.shard_and_hash(key, qey)?;
+        let shard = shard.read();
+        let (value, flags) = shard.get_with_flags(hash, key, qey)?;
+        Some((value.clone(), flags))
+    }
+
+    /// Peeks an item from the cache whose keys are `key` + `qey`, along with its flags.
+    /// Contrary to gets, peeks don't alter the key "hotness".
+    pub fn peek_with_flags<Q, W>(&self, key: &Q, qey: &W) -> Option<(Val, u16)>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        if !self.is_enabled() {
+            return None;
+        }
+        let (shard, hash) = self.shard_and_hash(key, qey)?;
+        let shard = shard.read();
+        let (value, flags) = shard.peek_with_flags(hash, key, qey)?;
+        Some((value.clone(), flags))
+    }
+
+    /// Replaces the flags of the item whose keys are `key` + `qey`, without altering its
+    /// "hotness" nor its version. Only a read lock of the shard is needed.
+    /// Returns the previous flags, or `None` if the item isn't cached.
+    pub fn set_flags<Q, W>(&self, key: &Q, qey: &W, flags: u16) -> Option<u16>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        if !self.is_enabled() {
+            return None;
+        }
+        let (shard, hash) = self.shard_and_hash(key, qey)?;
+        shard.read().set_flags(hash, key, qey, flags)
+    }
+
+    /// Remove an item from the cache whose key is `key` and qey is `qey`.
+    /// Returns whether an entry was removed, see [KQCache::remove_checked] to tell a
+    /// missing item from a frozen cache.
+    pub fn remove<Q, W>(&self, key: &Q, qey: &W) -> bool
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        if let Some((shard, hash)) = self.shard_and_hash(key, qey) {
+            // Any evictions will be dropped outside of the lock
+            let evicted = shard.write().remove(hash, key, qey);
+            matches!(evicted, Some(Entry::Resident(_)))
+        } else {
+            false
+        }
+    }
+
+    /// Remove an item from the cache whose key is `key` and qey is `qey`.
+    /// Contrary to [KQCache::remove], an error is returned if the cache is frozen,
+    /// see [KQCache::set_frozen].
+    pub fn remove_checked<Q, W>(&self, key: &Q, qey: &W) -> Result<bool, Frozen>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        let Some((shard, hash)) = self.shard_and_hash(key, qey) else {
+            return Ok(false);
+        };
+        let mut shard = shard.write();
+        if shard.is_frozen() {
+            return Err(Frozen);
+        }
+        let evicted = shard.remove(hash, key, qey);
+        drop(shard);
+        Ok(matches!(evicted, Some(Entry::Resident(_))))
+    }
+
+    /// Removes many items from the cache at once, with keys and qeys `keys`.
+    /// Returns the number of items that were cached.
+    ///
+    /// The keys are grouped by shard so each shard lock is acquired only once.
+    pub fn remove_many<'k, Q, W, I>(&self, keys: I) -> usize
+    where
+        I: IntoIterator<Item = (&'k Q, &'k W)>,
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized + 'k,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized + 'k,
+    {
+        let mut located: Vec<_> = keys
+            .into_iter()
+            .map(|(key, qey)| {
+                let (shard_idx, hash) = self.locate(key, qey);
+                (shard_idx, hash, key, qey)
+            })
+            .collect();
+        located.sort_unstable_by_key(|l| l.0);
+        let mut removed = Vec::new();
+        let mut located = located.into_iter().peekable();
+        while let Some(&(shard_idx, ..)) = located.peek() {
+            let mut shard = self.shards[shard_idx].write();
+            while let Some((_, hash, key, qey)) = located.next_if(|l| l.0 == shard_idx) {
+                if let Some(Entry::Resident(resident)) = shard.remove(hash, key, qey) {
+                    removed.push(resident);
+                }
+            }
+        }
+        // The removed items are dropped outside of the locks
+        removed.len()
+    }
+
+    /// Inserts an item in the cache with key `key` and qey `qey`.
+    pub fn insert(&self, key: Key, qey: Qey, value: Val) {
+        if let Some((shard, hash)) = self.shard_and_hash(&key, &qey) {
+            // Any evictions will be dropped outside of the lock
+            let _evicted = shard.write().insert(hash, key, qey, value, 0);
+        }
+    }
+
+    /// Inserts many items in the cache at once, as if inserted one by one in order.
+    /// Returns the items displaced by the insertions, i.e. evicted to make room
+    /// or replaced by an item with the same keys.
+    ///
+    /// The items are grouped by shard so each shard lock is acquired only once.
+    pub fn insert_many<I>(&self, items: I) -> Vec<(Key, Qey, Val)>
+    where
+        I: IntoIterator<Item = (Key, Qey, Val)>,
+    {
+        let mut located: Vec<_> = items
+            .into_iter()
+            .map(|(key, qey, value)| {
+                let (shard_idx, hash) = self.locate(&key, &qey);
+                (shard_idx, hash, key, qey, value)
+            })
+            .collect();
+        // stable, so the last of the items with the same keys wins
+        located.sort_by_key(|l| l.0);
+        let mut displaced = Vec::new();
+        // replaced placeholders are also dropped outside of the locks
+        let mut placeholders = Vec::new();
+        let mut located = located.into_iter().peekable();
+        while let Some(&(shard_idx, ..)) = located.peek() {
+            let mut shard = self.shards[shard_idx].write();
+            while let Some((_, hash, key, qey, value)) = located.next_if(|l| l.0 == shard_idx) {
+                for entry in shard.insert(hash, key, qey, value, 0) {
+                    match entry {
+                        Entry::Resident(resident) => displaced.push(resident),
+                        placeholder => placeholders.push(placeholder),
+                    }
+                }
+            }
+        }
+        drop(placeholders);
+        displaced.into_iter().map(Resident::into_parts).collect()
+    }
+
+    /// Inserts an item in the cache with key `key` and qey `qey`,
+    /// without waiting if the shard is locked.
+    /// On `Err` nothing is inserted and the item is dropped.
+    pub fn try_insert(&self, key: Key, qey: Qey, value: Val) -> Result<(), WouldBlock> {
+        if let Some((shard, hash)) = self.shard_and_hash(&key, &qey) {
+            // Any evictions will be dropped outside of the lock
+            let _evicted = shard
+                .try_write()
+                .ok_or(WouldBlock)?
+                .insert(hash, key, qey, value, 0);
+        }
+        Ok(())
+    }
+
+    /// Inserts an item in the cache with key `key` and qey `qey`,
+    /// waiting at most `timeout` if the shard is locked.
+    /// On `Err` nothing is inserted and the item is dropped.
+    pub fn insert_timeout(
+        &self,
+        key: Key,
+        qey: Qey,
+        value: Val,
+        timeout: Duration,
+    ) -> Result<(), WouldBlock> {
+        if let Some((shard, hash)) = self.shard_and_hash(&key, &qey) {
+            // Any evictions will be dropped outside of the lock
+            let _evicted = shard
+                .try_write_for(timeout)
+                .ok_or(WouldBlock)?
+                .insert(hash, key, qey, value, 0);
+        }
+        Ok(())
+    }
+
+    /// Inserts an item in the cache with key `key` and qey `qey`.
+    /// Contrary to [KQCache::insert], the value is given back if it's too heavy to be cached,
+    /// see [OptionsBuilder::max_item_weight].
+    pub fn insert_checked(&self, key: Key, qey: Qey, value: Val) -> Result<(), Rejected<Val>> {
+        if let Some((shard, hash)) = self.shard_and_hash(&key, &qey) {
+            // Any evictions (or the rejected value) will be dropped outside of the lock
+            let _evicted = shard.write().insert_checked(hash, key, qey, value, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Inserts an item in the cache with key `key` and qey `qey`, tagged with `flags`,
+    /// see [KQCache::get_with_flags]. Other insertions clear the flags,
+    /// while [KQCache::compare_exchange] keeps them.
+    pub fn insert_with_flags(&self, key: Key, qey: Qey, value: Val, flags: u16) {
+        if let Some((shard, hash)) = self.shard_and_hash(&key, &qey) {
+            // Any evictions will be dropped outside of the lock
+            let _evicted = shard.write().insert(hash, key, qey, value, flags);
+        }
+    }
+
+    /// Inserts an item in the cache with key `key` and qey `qey`,
+    /// returning the previous value if the item was cached, in a single shard lock acquisition.
+    /// If the new value is too heavy to be cached the previous item is removed (and returned)
+    /// instead of being kept. If the cache is frozen the value is given back as
+    /// [Rejected::Frozen].
+    pub fn replace(&self, key: Key, qey: Qey, value: Val) -> Result<Option<Val>, Rejected<Val>> {
+        let Some((shard, hash)) = self.shard_and_hash(&key, &qey) else {
+            return Ok(None);
+        };
+        // Any evictions will be dropped outside of the lock
+        let (old_value, _evicted) = shard.write().replace(hash, key, qey, value)?;
+        Ok(old_value)
+    }
+
+    /// Inserts an item in the cache with key `key` and qey `qey` only if it isn't cached yet,
+    /// for first-writer-wins semantics. Otherwise the cached value is returned and `value` is
+    /// dropped. Counts as a get of the item.
+    /// Like [KQCache::insert_checked], the value is given back if it can't be cached.
+    pub fn insert_if_absent(
+        &self,
+        key: Key,
+        qey: Qey,
+        value: Val,
+    ) -> Result<Option<Val>, Rejected<Val>> {
+        let Some((shard, hash)) = self.shard_and_hash(&key, &qey) else {
+            return Ok(None);
+        };
+        let mut shard = shard.write();
+        let (existing, evicted) = shard.insert_if_absent(hash, key, qey, value)?;
+        let existing = existing.cloned();
+        drop(shard);
+        drop(evicted);
+        Ok(existing)
+    }
+
+    /// Replaces the value of the item with key `key` and qey `qey` if its version,
+    /// as returned by [KQCache::get_with_version] or [KQCache::peek_with_version],
+    /// is still `expected`. Returns the new version of the entry.
+    ///
+    /// Otherwise nothing is changed and the current version is returned as an error,
+    /// or `None` if the item isn't in the cache.
+    /// If the new value is too heavy to be cached the item is removed instead,
+    /// and `None` is returned as an error as it isn't in the cache anymore.
+    ///
+    /// Versions are unique among the entries of a shard and increase with every insertion
+    /// or mutable access, so they can't be confused with the version of a previous entry.
+    pub fn compare_exchange(
+        &self,
+        key: Key,
+        qey: Qey,
+        expected: u64,
+        value: Val,
+    ) -> Result<u64, Option<u64>> {
+        let (shard, hash) = self.shard_and_hash(&key, &qey).ok_or(None)?;
+        // Any evictions will be dropped outside of the lock
+        let (version, _evicted) = shard
+            .write()
+            .compare_exchange(hash, key, qey, expected, value)?;
+        version.ok_or(None)
+    }
+
+    /// Runs `f` with mutable access to the value of the item with key `key` and qey `qey`,
+    /// or `None` if it isn't cached, and applies the returned [Compute] under the shard write lock:
+    /// a kept item has its weight recomputed with the [Weighter], an inserted value replaces
+    /// the cached one (see [KQCache::replace]) and a removed item is taken out of the cache.
+    /// Items may be evicted if the cache ends up over capacity.
+    ///
+    /// Counts as a get of the item, and mutable accesses change the item version.
+    /// If the cache is frozen nothing is done and `f` isn't run.
+    /// Returns the replaced or removed value, if any.
+    pub fn compute<F>(&self, key: Key, qey: Qey, f: F) -> Option<Val>
+    where
+        F: FnOnce(Option<&mut Val>) -> Compute<Val>,
+    {
+        let (shard, hash) = self.shard_and_hash(&key, &qey)?;
+        // Any evictions will be dropped outside of the lock
+        let (old_value, _evicted) = shard.write().compute(hash, key, qey, f);
+        old_value
+    }
+
+    /// Gets an item from the cache with key `key` and qey `qey`.
+    /// If the corresponding value isn't present in the cache, this functions returns a guard
+    /// that can be used to insert the value once it's computed.
+    /// While the returned guard is alive, other calls with the same key and qey using the
+    /// `get_value_guard` or `get_or_insert` family of functions will wait until the guard
+    /// is dropped or the value is inserted.
+    pub fn get_value_or_guard<'a>(
+        &'a self,
+        key: &Key,
+        qey: &Qey,
+        timeout: Option<Duration>,
+    ) -> GuardResult<'a, Key, Qey, Val, We, B>
+    where
+        Key: Clone,
+        Qey: Clone,
+    {
+        let (shard, hash) = self.shard_and_hash(key, qey).unwrap();
+        if !self.is_enabled() {
+            // the value is reloaded, so the cached one may be stale once re-enabled
+            let _removed = shard.write().remove_resident(hash, key, qey);
+        } else if let Some(v) = shard.read().get(hash, key, qey) {
+            return GuardResult::Value(v.clone());
+        }
+        PlaceholderGuard::join(shard, hash, key.clone(), qey.clone(), timeout)
+    }
+
+    /// Gets or inserts an item in the cache with key `key` and qey `qey`.
+    pub fn get_or_insert_with<E>(
+        &self,
+        key: &Key,
+        qey: &Qey,
         with: impl FnOnce() -> Result<Val, E>,
     ) -> Result<Val, E>
     where
         Key: Clone,
         Qey: Clone,
     {
-        match self.get_value_or_guard(key, qey, None) {
-            GuardResult::Value(v) => Ok(v),
-            GuardResult::Guard(g) => {
-                let v = with()?;
-                g.insert(v.clone());
-                Ok(v)
-            }
-            GuardResult::Timeout => unreachable!(),
-        }
+        match self.get_value_or_guard(key, qey, None) {
+            GuardResult::Value(v) => Ok(v),
+            GuardResult::Guard(g) => {
+                let v = with()?;
+                g.insert(v.clone());
+                Ok(v)
+            }
+            GuardResult::Timeout => unreachable!(),
+        }
+    }
+
+    /// Gets or inserts many items in the cache at once, with keys and qeys `keys`.
+    /// The values present in the cache are fetched right away, while all the missing keys are
+    /// passed to a single call of `with`, which must return their values
+    /// in the same order. Returns the values in the order of `keys`.
+    ///
+    /// While `with` runs, other calls of the `get_value_guard` or `get_or_insert` families of functions
+    /// for the missing keys wait for their value. Keys already being loaded by another caller are
+    /// also passed to `with` instead of waiting, but their loaded values are only returned, not inserted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `with` doesn't return as many values as it was given keys.
+    pub fn get_or_insert_with_many<E>(
+        &self,
+        keys: &[(Key, Qey)],
+        with: impl FnOnce(Vec<(Key, Qey)>) -> Result<Vec<Val>, E>,
+    ) -> Result<Vec<Val>, E>
+    where
+        Key: Clone,
+        Qey: Clone,
+    {
+        self.get_or_insert_with_many_by(keys, |(key, qey)| (key, qey), with)
+    }
+
+    fn get_or_insert_with_many_by<T: Clone, E>(
+        &self,
+        keys: &[T],
+        parts: impl Fn(&T) -> (&Key, &Qey),
+        with: impl FnOnce(Vec<T>) -> Result<Vec<Val>, E>,
+    ) -> Result<Vec<Val>, E>
+    where
+        Key: Clone,
+        Qey: Clone,
+    {
+        let mut values = Vec::with_capacity(keys.len());
+        let mut missing = Vec::new();
+        let mut guards = Vec::new();
+        for (i, k) in keys.iter().enumerate() {
+            let (key, qey) = parts(k);
+            // Never wait for other loaders while holding guards, as they may be waiting for ours
+            let guard = match self.get_value_or_guard(key, qey, Some(Duration::ZERO)) {
+                GuardResult::Value(v) => {
+                    values.push(Some(v));
+                    continue;
+                }
+                GuardResult::Guard(g) => Some(g),
+                GuardResult::Timeout => None,
+            };
+            values.push(None);
+            missing.push(k.clone());
+            guards.push((i, guard));
+        }
+        if !missing.is_empty() {
+            let loaded = with(missing)?;
+            assert_eq!(
+                loaded.len(),
+                guards.len(),
+                "get_or_insert_with_many loader returned the wrong number of values"
+            );
+            for ((i, guard), v) in guards.into_iter().zip(loaded) {
+                if let Some(guard) = guard {
+                    guard.insert(v.clone());
+                }
+                values[i] = Some(v);
+            }
+        }
+        Ok(values.into_iter().map(Option::unwrap).collect())
+    }
+
+    /// Gets an item from the cache with key `key` and qey `qey`.
+    /// If the corresponding value isn't present in the cache, this functions returns a guard
+    /// that can be used to insert the value once it's computed.
+    /// While the returned guard is alive, other calls with the same key and qey using the
+    /// `get_value_guard` or `get_or_insert` family of functions will wait until the guard
+    /// is dropped or the value is inserted.
+    pub async fn get_value_or_guard_async<'a, 'b>(
+        &'a self,
+        key: &'b Key,
+        qey: &'b Qey,
+    ) -> Result<Val, PlaceholderGuard<'a, Key, Qey, Val, We, B>>
+    where
+        Key: Clone,
+        Qey: Clone,
+    {
+        let (shard, hash) = self.shard_and_hash(key, qey).unwrap();
+        if !self.is_enabled() {
+            // the value is reloaded, so the cached one may be stale once re-enabled
+            let _removed = shard.write().remove_resident(hash, key, qey);
+        } else if let Some(v) = shard.read().get(hash, key, qey) {
+            return Ok(v.clone());
+        }
+        JoinFuture::new(shard, hash, key, qey).await
+    }
+
+    /// Gets or inserts an item in the cache with key `key` and qey `qey`.
+    pub async fn get_or_insert_async<E>(
+        &self,
+        key: &Key,
+        qey: &Qey,
+        with: impl Future<Output = Result<Val, E>>,
+    ) -> Result<Val, E>
+    where
+        Key: Clone,
+        Qey: Clone,
+    {
+        match self.get_value_or_guard_async(key, qey).await {
+            Ok(v) => Ok(v),
+            Err(g) => {
+                let v = with.await?;
+                g.insert(v.clone());
+                Ok(v)
+            }
+        }
+    }
+}
+
+/// Consumes the cache into an iterator over its items, in an arbitrary order.
+impl<
+        Key: Eq + Hash,
+        Qey: Eq + Hash,
+        Val: Clone,
+        We: Weighter<Key, Qey, Val> + Clone,
+        B: BuildHasher + Clone,
+    > IntoIterator for KQCache<Key, Qey, Val, We, B>
+{
+    type Item = (Key, Qey, Val);
+    type IntoIter = KQIntoIter<Key, Qey, Val>;
+
+    fn into_iter(self) -> KQIntoIter<Key, Qey, Val> {
+        let mut residents = Vec::with_capacity(self.len());
+        for s in &*self.shards {
+            residents.append(&mut s.write().take_residents());
+        }
+        KQIntoIter::new(residents)
+    }
+}
+
+/// A guard giving mutable access to a cached value, returned by the `get_mut` and `peek_mut`
+/// functions of [KQCache] and [Cache].
+///
+/// The guard holds the write lock of the item's shard. When dropped, the weight of the item
+/// is recomputed and items are evicted if the cache ended up over capacity.
+pub struct RefMut<
+    'a,
+    Key: Eq + Hash,
+    Qey: Eq + Hash,
+    Val,
+    We: Weighter<Key, Qey, Val>,
+    B: BuildHasher,
+> {
+    // Only taken on drop, so the lock is released before dropping the evicted items
+    #[allow(clippy::type_complexity)]
+    shard: Option<ShardWriteGuard<'a, Key, Qey, Val, We, B>>,
+    idx: Token,
+}
+
+impl<'a, Key: Eq + Hash, Qey: Eq + Hash, Val, We: Weighter<Key, Qey, Val>, B: BuildHasher>
+    RefMut<'a, Key, Qey, Val, We, B>
+{
+    /// Returns the key of the item.
+    pub fn key(&self) -> &Key {
+        self.shard.as_ref().unwrap().resident_at(self.idx).0
+    }
+
+    /// Returns the qey of the item.
+    pub fn qey(&self) -> &Qey {
+        self.shard.as_ref().unwrap().resident_at(self.idx).1
+    }
+}
+
+impl<'a, Key: Eq + Hash, Qey: Eq + Hash, Val, We: Weighter<Key, Qey, Val>, B: BuildHasher>
+    std::ops::Deref for RefMut<'a, Key, Qey, Val, We, B>
+{
+    type Target = Val;
+
+    fn deref(&self) -> &Val {
+        self.shard.as_ref().unwrap().resident_at(self.idx).2
+    }
+}
+
+impl<'a, Key: Eq + Hash, Qey: Eq + Hash, Val, We: Weighter<Key, Qey, Val>, B: BuildHasher>
+    std::ops::DerefMut for RefMut<'a, Key, Qey, Val, We, B>
+{
+    fn deref_mut(&mut self) -> &mut Val {
+        self.shard.as_mut().unwrap().value_mut_at(self.idx)
+    }
+}
+
+impl<'a, Key: Eq + Hash, Qey: Eq + Hash, Val, We: Weighter<Key, Qey, Val>, B: BuildHasher> Drop
+    for RefMut<'a, Key, Qey, Val, We, B>
+{
+    fn drop(&mut self) {
+        let Some(mut shard) = self.shard.take() else {
+            return;
+        };
+        let slot = self.idx.get() as usize - 1;
+        let evicted = if shard.reweigh(slot..slot + 1) != 0 {
+            shard.evict_excess()
+        } else {
+            Displaced::default()
+        };
+        drop(shard);
+        drop(evicted);
+    }
+}
+
+impl<'a, Key: Eq + Hash, Qey: Eq + Hash, Val, We: Weighter<Key, Qey, Val>, B: BuildHasher>
+    std::fmt::Debug for RefMut<'a, Key, Qey, Val, We, B>
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RefMut").finish_non_exhaustive()
+    }
+}
+
+impl<Key, Qey, Val, We, B> KQCache<Key, Qey, Val, We, B> {
+    /// Formats the cache size and counters, but not its contents.
+    fn fmt_summary(&self, name: &str, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (mut capacity, mut len, mut weight, mut hits, mut misses) = (0, 0, 0, 0, 0);
+        for s in &*self.shards {
+            let s = s.read();
+            capacity += s.capacity();
+            len += s.len();
+            weight += s.weight();
+            hits += s.hits();
+            misses += s.misses();
+        }
+        if let Some(counters) = &self.buffered_counters {
+            hits += counters.hits();
+            misses += counters.misses();
+        }
+        f.debug_struct(name)
+            .field("capacity", &capacity)
+            .field("len", &len)
+            .field("weight", &weight)
+            .field("hits", &hits)
+            .field("misses", &misses)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Key, Qey, Val, We, B> std::fmt::Debug for KQCache<Key, Qey, Val, We, B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_summary("KQCache", f)
+    }
+}
+
+/// A concurrent cache.
+///
+/// # Value
+/// Cache values are cloned when fetched. Users should wrap their values with `Arc<_>`
+/// if necessary to avoid expensive clone operations. If interior mutability is required
+/// `Arc<Mutex<_>>` or `Arc<RwLock<_>>` can also be used.
+///
+/// # Thread Safety and Concurrency
+/// The cache is a handle to shards shared between threads: cloning it is cheap
+/// and the clones access the same items, so no `Arc` wrapper is needed.
+/// All methods are accessible via non-mut references so no further synchronization (e.g. Mutex) is needed.
+pub struct Cache<Key, Val, We = UnitWeighter, B = DefaultHashBuilder>(
+    Arc<KQCache<Key, (), Val, We, B>>,
+    Option<Arc<HandleCounters>>,
+);
+
+impl<Key: Eq + Hash, Val: Clone> Cache<Key, Val, UnitWeighter, DefaultHashBuilder> {
+    /// Creates a new cache with holds up to `items_capacity` items (approximately).
+    pub fn new(items_capacity: usize) -> Self {
+        Self(Arc::new(KQCache::new(items_capacity)), None)
+    }
+
+    /// Returns a [CacheBuilder] to configure the options, weighter and hash builder of a cache
+    /// in one place.
+    pub fn builder() -> CacheBuilder<Key, Val> {
+        CacheBuilder::new()
+    }
+}
+
+/// Creates a cache which holds up to 1024 items (approximately), see [Cache::new].
+impl<Key: Eq + Hash, Val: Clone> Default for Cache<Key, Val, UnitWeighter, DefaultHashBuilder> {
+    fn default() -> Self {
+        Self::new(DEFAULT_ITEMS_CAPACITY)
+    }
+}
+
+impl<Key: Eq + Hash, Val: Clone, We: Weighter<Key, (), Val> + Clone>
+    Cache<Key, Val, We, DefaultHashBuilder>
+{
+    pub fn with_weighter(
+        estimated_items_capacity: usize,
+        weight_capacity: u64,
+        weighter: We,
+    ) -> Self {
+        Self::with(
+            estimated_items_capacity,
+            weight_capacity,
+            weighter,
+            DefaultHashBuilder::default(),
+        )
+    }
+}
+
+impl<Key: Eq + Hash, Val: Clone, We: Weighter<Key, (), Val> + Clone, B: BuildHasher + Clone>
+    Cache<Key, Val, We, B>
+{
+    /// Creates a new cache that can hold up to `weight_capacity` in weight.
+    /// `estimated_items_capacity` is the estimated number of items the cache is expected to hold,
+    /// roughly equivalent to `weight_capacity / average item weight`.
+    pub fn with(
+        estimated_items_capacity: usize,
+        weight_capacity: u64,
+        weighter: We,
+        hash_builder: B,
+    ) -> Self {
+        Self(
+            Arc::new(KQCache::with(
+                estimated_items_capacity,
+                weight_capacity,
+                weighter,
+                hash_builder,
+            )),
+            None,
+        )
+    }
+
+    /// Constructs a cache based on [OptionsBuilder].
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use quick_cache::{sync::Cache, OptionsBuilder, UnitWeighter, DefaultHashBuilder};
+    ///
+    /// Cache::<String, String>::with_options(
+    ///   OptionsBuilder::new()
+    ///     .estimated_items_capacity(10000)
+    ///     .weight_capacity(10000)
+    ///     .build()
+    ///     .unwrap(),
+    ///     UnitWeighter,
+    ///     DefaultHashBuilder::default(),
+    /// );
+    /// ```
+    pub fn with_options(options: Options, weighter: We, hash_builder: B) -> Self {
+        Self(
+            Arc::new(KQCache::with_options(options, weighter, hash_builder)),
+            None,
+        )
+    }
+
+    /// Returns whether the cache is empty.
+    /// Only takes the shard read locks, one at a time, stopping at the first non-empty shard.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the number of cached items.
+    /// Only takes the shard read locks, one at a time, so it doesn't block concurrent gets.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns the total weight of cached items
+    pub fn weight(&self) -> u64 {
+        self.0.weight()
+    }
+
+    /// Returns an estimate of the number of cached items, without taking any lock.
+    /// Each shard publishes its number of items when it's modified, so concurrent operations
+    /// may not be accounted for yet.
+    pub fn estimated_len(&self) -> usize {
+        self.0.estimated_len()
+    }
+
+    /// Returns an estimate of the total weight of cached items, without taking any lock,
+    /// see [Cache::estimated_len].
+    pub fn estimated_weight(&self) -> u64 {
+        self.0.estimated_weight()
+    }
+
+    /// Returns the maximum weight of cached items
+    pub fn capacity(&self) -> u64 {
+        self.0.capacity()
+    }
+
+    /// Returns a breakdown of the memory used by the cache internal structures
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.0.memory_usage()
+    }
+
+    /// Returns the number of misses
+    pub fn misses(&self) -> u64 {
+        self.0.misses()
+    }
+
+    /// Returns the number of hits
+    pub fn hits(&self) -> u64 {
+        self.0.hits()
+    }
+
+    /// Returns the number of entries evicted to make room for others
+    pub fn evictions(&self) -> u64 {
+        self.0.evictions()
+    }
+
+    /// Returns a snapshot of the cache counters, see [Stats::delta] to compute per-interval values.
+    pub fn stats(&self) -> Stats {
+        self.0.stats()
+    }
+
+    /// Returns a snapshot of the counters of each shard.
+    ///
+    /// With [OptionsBuilder::buffered_stats], hits and misses are counted for the whole cache
+    /// and are missing from the shard counters.
+    pub fn shard_stats(&self) -> Vec<Stats> {
+        self.0.shard_stats()
+    }
+
+    /// Returns a new handle to the same cache that also counts the hits and misses of the lookups
+    /// issued through it. See [Cache::handle_stats].
+    ///
+    /// Clones of the returned handle share its counters. The cache counters, like [Cache::stats],
+    /// keep counting the lookups of all handles.
+    ///
+    /// The `get` and `get_value_or_guard` families of functions count a hit when they return
+    /// a value and a miss otherwise, while the `get_or_insert` family counts a miss when
+    /// the loader is called. Peeks aren't counted.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use quick_cache::{sync::Cache, HandleStats};
+    ///
+    /// let cache: Cache<u64, u64> = Cache::new(100);
+    /// let sessions = cache.with_handle_stats();
+    /// let reports = cache.with_handle_stats();
+    /// sessions.insert(1, 1);
+    /// assert_eq!(sessions.get(&1), Some(1));
+    /// assert_eq!(reports.get(&2), None);
+    /// assert_eq!(sessions.handle_stats(), Some(HandleStats { hits: 1, misses: 0 }));
+    /// assert_eq!(reports.handle_stats(), Some(HandleStats { hits: 0, misses: 1 }));
+    /// assert_eq!(cache.handle_stats(), None);
+    /// assert_eq!((cache.hits(), cache.misses()), (1, 1));
+    /// ```
+    pub fn with_handle_stats(&self) -> Self {
+        Self(Arc::clone(&self.0), Some(Default::default()))
+    }
+
+    /// Returns a snapshot of the counters of this handle, or `None` if the handle wasn't created
+    /// with [Cache::with_handle_stats].
+    pub fn handle_stats(&self) -> Option<HandleStats> {
+        self.1.as_ref().map(|counters| counters.snapshot())
+    }
+
+    #[inline]
+    fn record_lookups(&self, hits: usize, misses: usize) {
+        if let Some(counters) = &self.1 {
+            counters.add(hits as u64, misses as u64);
+        }
+    }
+
+    #[inline]
+    fn record_lookup<T>(&self, found: Option<T>) -> Option<T> {
+        self.record_lookups(found.is_some() as usize, found.is_none() as usize);
+        found
+    }
+
+    /// Returns the number of shards of the cache.
+    pub fn shard_count(&self) -> usize {
+        self.0.shard_count()
+    }
+
+    /// Returns the index, in `0..shard_count()`, of the shard holding the item with key `key`.
+    /// The mapping only depends on the hash builder and the number of shards.
+    pub fn shard_for<Q>(&self, key: &Q) -> usize
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.0.shard_for(key, &())
+    }
+
+    /// Evicts entries, in eviction order, until the weight of the cache is at most `fraction`
+    /// of its capacity.
+    /// The capacity is unchanged, so the cache can grow back afterwards.
+    ///
+    /// Eviction is incremental: shards are processed one after the other, a batch of entries
+    /// at a time, and evicted entries are dropped outside of the shard locks.
+    /// `fraction` is clamped to `0.0..=1.0`. Returns the number of evicted entries.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use quick_cache::sync::Cache;
+    ///
+    /// let cache = Cache::<u64, u64>::new(1000);
+    /// for i in 0..1000 {
+    ///     cache.insert(i, i);
+    /// }
+    /// cache.relieve_pressure(0.5);
+    /// assert!(cache.weight() <= cache.capacity() / 2);
+    /// ```
+    pub fn relieve_pressure(&self, fraction: f64) -> usize {
+        self.0.relieve_pressure(fraction)
+    }
+
+    /// Changes the maximum weight of the cache at runtime.
+    /// The hot and cold targets and the ghost capacity are recomputed in proportion.
+    ///
+    /// When shrinking, entries are evicted by the eviction policy until the cache fits.
+    /// Eviction is incremental: shards are processed one after the other, a batch of entries
+    /// at a time, and evicted entries are dropped outside of the shard locks.
+    /// Returns the number of evicted entries.
+    pub fn set_capacity(&self, weight_capacity: u64) -> usize {
+        self.0.set_capacity(weight_capacity)
+    }
+
+    /// Runs the eviction policy once, returning the evicted item, if any.
+    /// The victim is chosen by the eviction policy of the heaviest shard.
+    pub fn pop(&self) -> Option<(Key, Val)> {
+        self.0.pop().map(|(key, _, value)| (key, value))
+    }
+
+    /// Runs the eviction policy up to `n` times, returning the evicted items.
+    ///
+    /// The evictions are spread over the shards, which are processed one after the other.
+    /// The items of each shard are returned in eviction order.
+    pub fn pop_n(&self, n: usize) -> Vec<(Key, Val)> {
+        self.0
+            .pop_n(n)
+            .into_iter()
+            .map(|(key, _, value)| (key, value))
+            .collect()
+    }
+
+    /// Runs the eviction policy until at least `weight` is freed or the cache is empty,
+    /// returning the evicted items.
+    ///
+    /// The evictions are spread over the shards, which are processed one after the other.
+    /// The items of each shard are returned in eviction order.
+    pub fn pop_weight(&self, weight: u64) -> Vec<(Key, Val)> {
+        self.0
+            .pop_weight(weight)
+            .into_iter()
+            .map(|(key, _, value)| (key, value))
+            .collect()
+    }
+
+    /// Enables or disables the cache at runtime.
+    ///
+    /// While disabled, gets and peeks return `None` without locking (nor counting a miss) and
+    /// insertions are dropped. The cached entries are preserved, so they're served again once the
+    /// cache is re-enabled; use [Cache::clear] to drop them as well.
+    /// To avoid serving stale values after re-enabling the cache, insertions of a key while
+    /// disabled still remove its cached value, as do the `get_or_insert` and `get_value_or_guard`
+    /// families of functions, which load the value without inserting it. Concurrent loads of a key
+    /// are still coordinated.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.0.set_enabled(enabled)
+    }
+
+    /// Returns whether the cache is enabled, see [Cache::set_enabled].
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_enabled()
+    }
+
+    /// Freezes or unfreezes the cache.
+    ///
+    /// While frozen, gets and peeks are still served but gets don't alter the entries hotness.
+    /// Insertions are dropped ([Cache::insert_checked] returns [Rejected::Frozen]), removals
+    /// remove nothing, including [Cache::retain], [Cache::extract_if], [Cache::merge_from] and
+    /// [Cache::split_off], and [Cache::compare_exchange] returns the current version. The
+    /// `get_or_insert` and `get_value_or_guard` families of functions return the loaded values
+    /// without inserting them. Whole cache and maintenance functions, like [Cache::clear],
+    /// [Cache::drain], [Cache::relieve_pressure] or [Cache::prune_idle], still remove entries.
+    pub fn set_frozen(&self, frozen: bool) {
+        self.0.set_frozen(frozen)
+    }
+
+    /// Returns whether the cache is frozen, see [Cache::set_frozen].
+    pub fn is_frozen(&self) -> bool {
+        self.0.is_frozen()
+    }
+
+    /// Re-runs the weighter over every cached item, fixing the accounted weight of the items whose
+    /// weight changed. Items are evicted if the cache ends up over capacity.
+    ///
+    /// The sweep is incremental: shards are processed one after the other, a batch of entries
+    /// at a time, and evicted entries are dropped outside of the shard locks.
+    /// Returns the number of items whose weight was fixed.
+    pub fn reweigh_all(&self) -> usize {
+        self.0.reweigh_all()
+    }
+
+    /// Removes all the items.
+    /// Ghost keys and the doorkeeper are reset as well, while the stats are kept.
+    /// Shards are cleared one after the other and the removed items are dropped outside
+    /// of the shard locks. Pending placeholders aren't affected, so the values being computed
+    /// are still inserted.
+    pub fn clear(&self) {
+        self.0.clear();
+    }
+
+    /// Iterates over clones of the cached items in an arbitrary order.
+    /// Like peeks, the iteration doesn't alter the keys "hotness".
+    ///
+    /// The iteration is weakly consistent: a single shard is locked at a time, for a batch of
+    /// entries, so items inserted or removed concurrently may or may not be yielded,
+    /// and items moved during the iteration may even be yielded twice.
+    pub fn iter(&self) -> impl Iterator<Item = (Key, Val)> + '_
+    where
+        Key: Clone,
+    {
+        self.0.iter().map(|(key, _, val)| (key, val))
+    }
+
+    /// Iterates over clones of the keys of the cached items in an arbitrary order,
+    /// without cloning the values. The iteration is weakly consistent, see [Cache::iter].
+    pub fn keys(&self) -> impl Iterator<Item = Key> + '_
+    where
+        Key: Clone,
+    {
+        self.0.keys().map(|(key, _)| key)
+    }
+
+    /// Iterates over clones of the cached values in an arbitrary order.
+    /// The iteration is weakly consistent, see [Cache::iter].
+    pub fn values(&self) -> impl Iterator<Item = Val> + '_ {
+        self.0.values()
+    }
+
+    /// Removes all the items, like [Cache::clear], returning them in an arbitrary order.
+    /// Shards are drained one after the other, so items inserted concurrently may be left behind.
+    pub fn drain(&self) -> IntoIter<Key, Val> {
+        IntoIter::new(self.0.drain())
+    }
+
+    /// Removes the items for which `predicate` returns `false`,
+    /// without altering the "hotness" of the kept ones.
+    /// Shards are processed one after the other, `predicate` being called with the shard lock held,
+    /// and the removed items are dropped outside of the shard locks.
+    pub fn retain<F>(&self, mut predicate: F)
+    where
+        F: FnMut(&Key, &Val) -> bool,
+    {
+        self.0.retain(|key, _, val| predicate(key, val));
+    }
+
+    /// Lazily removes the items for which `predicate` returns `true`, yielding them in an
+    /// arbitrary order.
+    ///
+    /// Contrary to [Cache::retain], a shard lock is only held while looking for the next
+    /// matching item, up to a batch of slots at a time, and the iteration is weakly consistent,
+    /// see [Cache::iter]. Items not reached before the iterator is dropped are kept.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use quick_cache::sync::Cache;
+    ///
+    /// let cache = Cache::new(1000);
+    /// for i in 0..100 {
+    ///     cache.insert(i, i);
+    /// }
+    /// let mut purged = cache.extract_if(|&key, _| key % 2 == 0);
+    /// let chunk = purged.by_ref().take(10).collect::<Vec<_>>();
+    /// assert_eq!(chunk.len(), 10);
+    /// // other work...
+    /// assert_eq!(purged.count(), 40);
+    /// assert_eq!(cache.len(), 50);
+    /// ```
+    pub fn extract_if<'a, F>(&'a self, mut predicate: F) -> impl Iterator<Item = (Key, Val)> + 'a
+    where
+        F: FnMut(&Key, &Val) -> bool + 'a,
+    {
+        self.0
+            .extract_if(move |key, _, val| predicate(key, val))
+            .map(|(key, _, val)| (key, val))
+    }
+
+    /// Evicts the entries that weren't accessed for the `idle_timeout` set in the [Options],
+    /// even if the cache is under capacity. Does nothing if `idle_timeout` isn't set.
+    /// Shards are pruned one after the other. Returns the number of evicted entries.
+    pub fn prune_idle(&self) -> usize {
+        self.0.prune_idle()
+    }
+
+    /// Ages the cache by clearing the "referenced" bit of about `fraction` of its entries, each pass
+    /// continuing where the previous one stopped. Entries not accessed since lose the protection of
+    /// past accesses, so they are demoted or evicted when the clock hand reaches them.
+    ///
+    /// In workloads with few insertions the clock hand rarely moves, so calling this periodically
+    /// prevents entries that were popular long ago from staying in the cache indefinitely.
+    /// Returns the number of cleared bits.
+    pub fn decay_referenced(&self, fraction: f64) -> usize {
+        self.0.decay_referenced(fraction)
+    }
+
+    /// Resets the hits, misses, evictions and rejections counters.
+    /// Each shard is reset atomically, but the shards are reset one after the other.
+    ///
+    /// With [OptionsBuilder::buffered_stats], the lookups buffered by threads before the reset
+    /// are flushed after it, so the hits and misses aren't exact after a reset.
+    pub fn reset_stats(&self) {
+        self.0.reset_stats()
+    }
+
+    /// Returns the latency histograms of the cache operations.
+    #[cfg(feature = "latency")]
+    pub fn latency_histograms(&self) -> LatencyHistograms {
+        self.0.latency_histograms()
+    }
+
+    /// Reserver additional space for `additional` entries.
+    /// The space is split evenly between the shards.
+    /// Note that this is counted in entries, and is not weighted.
+    pub fn reserve(&self, additional: usize) {
+        self.0.reserve(additional)
+    }
+
+    /// Tries to reserve additional space for `additional` entries,
+    /// returning an error instead of aborting if the allocation fails.
+    /// Note that this is counted in entries, and is not weighted.
+    /// On error, some shards may have already reserved their share.
+    pub fn try_reserve(&self, additional: usize) -> Result<(), TryReserveError> {
+        self.0.try_reserve(additional)
+    }
+
+    /// Shrinks the internal storage as much as possible, releasing the memory held
+    /// for entries that were evicted or removed.
+    /// Shards are compacted one after the other, each under its write lock.
+    pub fn shrink_to_fit(&self) {
+        self.0.shrink_to_fit()
+    }
+
+    /// Rebuilds the cache internal tables with `hash_builder`.
+    /// The shard hash seeds, if enabled, are also regenerated.
+    /// Entries keep their hot/cold state, but ghost keys and the doorkeeper are reset,
+    /// as they only retain key hashes. Entries are redistributed among the shards, so some of them
+    /// may be evicted if a shard ends up over its capacity.
+    ///
+    /// As the cache is rebuilt in place, this requires the only handle of the cache:
+    /// if other clones of this handle are alive nothing is changed and `hash_builder`
+    /// is given back as an error.
+    pub fn rehash_with(&mut self, hash_builder: B) -> Result<(), B> {
+        match Arc::get_mut(&mut self.0) {
+            Some(cache) => {
+                cache.rehash_with(hash_builder);
+                Ok(())
+            }
+            None => Err(hash_builder),
+        }
+    }
+
+    /// Moves all the items of `other` into this cache, leaving `other` empty.
+    /// Items keep their hot/cold state and replace the items with the same keys in this cache.
+    /// Their weight is recomputed with the weighter of this cache, and items are evicted
+    /// if the cache ends up over capacity.
+    ///
+    /// Shards of `other` are moved one after the other, so concurrent readers may find
+    /// an item in neither cache while it's moved. Pending placeholders of `other` are kept,
+    /// so their guards still insert into `other`. Nothing is moved if either cache is frozen.
+    pub fn merge_from(&self, other: &Self) {
+        self.0.merge_from(&other.0)
+    }
+
+    /// Moves the items matching `predicate` into a new cache built from `options`,
+    /// with clones of the weighter and hash builder of this cache.
+    /// Items keep their hot/cold state, and the new cache evicts them as usual if they don't fit.
+    ///
+    /// Shards are split one after the other and `predicate` is called with the shard lock held.
+    pub fn split_off<F>(&self, options: Options, mut predicate: F) -> Self
+    where
+        F: FnMut(&Key, &Val) -> bool,
+    {
+        Self(
+            Arc::new(self.0.split_off(options, |key, _, val| predicate(key, val))),
+            None,
+        )
+    }
+
+    /// Fetches an item from the cache.
+    pub fn get<Q>(&self, key: &Q) -> Option<Val>
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.record_lookup(self.0.get(key, &()))
     }
 
-    /// Gets an item from the cache with key `key` and qey `qey`.
-    /// If the corresponding value isn't present in the cache, this functions returns a guard
-    /// that can be used to insert the value once it's computed.
-    /// While the returned guard is alive, other calls with the same key and qey using the
-    /// `get_value_guard` or `get_or_insert` family of functions will wait until the guard
-    /// is dropped or the value is inserted.
-    pub async fn get_value_or_guard_async<'a, 'b>(
-        &'a self,
-        key: &'b Key,
-        qey: &'b Qey,
-    ) -> Result<Val, PlaceholderGuard<'a, Key, Qey, Val, We, B>>
+    /// Fetches many items from the cache at once, with keys `keys`.
+    /// Returns the values in the order of `keys`, `None` for the ones that aren't cached.
+    ///
+    /// The keys are grouped by shard so each shard lock is acquired only once.
+    pub fn get_many<'k, Q, I>(&self, keys: I) -> Vec<Option<Val>>
     where
-        Key: Clone,
-        Qey: Clone,
+        I: IntoIterator<Item = &'k Q>,
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized + 'k,
     {
-        let (shard, hash) = self.shard_for(key, qey).unwrap();
-        if let Some(v) = shard.read().get(hash, key, qey) {
-            return Ok(v.clone());
+        let values = self.0.get_many(keys.into_iter().map(|key| (key, &())));
+        if self.1.is_some() {
+            let hits = values.iter().filter(|value| value.is_some()).count();
+            self.record_lookups(hits, values.len() - hits);
         }
-        JoinFuture::new(shard, hash, key, qey).await
+        values
     }
 
-    /// Gets or inserts an item in the cache with key `key` and qey `qey`.
-    pub async fn get_or_insert_async<'a, E>(
-        &self,
-        key: &Key,
-        qey: &Qey,
-        with: impl Future<Output = Result<Val, E>>,
-    ) -> Result<Val, E>
+    /// Fetches an item from the cache, without waiting if the shard is locked by a writer.
+    pub fn try_get<Q>(&self, key: &Q) -> Result<Option<Val>, WouldBlock>
     where
-        Key: Clone,
-        Qey: Clone,
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
     {
-        match self.get_value_or_guard_async(key, qey).await {
-            Ok(v) => Ok(v),
-            Err(g) => {
-                let v = with.await?;
-                g.insert(v.clone());
-                Ok(v)
-            }
+        let result = self.0.try_get(key, &());
+        if let Ok(found) = &result {
+            self.record_lookup(found.as_ref());
         }
+        result
     }
-}
 
-impl<Key, Qey, Val, We, B> std::fmt::Debug for KQCache<Key, Qey, Val, We, B> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("KQCache").finish_non_exhaustive()
+    /// Fetches an item from the cache, waiting at most `timeout` if the shard is locked by a writer.
+    pub fn get_timeout<Q>(&self, key: &Q, timeout: Duration) -> Result<Option<Val>, WouldBlock>
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let result = self.0.get_timeout(key, &(), timeout);
+        if let Ok(found) = &result {
+            self.record_lookup(found.as_ref());
+        }
+        result
     }
-}
-
-/// A concurrent cache.
-///
-/// # Value
-/// Cache values are cloned when fetched. Users should wrap their values with `Arc<_>`
-/// if necessary to avoid expensive clone operations. If interior mutability is required
-/// `Arc<Mutex<_>>` or `Arc<RwLock<_>>` can also be used.
-///
-/// # Thread Safety and Concurrency
-/// The cache instance can wrapped with an `Arc` (or equivalent) and shared between threads.
-/// All methods are accessible via non-mut references so no further synchronization (e.g. Mutex) is needed.
-pub struct Cache<Key, Val, We = UnitWeighter, B = DefaultHashBuilder>(KQCache<Key, (), Val, We, B>);
 
-impl<Key: Eq + Hash, Val: Clone> Cache<Key, Val, UnitWeighter, DefaultHashBuilder> {
-    /// Creates a new cache with holds up to `items_capacity` items (approximately).
-    pub fn new(items_capacity: usize) -> Self {
-        Self(KQCache::new(items_capacity))
+    /// Samples an item of the cache at random.
+    /// Like peeks, sampling doesn't alter the key "hotness".
+    ///
+    /// Shards are picked in proportion to their number of items, so all the items are about
+    /// equally likely. Only a single shard read lock is usually taken.
+    /// Returns `None` if the cache is empty or disabled.
+    pub fn random_entry(&self) -> Option<(Key, Val)>
+    where
+        Key: Clone,
+    {
+        self.0.random_entry().map(|(key, _, val)| (key, val))
     }
-}
 
-impl<Key: Eq + Hash, Val: Clone, We: Weighter<Key, (), Val> + Clone>
-    Cache<Key, Val, We, DefaultHashBuilder>
-{
-    pub fn with_weighter(
-        estimated_items_capacity: usize,
-        weight_capacity: u64,
-        weighter: We,
-    ) -> Self {
-        Self::with(
-            estimated_items_capacity,
-            weight_capacity,
-            weighter,
-            DefaultHashBuilder::default(),
-        )
+    /// Peeks an item from the cache. Contrary to gets, peeks don't alter the key "hotness".
+    pub fn peek<Q>(&self, key: &Q) -> Option<Val>
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.0.peek(key, &())
     }
-}
 
-impl<Key: Eq + Hash, Val: Clone, We: Weighter<Key, (), Val> + Clone, B: BuildHasher + Clone>
-    Cache<Key, Val, We, B>
-{
-    /// Creates a new cache that can hold up to `weight_capacity` in weight.
-    /// `estimated_items_capacity` is the estimated number of items the cache is expected to hold,
-    /// roughly equivalent to `weight_capacity / average item weight`.
-    pub fn with(
-        estimated_items_capacity: usize,
-        weight_capacity: u64,
-        weighter: We,
-        hash_builder: B,
-    ) -> Self {
-        Self(KQCache::with(
-            estimated_items_capacity,
-            weight_capacity,
-            weighter,
-            hash_builder,
-        ))
+    /// Returns whether an item whose key is `key` is cached, without cloning it.
+    /// Like peeks, this doesn't alter the key "hotness" nor count a hit or a miss.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.0.contains_key(key, &())
     }
 
-    /// Constructs a cache based on [OptionsBuilder].
-    ///
-    /// # Example
+    /// Fetches an item from the cache for modification.
     ///
-    /// ```rust
-    /// use quick_cache::{sync::Cache, OptionsBuilder, UnitWeighter, DefaultHashBuilder};
+    /// The returned guard holds the shard write lock, blocking all the other operations
+    /// on the shard until it's dropped. On drop the weight of the item is recomputed with the
+    /// [Weighter], evicting items if the cache ended up over capacity.
+    /// Mutable accesses through the guard change the item version.
     ///
-    /// Cache::<String, String>::with_options(
-    ///   OptionsBuilder::new()
-    ///     .estimated_items_capacity(10000)
-    ///     .weight_capacity(10000)
-    ///     .build()
-    ///     .unwrap(),
-    ///     UnitWeighter,
-    ///     DefaultHashBuilder::default(),
-    /// );
-    /// ```
-    pub fn with_options(options: Options, weighter: We, hash_builder: B) -> Self {
-        Self(KQCache::with_options(options, weighter, hash_builder))
-    }
-
-    /// Returns whether the cache is empty
-    pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+    /// Returns `None` if the item isn't cached, or the cache is disabled or frozen.
+    pub fn get_mut<Q>(&self, key: &Q) -> Option<RefMut<'_, Key, (), Val, We, B>>
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.record_lookup(self.0.get_mut(key, &()))
     }
 
-    /// Returns the number of cached items
-    pub fn len(&self) -> usize {
-        self.0.len()
+    /// Peeks an item from the cache for modification.
+    /// Contrary to gets, peeks don't alter the key "hotness" nor count a hit or a miss.
+    ///
+    /// Like with [Cache::get_mut], the returned guard holds the shard write lock
+    /// and recomputes the weight of the item when dropped.
+    ///
+    /// Returns `None` if the item isn't cached, or the cache is disabled or frozen.
+    pub fn peek_mut<Q>(&self, key: &Q) -> Option<RefMut<'_, Key, (), Val, We, B>>
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.0.peek_mut(key, &())
     }
 
-    /// Returns the total weight of cached items
-    pub fn weight(&self) -> u64 {
-        self.0.weight()
+    /// Fetches an item from the cache, along with its version.
+    ///
+    /// The version changes every time the entry is modified, see [Cache::compare_exchange].
+    pub fn get_with_version<Q>(&self, key: &Q) -> Option<(Val, u64)>
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.record_lookup(self.0.get_with_version(key, &()))
     }
 
-    /// Returns the maximum weight of cached items
-    pub fn capacity(&self) -> u64 {
-        self.0.capacity()
+    /// Peeks an item from the cache, along with its version.
+    /// Contrary to gets, peeks don't alter the key "hotness".
+    pub fn peek_with_version<Q>(&self, key: &Q) -> Option<(Val, u64)>
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.0.peek_with_version(key, &())
     }
 
-    /// Returns the number of misses
-    pub fn misses(&self) -> u64 {
-        self.0.misses()
+    /// Fetches an item from the cache, along with its flags.
+    ///
+    /// Flags are a few bits set by the user and kept as is by the cache, see [Cache::insert_with_flags].
+    pub fn get_with_flags<Q>(&self, key: &Q) -> Option<(Val, u16)>
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.record_lookup(self.0.get_with_flags(key, &()))
     }
 
-    /// Returns the number of hits
-    pub fn hits(&self) -> u64 {
-        self.0.hits()
+    /// Peeks an item from the cache, along with its flags.
+    /// Contrary to gets, peeks don't alter the key "hotness".
+    pub fn peek_with_flags<Q>(&self, key: &Q) -> Option<(Val, u16)>
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.0.peek_with_flags(key, &())
     }
 
-    /// Reserver additional space for `additional` entries.
-    /// Note that this is counted in entries, and is not weighted.
-    pub fn reserve(&mut self, additional: usize) {
-        self.0.reserve(additional)
+    /// Replaces the flags of the item whose key is `key`, without altering its
+    /// "hotness" nor its version. Only a read lock of the shard is needed.
+    /// Returns the previous flags, or `None` if the item isn't cached.
+    pub fn set_flags<Q>(&self, key: &Q, flags: u16) -> Option<u16>
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.0.set_flags(key, &(), flags)
     }
 
-    /// Fetches an item from the cache.
-    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<Val>
+    /// Remove an item from the cache whose key is `key`.
+    /// Returns whether an entry was removed, see [Cache::remove_checked] to tell a
+    /// missing item from a frozen cache.
+    pub fn remove<Q>(&self, key: &Q) -> bool
     where
         Key: Borrow<Q>,
-        Q: Eq + Hash,
+        Q: Eq + Hash + ?Sized,
     {
-        self.0.get(key, &())
+        self.0.remove(key, &())
     }
 
-    /// Peeks an item from the cache. Contrary to gets, peeks don't alter the key "hotness".
-    pub fn peek<Q: ?Sized>(&self, key: &Q) -> Option<Val>
+    /// Remove an item from the cache whose key is `key`.
+    /// Contrary to [Cache::remove], an error is returned if the cache is frozen,
+    /// see [Cache::set_frozen].
+    pub fn remove_checked<Q>(&self, key: &Q) -> Result<bool, Frozen>
     where
         Key: Borrow<Q>,
-        Q: Eq + Hash,
+        Q: Eq + Hash + ?Sized,
     {
-        self.0.peek(key, &())
+        self.0.remove_checked(key, &())
     }
 
-    /// Remove an item from the cache whose key is `key`.
-    /// Returns whether an entry was removed.
-    pub fn remove<Q: ?Sized>(&self, key: &Q) -> bool
+    /// Removes many items from the cache at once, with keys `keys`.
+    /// Returns the number of items that were cached.
+    ///
+    /// The keys are grouped by shard so each shard lock is acquired only once.
+    pub fn remove_many<'k, Q, I>(&self, keys: I) -> usize
     where
+        I: IntoIterator<Item = &'k Q>,
         Key: Borrow<Q>,
-        Q: Eq + Hash,
+        Q: Eq + Hash + ?Sized + 'k,
     {
-        self.0.remove(key, &())
+        self.0.remove_many(keys.into_iter().map(|key| (key, &())))
     }
 
     /// Inserts an item in the cache with key `key`.
@@ -507,6 +2476,121 @@ impl<Key: Eq + Hash, Val: Clone, We: Weighter<Key, (), Val> + Clone, B: BuildHas
         self.0.insert(key, (), value);
     }
 
+    /// Inserts many items in the cache at once, as if inserted one by one in order.
+    /// Returns the items displaced by the insertions, i.e. evicted to make room
+    /// or replaced by an item with the same key.
+    ///
+    /// The items are grouped by shard so each shard lock is acquired only once.
+    pub fn insert_many<I>(&self, items: I) -> Vec<(Key, Val)>
+    where
+        I: IntoIterator<Item = (Key, Val)>,
+    {
+        self.0
+            .insert_many(items.into_iter().map(|(key, value)| (key, (), value)))
+            .into_iter()
+            .map(|(key, _, value)| (key, value))
+            .collect()
+    }
+
+    /// Inserts an item in the cache with key `key`, without waiting if the shard is locked.
+    /// On `Err` nothing is inserted and the item is dropped.
+    pub fn try_insert(&self, key: Key, value: Val) -> Result<(), WouldBlock> {
+        self.0.try_insert(key, (), value)
+    }
+
+    /// Inserts an item in the cache with key `key`, waiting at most `timeout` if the shard is locked.
+    /// On `Err` nothing is inserted and the item is dropped.
+    pub fn insert_timeout(
+        &self,
+        key: Key,
+        value: Val,
+        timeout: Duration,
+    ) -> Result<(), WouldBlock> {
+        self.0.insert_timeout(key, (), value, timeout)
+    }
+
+    /// Inserts an item in the cache with key `key`.
+    /// Contrary to [Cache::insert], the value is given back if it's too heavy to be cached,
+    /// see [OptionsBuilder::max_item_weight].
+    pub fn insert_checked(&self, key: Key, value: Val) -> Result<(), Rejected<Val>> {
+        self.0.insert_checked(key, (), value)
+    }
+
+    /// Inserts an item in the cache with key `key`, tagged with `flags`,
+    /// see [Cache::get_with_flags]. Other insertions clear the flags,
+    /// while [Cache::compare_exchange] keeps them.
+    pub fn insert_with_flags(&self, key: Key, value: Val, flags: u16) {
+        self.0.insert_with_flags(key, (), value, flags);
+    }
+
+    /// Inserts an item in the cache with key `key`,
+    /// returning the previous value if the item was cached, in a single shard lock acquisition.
+    /// If the new value is too heavy to be cached the previous item is removed (and returned)
+    /// instead of being kept. If the cache is frozen the value is given back as
+    /// [Rejected::Frozen].
+    pub fn replace(&self, key: Key, value: Val) -> Result<Option<Val>, Rejected<Val>> {
+        self.0.replace(key, (), value)
+    }
+
+    /// Inserts an item in the cache with key `key` only if it isn't cached yet,
+    /// for first-writer-wins semantics. Otherwise the cached value is returned and `value` is
+    /// dropped. Counts as a get of the item.
+    /// Like [Cache::insert_checked], the value is given back if it can't be cached.
+    pub fn insert_if_absent(&self, key: Key, value: Val) -> Result<Option<Val>, Rejected<Val>> {
+        self.0.insert_if_absent(key, (), value)
+    }
+
+    /// Replaces the value of the item with key `key` if its version, as returned by
+    /// [Cache::get_with_version] or [Cache::peek_with_version], is still `expected`.
+    /// Returns the new version of the entry.
+    ///
+    /// Otherwise nothing is changed and the current version is returned as an error,
+    /// or `None` if the item isn't in the cache.
+    /// If the new value is too heavy to be cached the item is removed instead,
+    /// and `None` is returned as an error as it isn't in the cache anymore.
+    pub fn compare_exchange(
+        &self,
+        key: Key,
+        expected: u64,
+        value: Val,
+    ) -> Result<u64, Option<u64>> {
+        self.0.compare_exchange(key, (), expected, value)
+    }
+
+    /// Runs `f` with mutable access to the value of the item with key `key`,
+    /// or `None` if it isn't cached, and applies the returned [Compute] under the shard write lock:
+    /// a kept item has its weight recomputed with the [Weighter], an inserted value replaces
+    /// the cached one (see [Cache::replace]) and a removed item is taken out of the cache.
+    /// Items may be evicted if the cache ends up over capacity.
+    ///
+    /// Counts as a get of the item, and mutable accesses change the item version.
+    /// If the cache is frozen nothing is done and `f` isn't run.
+    /// Returns the replaced or removed value, if any.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use quick_cache::{sync::Cache, Compute};
+    ///
+    /// let cache = Cache::new(100);
+    /// for _ in 0..3 {
+    ///     cache.compute("hits", |hits| match hits {
+    ///         Some(hits) => {
+    ///             *hits += 1;
+    ///             Compute::Keep
+    ///         }
+    ///         None => Compute::Insert(1),
+    ///     });
+    /// }
+    /// assert_eq!(cache.get(&"hits"), Some(3));
+    /// ```
+    pub fn compute<F>(&self, key: Key, f: F) -> Option<Val>
+    where
+        F: FnOnce(Option<&mut Val>) -> Compute<Val>,
+    {
+        self.0.compute(key, (), f)
+    }
+
     /// Gets an item from the cache with key `key`.
     /// If the corresponding value isn't present in the cache, this functions returns a guard
     /// that can be used to insert the value once it's computed.
@@ -521,7 +2605,10 @@ impl<Key: Eq + Hash, Val: Clone, We: Weighter<Key, (), Val> + Clone, B: BuildHas
     where
         Key: Clone,
     {
-        self.0.get_value_or_guard(key, &(), timeout)
+        let result = self.0.get_value_or_guard(key, &(), timeout);
+        let hit = matches!(result, GuardResult::Value(_));
+        self.record_lookups(hit as usize, !hit as usize);
+        result
     }
 
     /// Gets or inserts an item in the cache with key `key`.
@@ -533,7 +2620,44 @@ impl<Key: Eq + Hash, Val: Clone, We: Weighter<Key, (), Val> + Clone, B: BuildHas
     where
         Key: Clone,
     {
-        self.0.get_or_insert_with(key, &(), with)
+        let mut hit = true;
+        let result = self.0.get_or_insert_with(key, &(), || {
+            hit = false;
+            with()
+        });
+        self.record_lookups(hit as usize, !hit as usize);
+        result
+    }
+
+    /// Gets or inserts many items in the cache at once, with keys `keys`.
+    /// The values present in the cache are fetched right away, while all the missing keys are
+    /// passed to a single call of `with`, which must return their values
+    /// in the same order. Returns the values in the order of `keys`.
+    ///
+    /// See [KQCache::get_or_insert_with_many] for the interaction with concurrent loaders.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `with` doesn't return as many values as it was given keys.
+    pub fn get_or_insert_with_many<E>(
+        &self,
+        keys: &[Key],
+        with: impl FnOnce(Vec<Key>) -> Result<Vec<Val>, E>,
+    ) -> Result<Vec<Val>, E>
+    where
+        Key: Clone,
+    {
+        let mut misses = 0;
+        let result = self.0.get_or_insert_with_many_by(
+            keys,
+            |key| (key, &()),
+            |missing| {
+                misses = missing.len();
+                with(missing)
+            },
+        );
+        self.record_lookups(keys.len() - misses, misses);
+        result
     }
 
     /// Gets an item from the cache with key `key`.
@@ -542,18 +2666,20 @@ impl<Key: Eq + Hash, Val: Clone, We: Weighter<Key, (), Val> + Clone, B: BuildHas
     /// While the returned guard is alive, other calls with the same key using the
     /// `get_value_guard` or `get_or_insert` family of functions will wait until the guard
     /// is dropped or the value is inserted.
-    pub async fn get_value_or_guard_async<'a, 'b>(
+    pub async fn get_value_or_guard_async<'a>(
         &'a self,
-        key: &'b Key,
+        key: &Key,
     ) -> Result<Val, PlaceholderGuard<'a, Key, (), Val, We, B>>
     where
         Key: Clone,
     {
-        self.0.get_value_or_guard_async(key, &()).await
+        let result = self.0.get_value_or_guard_async(key, &()).await;
+        self.record_lookups(result.is_ok() as usize, result.is_err() as usize);
+        result
     }
 
     /// Gets or inserts an item in the cache with key `key`.
-    pub async fn get_or_insert_async<'a, E>(
+    pub async fn get_or_insert_async<E>(
         &self,
         key: &Key,
         with: impl Future<Output = Result<Val, E>>,
@@ -561,13 +2687,58 @@ impl<Key: Eq + Hash, Val: Clone, We: Weighter<Key, (), Val> + Clone, B: BuildHas
     where
         Key: Clone,
     {
-        self.0.get_or_insert_async(key, &(), with).await
+        let mut hit = true;
+        let result = self
+            .0
+            .get_or_insert_async(key, &(), async {
+                hit = false;
+                with.await
+            })
+            .await;
+        self.record_lookups(hit as usize, !hit as usize);
+        result
+    }
+}
+
+/// Consumes the cache into an iterator over its items, in an arbitrary order.
+/// If other clones of the cache handle are alive the cache is left untouched and the items
+/// are cloned instead, one shard at a time like [Cache::iter].
+impl<
+        Key: Eq + Hash + Clone,
+        Val: Clone,
+        We: Weighter<Key, (), Val> + Clone,
+        B: BuildHasher + Clone,
+    > IntoIterator for Cache<Key, Val, We, B>
+{
+    type Item = (Key, Val);
+    type IntoIter = IntoIter<Key, Val>;
+
+    fn into_iter(self) -> IntoIter<Key, Val> {
+        match Arc::try_unwrap(self.0) {
+            Ok(cache) => IntoIter::new(cache.into_iter()),
+            Err(shared) => {
+                let mut residents = Vec::with_capacity(shared.len());
+                if shared.is_enabled() {
+                    for s in &*shared.shards {
+                        residents.append(&mut s.read().clone_residents());
+                    }
+                }
+                IntoIter::new(KQIntoIter::new(residents))
+            }
+        }
+    }
+}
+
+/// Returns a new handle to the same cache.
+impl<Key, Val, We, B> Clone for Cache<Key, Val, We, B> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0), self.1.clone())
     }
 }
 
 impl<Key, Val, We, B> std::fmt::Debug for Cache<Key, Val, We, B> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Cache").finish_non_exhaustive()
+        self.0.fmt_summary("Cache", f)
     }
 }
 
@@ -621,4 +2792,54 @@ mod tests {
             t.join().unwrap();
         }
     }
+
+    #[test]
+    fn test_try_lock() {
+        let cache = Cache::new(100);
+        assert_eq!(cache.try_insert(1, 1), Ok(()));
+        assert_eq!(cache.try_get(&1), Ok(Some(1)));
+        assert_eq!(cache.try_get(&2), Ok(None));
+        let (shard, _) = cache.0.shard_and_hash(&1, &()).unwrap();
+        let guard = shard.write();
+        assert_eq!(cache.try_get(&1), Err(WouldBlock));
+        assert_eq!(cache.try_insert(1, 2), Err(WouldBlock));
+        let timeout = Duration::from_millis(10);
+        assert_eq!(cache.get_timeout(&1, timeout), Err(WouldBlock));
+        assert_eq!(cache.insert_timeout(1, 2, timeout), Err(WouldBlock));
+        drop(guard);
+        assert_eq!(cache.insert_timeout(1, 2, timeout), Ok(()));
+        assert_eq!(cache.get_timeout(&1, timeout), Ok(Some(2)));
+    }
+
+    #[test]
+    fn test_concurrent_set_frozen() {
+        let cache = Cache::<u64, u64>::with_options(
+            OptionsBuilder::new()
+                .estimated_items_capacity(100)
+                .weight_capacity(100)
+                .shards(4)
+                .build()
+                .unwrap(),
+            UnitWeighter,
+            DefaultHashBuilder::default(),
+        );
+        let shards = &cache.0.shards;
+        let last = shards[shards.len() - 1].write();
+        thread::scope(|s| {
+            // freezing stops at the last shard, after freezing the other ones
+            let freeze = s.spawn(|| cache.set_frozen(true));
+            while !shards[0].read().is_frozen() {
+                thread::yield_now();
+            }
+            let unfreeze = s.spawn(|| cache.set_frozen(false));
+            thread::sleep(Duration::from_millis(50));
+            // the second toggle waits for the first one to complete
+            assert!(shards[0].read().is_frozen());
+            drop(last);
+            freeze.join().unwrap();
+            unfreeze.join().unwrap();
+        });
+        assert!(!cache.is_frozen());
+        assert!(shards.iter().all(|s| !s.read().is_frozen()));
+    }
 }
@@ -0,0 +1,214 @@
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    hash::{BuildHasher, Hash},
+};
+
+use crate::{
+    rw_lock::RwLock, sync::Cache, DefaultHashBuilder, Options, OptionsBuilder, Stats, UnitWeighter,
+    Weighter,
+};
+
+type Tenants<Tenant, Key, Val, We, B> = HashMap<Tenant, Cache<Key, Val, We, B>>;
+
+/// A concurrent cache keyed by `(tenant, key)`, where each tenant has its own limits and stats.
+///
+/// Like the namespaces of a [NamespacedCache](crate::NamespacedCache), every tenant is an independent
+/// [Cache], so a tenant can only evict its own entries. Contrary to namespaces, tenants are created
+/// on their first insertion, with the default tenant [Options] unless configured with
+/// [TenantCache::add_tenant]. A tenant can be evicted with all its entries at once,
+/// in time proportional to its number of entries.
+///
+/// # Example
+///
+/// ```rust
+/// use quick_cache::TenantCache;
+///
+/// let cache = TenantCache::new(100);
+/// cache.insert(&"acme", 1, "a");
+/// cache.insert(&"globex", 1, "b");
+/// assert_eq!(cache.get(&"acme", &1), Some("a"));
+/// assert_eq!(cache.stats(&"acme").unwrap().hit_rate(), 1.0);
+/// assert!(cache.evict_tenant(&"acme"));
+/// assert_eq!(cache.get(&"acme", &1), None);
+/// assert_eq!(cache.get(&"globex", &1), Some("b"));
+/// ```
+pub struct TenantCache<Tenant, Key, Val, We = UnitWeighter, B = DefaultHashBuilder> {
+    tenants: RwLock<Tenants<Tenant, Key, Val, We, B>>,
+    tenant_options: Options,
+    weighter: We,
+    hash_builder: B,
+}
+
+impl<Tenant: Eq + Hash, Key: Eq + Hash, Val: Clone> TenantCache<Tenant, Key, Val> {
+    /// Creates a cache where each tenant holds up to `tenant_items_capacity` items.
+    pub fn new(tenant_items_capacity: usize) -> Self {
+        Self::with_options(
+            OptionsBuilder::new()
+                .estimated_items_capacity(tenant_items_capacity)
+                .weight_capacity(tenant_items_capacity as u64)
+                .build()
+                .unwrap(),
+            UnitWeighter,
+            DefaultHashBuilder::default(),
+        )
+    }
+}
+
+impl<
+        Tenant: Eq + Hash,
+        Key: Eq + Hash,
+        Val: Clone,
+        We: Weighter<Key, (), Val> + Clone,
+        B: BuildHasher + Clone,
+    > TenantCache<Tenant, Key, Val, We, B>
+{
+    /// Creates a cache where each tenant is configured with `tenant_options` by default.
+    pub fn with_options(tenant_options: Options, weighter: We, hash_builder: B) -> Self {
+        Self {
+            tenants: Default::default(),
+            tenant_options,
+            weighter,
+            hash_builder,
+        }
+    }
+
+    fn new_tenant_cache(&self, options: Options) -> Cache<Key, Val, We, B> {
+        Cache::with_options(options, self.weighter.clone(), self.hash_builder.clone())
+    }
+
+    /// Adds a tenant configured with its own `options`.
+    /// If the tenant already exists it's replaced and its entries are evicted.
+    pub fn add_tenant(&self, tenant: Tenant, options: Options) {
+        let cache = self.new_tenant_cache(options);
+        // The replaced cache, if any, will be dropped outside of the lock
+        let _replaced = self.tenants.write().insert(tenant, cache);
+    }
+
+    /// Returns a handle to the cache of `tenant`, giving access to its whole interface.
+    pub fn tenant<T>(&self, tenant: &T) -> Option<Cache<Key, Val, We, B>>
+    where
+        Tenant: Borrow<T>,
+        T: Eq + Hash + ?Sized,
+    {
+        self.tenants.read().get(tenant).cloned()
+    }
+
+    /// Returns a handle to the cache of `tenant`, creating it with the default options if needed.
+    pub fn tenant_or_insert(&self, tenant: Tenant) -> Cache<Key, Val, We, B> {
+        if let Some(cache) = self.tenant(&tenant) {
+            return cache;
+        }
+        let cache = self.new_tenant_cache(self.tenant_options.clone());
+        self.tenants.write().entry(tenant).or_insert(cache).clone()
+    }
+
+    /// Evicts `tenant` along with all its entries.
+    /// Returns whether the tenant existed.
+    ///
+    /// The tenant cache is cleared, so its entries are evicted even if handles returned by
+    /// [TenantCache::tenant] are still alive. Such handles keep working as a detached cache.
+    pub fn evict_tenant<T>(&self, tenant: &T) -> bool
+    where
+        Tenant: Borrow<T>,
+        T: Eq + Hash + ?Sized,
+    {
+        let evicted = self.tenants.write().remove(tenant);
+        // The entries are dropped outside of the tenants lock
+        match evicted {
+            Some(cache) => {
+                cache.clear();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the number of tenants.
+    pub fn num_tenants(&self) -> usize {
+        self.tenants.read().len()
+    }
+
+    /// Fetches an item of `tenant` from the cache.
+    pub fn get<T, Q>(&self, tenant: &T, key: &Q) -> Option<Val>
+    where
+        Tenant: Borrow<T>,
+        T: Eq + Hash + ?Sized,
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.tenants.read().get(tenant)?.get(key)
+    }
+
+    /// Peeks an item of `tenant` from the cache. Contrary to gets, peeks don't alter the key "hotness".
+    pub fn peek<T, Q>(&self, tenant: &T, key: &Q) -> Option<Val>
+    where
+        Tenant: Borrow<T>,
+        T: Eq + Hash + ?Sized,
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.tenants.read().get(tenant)?.peek(key)
+    }
+
+    /// Inserts an item of `tenant` in the cache, only evicting entries of the same tenant.
+    /// The tenant is created with the default options if needed.
+    pub fn insert<T>(&self, tenant: &T, key: Key, value: Val)
+    where
+        Tenant: Borrow<T>,
+        T: Eq + Hash + ToOwned<Owned = Tenant> + ?Sized,
+    {
+        if let Some(cache) = self.tenants.read().get(tenant) {
+            cache.insert(key, value);
+            return;
+        }
+        self.tenant_or_insert(tenant.to_owned()).insert(key, value);
+    }
+
+    /// Remove an item of `tenant` from the cache.
+    /// Returns whether an entry was removed.
+    pub fn remove<T, Q>(&self, tenant: &T, key: &Q) -> bool
+    where
+        Tenant: Borrow<T>,
+        T: Eq + Hash + ?Sized,
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.tenant(tenant).map_or(false, |cache| cache.remove(key))
+    }
+
+    /// Returns a snapshot of the counters of `tenant`.
+    pub fn stats<T>(&self, tenant: &T) -> Option<Stats>
+    where
+        Tenant: Borrow<T>,
+        T: Eq + Hash + ?Sized,
+    {
+        self.tenants.read().get(tenant).map(|cache| cache.stats())
+    }
+
+    /// Returns a snapshot of the counters of all the tenants combined.
+    /// Evicted tenants aren't accounted for.
+    pub fn total_stats(&self) -> Stats {
+        self.tenants
+            .read()
+            .values()
+            .map(|cache| cache.stats())
+            .fold(Stats::default(), Stats::merge)
+    }
+
+    /// Returns the number of cached items, of all the tenants
+    pub fn len(&self) -> usize {
+        self.tenants.read().values().map(|cache| cache.len()).sum()
+    }
+
+    /// Returns whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.tenants.read().values().all(|cache| cache.is_empty())
+    }
+}
+
+impl<Tenant, Key, Val, We, B> std::fmt::Debug for TenantCache<Tenant, Key, Val, We, B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TenantCache").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    mem,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+
+use crate::linked_slab::Token;
+
+/// Access and insertion times of the entries, by slab slot, in milliseconds since the creation
+/// of the shard. They're kept outside of the entries so they only take memory when the idle
+/// timeout or the minimum residency are set, each of them needing one of the times.
+///
+/// Times aren't cleared when an entry is removed, so they must be set again whenever an entry
+/// takes a slot, see [Timestamps::inserted].
+pub struct Timestamps {
+    epoch: Instant,
+    /// Time of the last access of each slot, if the idle timeout is set.
+    last_access: Option<Vec<AtomicU64>>,
+    /// Time of the insertion of the value of each slot, if the minimum residency is set.
+    inserted_at: Option<Vec<u64>>,
+}
+
+impl Timestamps {
+    pub fn new(track_access: bool, track_insertion: bool) -> Self {
+        Self {
+            epoch: Instant::now(),
+            last_access: track_access.then(Vec::new),
+            inserted_at: track_insertion.then(Vec::new),
+        }
+    }
+
+    /// Milliseconds since the shard creation.
+    /// Always 0 if no time is tracked, so the clock isn't read.
+    #[inline]
+    pub fn now(&self) -> u64 {
+        if self.last_access.is_some() || self.inserted_at.is_some() {
+            self.epoch.elapsed().as_millis() as u64
+        } else {
+            0
+        }
+    }
+
+    /// Records the insertion of a value in the `idx` slot at `now`, which also counts as an access.
+    #[inline]
+    pub fn inserted(&mut self, idx: Token, now: u64) {
+        let slot = idx.get() as usize - 1;
+        if let Some(last_access) = &mut self.last_access {
+            if slot >= last_access.len() {
+                last_access.resize_with(slot + 1, Default::default);
+            }
+            *last_access[slot].get_mut() = now;
+        }
+        if let Some(inserted_at) = &mut self.inserted_at {
+            if slot >= inserted_at.len() {
+                inserted_at.resize(slot + 1, 0);
+            }
+            inserted_at[slot] = now;
+        }
+    }
+
+    /// Records an access to the entry of the `idx` slot, reading the clock only if needed.
+    #[inline]
+    pub fn accessed(&self, idx: Token) {
+        if let Some(last_access) = &self.last_access {
+            let now = self.epoch.elapsed().as_millis() as u64;
+            last_access[idx.get() as usize - 1].store(now, Ordering::Relaxed);
+        }
+    }
+
+    /// Milliseconds elapsed at `now` since the last access to the entry of the `idx` slot.
+    /// Always 0 if accesses aren't tracked.
+    #[inline]
+    pub fn idle_for(&self, idx: Token, now: u64) -> u64 {
+        self.last_access.as_ref().map_or(0, |last_access| {
+            now.saturating_sub(last_access[idx.get() as usize - 1].load(Ordering::Relaxed))
+        })
+    }
+
+    /// Milliseconds elapsed at `now` since the insertion of the value of the `idx` slot.
+    /// Always `u64::MAX` if insertions aren't tracked.
+    #[inline]
+    pub fn resident_for(&self, idx: Token, now: u64) -> u64 {
+        self.inserted_at.as_ref().map_or(u64::MAX, |inserted_at| {
+            now.saturating_sub(inserted_at[idx.get() as usize - 1])
+        })
+    }
+
+    /// Moves the times of the `old` slot to the `new` one, see [LinkedSlab::compact](crate::linked_slab::LinkedSlab::compact).
+    pub fn relocate(&mut self, old: Token, new: Token) {
+        let (old, new) = (old.get() as usize - 1, new.get() as usize - 1);
+        if let Some(last_access) = &mut self.last_access {
+            let time = *last_access[old].get_mut();
+            *last_access[new].get_mut() = time;
+        }
+        if let Some(inserted_at) = &mut self.inserted_at {
+            inserted_at[new] = inserted_at[old];
+        }
+    }
+
+    /// Drops the times of the slots past `num_slots` and releases the excess memory.
+    pub fn shrink_to(&mut self, num_slots: usize) {
+        if let Some(last_access) = &mut self.last_access {
+            last_access.truncate(num_slots);
+            last_access.shrink_to_fit();
+        }
+        if let Some(inserted_at) = &mut self.inserted_at {
+            inserted_at.truncate(num_slots);
+            inserted_at.shrink_to_fit();
+        }
+    }
+
+    /// Bytes allocated for the times.
+    pub fn allocated_bytes(&self) -> usize {
+        self.last_access
+            .as_ref()
+            .map_or(0, |v| v.capacity() * mem::size_of::<AtomicU64>())
+            + self
+                .inserted_at
+                .as_ref()
+                .map_or(0, |v| v.capacity() * mem::size_of::<u64>())
+    }
+}
+
+impl Clone for Timestamps {
+    fn clone(&self) -> Self {
+        Self {
+            epoch: self.epoch,
+            last_access: self.last_access.as_ref().map(|last_access| {
+                last_access
+                    .iter()
+                    .map(|time| AtomicU64::new(time.load(Ordering::Relaxed)))
+                    .collect()
+            }),
+            inserted_at: self.inserted_at.clone(),
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Recording of access traces, which can be replayed offline with the `simulate` module.
+//!
+//! A trace is a text stream with one operation per line: a one letter operation code,
+//! the key hash in hexadecimal and the entry weight (0 if unknown).
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::sync::{Arc, Mutex};
+//! use quick_cache::{sync::Cache, trace::{read_trace, TraceOp, TraceRecorder}, OptionsBuilder, UnitWeighter, DefaultHashBuilder};
+//!
+//! #[derive(Clone, Default)]
+//! struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+//!
+//! impl std::io::Write for SharedBuffer {
+//!     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+//!         self.0.lock().unwrap().write(buf)
+//!     }
+//!     fn flush(&mut self) -> std::io::Result<()> {
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let buffer = SharedBuffer::default();
+//! let cache = Cache::with_options(
+//!     OptionsBuilder::new()
+//!         .estimated_items_capacity(100)
+//!         .weight_capacity(100)
+//!         .trace_recorder(TraceRecorder::new(buffer.clone()))
+//!         .build()
+//!         .unwrap(),
+//!     UnitWeighter,
+//!     DefaultHashBuilder::default(),
+//! );
+//! cache.get(&1);
+//! cache.insert(1, 1);
+//! cache.get(&1);
+//!
+//! let trace = buffer.0.lock().unwrap();
+//! let ops = read_trace(&trace[..]).map(|r| r.unwrap().op).collect::<Vec<_>>();
+//! assert_eq!(ops, [TraceOp::Miss, TraceOp::Insert, TraceOp::Hit]);
+//! ```
+
+use std::{
+    io::{self, BufRead, Write},
+    sync::Mutex,
+};
+
+/// Type of a traced operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceOp {
+    /// A lookup that found the key.
+    Hit,
+    /// A lookup that didn't find the key.
+    Miss,
+    /// An insertion, including the ones resolving placeholders.
+    Insert,
+    /// A removal.
+    Remove,
+}
+
+impl TraceOp {
+    fn code(self) -> char {
+        match self {
+            TraceOp::Hit => 'H',
+            TraceOp::Miss => 'M',
+            TraceOp::Insert => 'I',
+            TraceOp::Remove => 'R',
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Self> {
+        Some(match code {
+            "H" => TraceOp::Hit,
+            "M" => TraceOp::Miss,
+            "I" => TraceOp::Insert,
+            "R" => TraceOp::Remove,
+            _ => return None,
+        })
+    }
+}
+
+/// An operation read from a trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub op: TraceOp,
+    /// Hash of the key (and qey).
+    pub hash: u64,
+    /// Weight of the entry, 0 if unknown (e.g. misses).
+    pub weight: u32,
+}
+
+impl TraceRecord {
+    /// Converts the record to an access suitable for the `simulate` module, if any.
+    ///
+    /// The simulator models a read-through cache, where a miss is followed by an insertion.
+    /// Thus hits and insertions are accesses, while misses and removals are skipped.
+    pub fn access(&self) -> Option<(u64, u32)> {
+        match self.op {
+            TraceOp::Hit | TraceOp::Insert => Some((self.hash, self.weight)),
+            TraceOp::Miss | TraceOp::Remove => None,
+        }
+    }
+}
+
+/// Records sampled cache operations to a writer.
+///
+/// Sampling is done by key hash, so all the operations of a sampled key are recorded.
+/// Recording is best effort, write errors are ignored.
+pub struct TraceRecorder {
+    writer: Mutex<Box<dyn Write + Send>>,
+    sample_mask: u64,
+}
+
+impl TraceRecorder {
+    /// Creates a recorder that records every operation to `writer`.
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self::with_sample_rate(writer, 1)
+    }
+
+    /// Creates a recorder that records the operations of roughly 1 in `sample_rate` keys
+    /// to `writer`. The rate is rounded up to the next power of two.
+    pub fn with_sample_rate(writer: impl Write + Send + 'static, sample_rate: u64) -> Self {
+        Self {
+            writer: Mutex::new(Box::new(writer)),
+            sample_mask: sample_rate.max(1).next_power_of_two() - 1,
+        }
+    }
+
+    /// Flushes the underlying writer.
+    pub fn flush(&self) -> io::Result<()> {
+        self.writer
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .flush()
+    }
+
+    #[inline]
+    pub(crate) fn is_sampled(&self, hash: u64) -> bool {
+        // the top bits are used as they're the least related to the shard and map placement
+        (hash >> 32) & self.sample_mask == 0
+    }
+
+    pub(crate) fn record(&self, op: TraceOp, hash: u64, weight: u64) {
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = writeln!(writer, "{} {:016x} {}", op.code(), hash, weight);
+    }
+}
+
+impl std::fmt::Debug for TraceRecorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TraceRecorder")
+            .field("sample_rate", &(self.sample_mask + 1))
+            .finish_non_exhaustive()
+    }
+}
+
+/// Parses a trace written by a [TraceRecorder].
+pub fn read_trace(reader: impl BufRead) -> impl Iterator<Item = io::Result<TraceRecord>> {
+    reader.lines().map(|line| {
+        let line = line?;
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid trace record");
+        let mut parts = line.split_whitespace();
+        let op = parts
+            .next()
+            .and_then(TraceOp::from_code)
+            .ok_or_else(invalid)?;
+        let hash = parts
+            .next()
+            .and_then(|h| u64::from_str_radix(h, 16).ok())
+            .ok_or_else(invalid)?;
+        let weight = parts
+            .next()
+            .and_then(|w| w.parse().ok())
+            .ok_or_else(invalid)?;
+        Ok(TraceRecord { op, hash, weight })
+    })
+}
@@ -1,11 +1,17 @@
+#[cfg(feature = "latency")]
+use crate::LatencyHistograms;
 use crate::{
+    linked_slab::Token,
     options::*,
-    shard::{Entry, KQCacheShard},
-    DefaultHashBuilder, UnitWeighter, Weighter,
+    shard::{self, KQCacheShard, Resident},
+    stats::BufferedCounters,
+    Compute, DefaultHashBuilder, Frozen, IntoIter, KQIntoIter, MemoryUsage, Rejected, Stats,
+    TryReserveError, UnitWeighter, Weighter,
 };
 use std::{
     borrow::Borrow,
     hash::{BuildHasher, Hash},
+    sync::Arc,
 };
 
 /// A two key cache.
@@ -14,6 +20,10 @@ use std::{
 /// The key qey pair exists for cases where you want a cache keyed by (K, Q).
 /// Other rust maps/caches are accessed via the Borrow trait,
 /// so they require the caller to build &(K, Q) which might involve cloning K and/or Q.
+///
+/// # Clone
+/// Clones are deep copies of the cache, including the "hotness" of the items and the counters.
+#[derive(Clone)]
 pub struct KQCache<Key, Qey, Val, We = UnitWeighter, B = DefaultHashBuilder> {
     shard: KQCacheShard<Key, Qey, Val, We, B>,
 }
@@ -30,6 +40,15 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val> KQCache<Key, Qey, Val, UnitWeighter, D
     }
 }
 
+/// Creates a cache which holds up to 1024 items (approximately), see [KQCache::new].
+impl<Key: Eq + Hash, Qey: Eq + Hash, Val> Default
+    for KQCache<Key, Qey, Val, UnitWeighter, DefaultHashBuilder>
+{
+    fn default() -> Self {
+        Self::new(DEFAULT_ITEMS_CAPACITY)
+    }
+}
+
 impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: Weighter<Key, Qey, Val>>
     KQCache<Key, Qey, Val, We, DefaultHashBuilder>
 {
@@ -89,16 +108,26 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: Weighter<Key, Qey, Val>, B: BuildH
     /// ```
     pub fn with_options(options: Options, weighter: We, hash_builder: B) -> Self {
         let shard = KQCacheShard::new(
-            options.hot_allocation,
-            options.ghost_allocation,
+            &options,
             options.estimated_items_capacity,
             options.weight_capacity,
+            options.shard_max_items(1),
+            options.shard_ghost_capacity(options.estimated_items_capacity, 1),
             weighter,
             hash_builder,
+            None,
+            options
+                .buffered_stats
+                .then(|| Arc::new(BufferedCounters::default())),
         );
         Self { shard }
     }
 
+    #[cfg(fuzzing)]
+    pub fn validate(&self) {
+        self.shard.validate()
+    }
+
     /// Returns whether the cache is empty.
     pub fn is_empty(&self) -> bool {
         self.shard.len() == 0
@@ -119,14 +148,186 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: Weighter<Key, Qey, Val>, B: BuildH
         self.shard.capacity()
     }
 
+    /// Returns a breakdown of the memory used by the cache internal structures
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.shard.memory_usage()
+    }
+
     /// Returns the number of misses
     pub fn misses(&self) -> u64 {
-        self.shard.misses()
+        self.shard.misses() + self.shard.buffered_counters().map_or(0, |c| c.misses())
     }
 
     /// Returns the number of hits
     pub fn hits(&self) -> u64 {
-        self.shard.hits()
+        self.shard.hits() + self.shard.buffered_counters().map_or(0, |c| c.hits())
+    }
+
+    /// Returns the number of entries evicted to make room for others
+    pub fn evictions(&self) -> u64 {
+        self.shard.evictions()
+    }
+
+    /// Returns a snapshot of the cache counters, see [Stats::delta] to compute per-interval values.
+    pub fn stats(&self) -> Stats {
+        self.shard
+            .stats()
+            .with_buffered(self.shard.buffered_counters())
+    }
+
+    /// Enables or disables the cache at runtime.
+    ///
+    /// While disabled, gets and peeks return `None` (without counting a miss) and insertions are
+    /// dropped. The cached entries are preserved, so they're served again once the cache is
+    /// re-enabled; use [KQCache::clear] to drop them as well.
+    /// To avoid serving stale values after re-enabling the cache, insertions of a key while
+    /// disabled still remove its cached value.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.shard.set_enabled(enabled);
+    }
+
+    /// Returns whether the cache is enabled, see [KQCache::set_enabled].
+    pub fn is_enabled(&self) -> bool {
+        self.shard.is_enabled()
+    }
+
+    /// Freezes or unfreezes the cache.
+    ///
+    /// While frozen, gets and peeks are still served but gets don't alter the entries hotness.
+    /// Insertions are dropped ([KQCache::insert_checked] returns [Rejected::Frozen]), removals
+    /// remove nothing, including [KQCache::retain], [KQCache::extract_if], [KQCache::merge_from] and
+    /// [KQCache::split_off], [KQCache::get_mut] and [KQCache::peek_mut] return `None` and
+    /// [KQCache::compare_exchange] returns the current version. Whole cache and maintenance
+    /// functions, like [KQCache::clear], [KQCache::drain] or [KQCache::relieve_pressure], still remove
+    /// entries, and [KQCache::iter_mut] and cursors still give mutable access.
+    pub fn set_frozen(&mut self, frozen: bool) {
+        self.shard.set_frozen(frozen);
+    }
+
+    /// Returns whether the cache is frozen, see [KQCache::set_frozen].
+    pub fn is_frozen(&self) -> bool {
+        self.shard.is_frozen()
+    }
+
+    /// Evicts entries, in eviction order, until the weight of the cache is at most `fraction`
+    /// of its capacity.
+    /// The capacity is unchanged, so the cache can grow back afterwards.
+    /// `fraction` is clamped to `0.0..=1.0`. Returns the number of evicted entries.
+    pub fn relieve_pressure(&mut self, fraction: f64) -> usize {
+        let target_weight = (self.shard.capacity() as f64 * fraction.clamp(0.0, 1.0)) as u64;
+        self.shard.evict_down_to(target_weight, usize::MAX).len()
+    }
+
+    /// Changes the maximum weight of the cache at runtime.
+    /// The hot and cold targets and the ghost capacity are recomputed in proportion.
+    /// When shrinking, entries are evicted by the eviction policy until the cache fits.
+    /// Returns the number of evicted entries.
+    pub fn set_capacity(&mut self, weight_capacity: u64) -> usize {
+        self.shard.set_capacity(weight_capacity);
+        self.shard.evict_excess().len()
+    }
+
+    /// Runs the eviction policy once, returning the evicted item, if any.
+    pub fn pop(&mut self) -> Option<(Key, Qey, Val)> {
+        let evicted = self.shard.evict_n(1);
+        evicted.into_iter().next().map(Resident::into_parts)
+    }
+
+    /// Runs the eviction policy up to `n` times, returning the evicted items in eviction order.
+    pub fn pop_n(&mut self, n: usize) -> Vec<(Key, Qey, Val)> {
+        let evicted = self.shard.evict_n(n);
+        evicted.into_iter().map(Resident::into_parts).collect()
+    }
+
+    /// Runs the eviction policy until at least `weight` is freed or the cache is empty,
+    /// returning the evicted items in eviction order.
+    pub fn pop_weight(&mut self, weight: u64) -> Vec<(Key, Qey, Val)> {
+        let target_weight = self.shard.weight().saturating_sub(weight);
+        let evicted = self.shard.evict_down_to(target_weight, usize::MAX);
+        evicted.into_iter().map(Resident::into_parts).collect()
+    }
+
+    /// Removes all the items. Ghost keys and the doorkeeper are reset as well,
+    /// while the stats are kept.
+    pub fn clear(&mut self) {
+        self.shard.clear();
+    }
+
+    /// Removes all the items, like [KQCache::clear], returning them in an arbitrary order.
+    pub fn drain(&mut self) -> KQIntoIter<Key, Qey, Val> {
+        KQIntoIter::new(self.shard.clear())
+    }
+
+    /// Removes the items for which `predicate` returns `false`,
+    /// without altering the "hotness" of the kept ones.
+    pub fn retain<F>(&mut self, mut predicate: F)
+    where
+        F: FnMut(&Key, &Qey, &Val) -> bool,
+    {
+        self.shard
+            .take_matching(|key, qey, val| !predicate(key, qey, val));
+    }
+
+    /// Lazily removes the items for which `predicate` returns `true`, yielding them in an
+    /// arbitrary order.
+    /// Items not reached before the iterator is dropped are kept.
+    pub fn extract_if<'a, F>(
+        &'a mut self,
+        mut predicate: F,
+    ) -> impl Iterator<Item = (Key, Qey, Val)> + 'a
+    where
+        F: FnMut(&Key, &Qey, &Val) -> bool + 'a,
+    {
+        let mut slots = 0..usize::MAX;
+        std::iter::from_fn(move || {
+            self.shard
+                .take_first_matching_in(&mut slots, &mut predicate)
+                .map(Resident::into_parts)
+        })
+    }
+
+    /// Evicts the entries that weren't accessed for the `idle_timeout` set in the [Options],
+    /// even if the cache is under capacity. Does nothing if `idle_timeout` isn't set.
+    /// Returns the number of evicted entries.
+    pub fn prune_idle(&mut self) -> usize {
+        self.shard.prune_idle().len()
+    }
+
+    /// Re-runs the weighter over every cached item, fixing the accounted weight of the items whose
+    /// weight changed. Items are evicted if the cache ends up over capacity.
+    /// Returns the number of items whose weight was fixed.
+    pub fn reweigh_all(&mut self) -> usize {
+        let fixed = self.shard.reweigh(0..usize::MAX);
+        self.shard.evict_excess();
+        fixed
+    }
+
+    /// Ages the cache by clearing the "referenced" bit of about `fraction` of its entries, each pass
+    /// continuing where the previous one stopped. Entries not accessed since lose the protection of
+    /// past accesses, so they are demoted or evicted when the clock hand reaches them.
+    ///
+    /// In workloads with few insertions the clock hand rarely moves, so calling this periodically
+    /// prevents entries that were popular long ago from staying in the cache indefinitely.
+    /// Returns the number of cleared bits.
+    pub fn decay_referenced(&mut self, fraction: f64) -> usize {
+        self.shard.decay_referenced(fraction)
+    }
+
+    /// Resets the hits, misses, evictions and rejections counters.
+    ///
+    /// With [OptionsBuilder::buffered_stats], the lookups buffered by threads before the reset
+    /// are flushed after it, so the hits and misses aren't exact after a reset.
+    pub fn reset_stats(&mut self) {
+        self.shard.reset_stats();
+        if let Some(counters) = self.shard.buffered_counters() {
+            counters.reset();
+        }
+    }
+
+    /// Returns the latency histograms of the cache operations.
+    #[cfg(feature = "latency")]
+    pub fn latency_histograms(&self) -> LatencyHistograms {
+        self.shard.latency_histograms()
     }
 
     /// Reserver additional space for `additional` entries.
@@ -135,78 +336,659 @@ impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: Weighter<Key, Qey, Val>, B: BuildH
         self.shard.reserve(additional);
     }
 
+    /// Tries to reserve additional space for `additional` entries,
+    /// returning an error instead of aborting if the allocation fails.
+    /// Note that this is counted in entries, and is not weighted.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        self.shard.try_reserve(additional)
+    }
+
+    /// Shrinks the internal storage as much as possible, releasing the memory held
+    /// for entries that were evicted or removed.
+    pub fn shrink_to_fit(&mut self) {
+        self.shard.shrink_to_fit();
+    }
+
+    /// Rebuilds the cache internal table with `hash_builder`.
+    /// Entries keep their hot/cold state, but ghost keys and the doorkeeper are reset,
+    /// as they only retain key hashes.
+    pub fn rehash_with(&mut self, hash_builder: B) {
+        self.shard.rehash(hash_builder, None);
+    }
+
+    /// Moves all the items of `other` into this cache, leaving `other` empty.
+    /// Items keep their hot/cold state and replace the items with the same keys in this cache.
+    /// Their weight is recomputed with the weighter of this cache, and items are evicted
+    /// if the cache ends up over capacity.
+    pub fn merge_from(&mut self, other: &mut Self) {
+        if self.is_frozen() {
+            return;
+        }
+        let residents = other.shard.take_residents();
+        self.merge_residents(residents);
+    }
+
+    /// Moves the items matching `predicate` into a new cache built from `options`,
+    /// with clones of the weighter and hash builder of this cache.
+    /// Items keep their hot/cold state, and the new cache evicts them as usual if they don't fit.
+    pub fn split_off<F>(&mut self, options: Options, predicate: F) -> Self
+    where
+        We: Clone,
+        B: Clone,
+        F: FnMut(&Key, &Qey, &Val) -> bool,
+    {
+        let mut split = Self::with_options(
+            options,
+            self.shard.weighter().clone(),
+            self.shard.hash_builder().clone(),
+        );
+        let residents = self.shard.take_matching(predicate);
+        split.merge_residents(residents);
+        split
+    }
+
+    fn merge_residents(&mut self, residents: Vec<Resident<Key, Qey, Val>>) {
+        for resident in residents {
+            self.shard.merge_resident(resident);
+        }
+        self.shard.evict_excess();
+    }
+
     /// Fetches an item from the cache. Callers should prefer `get_mut` whenever possible as it's more efficient.
-    pub fn get<Q: ?Sized, W: ?Sized>(&self, key: &Q, qey: &W) -> Option<&Val>
+    pub fn get<Q, W>(&self, key: &Q, qey: &W) -> Option<&Val>
     where
         Key: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
         Qey: Borrow<W>,
-        W: Hash + Eq,
+        W: Hash + Eq + ?Sized,
     {
         self.shard.get(self.shard.hash(key, qey), key, qey)
     }
 
-    /// Fetches an item from the cache.
-    pub fn get_mut<Q: ?Sized, W: ?Sized>(&mut self, key: &Q, qey: &W) -> Option<&mut Val>
+    /// Fetches an item from the cache for modification, changing its version.
+    /// Returns `None` if the item isn't cached, or the cache is disabled or frozen.
+    pub fn get_mut<Q, W>(&mut self, key: &Q, qey: &W) -> Option<&mut Val>
     where
         Key: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
         Qey: Borrow<W>,
-        W: Hash + Eq,
+        W: Hash + Eq + ?Sized,
     {
         self.shard.get_mut(self.shard.hash(key, qey), key, qey)
     }
 
+    /// Samples an item of the cache uniformly at random.
+    /// Like peeks, sampling doesn't alter the key "hotness".
+    /// Returns `None` if the cache is empty or disabled.
+    pub fn random_entry(&self) -> Option<(&Key, &Qey, &Val)> {
+        self.shard.random_resident()
+    }
+
     /// Peeks an item from the cache. Contrary to gets, peeks don't alter the key "hotness".
-    pub fn peek<Q: ?Sized, W: ?Sized>(&self, key: &Q, qey: &W) -> Option<&Val>
+    pub fn peek<Q, W>(&self, key: &Q, qey: &W) -> Option<&Val>
     where
         Key: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
         Qey: Borrow<W>,
-        W: Hash + Eq,
+        W: Hash + Eq + ?Sized,
     {
         self.shard.peek(self.shard.hash(key, qey), key, qey)
     }
 
+    /// Returns whether an item whose keys are `key` + `qey` is cached.
+    /// Like peeks, this doesn't alter the key "hotness" nor count a hit or a miss.
+    pub fn contains_key<Q, W>(&self, key: &Q, qey: &W) -> bool
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        self.peek(key, qey).is_some()
+    }
+
     /// Peeks an item from the cache. Contrary to gets, peeks don't alter the key "hotness".
-    pub fn peek_mut<Q: ?Sized, W: ?Sized>(&mut self, key: &Q, qey: &W) -> Option<&mut Val>
+    /// Returns `None` if the item isn't cached, or the cache is disabled or frozen.
+    pub fn peek_mut<Q, W>(&mut self, key: &Q, qey: &W) -> Option<&mut Val>
     where
         Key: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
         Qey: Borrow<W>,
-        W: Hash + Eq,
+        W: Hash + Eq + ?Sized,
     {
         self.shard.peek_mut(self.shard.hash(key, qey), key, qey)
     }
 
+    /// Fetches an item from the cache, along with its version.
+    ///
+    /// The version changes every time the entry is modified, including mutable accesses,
+    /// see [KQCache::compare_exchange].
+    pub fn get_with_version<Q, W>(&self, key: &Q, qey: &W) -> Option<(&Val, u64)>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        self.shard
+            .get_with_version(self.shard.hash(key, qey), key, qey)
+    }
+
+    /// Peeks an item from the cache, along with its version.
+    /// Contrary to gets, peeks don't alter the key "hotness".
+    pub fn peek_with_version<Q, W>(&self, key: &Q, qey: &W) -> Option<(&Val, u64)>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        self.shard
+            .peek_with_version(self.shard.hash(key, qey), key, qey)
+    }
+
+    /// Fetches an item from the cache, along with its flags.
+    ///
+    /// Flags are a few bits set by the user and kept as is by the cache, see [KQCache::insert_with_flags].
+    pub fn get_with_flags<Q, W>(&self, key: &Q, qey: &W) -> Option<(&Val, u16)>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        self.shard
+            .get_with_flags(self.shard.hash(key, qey), key, qey)
+    }
+
+    /// Peeks an item from the cache, along with its flags.
+    /// Contrary to gets, peeks don't alter the key "hotness".
+    pub fn peek_with_flags<Q, W>(&self, key: &Q, qey: &W) -> Option<(&Val, u16)>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        self.shard
+            .peek_with_flags(self.shard.hash(key, qey), key, qey)
+    }
+
+    /// Replaces the flags of the item whose key is `key` and qey is `qey`,
+    /// without altering its "hotness" nor its version.
+    /// Returns the previous flags, or `None` if the item isn't cached.
+    pub fn set_flags<Q, W>(&mut self, key: &Q, qey: &W, flags: u16) -> Option<u16>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        self.shard
+            .set_flags(self.shard.hash(key, qey), key, qey, flags)
+    }
+
+    /// Iterates over the cached items in an arbitrary order.
+    /// Like peeks, the iteration doesn't alter the keys "hotness".
+    pub fn iter(&self) -> impl Iterator<Item = (&Key, &Qey, &Val)> + '_ {
+        self.shard.iter()
+    }
+
+    /// Iterates over the cached items in an arbitrary order, without cloning them.
+    /// Like peeks, the iteration doesn't alter the keys "hotness".
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&Key, &Qey, &mut Val)> + '_ {
+        self.shard.iter_mut()
+    }
+
+    /// Returns a cursor over the entries of the hot or cold `section`, starting from the clock hand,
+    /// that is, the next entry to be considered for demotion or eviction.
+    pub fn cursor(&mut self, section: Section) -> Cursor<'_, Key, Qey, Val, We, B> {
+        Cursor::new(&mut self.shard, section)
+    }
+
+    /// Gets the entry of `key` and `qey` for in-place inspection, modification, removal
+    /// or insertion, hashing and looking up the keys only once.
+    /// Counts as a get, so an occupied entry counts a hit and a vacant one a miss.
+    pub fn entry(&mut self, key: Key, qey: Qey) -> Entry<'_, Key, Qey, Val, We, B> {
+        let hash = self.shard.hash(&key, &qey);
+        match self.shard.lookup(hash, &key, &qey) {
+            Some(idx) => Entry::Occupied(OccupiedEntry {
+                shard: &mut self.shard,
+                idx,
+            }),
+            None => Entry::Vacant(VacantEntry {
+                shard: &mut self.shard,
+                hash,
+                key,
+                qey,
+            }),
+        }
+    }
+
     /// Remove an item from the cache whose key is `key` and qey is `qey`.
-    /// Returns whether an entry was removed.
-    pub fn remove<Q: ?Sized, W: ?Sized>(&mut self, key: &Q, qey: &W) -> bool
+    /// Returns whether an entry was removed, see [KQCache::remove_checked] to tell a
+    /// missing item from a frozen cache.
+    pub fn remove<Q, W>(&mut self, key: &Q, qey: &W) -> bool
     where
         Key: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
         Qey: Borrow<W>,
-        W: Hash + Eq,
+        W: Hash + Eq + ?Sized,
     {
         matches!(
             self.shard.remove(self.shard.hash(key, qey), key, qey),
-            Some(Entry::Resident(_))
+            Some(shard::Entry::Resident(_))
         )
     }
 
+    /// Remove an item from the cache whose key is `key` and qey is `qey`.
+    /// Contrary to [KQCache::remove], an error is returned if the cache is frozen,
+    /// see [KQCache::set_frozen].
+    pub fn remove_checked<Q, W>(&mut self, key: &Q, qey: &W) -> Result<bool, Frozen>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        Qey: Borrow<W>,
+        W: Hash + Eq + ?Sized,
+    {
+        if self.shard.is_frozen() {
+            return Err(Frozen);
+        }
+        Ok(self.remove(key, qey))
+    }
+
     /// Inserts an item in the cache with key `key` and qey `qey`.
     pub fn insert(&mut self, key: Key, qey: Qey, value: Val) {
         self.shard
-            .insert(self.shard.hash(&key, &qey), key, qey, value);
+            .insert(self.shard.hash(&key, &qey), key, qey, value, 0);
+    }
+
+    /// Inserts an item in the cache with key `key` and qey `qey`.
+    /// Contrary to [KQCache::insert], the value is given back if it's too heavy to be cached,
+    /// see [OptionsBuilder::max_item_weight].
+    pub fn insert_checked(&mut self, key: Key, qey: Qey, value: Val) -> Result<(), Rejected<Val>> {
+        let hash = self.shard.hash(&key, &qey);
+        self.shard.insert_checked(hash, key, qey, value, 0)?;
+        Ok(())
+    }
+
+    /// Inserts an item in the cache with key `key` and qey `qey`, tagged with `flags`,
+    /// see [KQCache::get_with_flags]. Other insertions clear the flags,
+    /// while [KQCache::compare_exchange] keeps them.
+    pub fn insert_with_flags(&mut self, key: Key, qey: Qey, value: Val, flags: u16) {
+        self.shard
+            .insert(self.shard.hash(&key, &qey), key, qey, value, flags);
+    }
+
+    /// Inserts an item in the cache with key `key` and qey `qey`,
+    /// returning the previous value if the item was cached.
+    /// If the new value is too heavy to be cached the previous item is removed (and returned)
+    /// instead of being kept. If the cache is frozen the value is given back as
+    /// [Rejected::Frozen].
+    pub fn replace(
+        &mut self,
+        key: Key,
+        qey: Qey,
+        value: Val,
+    ) -> Result<Option<Val>, Rejected<Val>> {
+        let hash = self.shard.hash(&key, &qey);
+        Ok(self.shard.replace(hash, key, qey, value)?.0)
+    }
+
+    /// Inserts an item in the cache with key `key` and qey `qey` only if it isn't cached yet,
+    /// for first-writer-wins semantics. Otherwise the cached value is returned and `value` is
+    /// dropped. Counts as a get of the item.
+    /// Like [KQCache::insert_checked], the value is given back if it can't be cached.
+    pub fn insert_if_absent(
+        &mut self,
+        key: Key,
+        qey: Qey,
+        value: Val,
+    ) -> Result<Option<&Val>, Rejected<Val>> {
+        let hash = self.shard.hash(&key, &qey);
+        Ok(self.shard.insert_if_absent(hash, key, qey, value)?.0)
+    }
+
+    /// Replaces the value of the item with key `key` and qey `qey` if its version,
+    /// as returned by [KQCache::get_with_version] or [KQCache::peek_with_version],
+    /// is still `expected`. Returns the new version of the entry.
+    ///
+    /// Otherwise nothing is changed and the current version is returned as an error,
+    /// or `None` if the item isn't in the cache.
+    /// If the new value is too heavy to be cached the item is removed instead,
+    /// and `None` is returned as an error as it isn't in the cache anymore.
+    pub fn compare_exchange(
+        &mut self,
+        key: Key,
+        qey: Qey,
+        expected: u64,
+        value: Val,
+    ) -> Result<u64, Option<u64>> {
+        let hash = self.shard.hash(&key, &qey);
+        let (version, _) = self
+            .shard
+            .compare_exchange(hash, key, qey, expected, value)?;
+        version.ok_or(None)
+    }
+
+    /// Runs `f` with mutable access to the value of the item with key `key` and qey `qey`,
+    /// or `None` if it isn't cached, and applies the returned [Compute]:
+    /// a kept item has its weight recomputed with the [Weighter], an inserted value replaces
+    /// the cached one (see [KQCache::replace]) and a removed item is taken out of the cache.
+    /// Items may be evicted if the cache ends up over capacity.
+    ///
+    /// Counts as a get of the item, and mutable accesses change the item version.
+    /// If the cache is frozen nothing is done and `f` isn't run.
+    /// Returns the replaced or removed value, if any.
+    pub fn compute<F>(&mut self, key: Key, qey: Qey, f: F) -> Option<Val>
+    where
+        F: FnOnce(Option<&mut Val>) -> Compute<Val>,
+    {
+        let hash = self.shard.hash(&key, &qey);
+        self.shard.compute(hash, key, qey, f).0
+    }
+}
+
+/// Consumes the cache into an iterator over its items, in an arbitrary order.
+impl<Key: Eq + Hash, Qey: Eq + Hash, Val, We: Weighter<Key, Qey, Val>, B: BuildHasher> IntoIterator
+    for KQCache<Key, Qey, Val, We, B>
+{
+    type Item = (Key, Qey, Val);
+    type IntoIter = KQIntoIter<Key, Qey, Val>;
+
+    fn into_iter(mut self) -> KQIntoIter<Key, Qey, Val> {
+        KQIntoIter::new(self.shard.take_residents())
+    }
+}
+
+impl<Key, Qey, Val, We, B> KQCache<Key, Qey, Val, We, B> {
+    /// Formats the cache size and counters, but not its contents.
+    fn fmt_summary(&self, name: &str, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let buffered = self.shard.buffered_counters();
+        f.debug_struct(name)
+            .field("capacity", &self.shard.capacity())
+            .field("len", &self.shard.len())
+            .field("weight", &self.shard.weight())
+            .field(
+                "hits",
+                &(self.shard.hits() + buffered.map_or(0, |c| c.hits())),
+            )
+            .field(
+                "misses",
+                &(self.shard.misses() + buffered.map_or(0, |c| c.misses())),
+            )
+            .finish_non_exhaustive()
     }
 }
 
 impl<Key, Qey, Val, We, B> std::fmt::Debug for KQCache<Key, Qey, Val, We, B> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("KQCache").finish_non_exhaustive()
+        self.fmt_summary("KQCache", f)
+    }
+}
+
+/// A section of the cache, see [KQCache::cursor].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    /// Entries accessed while in the cold section.
+    Hot,
+    /// Newly inserted entries and entries demoted from the hot section.
+    Cold,
+}
+
+/// A cursor over the entries of a cache section, in clock order.
+///
+/// The cursor visits each entry of the section at most once, which allows custom maintenance
+/// passes with a bounded budget.
+///
+/// # Example
+///
+/// ```rust
+/// use quick_cache::unsync::{Cache, Section};
+///
+/// let mut cache = Cache::new(100);
+/// for i in 0..10 {
+///     cache.insert(i, i);
+/// }
+/// // remove the odd keys among the first 5 cold entries
+/// let mut cursor = cache.cursor(Section::Cold);
+/// for _ in 0..5 {
+///     match cursor.current() {
+///         Some((key, _, _)) if key % 2 == 1 => {
+///             cursor.remove_current();
+///         }
+///         Some(_) => cursor.move_next(),
+///         None => break,
+///     }
+/// }
+/// ```
+pub struct Cursor<'a, Key, Qey, Val, We, B> {
+    shard: &'a mut KQCacheShard<Key, Qey, Val, We, B>,
+    current: Option<Token>,
+    /// Number of entries yet to be visited, including the current one.
+    remaining: usize,
+}
+
+impl<'a, Key: Eq + Hash, Qey: Eq + Hash, Val, We: Weighter<Key, Qey, Val>, B: BuildHasher>
+    Cursor<'a, Key, Qey, Val, We, B>
+{
+    fn new(shard: &'a mut KQCacheShard<Key, Qey, Val, We, B>, section: Section) -> Self {
+        let (current, remaining) = shard.list(section == Section::Hot);
+        Self {
+            shard,
+            current,
+            remaining,
+        }
+    }
+
+    /// Returns the entry under the cursor, or `None` once all the entries were visited.
+    pub fn current(&self) -> Option<(&Key, &Qey, &Val)> {
+        let (key, qey, value, _) = self.shard.resident_at(self.current?);
+        Some((key, qey, value))
+    }
+
+    /// Moves the cursor to the next entry of the section.
+    pub fn move_next(&mut self) {
+        if let Some(idx) = self.current {
+            let (_, _, _, next) = self.shard.resident_at(idx);
+            self.advance(Some(next));
+        }
+    }
+
+    /// Removes the entry under the cursor, returning it, and moves to the next entry.
+    pub fn remove_current(&mut self) -> Option<(Key, Qey, Val)> {
+        let (resident, next) = self.shard.remove_at(self.current?);
+        self.advance(next);
+        Some(resident.into_parts())
+    }
+
+    /// Demotes the entry under the cursor and moves to the next entry.
+    /// Hot entries are moved to the cold section, while cold entries lose the benefit of
+    /// being accessed, so they're evicted once the clock hand reaches them.
+    pub fn demote_current(&mut self) {
+        if let Some(idx) = self.current {
+            let next = self.shard.demote_at(idx);
+            self.advance(next);
+        }
+    }
+
+    fn advance(&mut self, next: Option<Token>) {
+        self.remaining -= 1;
+        self.current = if self.remaining == 0 { None } else { next };
+    }
+}
+
+impl<Key, Qey, Val, We, B> std::fmt::Debug for Cursor<'_, Key, Qey, Val, We, B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cursor")
+            .field("remaining", &self.remaining)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A view into a single item of a cache, which is either occupied or vacant,
+/// see [KQCache::entry] and [Cache::entry].
+///
+/// # Example
+///
+/// ```rust
+/// use quick_cache::unsync::{Cache, Entry};
+///
+/// let mut cache = Cache::new(100);
+/// cache.insert("hits", 1);
+/// match cache.entry("hits") {
+///     Entry::Occupied(mut entry) => *entry.get_mut().unwrap() += 1,
+///     Entry::Vacant(entry) => entry.insert(1).unwrap(),
+/// }
+/// assert_eq!(cache.get(&"hits"), Some(&2));
+/// ```
+pub enum Entry<'a, Key, Qey, Val, We, B> {
+    /// The item is cached.
+    Occupied(OccupiedEntry<'a, Key, Qey, Val, We, B>),
+    /// The item isn't cached.
+    Vacant(VacantEntry<'a, Key, Qey, Val, We, B>),
+}
+
+impl<'a, Key: Eq + Hash, Qey: Eq + Hash, Val, We: Weighter<Key, Qey, Val>, B: BuildHasher>
+    Entry<'a, Key, Qey, Val, We, B>
+{
+    /// Returns the key of the entry.
+    pub fn key(&self) -> &Key {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// Returns the qey of the entry.
+    pub fn qey(&self) -> &Qey {
+        match self {
+            Entry::Occupied(entry) => entry.qey(),
+            Entry::Vacant(entry) => entry.qey(),
+        }
+    }
+}
+
+impl<Key, Qey, Val, We, B> std::fmt::Debug for Entry<'_, Key, Qey, Val, We, B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Entry::Occupied(entry) => f.debug_tuple("Occupied").field(entry).finish(),
+            Entry::Vacant(entry) => f.debug_tuple("Vacant").field(entry).finish(),
+        }
+    }
+}
+
+/// A view into a cached item, see [Entry].
+pub struct OccupiedEntry<'a, Key, Qey, Val, We, B> {
+    shard: &'a mut KQCacheShard<Key, Qey, Val, We, B>,
+    idx: Token,
+}
+
+impl<'a, Key: Eq + Hash, Qey: Eq + Hash, Val, We: Weighter<Key, Qey, Val>, B: BuildHasher>
+    OccupiedEntry<'a, Key, Qey, Val, We, B>
+{
+    /// Returns the key of the item.
+    pub fn key(&self) -> &Key {
+        self.shard.resident_at(self.idx).0
+    }
+
+    /// Returns the qey of the item.
+    pub fn qey(&self) -> &Qey {
+        self.shard.resident_at(self.idx).1
+    }
+
+    /// Returns the value of the item.
+    pub fn get(&self) -> &Val {
+        self.shard.resident_at(self.idx).2
+    }
+
+    /// Returns the value of the item for modification, changing its version.
+    /// Like with [KQCache::get_mut], the weight of the item isn't recomputed.
+    /// An error is returned if the cache is frozen, see [KQCache::set_frozen].
+    pub fn get_mut(&mut self) -> Result<&mut Val, Frozen> {
+        if self.shard.is_frozen() {
+            return Err(Frozen);
+        }
+        Ok(self.shard.value_mut_at(self.idx))
+    }
+
+    /// Converts the entry into a mutable reference to the value of the item,
+    /// see [OccupiedEntry::get_mut].
+    pub fn into_mut(self) -> Result<&'a mut Val, Frozen> {
+        if self.shard.is_frozen() {
+            return Err(Frozen);
+        }
+        Ok(self.shard.value_mut_at(self.idx))
+    }
+
+    /// Replaces the value of the item, recomputing its weight, and returns the previous value.
+    /// Items, including this one, may be evicted if the cache ends up over capacity.
+    /// The value is given back if it's too heavy to be cached or the cache is frozen,
+    /// in which case the item is left as is.
+    pub fn insert(self, value: Val) -> Result<Val, Rejected<Val>> {
+        let (old_value, _evicted) = self.shard.replace_at(self.idx, value)?;
+        Ok(old_value)
+    }
+
+    /// Removes the item from the cache, returning its value.
+    /// An error is returned if the cache is frozen, see [KQCache::set_frozen].
+    pub fn remove(self) -> Result<Val, Frozen> {
+        if self.shard.is_frozen() {
+            return Err(Frozen);
+        }
+        let (resident, _) = self.shard.remove_at(self.idx);
+        Ok(resident.into_parts().2)
+    }
+}
+
+impl<Key, Qey, Val, We, B> std::fmt::Debug for OccupiedEntry<'_, Key, Qey, Val, We, B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OccupiedEntry").finish_non_exhaustive()
     }
 }
 
+/// A view into an item missing from the cache, see [Entry].
+pub struct VacantEntry<'a, Key, Qey, Val, We, B> {
+    shard: &'a mut KQCacheShard<Key, Qey, Val, We, B>,
+    hash: u64,
+    key: Key,
+    qey: Qey,
+}
+
+impl<'a, Key: Eq + Hash, Qey: Eq + Hash, Val, We: Weighter<Key, Qey, Val>, B: BuildHasher>
+    VacantEntry<'a, Key, Qey, Val, We, B>
+{
+    /// Returns the key of the entry.
+    pub fn key(&self) -> &Key {
+        &self.key
+    }
+
+    /// Returns the qey of the entry.
+    pub fn qey(&self) -> &Qey {
+        &self.qey
+    }
+
+    /// Takes back the key and qey of the entry.
+    pub fn into_keys(self) -> (Key, Qey) {
+        (self.key, self.qey)
+    }
+
+    /// Inserts the item, like [KQCache::insert_checked].
+    /// The item may still be left out by the admission policy.
+    pub fn insert(self, value: Val) -> Result<(), Rejected<Val>> {
+        self.shard
+            .insert_checked(self.hash, self.key, self.qey, value, 0)?;
+        Ok(())
+    }
+}
+
+impl<Key, Qey, Val, We, B> std::fmt::Debug for VacantEntry<'_, Key, Qey, Val, We, B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VacantEntry").finish_non_exhaustive()
+    }
+}
+
+#[derive(Clone)]
 pub struct Cache<Key, Val, We = UnitWeighter, B = DefaultHashBuilder>(KQCache<Key, (), Val, We, B>);
 
 impl<Key: Eq + Hash, Val> Cache<Key, Val, UnitWeighter, DefaultHashBuilder> {
@@ -216,6 +998,13 @@ impl<Key: Eq + Hash, Val> Cache<Key, Val, UnitWeighter, DefaultHashBuilder> {
     }
 }
 
+/// Creates a cache which holds up to 1024 items (approximately), see [Cache::new].
+impl<Key: Eq + Hash, Val> Default for Cache<Key, Val, UnitWeighter, DefaultHashBuilder> {
+    fn default() -> Self {
+        Self::new(DEFAULT_ITEMS_CAPACITY)
+    }
+}
+
 impl<Key: Eq + Hash, Val, We: Weighter<Key, (), Val>> Cache<Key, Val, We, DefaultHashBuilder> {
     /// Creates a new cache that can hold up to `weight_capacity` in weight.
     /// `estimated_items_capacity` is the estimated number of items the cache is expected to hold,
@@ -293,6 +1082,11 @@ impl<Key: Eq + Hash, Val, We: Weighter<Key, (), Val>, B: BuildHasher> Cache<Key,
         self.0.capacity()
     }
 
+    /// Returns a breakdown of the memory used by the cache internal structures
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.0.memory_usage()
+    }
+
     /// Returns the number of misses
     pub fn misses(&self) -> u64 {
         self.0.misses()
@@ -303,67 +1097,455 @@ impl<Key: Eq + Hash, Val, We: Weighter<Key, (), Val>, B: BuildHasher> Cache<Key,
         self.0.hits()
     }
 
+    /// Returns the number of entries evicted to make room for others
+    pub fn evictions(&self) -> u64 {
+        self.0.evictions()
+    }
+
+    /// Returns a snapshot of the cache counters, see [Stats::delta] to compute per-interval values.
+    pub fn stats(&self) -> Stats {
+        self.0.stats()
+    }
+
+    /// Evicts entries, in eviction order, until the weight of the cache is at most `fraction`
+    /// of its capacity.
+    /// The capacity is unchanged, so the cache can grow back afterwards.
+    /// `fraction` is clamped to `0.0..=1.0`. Returns the number of evicted entries.
+    pub fn relieve_pressure(&mut self, fraction: f64) -> usize {
+        self.0.relieve_pressure(fraction)
+    }
+
+    /// Changes the maximum weight of the cache at runtime.
+    /// The hot and cold targets and the ghost capacity are recomputed in proportion.
+    /// When shrinking, entries are evicted by the eviction policy until the cache fits.
+    /// Returns the number of evicted entries.
+    pub fn set_capacity(&mut self, weight_capacity: u64) -> usize {
+        self.0.set_capacity(weight_capacity)
+    }
+
+    /// Runs the eviction policy once, returning the evicted item, if any.
+    pub fn pop(&mut self) -> Option<(Key, Val)> {
+        self.0.pop().map(|(key, _, value)| (key, value))
+    }
+
+    /// Runs the eviction policy up to `n` times, returning the evicted items in eviction order.
+    pub fn pop_n(&mut self, n: usize) -> Vec<(Key, Val)> {
+        self.0
+            .pop_n(n)
+            .into_iter()
+            .map(|(key, _, value)| (key, value))
+            .collect()
+    }
+
+    /// Runs the eviction policy until at least `weight` is freed or the cache is empty,
+    /// returning the evicted items in eviction order.
+    pub fn pop_weight(&mut self, weight: u64) -> Vec<(Key, Val)> {
+        self.0
+            .pop_weight(weight)
+            .into_iter()
+            .map(|(key, _, value)| (key, value))
+            .collect()
+    }
+
+    /// Enables or disables the cache at runtime.
+    ///
+    /// While disabled, gets and peeks return `None` (without counting a miss) and insertions are
+    /// dropped. The cached entries are preserved, so they're served again once the cache is
+    /// re-enabled; use [Cache::clear] to drop them as well.
+    /// To avoid serving stale values after re-enabling the cache, insertions of a key while
+    /// disabled still remove its cached value.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.0.set_enabled(enabled)
+    }
+
+    /// Returns whether the cache is enabled, see [Cache::set_enabled].
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_enabled()
+    }
+
+    /// Freezes or unfreezes the cache.
+    ///
+    /// While frozen, gets and peeks are still served but gets don't alter the entries hotness.
+    /// Insertions are dropped ([Cache::insert_checked] returns [Rejected::Frozen]), removals
+    /// remove nothing, including [Cache::retain], [Cache::extract_if], [Cache::merge_from] and
+    /// [Cache::split_off], [Cache::get_mut] and [Cache::peek_mut] return `None` and
+    /// [Cache::compare_exchange] returns the current version. Whole cache and maintenance
+    /// functions, like [Cache::clear], [Cache::drain] or [Cache::relieve_pressure], still remove
+    /// entries, and [Cache::iter_mut] and cursors still give mutable access.
+    pub fn set_frozen(&mut self, frozen: bool) {
+        self.0.set_frozen(frozen);
+    }
+
+    /// Returns whether the cache is frozen, see [Cache::set_frozen].
+    pub fn is_frozen(&self) -> bool {
+        self.0.is_frozen()
+    }
+
+    /// Removes all the items. Ghost keys and the doorkeeper are reset as well,
+    /// while the stats are kept.
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    /// Removes all the items, like [Cache::clear], returning them in an arbitrary order.
+    pub fn drain(&mut self) -> IntoIter<Key, Val> {
+        IntoIter::new(self.0.drain())
+    }
+
+    /// Removes the items for which `predicate` returns `false`,
+    /// without altering the "hotness" of the kept ones.
+    pub fn retain<F>(&mut self, mut predicate: F)
+    where
+        F: FnMut(&Key, &Val) -> bool,
+    {
+        self.0.retain(|key, _, val| predicate(key, val));
+    }
+
+    /// Lazily removes the items for which `predicate` returns `true`, yielding them in an
+    /// arbitrary order.
+    /// Items not reached before the iterator is dropped are kept.
+    pub fn extract_if<'a, F>(
+        &'a mut self,
+        mut predicate: F,
+    ) -> impl Iterator<Item = (Key, Val)> + 'a
+    where
+        F: FnMut(&Key, &Val) -> bool + 'a,
+    {
+        self.0
+            .extract_if(move |key, _, val| predicate(key, val))
+            .map(|(key, _, val)| (key, val))
+    }
+
+    /// Evicts the entries that weren't accessed for the `idle_timeout` set in the [Options],
+    /// even if the cache is under capacity. Does nothing if `idle_timeout` isn't set.
+    /// Returns the number of evicted entries.
+    pub fn prune_idle(&mut self) -> usize {
+        self.0.prune_idle()
+    }
+
+    /// Re-runs the weighter over every cached item, fixing the accounted weight of the items whose
+    /// weight changed. Items are evicted if the cache ends up over capacity.
+    /// Returns the number of items whose weight was fixed.
+    pub fn reweigh_all(&mut self) -> usize {
+        self.0.reweigh_all()
+    }
+
+    /// Ages the cache by clearing the "referenced" bit of about `fraction` of its entries, each pass
+    /// continuing where the previous one stopped. Entries not accessed since lose the protection of
+    /// past accesses, so they are demoted or evicted when the clock hand reaches them.
+    ///
+    /// In workloads with few insertions the clock hand rarely moves, so calling this periodically
+    /// prevents entries that were popular long ago from staying in the cache indefinitely.
+    /// Returns the number of cleared bits.
+    pub fn decay_referenced(&mut self, fraction: f64) -> usize {
+        self.0.decay_referenced(fraction)
+    }
+
+    /// Resets the hits, misses, evictions and rejections counters.
+    ///
+    /// With [OptionsBuilder::buffered_stats], the lookups buffered by threads before the reset
+    /// are flushed after it, so the hits and misses aren't exact after a reset.
+    pub fn reset_stats(&mut self) {
+        self.0.reset_stats()
+    }
+
+    /// Returns the latency histograms of the cache operations.
+    #[cfg(feature = "latency")]
+    pub fn latency_histograms(&self) -> LatencyHistograms {
+        self.0.latency_histograms()
+    }
+
     /// Reserver additional space for `additional` entries.
     /// Note that this is counted in entries, and is not weighted.
     pub fn reserve(&mut self, additional: usize) {
         self.0.reserve(additional);
     }
 
+    /// Tries to reserve additional space for `additional` entries,
+    /// returning an error instead of aborting if the allocation fails.
+    /// Note that this is counted in entries, and is not weighted.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        self.0.try_reserve(additional)
+    }
+
+    /// Shrinks the internal storage as much as possible, releasing the memory held
+    /// for entries that were evicted or removed.
+    pub fn shrink_to_fit(&mut self) {
+        self.0.shrink_to_fit();
+    }
+
+    /// Rebuilds the cache internal table with `hash_builder`.
+    /// Entries keep their hot/cold state, but ghost keys and the doorkeeper are reset,
+    /// as they only retain key hashes.
+    pub fn rehash_with(&mut self, hash_builder: B) {
+        self.0.rehash_with(hash_builder);
+    }
+
+    /// Moves all the items of `other` into this cache, leaving `other` empty.
+    /// Items keep their hot/cold state and replace the items with the same keys in this cache.
+    /// Their weight is recomputed with the weighter of this cache, and items are evicted
+    /// if the cache ends up over capacity.
+    pub fn merge_from(&mut self, other: &mut Self) {
+        self.0.merge_from(&mut other.0);
+    }
+
+    /// Moves the items matching `predicate` into a new cache built from `options`,
+    /// with clones of the weighter and hash builder of this cache.
+    /// Items keep their hot/cold state, and the new cache evicts them as usual if they don't fit.
+    pub fn split_off<F>(&mut self, options: Options, mut predicate: F) -> Self
+    where
+        We: Clone,
+        B: Clone,
+        F: FnMut(&Key, &Val) -> bool,
+    {
+        Self(self.0.split_off(options, |key, _, val| predicate(key, val)))
+    }
+
     /// Fetches an item from the cache.
     /// Callers should prefer `get_mut` whenever possible as it's more efficient.
-    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&Val>
+    pub fn get<Q>(&self, key: &Q) -> Option<&Val>
     where
         Key: Borrow<Q>,
-        Q: Eq + Hash,
+        Q: Eq + Hash + ?Sized,
     {
         self.0.get(key, &())
     }
 
-    /// Fetches an item from the cache.
-    pub fn get_mut<Q: ?Sized>(&mut self, key: &Q) -> Option<&mut Val>
+    /// Fetches an item from the cache for modification, changing its version.
+    /// Returns `None` if the item isn't cached, or the cache is disabled or frozen.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut Val>
     where
         Key: Borrow<Q>,
-        Q: Eq + Hash,
+        Q: Eq + Hash + ?Sized,
     {
         self.0.get_mut(key, &())
     }
 
+    /// Samples an item of the cache uniformly at random.
+    /// Like peeks, sampling doesn't alter the key "hotness".
+    /// Returns `None` if the cache is empty or disabled.
+    pub fn random_entry(&self) -> Option<(&Key, &Val)> {
+        self.0.random_entry().map(|(key, _, val)| (key, val))
+    }
+
     /// Peeks an item from the cache. Contrary to gets, peeks don't alter the key "hotness".
-    pub fn peek<Q: ?Sized>(&self, key: &Q) -> Option<&Val>
+    pub fn peek<Q>(&self, key: &Q) -> Option<&Val>
     where
         Key: Borrow<Q>,
-        Q: Eq + Hash,
+        Q: Eq + Hash + ?Sized,
     {
         self.0.peek(key, &())
     }
 
+    /// Returns whether an item whose key is `key` is cached.
+    /// Like peeks, this doesn't alter the key "hotness" nor count a hit or a miss.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.0.contains_key(key, &())
+    }
+
     /// Peeks an item from the cache. Contrary to gets, peeks don't alter the key "hotness".
-    pub fn peek_mut<Q: ?Sized>(&mut self, key: &Q) -> Option<&mut Val>
+    /// Returns `None` if the item isn't cached, or the cache is disabled or frozen.
+    pub fn peek_mut<Q>(&mut self, key: &Q) -> Option<&mut Val>
     where
         Key: Borrow<Q>,
-        Q: Eq + Hash,
+        Q: Eq + Hash + ?Sized,
     {
         self.0.peek_mut(key, &())
     }
 
+    /// Fetches an item from the cache, along with its version.
+    ///
+    /// The version changes every time the entry is modified, including mutable accesses,
+    /// see [Cache::compare_exchange].
+    pub fn get_with_version<Q>(&self, key: &Q) -> Option<(&Val, u64)>
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.0.get_with_version(key, &())
+    }
+
+    /// Peeks an item from the cache, along with its version.
+    /// Contrary to gets, peeks don't alter the key "hotness".
+    pub fn peek_with_version<Q>(&self, key: &Q) -> Option<(&Val, u64)>
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.0.peek_with_version(key, &())
+    }
+
+    /// Fetches an item from the cache, along with its flags.
+    ///
+    /// Flags are a few bits set by the user and kept as is by the cache, see [Cache::insert_with_flags].
+    pub fn get_with_flags<Q>(&self, key: &Q) -> Option<(&Val, u16)>
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.0.get_with_flags(key, &())
+    }
+
+    /// Peeks an item from the cache, along with its flags.
+    /// Contrary to gets, peeks don't alter the key "hotness".
+    pub fn peek_with_flags<Q>(&self, key: &Q) -> Option<(&Val, u16)>
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.0.peek_with_flags(key, &())
+    }
+
+    /// Replaces the flags of the item whose key is `key`,
+    /// without altering its "hotness" nor its version.
+    /// Returns the previous flags, or `None` if the item isn't cached.
+    pub fn set_flags<Q>(&mut self, key: &Q, flags: u16) -> Option<u16>
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.0.set_flags(key, &(), flags)
+    }
+
+    /// Iterates over the cached items in an arbitrary order.
+    /// Like peeks, the iteration doesn't alter the keys "hotness".
+    pub fn iter(&self) -> impl Iterator<Item = (&Key, &Val)> + '_ {
+        self.0.iter().map(|(key, _, val)| (key, val))
+    }
+
+    /// Iterates over the cached items in an arbitrary order, without cloning them.
+    /// Like peeks, the iteration doesn't alter the keys "hotness".
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&Key, &mut Val)> + '_ {
+        self.0.iter_mut().map(|(key, _, value)| (key, value))
+    }
+
+    /// Returns a cursor over the entries of the hot or cold `section`, starting from the clock hand,
+    /// that is, the next entry to be considered for demotion or eviction.
+    pub fn cursor(&mut self, section: Section) -> Cursor<'_, Key, (), Val, We, B> {
+        self.0.cursor(section)
+    }
+
+    /// Gets the entry of `key` for in-place inspection, modification, removal
+    /// or insertion, hashing and looking up the key only once.
+    /// Counts as a get, so an occupied entry counts a hit and a vacant one a miss.
+    pub fn entry(&mut self, key: Key) -> Entry<'_, Key, (), Val, We, B> {
+        self.0.entry(key, ())
+    }
+
     /// Remove an item from the cache whose key is `key`.
-    /// Returns whether an entry was removed.
-    pub fn remove<Q: ?Sized>(&mut self, key: &Q) -> bool
+    /// Returns whether an entry was removed, see [Cache::remove_checked] to tell a
+    /// missing item from a frozen cache.
+    pub fn remove<Q>(&mut self, key: &Q) -> bool
     where
         Key: Borrow<Q>,
-        Q: Eq + Hash,
+        Q: Eq + Hash + ?Sized,
     {
         self.0.remove(key, &())
     }
 
+    /// Remove an item from the cache whose key is `key`.
+    /// Contrary to [Cache::remove], an error is returned if the cache is frozen,
+    /// see [Cache::set_frozen].
+    pub fn remove_checked<Q>(&mut self, key: &Q) -> Result<bool, Frozen>
+    where
+        Key: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.0.remove_checked(key, &())
+    }
+
     /// Inserts an item in the cache with key `key` and qey `qey`.
     pub fn insert(&mut self, key: Key, value: Val) {
         self.0.insert(key, (), value);
     }
+
+    /// Inserts an item in the cache with key `key`.
+    /// Contrary to [Cache::insert], the value is given back if it's too heavy to be cached,
+    /// see [OptionsBuilder::max_item_weight].
+    pub fn insert_checked(&mut self, key: Key, value: Val) -> Result<(), Rejected<Val>> {
+        self.0.insert_checked(key, (), value)
+    }
+
+    /// Inserts an item in the cache with key `key`, tagged with `flags`,
+    /// see [Cache::get_with_flags]. Other insertions clear the flags,
+    /// while [Cache::compare_exchange] keeps them.
+    pub fn insert_with_flags(&mut self, key: Key, value: Val, flags: u16) {
+        self.0.insert_with_flags(key, (), value, flags);
+    }
+
+    /// Inserts an item in the cache with key `key`,
+    /// returning the previous value if the item was cached.
+    /// If the new value is too heavy to be cached the previous item is removed (and returned)
+    /// instead of being kept. If the cache is frozen the value is given back as
+    /// [Rejected::Frozen].
+    pub fn replace(&mut self, key: Key, value: Val) -> Result<Option<Val>, Rejected<Val>> {
+        self.0.replace(key, (), value)
+    }
+
+    /// Inserts an item in the cache with key `key` only if it isn't cached yet,
+    /// for first-writer-wins semantics. Otherwise the cached value is returned and `value` is
+    /// dropped. Counts as a get of the item.
+    /// Like [Cache::insert_checked], the value is given back if it can't be cached.
+    pub fn insert_if_absent(
+        &mut self,
+        key: Key,
+        value: Val,
+    ) -> Result<Option<&Val>, Rejected<Val>> {
+        self.0.insert_if_absent(key, (), value)
+    }
+
+    /// Replaces the value of the item with key `key` if its version, as returned by
+    /// [Cache::get_with_version] or [Cache::peek_with_version], is still `expected`.
+    /// Returns the new version of the entry.
+    ///
+    /// Otherwise nothing is changed and the current version is returned as an error,
+    /// or `None` if the item isn't in the cache.
+    /// If the new value is too heavy to be cached the item is removed instead,
+    /// and `None` is returned as an error as it isn't in the cache anymore.
+    pub fn compare_exchange(
+        &mut self,
+        key: Key,
+        expected: u64,
+        value: Val,
+    ) -> Result<u64, Option<u64>> {
+        self.0.compare_exchange(key, (), expected, value)
+    }
+
+    /// Runs `f` with mutable access to the value of the item with key `key`,
+    /// or `None` if it isn't cached, and applies the returned [Compute]:
+    /// a kept item has its weight recomputed with the [Weighter], an inserted value replaces
+    /// the cached one (see [Cache::replace]) and a removed item is taken out of the cache.
+    /// Items may be evicted if the cache ends up over capacity.
+    ///
+    /// Counts as a get of the item, and mutable accesses change the item version.
+    /// If the cache is frozen nothing is done and `f` isn't run.
+    /// Returns the replaced or removed value, if any.
+    pub fn compute<F>(&mut self, key: Key, f: F) -> Option<Val>
+    where
+        F: FnOnce(Option<&mut Val>) -> Compute<Val>,
+    {
+        self.0.compute(key, (), f)
+    }
+}
+
+/// Consumes the cache into an iterator over its items, in an arbitrary order.
+impl<Key: Eq + Hash, Val, We: Weighter<Key, (), Val>, B: BuildHasher> IntoIterator
+    for Cache<Key, Val, We, B>
+{
+    type Item = (Key, Val);
+    type IntoIter = IntoIter<Key, Val>;
+
+    fn into_iter(self) -> IntoIter<Key, Val> {
+        IntoIter::new(self.0.into_iter())
+    }
 }
 
 impl<Key, Val, We, B> std::fmt::Debug for Cache<Key, Val, We, B> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Cache").finish_non_exhaustive()
+        self.0.fmt_summary("Cache", f)
     }
 }